@@ -65,6 +65,12 @@ pub enum Error {
     /** Method Not Allowed */
     #[error("Method Not Allowed: {internal_message}")]
     MethodNotAllowed { internal_message: String },
+    /**
+     * A precondition of the request (e.g., the version of the object that the
+     * client expected to be modifying) did not hold.
+     */
+    #[error("Precondition Failed: {message}")]
+    PreconditionFailed { message: String },
 }
 
 /** Indicates how an object was looked up (for an `ObjectNotFound` error) */
@@ -94,6 +100,7 @@ impl Error {
             | Error::InvalidValue { .. }
             | Error::Forbidden
             | Error::MethodNotAllowed { .. }
+            | Error::PreconditionFailed { .. }
             | Error::InternalError { .. } => false,
         }
     }
@@ -230,6 +237,14 @@ impl From<Error> for HttpError {
                 )
             }
 
+            Error::PreconditionFailed { message } => {
+                HttpError::for_client_error(
+                    Some(String::from("PreconditionFailed")),
+                    http::StatusCode::PRECONDITION_FAILED,
+                    message,
+                )
+            }
+
             Error::Forbidden => HttpError::for_client_error(
                 Some(String::from("Forbidden")),
                 http::StatusCode::FORBIDDEN,
@@ -254,7 +269,15 @@ impl From<anyhow::Error> for crate::api::external::Error {
     fn from(e: anyhow::Error) -> Self {
         // TODO this needs to be updated when progenitor gets better error types.
         if let Some(ee) = e.downcast_ref::<reqwest::Error>() {
+            if ee.is_timeout() {
+                return crate::api::external::Error::unavail(&e.to_string());
+            }
             if let Some(s) = ee.status() {
+                if s == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                    return crate::api::external::Error::unavail(
+                        &e.to_string(),
+                    );
+                }
                 if s.is_client_error() {
                     return crate::api::external::Error::InvalidRequest {
                         message: e.to_string(),
@@ -46,6 +46,8 @@ use crate::api::external::DataPageParams;
 use crate::api::external::Name;
 use crate::api::external::ObjectIdentity;
 use crate::api::external::PaginationOrder;
+use chrono::DateTime;
+use chrono::Utc;
 use dropshot::HttpError;
 use dropshot::PaginationParams;
 use dropshot::RequestContext;
@@ -485,15 +487,260 @@ fn data_page_params_nameid_id_limit(
     Ok(DataPageParams { limit, direction, marker })
 }
 
+/*
+ * Pagination by any of: name ascending, name descending, id ascending, or
+ * time modified in either direction.  Unlike names and ids, modification times
+ * aren't unique, so a scan by time modified is really a scan by (time modified,
+ * id).
+ *
+ * A scan may also be limited to items matching a search term.  Like the sort
+ * mode, the search term is part of the scan parameters, so it's carried along
+ * in the page token and applies to every page of the scan.
+ */
+
+/** Query parameters for pagination by name, id, or time modified */
+pub type PaginatedByNameIdOrModified =
+    PaginationParams<ScanByNameIdOrModified, PageSelectorByNameIdOrModified>;
+/** Page selector for pagination by name, id, or time modified */
+pub type PageSelectorByNameIdOrModified =
+    PageSelector<ScanByNameIdOrModified, NameIdOrModifiedMarker>;
+/**
+ * Scan parameters for resources that support scanning by name, id, or time
+ * modified
+ */
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct ScanByNameIdOrModified {
+    #[serde(default = "default_nameidmodified_sort_mode")]
+    sort_by: NameIdOrModifiedSortMode,
+    /**
+     * only list items whose name or description contains this (ignoring case)
+     */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    search: Option<String>,
+}
+
+impl ScanByNameIdOrModified {
+    /** Returns the search term that items must match, if any */
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+}
+/** Supported set of sort modes for scanning by name, id, or time modified */
+#[derive(Copy, Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NameIdOrModifiedSortMode {
+    /** sort in increasing order of "name" */
+    NameAscending,
+    /** sort in decreasing order of "name" */
+    NameDescending,
+    /** sort in increasing order of "id" */
+    IdAscending,
+    /** sort in increasing order of "time_modified" (least recent first) */
+    ModifiedAscending,
+    /** sort in decreasing order of "time_modified" (most recent first) */
+    ModifiedDescending,
+}
+
+fn default_nameidmodified_sort_mode() -> NameIdOrModifiedSortMode {
+    NameIdOrModifiedSortMode::NameAscending
+}
+
+/** Marker for a scan by time modified */
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct ModifiedMarker {
+    /** "time_modified" of the last item seen */
+    pub time_modified: DateTime<Utc>,
+    /** "id" of the last item seen, to order items modified at the same time */
+    pub id: Uuid,
+}
+
+/*
+ * See the TODO-correctness comment on `NameOrIdMarker`, which applies here,
+ * too.
+ */
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NameIdOrModifiedMarker {
+    Id(Uuid),
+    Name(Name),
+    Modified(ModifiedMarker),
+}
+
+impl ScanParams for ScanByNameIdOrModified {
+    type MarkerValue = NameIdOrModifiedMarker;
+
+    fn direction(&self) -> PaginationOrder {
+        match self.sort_by {
+            NameIdOrModifiedSortMode::NameAscending => {
+                PaginationOrder::Ascending
+            }
+            NameIdOrModifiedSortMode::NameDescending => {
+                PaginationOrder::Descending
+            }
+            NameIdOrModifiedSortMode::IdAscending => PaginationOrder::Ascending,
+            NameIdOrModifiedSortMode::ModifiedAscending => {
+                PaginationOrder::Ascending
+            }
+            NameIdOrModifiedSortMode::ModifiedDescending => {
+                PaginationOrder::Descending
+            }
+        }
+    }
+
+    fn marker_for_item<T: ObjectIdentity>(
+        &self,
+        item: &T,
+    ) -> NameIdOrModifiedMarker {
+        let identity = item.identity();
+        match self.sort_by {
+            NameIdOrModifiedSortMode::NameAscending
+            | NameIdOrModifiedSortMode::NameDescending => {
+                NameIdOrModifiedMarker::Name(identity.name.clone())
+            }
+            NameIdOrModifiedSortMode::IdAscending => {
+                NameIdOrModifiedMarker::Id(identity.id)
+            }
+            NameIdOrModifiedSortMode::ModifiedAscending
+            | NameIdOrModifiedSortMode::ModifiedDescending => {
+                NameIdOrModifiedMarker::Modified(ModifiedMarker {
+                    time_modified: identity.time_modified,
+                    id: identity.id,
+                })
+            }
+        }
+    }
+
+    fn from_query(
+        p: &PaginationParams<Self, PageSelector<Self, Self::MarkerValue>>,
+    ) -> Result<&Self, HttpError> {
+        match &p.page {
+            WhichPage::First(scan_mode) => Ok(scan_mode),
+            WhichPage::Next(PageSelectorByNameIdOrModified {
+                scan,
+                last_seen,
+            }) => {
+                let consistent = match (scan.sort_by, last_seen) {
+                    (
+                        NameIdOrModifiedSortMode::NameAscending
+                        | NameIdOrModifiedSortMode::NameDescending,
+                        NameIdOrModifiedMarker::Name(_),
+                    ) => true,
+                    (
+                        NameIdOrModifiedSortMode::IdAscending,
+                        NameIdOrModifiedMarker::Id(_),
+                    ) => true,
+                    (
+                        NameIdOrModifiedSortMode::ModifiedAscending
+                        | NameIdOrModifiedSortMode::ModifiedDescending,
+                        NameIdOrModifiedMarker::Modified(_),
+                    ) => true,
+                    _ => false,
+                };
+                if consistent {
+                    Ok(scan)
+                } else {
+                    Err(bad_token_error())
+                }
+            }
+        }
+    }
+}
+
+/**
+ * [`DataPageParams`] for a `ScanByNameIdOrModified`, with the marker type
+ * corresponding to the field being scanned
+ */
+#[derive(Debug)]
+pub enum NameIdOrModifiedPage<'a> {
+    Name(DataPageParams<'a, Name>),
+    Id(DataPageParams<'a, Uuid>),
+    Modified(DataPageParams<'a, ModifiedMarker>),
+}
+
+/**
+ * Serves the same purpose as [`data_page_params_for`] for
+ * `ScanByNameIdOrModified`
+ *
+ * Like [`data_page_params_nameid_name`] and [`data_page_params_nameid_id`],
+ * this extracts the specific marker type for the field being scanned, but it
+ * figures out which field that is itself.
+ */
+pub fn data_page_params_nameidmodified<'a, C>(
+    rqctx: &'a Arc<RequestContext<C>>,
+    pag_params: &'a PaginatedByNameIdOrModified,
+) -> Result<NameIdOrModifiedPage<'a>, HttpError>
+where
+    C: dropshot::ServerContext,
+{
+    let limit = rqctx.page_limit(&pag_params)?;
+    data_page_params_nameidmodified_limit(limit, pag_params)
+}
+
+fn data_page_params_nameidmodified_limit(
+    limit: NonZeroU32,
+    pag_params: &PaginatedByNameIdOrModified,
+) -> Result<NameIdOrModifiedPage, HttpError> {
+    let data_page = data_page_params_with_limit(limit, pag_params)?;
+    let direction = data_page.direction;
+    let scan = ScanByNameIdOrModified::from_query(pag_params)?;
+    Ok(match scan.sort_by {
+        NameIdOrModifiedSortMode::NameAscending
+        | NameIdOrModifiedSortMode::NameDescending => {
+            let marker = match data_page.marker {
+                None => None,
+                Some(NameIdOrModifiedMarker::Name(name)) => Some(name),
+                Some(_) => return Err(bad_token_error()),
+            };
+            NameIdOrModifiedPage::Name(DataPageParams {
+                limit,
+                direction,
+                marker,
+            })
+        }
+        NameIdOrModifiedSortMode::IdAscending => {
+            let marker = match data_page.marker {
+                None => None,
+                Some(NameIdOrModifiedMarker::Id(id)) => Some(id),
+                Some(_) => return Err(bad_token_error()),
+            };
+            NameIdOrModifiedPage::Id(DataPageParams {
+                limit,
+                direction,
+                marker,
+            })
+        }
+        NameIdOrModifiedSortMode::ModifiedAscending
+        | NameIdOrModifiedSortMode::ModifiedDescending => {
+            let marker = match data_page.marker {
+                None => None,
+                Some(NameIdOrModifiedMarker::Modified(modified)) => {
+                    Some(modified)
+                }
+                Some(_) => return Err(bad_token_error()),
+            };
+            NameIdOrModifiedPage::Modified(DataPageParams {
+                limit,
+                direction,
+                marker,
+            })
+        }
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::data_page_params_nameid_id_limit;
     use super::data_page_params_nameid_name_limit;
+    use super::data_page_params_nameidmodified_limit;
     use super::data_page_params_with_limit;
     use super::page_selector_for;
     use super::pagination_field_for_scan_params;
     use super::IdSortMode;
+    use super::ModifiedMarker;
     use super::Name;
+    use super::NameIdOrModifiedMarker;
+    use super::NameIdOrModifiedPage;
+    use super::NameIdOrModifiedSortMode;
     use super::NameOrIdMarker;
     use super::NameOrIdSortMode;
     use super::NameSortMode;
@@ -501,16 +748,20 @@ mod test {
     use super::PageSelector;
     use super::PageSelectorById;
     use super::PageSelectorByName;
+    use super::PageSelectorByNameIdOrModified;
     use super::PageSelectorByNameOrId;
     use super::PaginatedById;
     use super::PaginatedByName;
+    use super::PaginatedByNameIdOrModified;
     use super::PaginatedByNameOrId;
     use super::ScanById;
     use super::ScanByName;
+    use super::ScanByNameIdOrModified;
     use super::ScanByNameOrId;
     use super::ScanParams;
     use crate::api::external::IdentityMetadata;
     use crate::api::external::ObjectIdentity;
+    use chrono::TimeZone;
     use chrono::Utc;
     use dropshot::PaginationOrder;
     use dropshot::PaginationParams;
@@ -543,6 +794,10 @@ mod test {
                 "scan parameters, scan by name or id",
                 schema_for!(ScanByNameOrId),
             ),
+            (
+                "scan parameters, scan by name, id, or time modified",
+                schema_for!(ScanByNameIdOrModified),
+            ),
             (
                 "page selector, scan by name only",
                 schema_for!(PageSelectorByName),
@@ -552,6 +807,10 @@ mod test {
                 "page selector, scan by name or id",
                 schema_for!(PageSelectorByNameOrId),
             ),
+            (
+                "page selector, scan by name, id, or time modified",
+                schema_for!(PageSelectorByNameIdOrModified),
+            ),
         ];
 
         let mut found_output = String::new();
@@ -578,8 +837,13 @@ mod test {
             ScanByNameOrId { sort_by: NameOrIdSortMode::NameAscending };
         let scan_by_nameid_id =
             ScanByNameOrId { sort_by: NameOrIdSortMode::IdAscending };
+        let scan_by_nameidmodified_modified = ScanByNameIdOrModified {
+            sort_by: NameIdOrModifiedSortMode::ModifiedDescending,
+            search: None,
+        };
         let id: Uuid = "61a78113-d3c6-4b35-a410-23e9eae64328".parse().unwrap();
         let name: Name = "bort".parse().unwrap();
+        let time_modified = Utc.ymd(2021, 12, 1).and_hms(0, 0, 0);
         let examples = vec![
             /* scan parameters only */
             ("scan by id ascending", to_string_pretty(&scan_by_id).unwrap()),
@@ -595,6 +859,11 @@ mod test {
                 "scan by name or id, using name ascending",
                 to_string_pretty(&scan_by_nameid_name).unwrap(),
             ),
+            (
+                "scan by name, id, or time modified, using time modified \
+                 descending",
+                to_string_pretty(&scan_by_nameidmodified_modified).unwrap(),
+            ),
             /* page selectors */
             (
                 "page selector: by id ascending",
@@ -628,6 +897,17 @@ mod test {
                 })
                 .unwrap(),
             ),
+            (
+                "page selector: by name, id, or time modified, using time \
+                 modified descending",
+                to_string_pretty(&PageSelectorByNameIdOrModified {
+                    scan: scan_by_nameidmodified_modified,
+                    last_seen: NameIdOrModifiedMarker::Modified(
+                        ModifiedMarker { time_modified, id },
+                    ),
+                })
+                .unwrap(),
+            ),
         ];
 
         let mut found_output = String::new();
@@ -914,4 +1194,87 @@ mod test {
         assert_eq!(error.status_code, StatusCode::BAD_REQUEST);
         assert_eq!(error.external_message, "invalid page token");
     }
+
+    #[test]
+    fn test_scan_by_nameidmodified() {
+        /* Start with the common battery of tests. */
+        /*
+         * Include a search term to check that it's carried along in the page
+         * token.
+         */
+        let scan = ScanByNameIdOrModified {
+            sort_by: NameIdOrModifiedSortMode::ModifiedDescending,
+            search: Some(String::from("Thing")),
+        };
+        assert_eq!(scan.direction(), PaginationOrder::Descending);
+        assert_eq!(scan.search(), Some("Thing"));
+
+        let list = list_of_things();
+        let marker_for = |thing: &MyThing| ModifiedMarker {
+            time_modified: thing.identity.time_modified,
+            id: thing.identity.id,
+        };
+        let thing0_marker =
+            NameIdOrModifiedMarker::Modified(marker_for(&list[0]));
+        let thinglast = marker_for(&list[list.len() - 1]);
+        let thinglast_marker =
+            NameIdOrModifiedMarker::Modified(thinglast.clone());
+        let (p0, p1) = test_scan_param_common(
+            &list,
+            &scan,
+            "sort_by=modified-descending&search=Thing",
+            &thing0_marker,
+            &thinglast_marker,
+            &ScanByNameIdOrModified {
+                sort_by: NameIdOrModifiedSortMode::NameAscending,
+                search: None,
+            },
+        );
+
+        /* Verify data pages based on the query params. */
+        let limit = NonZeroU32::new(123).unwrap();
+        match data_page_params_nameidmodified_limit(limit, &p0).unwrap() {
+            NameIdOrModifiedPage::Modified(data_page) => {
+                assert_eq!(data_page.marker, None);
+                assert_eq!(data_page.direction, PaginationOrder::Descending);
+                assert_eq!(data_page.limit, limit);
+            }
+            other => panic!("unexpected data page: {:?}", other),
+        }
+
+        match data_page_params_nameidmodified_limit(limit, &p1).unwrap() {
+            NameIdOrModifiedPage::Modified(data_page) => {
+                assert_eq!(data_page.marker, Some(&thinglast));
+                assert_eq!(data_page.direction, PaginationOrder::Descending);
+                assert_eq!(data_page.limit, limit);
+            }
+            other => panic!("unexpected data page: {:?}", other),
+        }
+
+        /* The other sort modes produce the corresponding kind of page. */
+        let p: PaginatedByNameIdOrModified =
+            serde_urlencoded::from_str("").unwrap();
+        assert!(matches!(
+            data_page_params_nameidmodified_limit(limit, &p).unwrap(),
+            NameIdOrModifiedPage::Name(_)
+        ));
+        let p: PaginatedByNameIdOrModified =
+            serde_urlencoded::from_str("sort_by=id-ascending").unwrap();
+        assert!(matches!(
+            data_page_params_nameidmodified_limit(limit, &p).unwrap(),
+            NameIdOrModifiedPage::Id(_)
+        ));
+
+        /* Test from_query(): error case. */
+        let error = serde_urlencoded::from_str::<PaginatedByNameIdOrModified>(
+            "sort_by=id-descending",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown variant `id-descending`, expected one of \
+             `name-ascending`, `name-descending`, `id-ascending`, \
+             `modified-ascending`, `modified-descending`"
+        );
+    }
 }
@@ -133,6 +133,13 @@ impl<'a, NameType> DataPageParams<'a, NameType> {
  * Names are generally user-provided unique identifiers, highly constrained as
  * described in RFD 4.  An `Name` can only be constructed with a string
  * that's valid as a name.
+ *
+ * A string that isn't a valid name can't identify any object, but we still
+ * distinguish that from a valid name that doesn't identify an object: when
+ * an invalid name appears in a URL path, the request fails with a 400 ("Bad
+ * Request") error whose message begins with "invalid name: " (after whatever
+ * prefix Dropshot adds), rather than a 404 ("Not Found").  A 404 for a named
+ * object always means that the name was valid but no such object exists.
  */
 #[derive(
     Clone,
@@ -160,17 +167,19 @@ impl TryFrom<String> for Name {
     type Error = String;
     fn try_from(value: String) -> Result<Self, Self::Error> {
         if value.len() > 63 {
-            return Err(String::from("name may contain at most 63 characters"));
+            return Err(String::from(
+                "invalid name: may contain at most 63 characters",
+            ));
         }
 
         let mut iter = value.chars();
 
         let first = iter.next().ok_or_else(|| {
-            String::from("name requires at least one character")
+            String::from("invalid name: requires at least one character")
         })?;
         if !first.is_ascii_lowercase() {
             return Err(String::from(
-                "name must begin with an ASCII lowercase character",
+                "invalid name: must begin with an ASCII lowercase character",
             ));
         }
 
@@ -180,15 +189,15 @@ impl TryFrom<String> for Name {
 
             if !c.is_ascii_lowercase() && !c.is_digit(10) && c != '-' {
                 return Err(format!(
-                    "name contains invalid character: \"{}\" (allowed \
-                     characters are lowercase ASCII, digits, and \"-\")",
+                    "invalid name: contains \"{}\" (allowed characters are \
+                     lowercase ASCII, digits, and \"-\")",
                     c
                 ));
             }
         }
 
         if last == '-' {
-            return Err(String::from("name cannot end with \"-\""));
+            return Err(String::from("invalid name: cannot end with \"-\""));
         }
 
         Ok(Name(value))
@@ -288,6 +297,21 @@ impl Name {
         })
     }
 
+    /**
+     * Parse a `Name` from free-form client input.
+     *
+     * Leading and trailing whitespace is trimmed before validation, so
+     * `"  my-name\n"` produces the same `Name` as `"my-name"`.  Whitespace
+     * inside the name is still rejected.  No case folding is done: uppercase
+     * characters remain invalid rather than being silently lowercased, since
+     * that could make two distinct inputs refer to the same object.  Any
+     * validation failure is reported as `Error::InvalidValue` with label
+     * "name".
+     */
+    pub fn normalize(input: &str) -> Result<Name, Error> {
+        Name::from_param(String::from(input.trim()), "name")
+    }
+
     /**
      * Return the `&str` representing the actual name.
      */
@@ -315,7 +339,9 @@ impl Name {
  * the database as an i64.  Constraining it here ensures that we can't fail to
  * serialize the value.
  */
-#[derive(Copy, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[derive(
+    Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize, JsonSchema,
+)]
 pub struct ByteCount(u64);
 
 impl ByteCount {
@@ -420,13 +446,24 @@ impl Generation {
 
     pub fn next(&self) -> Generation {
         /*
-         * It should technically be an operational error if this wraps or even
-         * exceeds the value allowed by an i64.  But it seems unlikely enough to
-         * happen in practice that we can probably feel safe with this.
+         * This panics rather than wrapping once the generation would exceed
+         * what an i64 can hold.  That seems unlikely enough to happen in
+         * practice that callers can use this, but those that would rather
+         * handle it as an operational error should use `checked_next()`.
          */
-        let next_gen = self.0 + 1;
-        assert!(next_gen <= u64::try_from(i64::MAX).unwrap());
-        Generation(next_gen)
+        self.checked_next().expect("generation number overflowed")
+    }
+
+    /**
+     * Returns the generation after this one, or `None` if this is the largest
+     * generation that can be represented (which is limited by what we can
+     * store in the database as an i64).
+     */
+    pub fn checked_next(&self) -> Option<Generation> {
+        self.0
+            .checked_add(1)
+            .filter(|next_gen| *next_gen <= u64::try_from(i64::MAX).unwrap())
+            .map(Generation)
     }
 }
 
@@ -547,8 +584,16 @@ pub struct IdentityMetadata {
     pub time_modified: DateTime<Utc>,
 }
 
+/**
+ * Description given to resources that were created or updated with an empty
+ * one
+ */
+pub const DEFAULT_DESCRIPTION: &str = "no description";
+
 /**
  * Create-time identity-related parameters
+ *
+ * An empty `description` is replaced with [`DEFAULT_DESCRIPTION`].
  */
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
@@ -559,6 +604,8 @@ pub struct IdentityMetadataCreateParams {
 
 /**
  * Updateable identity-related parameters
+ *
+ * An empty `description` is replaced with [`DEFAULT_DESCRIPTION`].
  */
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
@@ -679,7 +726,9 @@ impl InstanceState {
 }
 
 /** The number of CPUs in an Instance */
-#[derive(Copy, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[derive(
+    Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize, JsonSchema,
+)]
 pub struct InstanceCpuCount(pub u16);
 
 impl TryFrom<i64> for InstanceCpuCount {
@@ -931,6 +980,12 @@ impl From<steno::SagaView> for Saga {
 pub enum SagaState {
     Running,
     Succeeded,
+    /*
+     * The saga failed and has been unwound.  Steno runs the undo actions of
+     * every completed node before reporting a saga as done, so a failed saga
+     * that's visible here has always been unwound.  (Steno does not currently
+     * support undo actions that fail.)
+     */
     Failed { error_node_name: String, error_info: SagaErrorInfo },
 }
 
@@ -1838,7 +1893,7 @@ pub struct NetworkInterface {
 #[cfg(test)]
 mod test {
     use super::{
-        ByteCount, L4Port, L4PortRange, Name, NetworkTarget,
+        ByteCount, Generation, L4Port, L4PortRange, Name, NetworkTarget,
         VpcFirewallRuleAction, VpcFirewallRuleDirection, VpcFirewallRuleFilter,
         VpcFirewallRuleHostFilter, VpcFirewallRulePriority,
         VpcFirewallRuleProtocol, VpcFirewallRuleStatus, VpcFirewallRuleTarget,
@@ -1849,6 +1904,26 @@ mod test {
     use std::net::IpAddr;
     use std::net::Ipv4Addr;
 
+    #[test]
+    fn test_generation_next() {
+        let first = Generation::new();
+        let second = first.next();
+        let third = second.next();
+        assert!(first < second);
+        assert!(second < third);
+        assert_eq!(first.checked_next(), Some(second));
+
+        let last = Generation::try_from(i64::MAX).unwrap();
+        assert_eq!(last.checked_next(), None);
+        assert_eq!(i64::from(&last), i64::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "generation number overflowed")]
+    fn test_generation_next_overflow() {
+        Generation::try_from(i64::MAX).unwrap().next();
+    }
+
     #[test]
     fn test_name_parse() {
         /*
@@ -1858,24 +1933,30 @@ mod test {
             "a234567890123456789012345678901234567890123456789012345678901234";
         assert_eq!(long_name.len(), 64);
         let error_cases: Vec<(&str, &str)> = vec![
-            ("", "name requires at least one character"),
-            (long_name, "name may contain at most 63 characters"),
-            ("123", "name must begin with an ASCII lowercase character"),
-            ("-abc", "name must begin with an ASCII lowercase character"),
-            ("abc-", "name cannot end with \"-\""),
+            ("", "invalid name: requires at least one character"),
+            (long_name, "invalid name: may contain at most 63 characters"),
+            (
+                "123",
+                "invalid name: must begin with an ASCII lowercase character",
+            ),
+            (
+                "-abc",
+                "invalid name: must begin with an ASCII lowercase character",
+            ),
+            ("abc-", "invalid name: cannot end with \"-\""),
             (
                 "aBc",
-                "name contains invalid character: \"B\" (allowed characters \
+                "invalid name: contains \"B\" (allowed characters \
                  are lowercase ASCII, digits, and \"-\")",
             ),
             (
                 "a_c",
-                "name contains invalid character: \"_\" (allowed characters \
+                "invalid name: contains \"_\" (allowed characters \
                  are lowercase ASCII, digits, and \"-\")",
             ),
             (
                 "a\u{00e9}cc",
-                "name contains invalid character: \"\u{00e9}\" (allowed \
+                "invalid name: contains \"\u{00e9}\" (allowed \
                  characters are lowercase ASCII, digits, and \"-\")",
             ),
         ];
@@ -1909,11 +1990,62 @@ mod test {
             result,
             Err(Error::InvalidValue {
                 label: "the_name".to_string(),
-                message: "name requires at least one character".to_string()
+                message: "invalid name: requires at least one character"
+                    .to_string()
             })
         );
     }
 
+    #[test]
+    fn test_name_normalize() {
+        /*
+         * Leading and trailing whitespace is trimmed.
+         */
+        for input in &["my-name", " my-name", "my-name ", "\t my-name\n"] {
+            eprintln!("check normalize \"{}\" (should be valid)", input);
+            assert_eq!(Name::normalize(input).unwrap().as_str(), "my-name");
+        }
+
+        /*
+         * Interior whitespace, casing, and the usual character rules are all
+         * still enforced after trimming.
+         */
+        let error_cases: Vec<(&str, &str)> = vec![
+            ("", "invalid name: requires at least one character"),
+            ("   ", "invalid name: requires at least one character"),
+            (
+                "my name",
+                "invalid name: contains \" \" (allowed characters \
+                 are lowercase ASCII, digits, and \"-\")",
+            ),
+            (
+                " My-name ",
+                "invalid name: must begin with an ASCII lowercase character",
+            ),
+            (
+                "my-Name",
+                "invalid name: contains \"N\" (allowed characters \
+                 are lowercase ASCII, digits, and \"-\")",
+            ),
+            (
+                " 123 ",
+                "invalid name: must begin with an ASCII lowercase character",
+            ),
+            ("abc- ", "invalid name: cannot end with \"-\""),
+        ];
+
+        for (input, expected_message) in error_cases {
+            eprintln!("check normalize \"{}\" (expecting error)", input);
+            assert_eq!(
+                Name::normalize(input).unwrap_err(),
+                Error::InvalidValue {
+                    label: String::from("name"),
+                    message: String::from(expected_message),
+                }
+            );
+        }
+    }
+
     #[test]
     fn test_bytecount() {
         /* Smallest supported value: all constructors */
@@ -0,0 +1,106 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Typed identifiers for control plane objects
+//!
+//! Each of these wraps a [`Uuid`] so that, for example, a sled id can't be
+//! passed where an instance id is expected.  They serialize exactly like the
+//! underlying [`Uuid`], so switching an interface over to them doesn't change
+//! what goes over the wire or into saga logs.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use uuid::Uuid;
+
+macro_rules! id_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            Deserialize,
+            Eq,
+            Hash,
+            JsonSchema,
+            Ord,
+            PartialEq,
+            PartialOrd,
+            Serialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub Uuid);
+
+        impl $name {
+            /// Returns a new, random id.
+            pub fn new_v4() -> $name {
+                $name(Uuid::new_v4())
+            }
+        }
+
+        impl From<Uuid> for $name {
+            fn from(id: Uuid) -> $name {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for Uuid {
+            fn from(id: $name) -> Uuid {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Unique identifier for a sled
+    SledId
+);
+id_type!(
+    /// Unique identifier for an Instance
+    InstanceId
+);
+id_type!(
+    /// Unique identifier for a Project
+    ProjectId
+);
+
+#[cfg(test)]
+mod test {
+    use super::{InstanceId, ProjectId, SledId};
+    use std::collections::BTreeSet;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_ids_serialize_like_uuids() {
+        let uuid = Uuid::new_v4();
+        let expected = serde_json::to_string(&uuid).unwrap();
+        assert_eq!(serde_json::to_string(&SledId(uuid)).unwrap(), expected);
+        assert_eq!(serde_json::to_string(&InstanceId(uuid)).unwrap(), expected);
+        assert_eq!(serde_json::to_string(&ProjectId(uuid)).unwrap(), expected);
+
+        let sled_id: SledId = serde_json::from_str(&expected).unwrap();
+        assert_eq!(Uuid::from(sled_id), uuid);
+        assert_eq!(sled_id.to_string(), uuid.to_string());
+
+        // Collections of ids are interchangeable with collections of uuids.
+        let uuids: BTreeSet<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let sled_ids: BTreeSet<SledId> =
+            uuids.iter().copied().map(SledId::from).collect();
+        let serialized = serde_json::to_string(&uuids).unwrap();
+        assert_eq!(serde_json::to_string(&sled_ids).unwrap(), serialized);
+        assert_eq!(
+            serde_json::from_str::<BTreeSet<SledId>>(&serialized).unwrap(),
+            sled_ids
+        );
+    }
+}
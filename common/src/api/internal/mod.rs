@@ -4,5 +4,6 @@
 
 //! Internally facing APIs.
 
+pub mod ids;
 pub mod nexus;
 pub mod sled_agent;
@@ -16,7 +16,7 @@ use uuid::Uuid;
 
 /// Runtime state of the Disk, which includes its attach state and some minimal
 /// metadata
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
 pub struct DiskRuntimeState {
     /// runtime state of the Disk
     pub disk_state: DiskState,
@@ -30,7 +30,7 @@ pub struct DiskRuntimeState {
 /// metadata
 ///
 /// This state is owned by the sled agent running that Instance.
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
 pub struct InstanceRuntimeState {
     /// runtime state of the Instance
     pub run_state: InstanceState,
@@ -51,6 +51,30 @@ pub struct InstanceRuntimeState {
     pub time_updated: DateTime<Utc>,
 }
 
+impl InstanceRuntimeState {
+    /// Returns whether `self` and `other` are identical apart from
+    /// `time_updated`.
+    pub fn equivalent_ignoring_time(&self, other: &Self) -> bool {
+        let InstanceRuntimeState {
+            run_state,
+            sled_uuid,
+            propolis_uuid,
+            ncpus,
+            memory,
+            hostname,
+            gen,
+            time_updated: _,
+        } = self;
+        *run_state == other.run_state
+            && *sled_uuid == other.sled_uuid
+            && *propolis_uuid == other.propolis_uuid
+            && *ncpus == other.ncpus
+            && *memory == other.memory
+            && *hostname == other.hostname
+            && *gen == other.gen
+    }
+}
+
 // Oximeter producer/collector objects.
 
 /// Information announced by a metric server, used so that clients can contact it and collect
@@ -71,3 +95,45 @@ impl ProducerEndpoint {
         format!("{}/{}", &self.base_route, &self.id)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::InstanceRuntimeState;
+    use crate::api::external::{
+        ByteCount, Generation, InstanceCpuCount, InstanceState,
+    };
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_instance_runtime_state_equivalent_ignoring_time() {
+        let state = InstanceRuntimeState {
+            run_state: InstanceState::Running,
+            sled_uuid: Uuid::new_v4(),
+            propolis_uuid: Uuid::new_v4(),
+            ncpus: InstanceCpuCount(2),
+            memory: ByteCount::from_mebibytes_u32(512),
+            hostname: "myvm".to_string(),
+            gen: Generation::new(),
+            time_updated: Utc::now(),
+        };
+        assert!(state.equivalent_ignoring_time(&state));
+
+        let later = InstanceRuntimeState {
+            time_updated: state.time_updated + Duration::seconds(1),
+            ..state.clone()
+        };
+        assert_ne!(state, later);
+        assert!(state.equivalent_ignoring_time(&later));
+        assert!(later.equivalent_ignoring_time(&state));
+
+        let stopped = InstanceRuntimeState {
+            run_state: InstanceState::Stopped,
+            ..state.clone()
+        };
+        assert!(!state.equivalent_ignoring_time(&stopped));
+
+        let next = InstanceRuntimeState { gen: state.gen.next(), ..later };
+        assert!(!state.equivalent_ignoring_time(&next));
+    }
+}
@@ -6,3 +6,4 @@
 
 pub mod external;
 pub mod internal;
+pub mod registration;
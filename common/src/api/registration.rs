@@ -0,0 +1,203 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Registering endpoints with a Dropshot API, with conflict detection
+//!
+//! Dropshot's own checks for conflicting routes describe the route, not the
+//! endpoints involved, and with dozens of endpoints in one API that can be hard
+//! to track down.  [`EndpointRegistrar`] checks for conflicts itself before
+//! handing each endpoint to Dropshot and reports both endpoints by name.
+
+use dropshot::ApiDescription;
+use dropshot::ApiEndpoint;
+use dropshot::ServerContext;
+use std::collections::BTreeMap;
+
+/// Registers endpoints with an [`ApiDescription`], failing with a descriptive
+/// error if two of them would handle the same requests
+pub struct EndpointRegistrar<'a, C: ServerContext> {
+    api: &'a mut ApiDescription<C>,
+    /// operation id of the endpoint registered for each (method, path), where
+    /// the path has its variable names removed
+    routes: BTreeMap<(String, String), String>,
+}
+
+impl<'a, C: ServerContext> EndpointRegistrar<'a, C> {
+    pub fn new(api: &'a mut ApiDescription<C>) -> Self {
+        EndpointRegistrar { api, routes: BTreeMap::new() }
+    }
+
+    /// Registers `endpoint`, unless an endpoint has already been registered
+    /// with the same method and path
+    ///
+    /// Paths that differ only in the names of their variables (e.g.,
+    /// `/instances/{id}` and `/instances/{name}`) are considered the same,
+    /// since they'd match the same requests.
+    pub fn register<T>(&mut self, endpoint: T) -> Result<(), String>
+    where
+        T: Into<ApiEndpoint<C>>,
+    {
+        let endpoint = endpoint.into();
+        let key = (endpoint.method.to_string(), route_shape(&endpoint.path));
+        if let Some(existing) = self.routes.get(&key) {
+            return Err(format!(
+                "endpoints {:?} and {:?} are both registered for {} {:?}",
+                existing, endpoint.operation_id, endpoint.method, endpoint.path
+            ));
+        }
+
+        let operation_id = endpoint.operation_id.clone();
+        self.api.register(endpoint)?;
+        self.routes.insert(key, operation_id);
+        Ok(())
+    }
+}
+
+/// Returns `path` with the name of each variable segment removed, so that two
+/// paths that match the same requests have the same shape
+fn route_shape(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if segment.starts_with('{') && segment.ends_with('}') {
+                "{}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::route_shape;
+    use super::EndpointRegistrar;
+    use dropshot::endpoint;
+    use dropshot::ApiDescription;
+    use dropshot::HttpError;
+    use dropshot::HttpResponseOk;
+    use dropshot::HttpResponseUpdatedNoContent;
+    use dropshot::Path;
+    use dropshot::RequestContext;
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use std::sync::Arc;
+
+    #[derive(Deserialize, JsonSchema)]
+    struct ByName {
+        #[allow(dead_code)]
+        name: String,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    struct ById {
+        #[allow(dead_code)]
+        id: String,
+    }
+
+    #[endpoint {
+        method = GET,
+        path = "/things/{name}",
+    }]
+    async fn thing_get(
+        _rqctx: Arc<RequestContext<()>>,
+        _path: Path<ByName>,
+    ) -> Result<HttpResponseOk<()>, HttpError> {
+        Ok(HttpResponseOk(()))
+    }
+
+    #[endpoint {
+        method = PUT,
+        path = "/things/{name}",
+    }]
+    async fn thing_put(
+        _rqctx: Arc<RequestContext<()>>,
+        _path: Path<ByName>,
+    ) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+        Ok(HttpResponseUpdatedNoContent())
+    }
+
+    #[endpoint {
+        method = GET,
+        path = "/things/{name}",
+    }]
+    async fn thing_get_again(
+        _rqctx: Arc<RequestContext<()>>,
+        _path: Path<ByName>,
+    ) -> Result<HttpResponseOk<()>, HttpError> {
+        Ok(HttpResponseOk(()))
+    }
+
+    #[endpoint {
+        method = GET,
+        path = "/things/{id}",
+    }]
+    async fn thing_get_by_id(
+        _rqctx: Arc<RequestContext<()>>,
+        _path: Path<ById>,
+    ) -> Result<HttpResponseOk<()>, HttpError> {
+        Ok(HttpResponseOk(()))
+    }
+
+    #[test]
+    fn test_route_shape() {
+        assert_eq!(route_shape("/"), "/");
+        assert_eq!(route_shape("/things"), "/things");
+        assert_eq!(route_shape("/things/{name}"), "/things/{}");
+        assert_eq!(
+            route_shape("/things/{name}/parts/{part}"),
+            "/things/{}/parts/{}"
+        );
+    }
+
+    #[test]
+    fn test_register_distinct() {
+        let mut api = ApiDescription::new();
+        let mut registrar = EndpointRegistrar::new(&mut api);
+        registrar.register(thing_get).unwrap();
+        registrar.register(thing_put).unwrap();
+    }
+
+    #[test]
+    fn test_register_conflict() {
+        let mut api = ApiDescription::new();
+        let mut registrar = EndpointRegistrar::new(&mut api);
+        registrar.register(thing_get).unwrap();
+        let error = registrar.register(thing_get_again).unwrap_err();
+        assert_eq!(
+            error,
+            "endpoints \"thing_get\" and \"thing_get_again\" are both \
+             registered for GET \"/things/{name}\""
+        );
+
+        // Paths that differ only in variable names conflict, too.
+        let error = registrar.register(thing_get_by_id).unwrap_err();
+        assert_eq!(
+            error,
+            "endpoints \"thing_get\" and \"thing_get_by_id\" are both \
+             registered for GET \"/things/{id}\""
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to register entrypoints: endpoints \
+                               \"thing_get\" and \"thing_get_again\"")]
+    fn test_register_conflict_panics() {
+        // This is how each of our APIs registers its endpoints.
+        fn register_endpoints(
+            api: &mut EndpointRegistrar<()>,
+        ) -> Result<(), String> {
+            api.register(thing_get)?;
+            api.register(thing_get_again)?;
+            Ok(())
+        }
+
+        let mut api = ApiDescription::new();
+        if let Err(err) =
+            register_endpoints(&mut EndpointRegistrar::new(&mut api))
+        {
+            panic!("failed to register entrypoints: {}", err);
+        }
+    }
+}
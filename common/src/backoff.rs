@@ -28,3 +28,63 @@ pub fn internal_service_policy() -> ::backoff::ExponentialBackoff {
         ..backoff::ExponentialBackoff::default()
     }
 }
+
+/**
+ * Return a backoff policy that retries indefinitely, starting with
+ * `initial_interval` and multiplying the interval by `multiplier` after each
+ * attempt, up to `max_interval`.
+ *
+ * Each interval is randomly adjusted by up to `randomization_factor` (a
+ * fraction of the interval) in either direction, so that many callers
+ * retrying at once spread out.  A factor of 0 makes the intervals exact.
+ */
+pub fn exponential_policy(
+    initial_interval: Duration,
+    max_interval: Duration,
+    multiplier: f64,
+    randomization_factor: f64,
+) -> ::backoff::ExponentialBackoff {
+    ::backoff::ExponentialBackoff {
+        current_interval: initial_interval,
+        initial_interval,
+        randomization_factor,
+        multiplier,
+        max_interval,
+        max_elapsed_time: None,
+        ..backoff::ExponentialBackoff::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::exponential_policy;
+    use super::Backoff;
+    use std::time::Duration;
+
+    const INITIAL: Duration = Duration::from_millis(100);
+
+    fn next_intervals(policy: &mut dyn Backoff, n: usize) -> Vec<u64> {
+        (0..n)
+            .map(|_| policy.next_backoff().unwrap().as_millis() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn test_exponential_policy() {
+        /* Without randomization, the intervals grow exactly, up to the cap. */
+        let max = Duration::from_millis(500);
+        let mut policy = exponential_policy(INITIAL, max, 2.0, 0.0);
+        assert_eq!(next_intervals(&mut policy, 5), [100, 200, 400, 500, 500]);
+        policy.reset();
+        assert_eq!(next_intervals(&mut policy, 2), [100, 200]);
+
+        let mut policy = exponential_policy(INITIAL, max, 3.0, 0.0);
+        assert_eq!(next_intervals(&mut policy, 3), [100, 300, 500]);
+
+        /* With it, each interval stays within the given fraction. */
+        let mut policy = exponential_policy(INITIAL, INITIAL, 2.0, 0.25);
+        for interval in next_intervals(&mut policy, 100) {
+            assert!((75..=125).contains(&interval), "interval {}", interval);
+        }
+    }
+}
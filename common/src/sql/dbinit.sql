@@ -171,7 +171,10 @@ CREATE TABLE omicron.public.project (
     time_deleted TIMESTAMPTZ,
 
     /* Which organization this project belongs to */
-    organization_id UUID NOT NULL /* foreign key into "Organization" table */
+    organization_id UUID NOT NULL, /* foreign key into "Organization" table */
+
+    /* Incremented on each update (for optimistic concurrency control) */
+    generation INT NOT NULL
 );
 
 CREATE UNIQUE INDEX ON omicron.public.project (
@@ -251,6 +254,23 @@ CREATE UNIQUE INDEX ON omicron.public.instance (
 ) WHERE
     time_deleted IS NULL;
 
+/*
+ * Sleds allocated to Instances that are still being created.  A reservation
+ * counts towards its sled's load from when the sled is chosen until the
+ * Instance's own record exists (or creation is abandoned).
+ */
+CREATE TABLE omicron.public.sled_reservation (
+    /* Instance being created */
+    instance_id UUID PRIMARY KEY,
+    /* Sled allocated to it */
+    sled_id UUID NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX ON omicron.public.sled_reservation (
+    sled_id
+);
+
 
 /*
  * Guest-Visible, Virtual Disks
@@ -294,4 +294,77 @@ mod test {
         assert_eq!(expected_count1, count1.load(Ordering::SeqCst));
         assert_eq!(expected_count2, count2.load(Ordering::SeqCst));
     }
+
+    /// HttpAuthnScheme that rejects any request lacking a particular header
+    #[derive(Debug)]
+    struct RequireHeaderScheme {
+        /// actor to use when the header is present
+        actor: authn::Actor,
+    }
+
+    const REQUIRED_HEADER: &str = "x-test-required";
+
+    #[async_trait]
+    impl HttpAuthnScheme<()> for RequireHeaderScheme {
+        fn name(&self) -> authn::SchemeName {
+            authn::SchemeName("require-header")
+        }
+
+        async fn authn(
+            &self,
+            _ctx: &(),
+            _log: &slog::Logger,
+            request: &http::Request<hyper::Body>,
+        ) -> SchemeResult {
+            if request.headers().contains_key(REQUIRED_HEADER) {
+                SchemeResult::Authenticated(authn::Details {
+                    actor: self.actor,
+                })
+            } else {
+                SchemeResult::Failed(Reason::UnknownActor {
+                    actor: String::from("<none>"),
+                })
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authn_rejects_missing_header() {
+        // This test verifies that a scheme can reject a request outright (not
+        // just decline to authenticate it) and that the resulting error is
+        // surfaced to the client as a 401.
+        let actor = authn::Actor(
+            "6a2c1e09-8d4b-4f7a-9f1e-0c3b5d7e9a21".parse().unwrap(),
+        );
+        let scheme = Box::new(RequireHeaderScheme { actor })
+            as Box<dyn HttpAuthnScheme<()>>;
+        let authn = Authenticator::new(vec![scheme]);
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        let request = http::Request::builder()
+            .uri("/unused")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let error = authn
+            .authn_request_generic(&(), &log, &request)
+            .await
+            .expect_err("expected authn to fail");
+        let http_error = dropshot::HttpError::from(error);
+        assert_eq!(http_error.status_code, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            http_error.external_message,
+            "credentials missing or invalid"
+        );
+
+        let request = http::Request::builder()
+            .uri("/unused")
+            .header(REQUIRED_HEADER, "yes")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let ctx = authn
+            .authn_request_generic(&(), &log, &request)
+            .await
+            .expect("expected authn to succeed");
+        assert_eq!(ctx.actor(), Some(&actor));
+    }
 }
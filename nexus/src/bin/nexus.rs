@@ -19,6 +19,7 @@
 
 use omicron_common::cmd::fatal;
 use omicron_common::cmd::CmdError;
+use omicron_nexus::run_json_schemas_external;
 use omicron_nexus::run_openapi_external;
 use omicron_nexus::run_openapi_internal;
 use omicron_nexus::run_server;
@@ -43,6 +44,13 @@ struct Args {
     )]
     openapi_internal: bool,
 
+    #[structopt(
+        short = "J",
+        long = "json-schemas",
+        help = "Print the JSON Schemas for external API types and exit"
+    )]
+    json_schemas: bool,
+
     #[structopt(name = "CONFIG_FILE_PATH", parse(from_os_str))]
     config_file_path: PathBuf,
 }
@@ -66,6 +74,8 @@ async fn do_run() -> Result<(), CmdError> {
         run_openapi_external().map_err(CmdError::Failure)
     } else if args.openapi_internal {
         run_openapi_internal().map_err(CmdError::Failure)
+    } else if args.json_schemas {
+        run_json_schemas_external().map_err(CmdError::Failure)
     } else {
         run_server(&config).await.map_err(CmdError::Failure)
     }
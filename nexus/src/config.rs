@@ -8,6 +8,7 @@
  */
 
 use crate::db;
+use crate::sled_selection::SledSelectorKind;
 use anyhow::anyhow;
 use dropshot::ConfigDropshot;
 use dropshot::ConfigLogging;
@@ -40,6 +41,20 @@ pub struct ConsoleConfig {
     pub session_absolute_timeout_minutes: u32,
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PlacementConfig {
+    /** how to choose the sled on which to place a new Instance */
+    pub sled_selector: SledSelectorKind,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IdempotencyConfig {
+    /** how long a response to a create request may be replayed for retries */
+    pub key_expiry_minutes: u32,
+    /** how many keys' responses may be cached at once */
+    pub max_keys: usize,
+}
+
 /**
  * Configuration for a nexus server
  */
@@ -59,6 +74,10 @@ pub struct Config {
     pub database: db::Config,
     /** Authentication-related configuration */
     pub authn: AuthnConfig,
+    /** Handling of the `Idempotency-Key` header on create requests */
+    pub idempotency: IdempotencyConfig,
+    /** Placement of new Instances on sleds */
+    pub placement: PlacementConfig,
 }
 
 #[derive(Debug)]
@@ -163,10 +182,11 @@ impl Config {
 #[cfg(test)]
 mod test {
     use super::{
-        AuthnConfig, Config, ConsoleConfig, LoadError, LoadErrorKind,
-        SchemeName,
+        AuthnConfig, Config, ConsoleConfig, IdempotencyConfig, LoadError,
+        LoadErrorKind, PlacementConfig, SchemeName,
     };
     use crate::db;
+    use crate::sled_selection::SledSelectorKind;
     use dropshot::ConfigDropshot;
     use dropshot::ConfigLogging;
     use dropshot::ConfigLoggingIfExists;
@@ -278,6 +298,11 @@ mod test {
             cache_control_max_age_minutes = 10
             session_idle_timeout_minutes = 60
             session_absolute_timeout_minutes = 480
+            [idempotency]
+            key_expiry_minutes = 1440
+            max_keys = 10000
+            [placement]
+            sled_selector = "first_fit"
             [authn]
             schemes_external = []
             [dropshot_external]
@@ -308,6 +333,13 @@ mod test {
                     session_absolute_timeout_minutes: 480
                 },
                 authn: AuthnConfig { schemes_external: Vec::new() },
+                idempotency: IdempotencyConfig {
+                    key_expiry_minutes: 1440,
+                    max_keys: 10000,
+                },
+                placement: PlacementConfig {
+                    sled_selector: SledSelectorKind::FirstFit,
+                },
                 dropshot_external: ConfigDropshot {
                     bind_address: "10.1.2.3:4567"
                         .parse::<SocketAddr>()
@@ -342,6 +374,11 @@ mod test {
             cache_control_max_age_minutes = 10
             session_idle_timeout_minutes = 60
             session_absolute_timeout_minutes = 480
+            [idempotency]
+            key_expiry_minutes = 1440
+            max_keys = 10000
+            [placement]
+            sled_selector = "first_fit"
             [authn]
             schemes_external = [ "spoof", "session_cookie" ]
             [dropshot_external]
@@ -380,6 +417,11 @@ mod test {
             cache_control_max_age_minutes = 10
             session_idle_timeout_minutes = 60
             session_absolute_timeout_minutes = 480
+            [idempotency]
+            key_expiry_minutes = 1440
+            max_keys = 10000
+            [placement]
+            sled_selector = "first_fit"
             [authn]
             schemes_external = ["trust-me"]
             [dropshot_external]
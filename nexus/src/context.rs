@@ -13,6 +13,7 @@ use super::Nexus;
 use crate::authn::external::session_cookie::{Session, SessionStore};
 use crate::authn::Actor;
 use crate::db::model::ConsoleSession;
+use crate::external_api::idempotency::IdempotencyCache;
 use async_trait::async_trait;
 use authn::external::session_cookie::HttpAuthnSessionCookie;
 use authn::external::spoof::HttpAuthnSpoof;
@@ -51,6 +52,8 @@ pub struct ServerContext {
     pub producer_registry: ProducerRegistry,
     /** tunable settings needed for the console at runtime */
     pub console_config: ConsoleConfig,
+    /** responses to create requests, for replaying to retries */
+    pub idempotency_cache: IdempotencyCache,
 }
 
 pub struct ConsoleConfig {
@@ -159,6 +162,12 @@ impl ServerContext {
                     config.console.cache_control_max_age_minutes.into(),
                 ),
             },
+            idempotency_cache: IdempotencyCache::new(
+                std::time::Duration::from_secs(
+                    u64::from(config.idempotency.key_expiry_minutes) * 60,
+                ),
+                config.idempotency.max_keys,
+            ),
         }))
     }
 }
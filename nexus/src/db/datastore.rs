@@ -30,12 +30,20 @@ use super::identity::{Asset, Resource};
 use super::Pool;
 use crate::authz;
 use crate::context::OpContext;
+use crate::sled_selection::{
+    SledCandidate, SledOrder, SledSelector, SLED_CANDIDATES_MAX,
+};
 use async_bb8_diesel::{AsyncConnection, AsyncRunQueryDsl, ConnectionManager};
+use chrono::DateTime;
+use chrono::TimeZone;
 use chrono::Utc;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::upsert::excluded;
+use diesel::PgTextExpressionMethods;
 use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
 use omicron_common::api;
+use omicron_common::api::external::http_pagination::ModifiedMarker;
 use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::DeleteResult;
@@ -46,7 +54,9 @@ use omicron_common::api::external::LookupType;
 use omicron_common::api::external::ResourceType;
 use omicron_common::api::external::UpdateResult;
 use omicron_common::bail_unless;
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
+use std::num::NonZeroU32;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -59,14 +69,27 @@ use crate::db::{
         ConsoleSession, Dataset, Disk, DiskAttachment, DiskRuntimeState,
         Generation, Instance, InstanceRuntimeState, Name, Organization,
         OrganizationUpdate, OximeterInfo, ProducerEndpoint, Project,
-        ProjectUpdate, RouterRoute, RouterRouteUpdate, Sled, Vpc,
-        VpcFirewallRule, VpcRouter, VpcRouterUpdate, VpcSubnet,
+        ProjectUpdate, RouterRoute, RouterRouteUpdate, Sled, SledReservation,
+        Vpc, VpcFirewallRule, VpcRouter, VpcRouterUpdate, VpcSubnet,
         VpcSubnetUpdate, VpcUpdate, Zpool,
     },
     pagination::paginated,
     update_and_check::{UpdateAndCheck, UpdateStatus},
 };
 
+/// How many times [`DataStore::sled_allocate()`] picks a sled before giving up
+/// because other allocations keep changing the load of the sled it picked
+pub const SLED_ALLOCATE_ATTEMPTS: usize = 3;
+
+/// SQL expression for the load of the sled in scope as `sled`: the number of
+/// instances on it, including those still being created on it (see
+/// [`DataStore::sled_reservation_create()`])
+const SLED_LOAD_SQL: &str = "((SELECT COUNT(*) FROM instance \
+     WHERE instance.active_server_id = sled.id \
+     AND instance.time_deleted IS NULL) + \
+    (SELECT COUNT(*) FROM sled_reservation \
+     WHERE sled_reservation.sled_id = sled.id))";
+
 pub struct DataStore {
     pool: Arc<Pool>,
 }
@@ -94,7 +117,11 @@ impl DataStore {
         Ok(self.pool.pool())
     }
 
-    /// Stores a new sled in the database.
+    /// Stores a new sled in the database, or updates the address of an
+    /// existing one.
+    ///
+    /// Instances refer to their sled by id, so a sled agent that re-registers
+    /// after restarting keeps the instances it had.
     pub async fn sled_upsert(&self, sled: Sled) -> CreateResult<Sled> {
         use db::schema::sled::dsl;
         diesel::insert_into(dsl::sled)
@@ -118,6 +145,27 @@ impl DataStore {
             })
     }
 
+    /// Stores a new sled, failing if a sled with the same id already exists.
+    ///
+    /// Sled agents register themselves with [`DataStore::sled_upsert`]
+    /// instead so that they can re-register (e.g., with a new address) after
+    /// restarting.
+    pub async fn sled_insert(&self, sled: Sled) -> CreateResult<Sled> {
+        use db::schema::sled::dsl;
+        diesel::insert_into(dsl::sled)
+            .values(sled.clone())
+            .returning(Sled::as_returning())
+            .get_result_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool_create(
+                    e,
+                    ResourceType::Sled,
+                    &sled.id().to_string(),
+                )
+            })
+    }
+
     pub async fn sled_list(
         &self,
         pagparams: &DataPageParams<'_, Uuid>,
@@ -152,6 +200,242 @@ impl DataStore {
             })
     }
 
+    /// Lists up to `limit` of the sleds that could host a new instance (all
+    /// of them, other than those in `avoid_sleds`), along with how many
+    /// instances each one currently has (including those still being created
+    /// on it, see [`DataStore::sled_reservation_create()`])
+    ///
+    /// The sleds are sorted as `order` says, so that only the best few need
+    /// to be loaded.
+    pub async fn sled_candidates(
+        &self,
+        avoid_sleds: &BTreeSet<Uuid>,
+        order: SledOrder,
+        limit: NonZeroU32,
+    ) -> ListResultVec<SledCandidate> {
+        #[derive(QueryableByName)]
+        struct CandidateRow {
+            #[sql_type = "diesel::sql_types::Uuid"]
+            id: Uuid,
+            #[sql_type = "diesel::sql_types::BigInt"]
+            ninstances: i64,
+        }
+
+        // Diesel can't yet express the correlated subqueries needed to count
+        // each sled's instances and reservations, so the query is written out
+        // here.
+        let order_by = match order {
+            SledOrder::ById => "sled.id",
+            SledOrder::ByLoad => "ninstances, sled.id",
+        };
+        let query = format!(
+            "SELECT sled.id AS id, {} AS ninstances \
+             FROM sled \
+             WHERE sled.id != ALL($1) \
+             ORDER BY {} \
+             LIMIT $2",
+            SLED_LOAD_SQL, order_by
+        );
+        let avoid_sleds = avoid_sleds.iter().cloned().collect::<Vec<_>>();
+        let rows = diesel::sql_query(query)
+            .bind::<diesel::sql_types::Array<diesel::sql_types::Uuid>, _>(
+                avoid_sleds,
+            )
+            .bind::<diesel::sql_types::BigInt, _>(i64::from(limit.get()))
+            .load_async::<CandidateRow>(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Sled,
+                    LookupType::Other("Listing Candidates".to_string()),
+                )
+            })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| SledCandidate {
+                id: row.id,
+                ninstances: row.ninstances as u64,
+            })
+            .collect())
+    }
+
+    /// Picks a sled on which to place new instance `instance_id` using
+    /// `selector`, other than any of the sleds in `avoid_sleds`, and reserves
+    /// it for the instance (see [`DataStore::sled_reservation_create()`])
+    ///
+    /// The sled is only reserved if its load is still what it was when it was
+    /// picked (see [`DataStore::sled_reservation_try_create()`]), so that
+    /// concurrent allocations don't all pick the same sled based on the same
+    /// stale load.  If it isn't, the sled is picked again, up to
+    /// [`SLED_ALLOCATE_ATTEMPTS`] times.  If the instance already has a
+    /// reservation (e.g., because the saga action making it is being
+    /// replayed), that one is kept and returned.
+    ///
+    /// Returns `Error::ServiceUnavailable` if there are no sleds (or they're
+    /// all to be avoided), or if every attempt lost a race with another
+    /// allocation.
+    pub async fn sled_allocate(
+        &self,
+        instance_id: Uuid,
+        selector: &dyn SledSelector,
+        avoid_sleds: &BTreeSet<Uuid>,
+    ) -> CreateResult<SledReservation> {
+        for _ in 0..SLED_ALLOCATE_ATTEMPTS {
+            let candidates = self
+                .sled_candidates(
+                    avoid_sleds,
+                    selector.order(),
+                    NonZeroU32::new(SLED_CANDIDATES_MAX).unwrap(),
+                )
+                .await?;
+            let sled = selector.select(&candidates).ok_or_else(|| {
+                Error::ServiceUnavailable {
+                    internal_message: String::from(
+                        "no sleds available for new Instance",
+                    ),
+                }
+            })?;
+            let reservation = self
+                .sled_reservation_try_create(
+                    SledReservation::new(instance_id, sled.id),
+                    sled.ninstances,
+                )
+                .await?;
+            if let Some(reservation) = reservation {
+                return Ok(reservation);
+            }
+        }
+        Err(Error::ServiceUnavailable {
+            internal_message: format!(
+                "gave up allocating a sled for new Instance after {} attempts",
+                SLED_ALLOCATE_ATTEMPTS
+            ),
+        })
+    }
+
+    /// Makes `reservation`, but only if its sled still has `ninstances`
+    /// instances (counted as in [`DataStore::sled_candidates()`]), checking
+    /// and reserving in a single statement
+    ///
+    /// Returns `None` if the sled's load has changed.  If the instance already
+    /// has a reservation, that one is kept and returned.
+    async fn sled_reservation_try_create(
+        &self,
+        reservation: SledReservation,
+        ninstances: u64,
+    ) -> Result<Option<SledReservation>, Error> {
+        use db::schema::sled_reservation::dsl;
+
+        let instance_id = reservation.instance_id;
+        let lookup_type =
+            || LookupType::Other(format!("reservation for {}", instance_id));
+        // As in sled_candidates(), Diesel can't express the load subqueries.
+        let query = format!(
+            "INSERT INTO sled_reservation \
+             (instance_id, sled_id, time_created) \
+             SELECT $1, sled.id, $3 \
+             FROM sled \
+             WHERE sled.id = $2 AND {} = $4 \
+             ON CONFLICT (instance_id) DO NOTHING",
+            SLED_LOAD_SQL
+        );
+        diesel::sql_query(query)
+            .bind::<diesel::sql_types::Uuid, _>(reservation.instance_id)
+            .bind::<diesel::sql_types::Uuid, _>(reservation.sled_id)
+            .bind::<diesel::sql_types::Timestamptz, _>(reservation.time_created)
+            .bind::<diesel::sql_types::BigInt, _>(ninstances as i64)
+            .execute_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Sled,
+                    lookup_type(),
+                )
+            })?;
+
+        // Whether or not a row was inserted, the instance's reservation (if
+        // any) is the one to report: either the one we just made or one made
+        // earlier.
+        diesel_pool_result_optional(
+            dsl::sled_reservation
+                .filter(dsl::instance_id.eq(instance_id))
+                .select(SledReservation::as_select())
+                .get_result_async(self.pool())
+                .await,
+        )
+        .map_err(|e| {
+            public_error_from_diesel_pool(e, ResourceType::Sled, lookup_type())
+        })
+    }
+
+    /// Records that a sled has been allocated to an instance that's still
+    /// being created, so that the sled's load accounts for it
+    ///
+    /// If the instance already has a reservation (e.g., because the saga
+    /// action making it is being replayed), that one is kept and returned.
+    pub async fn sled_reservation_create(
+        &self,
+        reservation: SledReservation,
+    ) -> CreateResult<SledReservation> {
+        use db::schema::sled_reservation::dsl;
+
+        let instance_id = reservation.instance_id;
+        let lookup_type =
+            || LookupType::Other(format!("reservation for {}", instance_id));
+        diesel::insert_into(dsl::sled_reservation)
+            .values(reservation)
+            .on_conflict(dsl::instance_id)
+            .do_nothing()
+            .execute_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Sled,
+                    lookup_type(),
+                )
+            })?;
+        dsl::sled_reservation
+            .filter(dsl::instance_id.eq(instance_id))
+            .select(SledReservation::as_select())
+            .get_result_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Sled,
+                    lookup_type(),
+                )
+            })
+    }
+
+    /// Removes the sled reservation made for instance `instance_id`, if any
+    pub async fn sled_reservation_delete(
+        &self,
+        instance_id: &Uuid,
+    ) -> DeleteResult {
+        use db::schema::sled_reservation::dsl;
+
+        diesel::delete(dsl::sled_reservation)
+            .filter(dsl::instance_id.eq(*instance_id))
+            .execute_async(self.pool())
+            .await
+            .map(|_rows_deleted| ())
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Sled,
+                    LookupType::Other(format!(
+                        "reservation for {}",
+                        instance_id
+                    )),
+                )
+            })
+    }
+
     /// Stores a new zpool in the database.
     pub async fn zpool_upsert(&self, zpool: Zpool) -> CreateResult<Zpool> {
         use db::schema::zpool::dsl;
@@ -272,7 +556,15 @@ impl DataStore {
     }
 
     /// Delete a organization
-    pub async fn organization_delete(&self, name: &Name) -> DeleteResult {
+    ///
+    /// If `unmodified_since` is given and the organization has been modified
+    /// since then, this fails with `Error::PreconditionFailed` and the
+    /// organization is left alone.
+    pub async fn organization_delete(
+        &self,
+        name: &Name,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> DeleteResult {
         use db::schema::organization::dsl;
         use db::schema::project;
 
@@ -315,22 +607,57 @@ impl DataStore {
         }
 
         let now = Utc::now();
-        let updated_rows = diesel::update(dsl::organization)
-            .filter(dsl::time_deleted.is_null())
-            .filter(dsl::id.eq(id))
-            .filter(dsl::rcgen.eq(rcgen))
-            .set(dsl::time_deleted.eq(now))
-            .execute_async(self.pool())
-            .await
-            .map_err(|e| {
-                public_error_from_diesel_pool(
-                    e,
-                    ResourceType::Organization,
-                    LookupType::ById(id),
-                )
-            })?;
+        let deleted = match unmodified_since {
+            None => {
+                let updated_rows = diesel::update(dsl::organization)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(id))
+                    .filter(dsl::rcgen.eq(rcgen))
+                    .set(dsl::time_deleted.eq(now))
+                    .execute_async(self.pool())
+                    .await
+                    .map_err(|e| {
+                        public_error_from_diesel_pool(
+                            e,
+                            ResourceType::Organization,
+                            LookupType::ById(id),
+                        )
+                    })?;
+                updated_rows > 0
+            }
+            Some(since) => {
+                let result = diesel::update(dsl::organization)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(id))
+                    .filter(dsl::rcgen.eq(rcgen))
+                    .filter(
+                        dsl::time_modified.lt(unmodified_since_bound(since)),
+                    )
+                    .set(dsl::time_deleted.eq(now))
+                    .check_if_exists::<Organization>(id)
+                    .execute_and_check(self.pool())
+                    .await
+                    .map_err(|e| {
+                        public_error_from_diesel_pool(
+                            e,
+                            ResourceType::Organization,
+                            LookupType::ById(id),
+                        )
+                    })?;
+                match result.status {
+                    UpdateStatus::Updated => true,
+                    UpdateStatus::NotUpdatedButExists => {
+                        return Err(not_updated_error(
+                            ResourceType::Organization,
+                            &result.found,
+                            since,
+                        ));
+                    }
+                }
+            }
+        };
 
-        if updated_rows == 0 {
+        if !deleted {
             return Err(Error::InvalidRequest {
                 message: "deletion failed due to concurrent modification"
                     .to_string(),
@@ -414,14 +741,46 @@ impl DataStore {
             })
     }
 
-    /// Updates a organization by name (clobbering update -- no etag)
+    /// Updates a organization by name
+    ///
+    /// If `unmodified_since` is given and the organization has been modified
+    /// since then, this fails with `Error::PreconditionFailed` and the
+    /// organization is left unchanged.  Otherwise, this is a clobbering update.
     pub async fn organization_update(
         &self,
         name: &Name,
         updates: OrganizationUpdate,
+        unmodified_since: Option<DateTime<Utc>>,
     ) -> UpdateResult<Organization> {
         use db::schema::organization::dsl;
 
+        if let Some(since) = unmodified_since {
+            let id = self.organization_lookup_id_by_name(name).await?;
+            let result = diesel::update(dsl::organization)
+                .filter(dsl::time_deleted.is_null())
+                .filter(dsl::id.eq(id))
+                .filter(dsl::time_modified.lt(unmodified_since_bound(since)))
+                .set(updates)
+                .check_if_exists::<Organization>(id)
+                .execute_and_check(self.pool())
+                .await
+                .map_err(|e| {
+                    public_error_from_diesel_pool(
+                        e,
+                        ResourceType::Organization,
+                        LookupType::ById(id),
+                    )
+                })?;
+            return match result.status {
+                UpdateStatus::Updated => Ok(result.found),
+                UpdateStatus::NotUpdatedButExists => Err(not_updated_error(
+                    ResourceType::Organization,
+                    &result.found,
+                    since,
+                )),
+            };
+        }
+
         diesel::update(dsl::organization)
             .filter(dsl::time_deleted.is_null())
             .filter(dsl::name.eq(name.clone()))
@@ -495,35 +854,147 @@ impl DataStore {
             })
     }
 
-    /// Delete a project
+    /// Fetch a project by its id, regardless of which organization it's in
+    pub async fn project_fetch_by_id(
+        &self,
+        project_id: &Uuid,
+    ) -> LookupResult<Project> {
+        use db::schema::project::dsl;
+        dsl::project
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(*project_id))
+            .select(Project::as_select())
+            .first_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Project,
+                    LookupType::ById(*project_id),
+                )
+            })
+    }
+
+    /// Delete a project, which must not have any Instances
+    ///
+    /// Instances are checked for in the same statement that deletes the
+    /// project, so one can't be created in between.
+    ///
+    /// If `unmodified_since` is given and the project has been modified since
+    /// then, this fails with `Error::PreconditionFailed` and the project is
+    /// left alone.
     /*
-     * TODO-correctness This needs to check whether there are any resources that
-     * depend on the Project (Disks, Instances).  We can do this with a
-     * generation counter that gets bumped when these resources are created.
+     * TODO-correctness This should also refuse to delete a Project that still
+     * has Disks in it.
      */
     pub async fn project_delete(
         &self,
         organization_id: &Uuid,
         name: &Name,
+        unmodified_since: Option<DateTime<Utc>>,
     ) -> DeleteResult {
+        use db::schema::instance;
         use db::schema::project::dsl;
         let now = Utc::now();
-        diesel::update(dsl::project)
-            .filter(dsl::time_deleted.is_null())
-            .filter(dsl::organization_id.eq(*organization_id))
-            .filter(dsl::name.eq(name.clone()))
-            .set(dsl::time_deleted.eq(now))
-            .returning(Project::as_returning())
-            .get_result_async(self.pool())
+
+        let id = self.project_lookup_id_by_name(organization_id, name).await?;
+        let no_instances = || {
+            diesel::dsl::not(diesel::dsl::exists(
+                instance::dsl::instance
+                    .filter(instance::dsl::project_id.eq(id))
+                    .filter(instance::dsl::time_deleted.is_null()),
+            ))
+        };
+        let result = match unmodified_since {
+            Some(since) => {
+                diesel::update(dsl::project)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(id))
+                    .filter(no_instances())
+                    .filter(
+                        dsl::time_modified.lt(unmodified_since_bound(since)),
+                    )
+                    .set(dsl::time_deleted.eq(now))
+                    .check_if_exists::<Project>(id)
+                    .execute_and_check(self.pool())
+                    .await
+            }
+            None => {
+                diesel::update(dsl::project)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(id))
+                    .filter(no_instances())
+                    .set(dsl::time_deleted.eq(now))
+                    .check_if_exists::<Project>(id)
+                    .execute_and_check(self.pool())
+                    .await
+            }
+        }
+        .map_err(|e| {
+            public_error_from_diesel_pool(
+                e,
+                ResourceType::Project,
+                LookupType::ById(id),
+            )
+        })?;
+
+        match result.status {
+            UpdateStatus::Updated => Ok(()),
+            UpdateStatus::NotUpdatedButExists => {
+                let found = &result.found;
+                if found.time_deleted().is_none() {
+                    let ninstances = self.project_instances_count(&id).await?;
+                    if ninstances > 0 {
+                        return Err(Error::InvalidRequest {
+                            message: format!(
+                                "project cannot be deleted while it has \
+                                 instances ({} remaining)",
+                                ninstances
+                            ),
+                        });
+                    }
+                }
+                Err(match unmodified_since {
+                    Some(since) => {
+                        not_updated_error(ResourceType::Project, found, since)
+                    }
+                    None if found.time_deleted().is_some() => {
+                        Error::ObjectNotFound {
+                            type_name: ResourceType::Project,
+                            lookup_type: LookupType::ByName(
+                                name.as_str().to_owned(),
+                            ),
+                        }
+                    }
+                    None => Error::InvalidRequest {
+                        message: "deletion failed due to concurrent \
+                                  modification"
+                            .to_string(),
+                    },
+                })
+            }
+        }
+    }
+
+    /// Returns whether any project (including a deleted one) has the given id
+    pub async fn project_id_in_use(
+        &self,
+        project_id: &Uuid,
+    ) -> Result<bool, Error> {
+        use db::schema::project::dsl;
+        let count = dsl::project
+            .filter(dsl::id.eq(*project_id))
+            .count()
+            .get_result_async::<i64>(self.pool())
             .await
             .map_err(|e| {
                 public_error_from_diesel_pool(
                     e,
                     ResourceType::Project,
-                    LookupType::ByName(name.as_str().to_owned()),
+                    LookupType::ById(*project_id),
                 )
             })?;
-        Ok(())
+        Ok(count > 0)
     }
 
     /// Look up the id for a project based on its name
@@ -549,15 +1020,22 @@ impl DataStore {
             })
     }
 
+    /// Lists projects in order of id
+    ///
+    /// If `search` is given, only projects whose name or description contains
+    /// it (ignoring case) are listed.  The same goes for the other
+    /// `projects_list_by_*` functions.
     pub async fn projects_list_by_id(
         &self,
         organization_id: &Uuid,
         pagparams: &DataPageParams<'_, Uuid>,
+        search: Option<&str>,
     ) -> ListResultVec<Project> {
         use db::schema::project::dsl;
-        paginated(dsl::project, dsl::id, pagparams)
+        let query = paginated(dsl::project, dsl::id, pagparams)
             .filter(dsl::organization_id.eq(*organization_id))
-            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::time_deleted.is_null());
+        project_search_filter(query, search)
             .select(Project::as_select())
             .load_async(self.pool())
             .await
@@ -574,12 +1052,72 @@ impl DataStore {
         &self,
         organization_id: &Uuid,
         pagparams: &DataPageParams<'_, Name>,
+        search: Option<&str>,
     ) -> ListResultVec<Project> {
         use db::schema::project::dsl;
 
-        paginated(dsl::project, dsl::name, &pagparams)
+        let query = paginated(dsl::project, dsl::name, &pagparams)
+            .filter(dsl::organization_id.eq(*organization_id))
+            .filter(dsl::time_deleted.is_null());
+        project_search_filter(query, search)
+            .select(Project::as_select())
+            .load_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Project,
+                    LookupType::Other("Listing All".to_string()),
+                )
+            })
+    }
+
+    /// Lists projects in order of when they were last modified
+    ///
+    /// Projects modified at the same time are ordered by id, so the marker is
+    /// both the time modified and the id of the last project seen.
+    pub async fn projects_list_by_modified(
+        &self,
+        organization_id: &Uuid,
+        pagparams: &DataPageParams<'_, ModifiedMarker>,
+        search: Option<&str>,
+    ) -> ListResultVec<Project> {
+        use db::schema::project::dsl;
+
+        let query = dsl::project
             .filter(dsl::organization_id.eq(*organization_id))
             .filter(dsl::time_deleted.is_null())
+            .limit(pagparams.limit.get().into())
+            .into_boxed();
+        let mut query = project_search_filter(query, search);
+        query = match pagparams.direction {
+            dropshot::PaginationOrder::Ascending => {
+                if let Some(marker) = pagparams.marker {
+                    query = query.filter(
+                        dsl::time_modified.gt(marker.time_modified).or(
+                            dsl::time_modified
+                                .eq(marker.time_modified)
+                                .and(dsl::id.gt(marker.id)),
+                        ),
+                    );
+                }
+                query.order((dsl::time_modified.asc(), dsl::id.asc()))
+            }
+            dropshot::PaginationOrder::Descending => {
+                if let Some(marker) = pagparams.marker {
+                    query = query.filter(
+                        dsl::time_modified.lt(marker.time_modified).or(
+                            dsl::time_modified
+                                .eq(marker.time_modified)
+                                .and(dsl::id.lt(marker.id)),
+                        ),
+                    );
+                }
+                query.order((dsl::time_modified.desc(), dsl::id.desc()))
+            }
+        };
+
+        query
             .select(Project::as_select())
             .load_async(self.pool())
             .await
@@ -592,20 +1130,57 @@ impl DataStore {
             })
     }
 
-    /// Updates a project by name (clobbering update -- no etag)
+    /// Updates a project without regard to its generation
+    ///
+    /// The project's generation is incremented.  See
+    /// [`DataStore::project_update_if_generation()`] to avoid clobbering
+    /// concurrent updates.
+    ///
+    /// If `unmodified_since` is given and the project has been modified since
+    /// then, this fails with `Error::PreconditionFailed` and the project is
+    /// left unchanged.
     pub async fn project_update(
         &self,
         organization_id: &Uuid,
         name: &Name,
         updates: ProjectUpdate,
+        unmodified_since: Option<DateTime<Utc>>,
     ) -> UpdateResult<Project> {
         use db::schema::project::dsl;
 
+        if let Some(since) = unmodified_since {
+            let id =
+                self.project_lookup_id_by_name(organization_id, name).await?;
+            let result = diesel::update(dsl::project)
+                .filter(dsl::time_deleted.is_null())
+                .filter(dsl::id.eq(id))
+                .filter(dsl::time_modified.lt(unmodified_since_bound(since)))
+                .set((updates, dsl::generation.eq(dsl::generation + 1)))
+                .check_if_exists::<Project>(id)
+                .execute_and_check(self.pool())
+                .await
+                .map_err(|e| {
+                    public_error_from_diesel_pool(
+                        e,
+                        ResourceType::Project,
+                        LookupType::ById(id),
+                    )
+                })?;
+            return match result.status {
+                UpdateStatus::Updated => Ok(result.found),
+                UpdateStatus::NotUpdatedButExists => Err(not_updated_error(
+                    ResourceType::Project,
+                    &result.found,
+                    since,
+                )),
+            };
+        }
+
         diesel::update(dsl::project)
             .filter(dsl::time_deleted.is_null())
             .filter(dsl::organization_id.eq(*organization_id))
             .filter(dsl::name.eq(name.clone()))
-            .set(updates)
+            .set((updates, dsl::generation.eq(dsl::generation + 1)))
             .returning(Project::as_returning())
             .get_result_async(self.pool())
             .await
@@ -618,6 +1193,52 @@ impl DataStore {
             })
     }
 
+    /// Updates a project only if its generation is `expected_generation`
+    ///
+    /// On success, the project's generation is incremented.  If the stored
+    /// generation differs (i.e., someone else updated the project since the
+    /// caller last read it), this fails with `Error::PreconditionFailed` and
+    /// the project is left unchanged.
+    pub async fn project_update_if_generation(
+        &self,
+        organization_id: &Uuid,
+        name: &Name,
+        expected_generation: Generation,
+        updates: ProjectUpdate,
+    ) -> UpdateResult<Project> {
+        use db::schema::project::dsl;
+
+        let project_id =
+            self.project_lookup_id_by_name(organization_id, name).await?;
+        let result = diesel::update(dsl::project)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(project_id))
+            .filter(dsl::generation.eq(expected_generation))
+            .set((updates, dsl::generation.eq(dsl::generation + 1)))
+            .check_if_exists::<Project>(project_id)
+            .execute_and_check(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Project,
+                    LookupType::ById(project_id),
+                )
+            })?;
+        match result.status {
+            UpdateStatus::Updated => Ok(result.found),
+            UpdateStatus::NotUpdatedButExists => {
+                Err(Error::PreconditionFailed {
+                    message: format!(
+                        "project has been modified (expected generation {}, \
+                         found {})",
+                        *expected_generation, *result.found.generation
+                    ),
+                })
+            }
+        }
+    }
+
     /*
      * Instances
      */
@@ -682,6 +1303,29 @@ impl DataStore {
         Ok(instance)
     }
 
+    /// Returns the number of (undeleted) instances in a project, without
+    /// listing them
+    pub async fn project_instances_count(
+        &self,
+        project_id: &Uuid,
+    ) -> Result<u64, Error> {
+        use db::schema::instance::dsl;
+        let count = dsl::instance
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::project_id.eq(*project_id))
+            .count()
+            .get_result_async::<i64>(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Project,
+                    LookupType::ById(*project_id),
+                )
+            })?;
+        Ok(count as u64)
+    }
+
     pub async fn project_list_instances(
         &self,
         project_id: &Uuid,
@@ -756,6 +1400,9 @@ impl DataStore {
      * update is older than the one in the database, we would have to fetch
      * the current state explicitly.  For now, we'll just require consumers
      * to explicitly fetch the state if they want that.
+     *
+     * Returns whether the state was updated.  It's only updated if
+     * `new_runtime`'s generation is newer than the stored one.
      */
     pub async fn instance_update_runtime(
         &self,
@@ -768,7 +1415,7 @@ impl DataStore {
             .filter(dsl::time_deleted.is_null())
             .filter(dsl::id.eq(*instance_id))
             .filter(dsl::state_generation.lt(new_runtime.gen))
-            .set(new_runtime.clone())
+            .set((new_runtime.clone(), dsl::time_modified.eq(Utc::now())))
             .check_if_exists::<Instance>(*instance_id)
             .execute_and_check(self.pool())
             .await
@@ -838,6 +1485,47 @@ impl DataStore {
         }
     }
 
+    /// Moves an instance directly to "destroyed" and deletes it, regardless of
+    /// its current state.
+    ///
+    /// This is intended for recovering instances stuck in an intermediate
+    /// state.  Unlike [`DataStore::project_delete_instance`], it does not
+    /// require the instance to have stopped first.  This only changes the
+    /// database: the caller is responsible for tearing down the instance on
+    /// its sled, if it's still running there.
+    pub async fn project_delete_instance_force(
+        &self,
+        instance_id: &Uuid,
+    ) -> DeleteResult {
+        use api::external::InstanceState as ApiInstanceState;
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::instance::dsl;
+
+        let now = Utc::now();
+        let destroyed = DbInstanceState::new(ApiInstanceState::Destroyed);
+        diesel::update(dsl::instance)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(*instance_id))
+            .set((
+                dsl::state.eq(destroyed),
+                dsl::state_generation.eq(dsl::state_generation + 1),
+                dsl::time_state_updated.eq(now),
+                dsl::time_modified.eq(now),
+                dsl::time_deleted.eq(now),
+            ))
+            .returning(Instance::as_returning())
+            .get_result_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ResourceType::Instance,
+                    LookupType::ById(*instance_id),
+                )
+            })?;
+        Ok(())
+    }
+
     /*
      * Disks
      */
@@ -1940,29 +2628,149 @@ impl DataStore {
     }
 }
 
+/// Returns the (exclusive) bound on the modification time of a resource that
+/// hasn't been modified since `since`, the time given in an
+/// `If-Unmodified-Since` header
+///
+/// HTTP dates only have one-second resolution, while we track modification
+/// times much more precisely, so a resource modified during the same second as
+/// `since` counts as unmodified.
+fn unmodified_since_bound(since: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.timestamp(since.timestamp() + 1, 0)
+}
+
+/// Returns the error for a resource, last modified at `time_modified`, that
+/// has been modified since `since`
+fn modified_since_error(
+    since: DateTime<Utc>,
+    time_modified: DateTime<Utc>,
+) -> Error {
+    Error::PreconditionFailed {
+        message: format!(
+            "resource has been modified since {} (at {})",
+            since.to_rfc2822(),
+            time_modified.to_rfc3339()
+        ),
+    }
+}
+
+/// Returns the error for a conditional update (or delete) of `found` that
+/// didn't happen, given the time `since` from an `If-Unmodified-Since` header
+///
+/// The resource was looked up before the update was attempted, so it may have
+/// been deleted in the meantime, in which case it's reported as not found.
+/// Otherwise, it was modified after `since`.
+fn not_updated_error<R: Resource>(
+    resource_type: ResourceType,
+    found: &R,
+    since: DateTime<Utc>,
+) -> Error {
+    if found.time_deleted().is_some() {
+        return Error::ObjectNotFound {
+            type_name: resource_type,
+            lookup_type: LookupType::ByName(found.name().as_str().to_owned()),
+        };
+    }
+    let time_modified = found.time_modified();
+    if time_modified >= unmodified_since_bound(since) {
+        return modified_since_error(since, time_modified);
+    }
+    Error::InvalidRequest {
+        message: "update failed due to concurrent modification".to_string(),
+    }
+}
+
+/// Restricts `query` to projects whose name or description contains `search`
+/// (ignoring case), if it's given
+fn project_search_filter(
+    query: db::schema::project::BoxedQuery<'static, Pg>,
+    search: Option<&str>,
+) -> db::schema::project::BoxedQuery<'static, Pg> {
+    use db::schema::project::dsl;
+    match search {
+        Some(search) => {
+            let pattern = contains_pattern(search);
+            query.filter(
+                dsl::name
+                    .ilike(pattern.clone())
+                    .or(dsl::description.ilike(pattern)),
+            )
+        }
+        None => query,
+    }
+}
+
+/// Returns a pattern for `LIKE` (or `ILIKE`) that matches any string containing
+/// `search`, which is taken literally
+fn contains_pattern(search: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in search.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
 #[cfg(test)]
 mod test {
+    use super::contains_pattern;
+    use super::not_updated_error;
+    use super::unmodified_since_bound;
     use crate::authz;
     use crate::context::OpContext;
     use crate::db;
+    use crate::db::identity::Asset;
     use crate::db::identity::Resource;
-    use crate::db::model::{ConsoleSession, Organization, Project};
+    use crate::db::model::{
+        ConsoleSession, Instance, Organization, Project, ProjectUpdate, Sled,
+        SledReservation,
+    };
     use crate::db::DataStore;
     use crate::external_api::params;
-    use chrono::{Duration, Utc};
-    use omicron_common::api::external::{Error, IdentityMetadataCreateParams};
+    use crate::sled_selection::{
+        FirstFit, LeastLoaded, SledCandidate, SledOrder,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use dropshot::test_util::LogContext;
+    use omicron_common::api::external::{
+        ByteCount, DataPageParams, Error, Generation,
+        IdentityMetadataCreateParams, InstanceCpuCount, InstanceState,
+        LookupType, ResourceType,
+    };
+    use omicron_common::api::internal::nexus::InstanceRuntimeState;
     use omicron_test_utils::dev;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::net::SocketAddr;
+    use std::num::NonZeroU32;
     use std::sync::Arc;
     use uuid::Uuid;
 
-    #[tokio::test]
-    async fn test_project_creation() {
-        let logctx = dev::test_setup_log("test_collection_not_present");
-        let opctx = OpContext::for_unit_tests(logctx.log.new(o!()));
-        let mut db = dev::test_setup_database(&logctx.log).await;
+    /**
+     * Sets up logging and a fresh database for the test `test_name`, returning
+     * them along with a `DataStore` backed by that database
+     *
+     * Callers should clean up the database with `db.cleanup()` when they're
+     * done.
+     */
+    async fn test_setup(
+        test_name: &str,
+    ) -> (LogContext, dev::db::CockroachInstance, DataStore) {
+        let logctx = dev::test_setup_log(test_name);
+        let db = dev::test_setup_database(&logctx.log).await;
         let cfg = db::Config { url: db.pg_config().clone() };
         let pool = db::Pool::new(&cfg);
         let datastore = DataStore::new(Arc::new(pool));
+        (logctx, db, datastore)
+    }
+
+    #[tokio::test]
+    async fn test_project_creation() {
+        let (logctx, mut db, datastore) =
+            test_setup("test_collection_not_present").await;
+        let opctx = OpContext::for_unit_tests(logctx.log.new(o!()));
 
         let organization = Organization::new(params::OrganizationCreate {
             identity: IdentityMetadataCreateParams {
@@ -1980,6 +2788,7 @@ mod test {
                     name: "project".parse().unwrap(),
                     description: "desc".to_string(),
                 },
+                id: None,
             },
         );
         let org = authz::FLEET.organization(organization.id());
@@ -1991,13 +2800,838 @@ mod test {
         let _ = db.cleanup().await;
     }
 
+    #[tokio::test]
+    async fn test_project_update_generation() {
+        let (logctx, mut db, datastore) =
+            test_setup("test_project_update_generation").await;
+        let opctx = OpContext::for_unit_tests(logctx.log.new(o!()));
+
+        let organization = Organization::new(params::OrganizationCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "org".parse().unwrap(),
+                description: "desc".to_string(),
+            },
+        });
+        let organization =
+            datastore.organization_create(&opctx, organization).await.unwrap();
+        let project = Project::new(
+            organization.id(),
+            params::ProjectCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "project".parse().unwrap(),
+                    description: "desc".to_string(),
+                },
+                id: None,
+            },
+        );
+        let org = authz::FLEET.organization(organization.id());
+        let project =
+            datastore.project_create(&opctx, &org, project).await.unwrap();
+        let name = project.name().clone();
+        let generation = project.generation;
+
+        // Two clients read the project at the same generation and each try to
+        // update it.  The first update succeeds and bumps the generation.
+        let update = |description: &str| ProjectUpdate {
+            name: None,
+            description: Some(description.to_string()),
+            time_modified: Utc::now(),
+        };
+        let updated = datastore
+            .project_update_if_generation(
+                &organization.id(),
+                &name,
+                generation,
+                update("first"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.description(), "first");
+        assert!(updated.generation > generation);
+
+        // The second update is based on a stale generation, so it's rejected
+        // and doesn't modify the project.
+        let error = datastore
+            .project_update_if_generation(
+                &organization.id(),
+                &name,
+                generation,
+                update("second"),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::PreconditionFailed { .. }));
+        let fetched =
+            datastore.project_fetch(&organization.id(), &name).await.unwrap();
+        assert_eq!(fetched.description(), "first");
+        assert_eq!(fetched.generation, updated.generation);
+
+        // Unconditional updates bump the generation too.
+        let clobbered = datastore
+            .project_update(&organization.id(), &name, update("third"), None)
+            .await
+            .unwrap();
+        assert!(clobbered.generation > updated.generation);
+
+        let _ = db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_project_not_updated_error() {
+        let (logctx, mut db, datastore) =
+            test_setup("test_project_not_updated_error").await;
+        let opctx = OpContext::for_unit_tests(logctx.log.new(o!()));
+
+        let organization = Organization::new(params::OrganizationCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "org".parse().unwrap(),
+                description: "desc".to_string(),
+            },
+        });
+        let organization =
+            datastore.organization_create(&opctx, organization).await.unwrap();
+        let project = Project::new(
+            organization.id(),
+            params::ProjectCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "project".parse().unwrap(),
+                    description: "desc".to_string(),
+                },
+                id: None,
+            },
+        );
+        let org = authz::FLEET.organization(organization.id());
+        let project =
+            datastore.project_create(&opctx, &org, project).await.unwrap();
+        let name = project.name().clone();
+        let before = project.time_modified() - Duration::seconds(5);
+
+        // A live project that was modified after `since` fails the
+        // precondition.
+        let error = not_updated_error(ResourceType::Project, &project, before);
+        assert!(matches!(error, Error::PreconditionFailed { .. }));
+
+        // A project deleted between the lookup and the conditional update is
+        // reported as not found, even though it was also modified after
+        // `since`.
+        datastore
+            .project_delete(&organization.id(), &name, None)
+            .await
+            .unwrap();
+        let deleted = {
+            use async_bb8_diesel::AsyncRunQueryDsl;
+            use db::schema::project::dsl;
+            use diesel::prelude::*;
+            dsl::project
+                .filter(dsl::id.eq(project.id()))
+                .select(Project::as_select())
+                .get_result_async::<Project>(datastore.pool())
+                .await
+                .unwrap()
+        };
+        assert!(deleted.time_deleted().is_some());
+        let error = not_updated_error(ResourceType::Project, &deleted, before);
+        assert_eq!(
+            error,
+            Error::ObjectNotFound {
+                type_name: ResourceType::Project,
+                lookup_type: LookupType::ByName("project".to_string()),
+            }
+        );
+
+        // Once the delete is visible, the lookup itself fails the same way.
+        let error = datastore
+            .project_delete(&organization.id(), &name, Some(Utc::now()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::ObjectNotFound { .. }));
+
+        let _ = db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_project_delete_with_instances() {
+        let (logctx, mut db, datastore) =
+            test_setup("test_project_delete_with_instances").await;
+        let opctx = OpContext::for_unit_tests(logctx.log.new(o!()));
+
+        let organization = Organization::new(params::OrganizationCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "org".parse().unwrap(),
+                description: "desc".to_string(),
+            },
+        });
+        let organization =
+            datastore.organization_create(&opctx, organization).await.unwrap();
+        let project = Project::new(
+            organization.id(),
+            params::ProjectCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "project".parse().unwrap(),
+                    description: "desc".to_string(),
+                },
+                id: None,
+            },
+        );
+        let org = authz::FLEET.organization(organization.id());
+        let project =
+            datastore.project_create(&opctx, &org, project).await.unwrap();
+        let name = project.name().clone();
+        let instance = create_test_instance(
+            &datastore,
+            project.id(),
+            Uuid::new_v4(),
+            "instance",
+        )
+        .await;
+
+        // The project can't be deleted while it has an instance, with or
+        // without a precondition, and is left alone.
+        for unmodified_since in [None, Some(Utc::now())] {
+            let error = datastore
+                .project_delete(&organization.id(), &name, unmodified_since)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error,
+                Error::InvalidRequest {
+                    message: "project cannot be deleted while it has \
+                              instances (1 remaining)"
+                        .to_string(),
+                }
+            );
+            datastore.project_fetch(&organization.id(), &name).await.unwrap();
+        }
+
+        // Once the instance is gone, the project can be deleted.
+        datastore.project_delete_instance_force(&instance.id()).await.unwrap();
+        datastore
+            .project_delete(&organization.id(), &name, None)
+            .await
+            .unwrap();
+        let error = datastore
+            .project_fetch(&organization.id(), &name)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::ObjectNotFound { .. }));
+
+        let _ = db.cleanup().await;
+    }
+
+    /// Checks that looking up each of `expected`'s projects by id and by name
+    /// agree with each other and with `expected`, and that `gone` (projects
+    /// that were renamed or deleted) can no longer be found.
+    async fn check_project_lookups_agree(
+        datastore: &DataStore,
+        organization_id: &Uuid,
+        expected: &BTreeMap<Uuid, db::model::Name>,
+        gone: &[(Uuid, db::model::Name)],
+    ) {
+        for (id, name) in expected {
+            let by_id = datastore.project_fetch_by_id(id).await.unwrap();
+            assert_eq!(by_id.name(), name);
+            let by_name =
+                datastore.project_fetch(organization_id, name).await.unwrap();
+            assert_eq!(by_name.id(), *id);
+        }
+        for (id, name) in gone {
+            if !expected.contains_key(id) {
+                let error =
+                    datastore.project_fetch_by_id(id).await.unwrap_err();
+                assert!(matches!(error, Error::ObjectNotFound { .. }));
+            }
+            if !expected.values().any(|n| n == name) {
+                let error = datastore
+                    .project_fetch(organization_id, name)
+                    .await
+                    .unwrap_err();
+                assert!(matches!(error, Error::ObjectNotFound { .. }));
+            }
+        }
+
+        let pagparams = DataPageParams {
+            marker: None,
+            direction: dropshot::PaginationOrder::Ascending,
+            limit: NonZeroU32::new(100).unwrap(),
+        };
+        let listed: Vec<Uuid> = datastore
+            .projects_list_by_id(organization_id, &pagparams, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(listed, expected.keys().cloned().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_project_lookups_agree() {
+        let (logctx, mut db, datastore) =
+            test_setup("test_project_lookups_agree").await;
+        let opctx = OpContext::for_unit_tests(logctx.log.new(o!()));
+
+        let organization = Organization::new(params::OrganizationCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "org".parse().unwrap(),
+                description: "desc".to_string(),
+            },
+        });
+        let organization =
+            datastore.organization_create(&opctx, organization).await.unwrap();
+        let organization_id = organization.id();
+        let org = authz::FLEET.organization(organization_id);
+        let db_name = |s: String| db::model::Name(s.parse().unwrap());
+
+        // Build up a set of projects, checking after each creation.
+        let mut expected = BTreeMap::new();
+        let mut gone = Vec::new();
+        for i in 0..10 {
+            let project = Project::new(
+                organization_id,
+                params::ProjectCreate {
+                    identity: IdentityMetadataCreateParams {
+                        name: format!("project-{}", i).parse().unwrap(),
+                        description: "desc".to_string(),
+                    },
+                    id: None,
+                },
+            );
+            let project =
+                datastore.project_create(&opctx, &org, project).await.unwrap();
+            expected.insert(project.id(), project.name().clone());
+            check_project_lookups_agree(
+                &datastore,
+                &organization_id,
+                &expected,
+                &gone,
+            )
+            .await;
+        }
+
+        // Rename every other project.  Its id must keep working while its old
+        // name stops resolving.
+        for i in (0..10).step_by(2) {
+            let old_name = db_name(format!("project-{}", i));
+            let new_name = db_name(format!("renamed-{}", i));
+            let updated = datastore
+                .project_update(
+                    &organization_id,
+                    &old_name,
+                    ProjectUpdate {
+                        name: Some(new_name.clone()),
+                        description: None,
+                        time_modified: Utc::now(),
+                    },
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(*updated.name(), new_name);
+            expected.insert(updated.id(), new_name);
+            gone.push((updated.id(), old_name));
+            check_project_lookups_agree(
+                &datastore,
+                &organization_id,
+                &expected,
+                &gone,
+            )
+            .await;
+        }
+
+        // Delete a mix of renamed and never-renamed projects.
+        for i in (0..10).step_by(3) {
+            let name = if i % 2 == 0 {
+                db_name(format!("renamed-{}", i))
+            } else {
+                db_name(format!("project-{}", i))
+            };
+            let id = datastore
+                .project_lookup_id_by_name(&organization_id, &name)
+                .await
+                .unwrap();
+            datastore
+                .project_delete(&organization_id, &name, None)
+                .await
+                .unwrap();
+            expected.remove(&id);
+            gone.push((id, name));
+            check_project_lookups_agree(
+                &datastore,
+                &organization_id,
+                &expected,
+                &gone,
+            )
+            .await;
+        }
+
+        // Reusing a deleted project's name yields a new project with a new id;
+        // the old id must stay gone.
+        let project = Project::new(
+            organization_id,
+            params::ProjectCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "project-3".parse().unwrap(),
+                    description: "desc".to_string(),
+                },
+                id: None,
+            },
+        );
+        let project =
+            datastore.project_create(&opctx, &org, project).await.unwrap();
+        assert!(!gone.iter().any(|(id, _)| *id == project.id()));
+        expected.insert(project.id(), project.name().clone());
+        check_project_lookups_agree(
+            &datastore,
+            &organization_id,
+            &expected,
+            &gone,
+        )
+        .await;
+
+        let _ = db.cleanup().await;
+    }
+
+    #[test]
+    fn test_contains_pattern() {
+        assert_eq!(contains_pattern(""), "%%");
+        assert_eq!(contains_pattern("web"), "%web%");
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+
+    #[test]
+    fn test_unmodified_since_bound() {
+        let since = Utc.ymd(1994, 11, 6).and_hms(8, 49, 37);
+        let bound = unmodified_since_bound(since);
+        assert!(since - Duration::seconds(1) < bound);
+        assert!(since < bound);
+        assert!(since + Duration::milliseconds(999) < bound);
+        assert!(since + Duration::seconds(1) >= bound);
+
+        // Any fraction of a second in `since` itself is ignored.
+        assert_eq!(
+            unmodified_since_bound(since + Duration::milliseconds(500)),
+            bound
+        );
+    }
+
+    // Creates an instance called `name` in project `project_id`, placed on
+    // sled `sled_id`.
+    async fn create_test_instance(
+        datastore: &DataStore,
+        project_id: Uuid,
+        sled_id: Uuid,
+        name: &str,
+    ) -> Instance {
+        let create_params = params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: String::from("test instance"),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: name.to_string(),
+        };
+        let runtime = InstanceRuntimeState {
+            run_state: InstanceState::Creating,
+            sled_uuid: sled_id,
+            propolis_uuid: Uuid::new_v4(),
+            ncpus: create_params.ncpus,
+            memory: create_params.memory,
+            hostname: name.to_string(),
+            gen: Generation::new(),
+            time_updated: Utc::now(),
+        };
+        datastore
+            .project_create_instance(Instance::new(
+                Uuid::new_v4(),
+                project_id,
+                &create_params,
+                runtime.into(),
+            ))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sled_allocate() {
+        let (_logctx, mut db, datastore) =
+            test_setup("test_sled_allocate").await;
+
+        // With no sleds, there's nothing to allocate.
+        let error = datastore
+            .sled_allocate(Uuid::new_v4(), &FirstFit, &BTreeSet::new())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::ServiceUnavailable {
+                internal_message: String::from(
+                    "no sleds available for new Instance"
+                ),
+            }
+        );
+
+        // Insert a few sleds, out of order.
+        let address: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let ids: Vec<Uuid> = vec![
+            "a5b9ad1e-2a6e-4c3b-9a8d-3f0e7c1d2b40".parse().unwrap(),
+            "1f3c7a8e-5b2d-4e6f-8a9b-0c1d2e3f4a5b".parse().unwrap(),
+            "7e6d5c4b-3a29-4817-8f6e-5d4c3b2a1908".parse().unwrap(),
+        ];
+        for id in &ids {
+            let sled = datastore.sled_upsert(Sled::new(*id, address)).await;
+            assert_eq!(sled.unwrap().id(), *id);
+        }
+
+        // They should be listed in id order.
+        let pagparams = DataPageParams {
+            marker: None,
+            direction: dropshot::PaginationOrder::Ascending,
+            limit: std::num::NonZeroU32::new(100).unwrap(),
+        };
+        let listed = datastore
+            .sled_list(&pagparams)
+            .await
+            .unwrap()
+            .iter()
+            .map(|sled| sled.id())
+            .collect::<Vec<Uuid>>();
+        let mut expected = ids.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        // Each can be looked up by id, and upserting an existing sled updates
+        // its address.
+        let new_address: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+        datastore.sled_upsert(Sled::new(ids[0], new_address)).await.unwrap();
+        let fetched = datastore.sled_fetch(ids[0]).await.unwrap();
+        assert_eq!(fetched.address(), new_address);
+        let fetched = datastore.sled_fetch(ids[1]).await.unwrap();
+        assert_eq!(fetched.address(), address);
+
+        // Allocation picks the first sled by id.
+        let none = BTreeSet::new();
+        let allocated = datastore
+            .sled_allocate(Uuid::new_v4(), &FirstFit, &none)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, expected[0]);
+
+        // ... other than any that are to be avoided.
+        let avoid = expected[..2].iter().cloned().collect::<BTreeSet<_>>();
+        let allocated = datastore
+            .sled_allocate(Uuid::new_v4(), &FirstFit, &avoid)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, expected[2]);
+
+        // If every sled is to be avoided, allocation fails.
+        let avoid = expected.iter().cloned().collect::<BTreeSet<_>>();
+        let error = datastore
+            .sled_allocate(Uuid::new_v4(), &FirstFit, &avoid)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::ServiceUnavailable { .. }));
+
+        let _ = db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_sled_allocate_by_load() {
+        let (_logctx, mut db, datastore) =
+            test_setup("test_sled_allocate_by_load").await;
+
+        let address: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let sled_ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+        for id in &sled_ids {
+            datastore.sled_upsert(Sled::new(*id, address)).await.unwrap();
+        }
+
+        // Put two instances on the first sled and one on the second.
+        let project_id = Uuid::new_v4();
+        for (i, sled_id) in
+            [sled_ids[0], sled_ids[0], sled_ids[1]].iter().enumerate()
+        {
+            let name = format!("instance-{}", i);
+            create_test_instance(&datastore, project_id, *sled_id, &name).await;
+        }
+
+        // Candidates come back in the requested order, and only as many as
+        // were asked for.
+        let none = BTreeSet::new();
+        let limit = NonZeroU32::new(3).unwrap();
+        let candidates = datastore
+            .sled_candidates(&none, SledOrder::ById, limit)
+            .await
+            .unwrap();
+        assert_eq!(
+            candidates,
+            vec![
+                SledCandidate { id: sled_ids[0], ninstances: 2 },
+                SledCandidate { id: sled_ids[1], ninstances: 1 },
+                SledCandidate { id: sled_ids[2], ninstances: 0 },
+            ]
+        );
+        let limit = NonZeroU32::new(2).unwrap();
+        let candidates = datastore
+            .sled_candidates(&none, SledOrder::ByLoad, limit)
+            .await
+            .unwrap();
+        assert_eq!(
+            candidates,
+            vec![
+                SledCandidate { id: sled_ids[2], ninstances: 0 },
+                SledCandidate { id: sled_ids[1], ninstances: 1 },
+            ]
+        );
+
+        // FirstFit ignores load, while LeastLoaded picks the idle sled (or
+        // the least busy one that's not to be avoided).  Each allocation
+        // reserves its sled, so release them again afterwards.
+        let instance_ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let allocated = datastore
+            .sled_allocate(instance_ids[0], &FirstFit, &none)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, sled_ids[0]);
+        let allocated = datastore
+            .sled_allocate(instance_ids[1], &LeastLoaded, &none)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, sled_ids[2]);
+        let avoid = vec![sled_ids[2]].into_iter().collect();
+        let allocated = datastore
+            .sled_allocate(instance_ids[2], &LeastLoaded, &avoid)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, sled_ids[1]);
+        for instance_id in &instance_ids {
+            datastore.sled_reservation_delete(instance_id).await.unwrap();
+        }
+
+        // A reservation is only made (in a single statement) if the sled's
+        // load is what the caller last saw.
+        let instance_id = Uuid::new_v4();
+        let reservation = datastore
+            .sled_reservation_try_create(
+                SledReservation::new(instance_id, sled_ids[1]),
+                0,
+            )
+            .await
+            .unwrap();
+        assert!(reservation.is_none());
+        let reservation = datastore
+            .sled_reservation_try_create(
+                SledReservation::new(instance_id, sled_ids[1]),
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(reservation.unwrap().sled_id, sled_ids[1]);
+
+        // Allocating a sled for an instance that already has one keeps it.
+        let allocated = datastore
+            .sled_allocate(instance_id, &LeastLoaded, &none)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, sled_ids[1]);
+        datastore.sled_reservation_delete(&instance_id).await.unwrap();
+
+        // A reservation counts towards its sled's load until it's deleted.
+        // Making it again keeps the original reservation.
+        let instance_id = Uuid::new_v4();
+        let reservation = datastore
+            .sled_reservation_create(SledReservation::new(
+                instance_id,
+                sled_ids[2],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(reservation.sled_id, sled_ids[2]);
+        let reservation = datastore
+            .sled_reservation_create(SledReservation::new(
+                instance_id,
+                sled_ids[1],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(reservation.sled_id, sled_ids[2]);
+        let candidates = datastore
+            .sled_candidates(&none, SledOrder::ByLoad, limit)
+            .await
+            .unwrap();
+        assert_eq!(
+            candidates,
+            vec![
+                SledCandidate { id: sled_ids[1], ninstances: 1 },
+                SledCandidate { id: sled_ids[2], ninstances: 1 },
+            ]
+        );
+        datastore.sled_reservation_delete(&instance_id).await.unwrap();
+        datastore.sled_reservation_delete(&instance_id).await.unwrap();
+        let allocated = datastore
+            .sled_allocate(Uuid::new_v4(), &LeastLoaded, &none)
+            .await
+            .unwrap();
+        assert_eq!(allocated.sled_id, sled_ids[2]);
+
+        let _ = db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_paginated_marker_edge_cases() {
+        let (_logctx, mut db, datastore) =
+            test_setup("test_paginated_marker_edge_cases").await;
+
+        let project_id = Uuid::new_v4();
+        let mut ids = BTreeMap::new();
+        for suffix in ["a", "b", "c", "d", "e"] {
+            let name = format!("instance-{}", suffix);
+            let instance = create_test_instance(
+                &datastore,
+                project_id,
+                Uuid::new_v4(),
+                &name,
+            )
+            .await;
+            ids.insert(name, instance.id());
+        }
+
+        // Lists (up to) two instance names after `marker` in `direction`.
+        let list = |marker: &'static str,
+                    direction: dropshot::PaginationOrder| {
+            let datastore = &datastore;
+            async move {
+                let marker = db::model::Name(marker.parse().unwrap());
+                let pagparams = DataPageParams {
+                    marker: Some(&marker),
+                    direction,
+                    limit: NonZeroU32::new(2).unwrap(),
+                };
+                datastore
+                    .project_list_instances(&project_id, &pagparams)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|i| i.name().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+        let ascending = dropshot::PaginationOrder::Ascending;
+        let descending = dropshot::PaginationOrder::Descending;
+
+        // A marker past the end of the collection (in either direction)
+        // yields an empty page, not an error.
+        assert!(list("instance-z", ascending).await.is_empty());
+        assert!(list("instance", descending).await.is_empty());
+
+        // A marker equal to the last key also yields an empty page.
+        assert!(list("instance-e", ascending).await.is_empty());
+        assert!(list("instance-a", descending).await.is_empty());
+
+        // A marker naming an instance that has since been deleted resumes at
+        // the next existing key, in either direction.
+        datastore
+            .project_delete_instance_force(&ids["instance-c"])
+            .await
+            .unwrap();
+        assert_eq!(
+            list("instance-c", ascending).await,
+            vec!["instance-d", "instance-e"]
+        );
+        assert_eq!(
+            list("instance-c", descending).await,
+            vec!["instance-b", "instance-a"]
+        );
+
+        // The same is true for a marker that never existed at all.
+        assert_eq!(
+            list("instance-bb", ascending).await,
+            vec!["instance-d", "instance-e"]
+        );
+
+        let _ = db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_sled_insert_duplicate() {
+        let (_logctx, mut db, datastore) =
+            test_setup("test_sled_insert_duplicate").await;
+
+        let id = Uuid::new_v4();
+        let address: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        datastore.sled_insert(Sled::new(id, address)).await.unwrap();
+
+        // Adding a second sled with the same id fails and leaves the first
+        // one alone.
+        let new_address: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+        let error = datastore
+            .sled_insert(Sled::new(id, new_address))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::ObjectAlreadyExists {
+                type_name: ResourceType::Sled,
+                object_name: id.to_string(),
+            }
+        );
+        let fetched = datastore.sled_fetch(id).await.unwrap();
+        assert_eq!(fetched.address(), address);
+
+        let _ = db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_sled_upsert_keeps_instances() {
+        let (_logctx, mut db, datastore) =
+            test_setup("test_sled_upsert_keeps_instances").await;
+
+        let sled_id = Uuid::new_v4();
+        let address: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        datastore.sled_upsert(Sled::new(sled_id, address)).await.unwrap();
+
+        // Put a couple of instances on the sled.
+        let project_id = Uuid::new_v4();
+        let mut instance_ids = Vec::new();
+        for i in 0..2 {
+            let name = format!("instance-{}", i);
+            let instance =
+                create_test_instance(&datastore, project_id, sled_id, &name)
+                    .await;
+            instance_ids.push(instance.id());
+        }
+
+        // The sled agent restarts and re-registers at a new address.  The
+        // sled's record is updated in place, so its instances still refer to
+        // it and are still counted against it.
+        let new_address: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+        let sled = datastore.sled_upsert(Sled::new(sled_id, new_address)).await;
+        assert_eq!(sled.unwrap().address(), new_address);
+        for instance_id in &instance_ids {
+            let instance = datastore.instance_fetch(instance_id).await.unwrap();
+            assert_eq!(instance.runtime().sled_uuid, sled_id);
+        }
+        let candidates = datastore
+            .sled_candidates(
+                &BTreeSet::new(),
+                SledOrder::ById,
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            candidates,
+            vec![SledCandidate { id: sled_id, ninstances: 2 }]
+        );
+
+        let _ = db.cleanup().await;
+    }
+
     #[tokio::test]
     async fn test_session_methods() {
-        let logctx = dev::test_setup_log("test_collection_not_present");
-        let mut db = dev::test_setup_database(&logctx.log).await;
-        let cfg = db::Config { url: db.pg_config().clone() };
-        let pool = db::Pool::new(&cfg);
-        let datastore = DataStore::new(Arc::new(pool));
+        let (_logctx, mut db, datastore) =
+            test_setup("test_collection_not_present").await;
 
         let token = "a_token".to_string();
         let session = ConsoleSession {
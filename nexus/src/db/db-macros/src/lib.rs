@@ -203,10 +203,16 @@ fn build_resource_identity(
                 params: ::omicron_common::api::external::IdentityMetadataCreateParams
             ) -> Self {
                 let now = ::chrono::Utc::now();
+                let description = if params.description.is_empty() {
+                    ::omicron_common::api::external::DEFAULT_DESCRIPTION
+                        .to_string()
+                } else {
+                    params.description
+                };
                 Self {
                     id,
                     name: params.name.into(),
-                    description: params.description,
+                    description,
                     time_created: now,
                     time_modified: now,
                     time_deleted: None,
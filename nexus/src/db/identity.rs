@@ -54,7 +54,7 @@ pub trait Asset {
         external::IdentityMetadata {
             id: self.id(),
             name: "no-name".parse().unwrap(),
-            description: "no description".to_string(),
+            description: external::DEFAULT_DESCRIPTION.to_string(),
             time_created: self.time_created(),
             time_modified: self.time_modified(),
         }
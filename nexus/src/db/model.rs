@@ -9,7 +9,8 @@ use crate::db::identity::{Asset, Resource};
 use crate::db::schema::{
     console_session, dataset, disk, instance, metric_producer,
     network_interface, organization, oximeter, project, rack, region,
-    router_route, sled, vpc, vpc_firewall_rule, vpc_router, vpc_subnet, zpool,
+    router_route, sled, sled_reservation, vpc, vpc_firewall_rule, vpc_router,
+    vpc_subnet, zpool,
 };
 use crate::external_api::params;
 use crate::internal_api;
@@ -430,6 +431,22 @@ impl Sled {
     }
 }
 
+/// Database representation of a Sled allocated to an Instance that's still
+/// being created.
+#[derive(Queryable, Insertable, Debug, Clone, Selectable)]
+#[table_name = "sled_reservation"]
+pub struct SledReservation {
+    pub instance_id: Uuid,
+    pub sled_id: Uuid,
+    pub time_created: DateTime<Utc>,
+}
+
+impl SledReservation {
+    pub fn new(instance_id: Uuid, sled_id: Uuid) -> Self {
+        Self { instance_id, sled_id, time_created: Utc::now() }
+    }
+}
+
 impl DatastoreCollection<Zpool> for Sled {
     type CollectionId = Uuid;
     type GenerationNumberColumn = sled::dsl::rcgen;
@@ -608,6 +625,18 @@ impl DatastoreCollection<Project> for Organization {
     type CollectionIdColumn = project::dsl::organization_id;
 }
 
+/// Returns the description to store for an update, replacing an empty one
+/// with [`external::DEFAULT_DESCRIPTION`] just as creating a resource does.
+fn update_description(description: Option<String>) -> Option<String> {
+    description.map(|description| {
+        if description.is_empty() {
+            external::DEFAULT_DESCRIPTION.to_string()
+        } else {
+            description
+        }
+    })
+}
+
 /// Describes a set of updates for the [`Organization`] model.
 #[derive(AsChangeset)]
 #[table_name = "organization"]
@@ -621,7 +650,7 @@ impl From<params::OrganizationUpdate> for OrganizationUpdate {
     fn from(params: params::OrganizationUpdate) -> Self {
         Self {
             name: params.identity.name.map(|n| n.into()),
-            description: params.identity.description,
+            description: update_description(params.identity.description),
             time_modified: Utc::now(),
         }
     }
@@ -635,14 +664,21 @@ pub struct Project {
     identity: ProjectIdentity,
 
     pub organization_id: Uuid,
+
+    /// incremented on each update, for optimistic concurrency control
+    pub generation: Generation,
 }
 
 impl Project {
     /// Creates a new database Project object.
     pub fn new(organization_id: Uuid, params: params::ProjectCreate) -> Self {
         Self {
-            identity: ProjectIdentity::new(Uuid::new_v4(), params.identity),
+            identity: ProjectIdentity::new(
+                params.id.unwrap_or_else(Uuid::new_v4),
+                params.identity,
+            ),
             organization_id: organization_id,
+            generation: Generation::new(),
         }
     }
 }
@@ -660,7 +696,7 @@ impl From<params::ProjectUpdate> for ProjectUpdate {
     fn from(params: params::ProjectUpdate) -> Self {
         Self {
             name: params.identity.name.map(Name),
-            description: params.identity.description,
+            description: update_description(params.identity.description),
             time_modified: Utc::now(),
         }
     }
@@ -723,7 +759,9 @@ pub struct InstanceRuntimeState {
     #[column_name = "state"]
     pub state: InstanceState,
     /// timestamp for this information
-    // TODO: Is this redundant with "time_modified"?
+    ///
+    /// Unlike "time_modified", which also advances whenever the runtime state
+    /// is written, this is set by the sled agent that reported the state.
     #[column_name = "time_state_updated"]
     pub time_updated: DateTime<Utc>,
     /// generation number for this state
@@ -1158,7 +1196,7 @@ impl From<params::VpcUpdate> for VpcUpdate {
     fn from(params: params::VpcUpdate) -> Self {
         Self {
             name: params.identity.name.map(Name),
-            description: params.identity.description,
+            description: update_description(params.identity.description),
             time_modified: Utc::now(),
             dns_name: params.dns_name.map(Name),
         }
@@ -1206,7 +1244,7 @@ impl From<params::VpcSubnetUpdate> for VpcSubnetUpdate {
     fn from(params: params::VpcSubnetUpdate) -> Self {
         Self {
             name: params.identity.name.map(Name),
-            description: params.identity.description,
+            description: update_description(params.identity.description),
             time_modified: Utc::now(),
             ipv4_block: params.ipv4_block.map(Ipv4Net),
             ipv6_block: params.ipv6_block.map(Ipv6Net),
@@ -1285,7 +1323,7 @@ impl From<params::VpcRouterUpdate> for VpcRouterUpdate {
     fn from(params: params::VpcRouterUpdate) -> Self {
         Self {
             name: params.identity.name.map(Name),
-            description: params.identity.description,
+            description: update_description(params.identity.description),
             time_modified: Utc::now(),
         }
     }
@@ -1431,7 +1469,7 @@ impl From<external::RouterRouteUpdateParams> for RouterRouteUpdate {
     fn from(params: external::RouterRouteUpdateParams) -> Self {
         Self {
             name: params.identity.name.map(Name),
-            description: params.identity.description,
+            description: update_description(params.identity.description),
             time_modified: Utc::now(),
             target: RouteTarget(params.target),
             destination: RouteDestination::new(params.destination),
@@ -23,6 +23,11 @@ type TableSqlType<T> = <T as AsQuery>::SqlType;
 type BoxedQuery<T> = BoxedSelectStatement<'static, TableSqlType<T>, T, Pg>;
 
 /// Uses `pagparams` to list a subset of rows in `table`, ordered by `column`.
+///
+/// The marker is exclusive: the page starts with the first row strictly after
+/// it (in the requested direction), whether or not a row with the marker's
+/// value still exists.  So a marker for a since-deleted row resumes at the
+/// next row, and a marker past the end of the collection yields an empty page.
 pub fn paginated<T, C, M>(
     table: T,
     column: C,
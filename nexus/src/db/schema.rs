@@ -104,6 +104,7 @@ table! {
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
         organization_id -> Uuid,
+        generation -> Int8,
     }
 }
 
@@ -162,6 +163,14 @@ table! {
     }
 }
 
+table! {
+    sled_reservation (instance_id) {
+        instance_id -> Uuid,
+        sled_id -> Uuid,
+        time_created -> Timestamptz,
+    }
+}
+
 table! {
     zpool (id) {
         id -> Uuid,
@@ -301,6 +310,7 @@ allow_tables_to_appear_in_same_query!(
     saga_node_event,
     console_session,
     sled,
+    sled_reservation,
     router_route,
     vpc,
     vpc_subnet,
@@ -19,7 +19,10 @@ use crate::authn::external::{
 use crate::authn::{TEST_USER_UUID_PRIVILEGED, TEST_USER_UUID_UNPRIVILEGED};
 use crate::context::OpContext;
 use crate::ServerContext;
-use dropshot::{endpoint, HttpError, Path, RequestContext, TypedBody};
+use dropshot::{
+    endpoint, ApiEndpointResponse, HttpError, HttpResponse,
+    HttpResponseUpdatedNoContent, Path, RequestContext, TypedBody,
+};
 use http::{header, Response, StatusCode};
 use hyper::Body;
 use lazy_static::lazy_static;
@@ -35,6 +38,26 @@ pub struct LoginParams {
     pub username: String,
 }
 
+/// A "204 No Content" response carrying a `Set-Cookie` header
+///
+/// TODO-cleanup Dropshot doesn't yet let us attach headers to a typed response,
+/// so the logout handler builds its response itself. Wrapping it in this type
+/// describes it in the OpenAPI document the way `HttpResponseUpdatedNoContent`
+/// would be, rather than with no responses at all. (Login builds its response
+/// the same way, but can return either a 200 or a 401, so it isn't described
+/// at all.)
+pub struct HttpResponseNoContentWithCookie(Response<Body>);
+
+impl HttpResponse for HttpResponseNoContentWithCookie {
+    fn to_result(self) -> Result<Response<Body>, HttpError> {
+        Ok(self.0)
+    }
+
+    fn metadata() -> ApiEndpointResponse {
+        HttpResponseUpdatedNoContent::metadata()
+    }
+}
+
 // This is just for demo purposes. we will probably end up with a real username/password login
 // endpoint, but I think it will only be for use while setting up the rack
 #[endpoint {
@@ -90,7 +113,7 @@ pub async fn spoof_login(
 pub async fn logout(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
     cookies: Cookies,
-) -> Result<Response<Body>, HttpError> {
+) -> Result<HttpResponseNoContentWithCookie, HttpError> {
     let nexus = &rqctx.context().nexus;
     let opctx = OpContext::for_external_api(&rqctx).await;
     let token = cookies.get(SESSION_COOKIE_COOKIE_NAME);
@@ -108,10 +131,12 @@ pub async fn logout(
     // we would for a normal request. They are in fact logged out like they
     // intended, and we should send the standard success response.
 
-    Ok(Response::builder()
-        .status(StatusCode::NO_CONTENT)
-        .header(header::SET_COOKIE, clear_session_cookie_header_value())
-        .body("".into())?)
+    Ok(HttpResponseNoContentWithCookie(
+        Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::SET_COOKIE, clear_session_cookie_header_value())
+            .body("".into())?,
+    ))
 }
 
 #[derive(Deserialize, JsonSchema)]
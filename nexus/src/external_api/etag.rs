@@ -0,0 +1,194 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*!
+ * Support for `ETag` and `If-None-Match` on GET requests
+ *
+ * The entity tag for a response is derived from the serialized response body,
+ * so it changes whenever anything a client could see in the resource changes
+ * (including its runtime state and generation number).  A client polling a
+ * resource can send back the tag it last saw in an `If-None-Match` header and
+ * get "304 Not Modified", with no body, if nothing has changed since.
+ *
+ * The tags depend on the exact serialization of the body and on the hash
+ * function used, so they're only guaranteed to be stable for a given version
+ * of Nexus.  After an upgrade, a client might get a full response even though
+ * the resource didn't change.  That's harmless.
+ *
+ * Since we serialize these responses ourselves, we can also pretty-print them
+ * on request, which is handy when debugging against a server by hand.  The tag
+ * is computed from the compact form either way, so it doesn't depend on
+ * whether the client asked for pretty output.  That means two responses with
+ * the same tag needn't be byte-for-byte identical, so the tags are weak ones
+ * (`W/"..."`), which only promise that the bodies are equivalent.
+ *
+ * TODO-cleanup Dropshot doesn't yet let us attach headers to a typed response,
+ * so we build the response ourselves and describe it to Dropshot (and so to
+ * the OpenAPI document) as the equivalent `HttpResponseOk`.
+ */
+
+use dropshot::ApiEndpointResponse;
+use dropshot::HttpError;
+use dropshot::HttpResponse;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use http::header;
+use http::Response;
+use http::StatusCode;
+use hyper::Body;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::marker::PhantomData;
+
+/**
+ * Returns the weak entity tag for a response whose compact serialization is
+ * `body`, including the `W/` prefix and the surrounding double quotes
+ */
+fn etag_for(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/**
+ * Returns whether the value of an `If-None-Match` header matches `etag`
+ *
+ * The header value is either `*` or a comma-separated list of entity tags.
+ * Per RFC 7232, `If-None-Match` uses the weak comparison, so a `W/` prefix on
+ * `etag` or any of the listed tags is ignored.
+ */
+fn if_none_match_matches(header_value: &str, etag: &str) -> bool {
+    let header_value = header_value.trim();
+    if header_value == "*" {
+        return true;
+    }
+
+    let etag = etag.strip_prefix("W/").unwrap_or(etag);
+    header_value
+        .split(',')
+        .map(|tag| tag.trim())
+        .map(|tag| tag.strip_prefix("W/").unwrap_or(tag))
+        .any(|tag| tag == etag)
+}
+
+/**
+ * A "200 OK" response with a JSON body of type `T` and an `ETag` header, or
+ * the "304 Not Modified" that stands in for it
+ *
+ * The OpenAPI document describes this exactly as it does `HttpResponseOk<T>`.
+ */
+pub struct HttpResponseOkEtag<T> {
+    response: Response<Body>,
+    body_type: PhantomData<fn() -> T>,
+}
+
+impl<T> HttpResponseOkEtag<T> {
+    /** Returns the status code of the response (200 or 304) */
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+}
+
+impl<T> HttpResponse for HttpResponseOkEtag<T>
+where
+    T: JsonSchema + Serialize + Send + Sync + 'static,
+{
+    fn to_result(self) -> Result<Response<Body>, HttpError> {
+        Ok(self.response)
+    }
+
+    fn metadata() -> ApiEndpointResponse {
+        HttpResponseOk::<T>::metadata()
+    }
+}
+
+/**
+ * Builds a "200 OK" response to the GET request described by `rqctx`, with
+ * JSON body `body` and an `ETag` header
+ *
+ * If the request's `If-None-Match` header matches the tag, the response is
+ * instead "304 Not Modified" with the same `ETag` and no body.  If `pretty` is
+ * true, the body is pretty-printed rather than compact.
+ */
+pub async fn http_response_ok_etag<C, T>(
+    rqctx: &RequestContext<C>,
+    body: &T,
+    pretty: bool,
+) -> Result<HttpResponseOkEtag<T>, HttpError>
+where
+    C: Send + Sync + 'static,
+    T: Serialize,
+{
+    let serialize_error = |e: serde_json::Error| {
+        HttpError::for_internal_error(format!(
+            "failed to serialize response: {}",
+            e
+        ))
+    };
+    let mut serialized = serde_json::to_vec(body).map_err(serialize_error)?;
+    let etag = etag_for(&serialized);
+    if pretty {
+        serialized =
+            serde_json::to_vec_pretty(body).map_err(serialize_error)?;
+    }
+
+    let not_modified = {
+        let request = rqctx.request.lock().await;
+        request
+            .headers()
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| if_none_match_matches(value, &etag))
+    };
+
+    let builder = Response::builder().header(header::ETAG, &etag);
+    let response = if not_modified {
+        builder.status(StatusCode::NOT_MODIFIED).body(Body::empty())?
+    } else {
+        builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serialized.into())?
+    };
+    Ok(HttpResponseOkEtag { response, body_type: PhantomData })
+}
+
+#[cfg(test)]
+mod test {
+    use super::etag_for;
+    use super::if_none_match_matches;
+
+    #[test]
+    fn test_etag_for() {
+        let etag = etag_for(b"{\"name\":\"foo\"}");
+        assert!(etag.starts_with("W/\"") && etag.ends_with('"'));
+        assert_eq!(etag, etag_for(b"{\"name\":\"foo\"}"));
+        assert_ne!(etag, etag_for(b"{\"name\":\"bar\"}"));
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = "\"0123456789abcdef\"";
+        assert!(if_none_match_matches(etag, etag));
+        assert!(if_none_match_matches("*", etag));
+        assert!(if_none_match_matches(" W/\"0123456789abcdef\" ", etag));
+        assert!(if_none_match_matches(
+            "\"fedcba9876543210\", \"0123456789abcdef\"",
+            etag
+        ));
+
+        assert!(!if_none_match_matches("\"fedcba9876543210\"", etag));
+        assert!(!if_none_match_matches("0123456789abcdef", etag));
+        assert!(!if_none_match_matches("", etag));
+
+        /* Our own tags are weak, which doesn't change how they compare. */
+        let etag = "W/\"0123456789abcdef\"";
+        assert!(if_none_match_matches(etag, etag));
+        assert!(if_none_match_matches("\"0123456789abcdef\"", etag));
+        assert!(!if_none_match_matches("W/\"fedcba9876543210\"", etag));
+    }
+}
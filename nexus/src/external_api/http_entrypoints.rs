@@ -11,8 +11,14 @@ use crate::db::model::Name;
 use crate::ServerContext;
 
 use super::{
-    console_api, params,
-    views::{Organization, Project, Rack, Sled, Vpc, VpcSubnet},
+    console_api,
+    etag::{http_response_ok_etag, HttpResponseOkEtag},
+    params,
+    preconditions::if_unmodified_since,
+    views::{
+        InstanceCount, Organization, Project, ProjectDeleteResult, Rack, Sled,
+        Vpc, VpcSubnet,
+    },
 };
 use crate::context::OpContext;
 use dropshot::endpoint;
@@ -30,13 +36,17 @@ use dropshot::TypedBody;
 use omicron_common::api::external::http_pagination::data_page_params_for;
 use omicron_common::api::external::http_pagination::data_page_params_nameid_id;
 use omicron_common::api::external::http_pagination::data_page_params_nameid_name;
+use omicron_common::api::external::http_pagination::data_page_params_nameidmodified;
 use omicron_common::api::external::http_pagination::pagination_field_for_scan_params;
+use omicron_common::api::external::http_pagination::NameIdOrModifiedPage;
 use omicron_common::api::external::http_pagination::PagField;
 use omicron_common::api::external::http_pagination::PaginatedById;
 use omicron_common::api::external::http_pagination::PaginatedByName;
+use omicron_common::api::external::http_pagination::PaginatedByNameIdOrModified;
 use omicron_common::api::external::http_pagination::PaginatedByNameOrId;
 use omicron_common::api::external::http_pagination::ScanById;
 use omicron_common::api::external::http_pagination::ScanByName;
+use omicron_common::api::external::http_pagination::ScanByNameIdOrModified;
 use omicron_common::api::external::http_pagination::ScanByNameOrId;
 use omicron_common::api::external::http_pagination::ScanParams;
 use omicron_common::api::external::to_list;
@@ -55,6 +65,7 @@ use omicron_common::api::external::VpcFirewallRuleUpdateParams;
 use omicron_common::api::external::VpcFirewallRuleUpdateResult;
 use omicron_common::api::external::VpcRouter;
 use omicron_common::api::external::VpcRouterKind;
+use omicron_common::api::registration::EndpointRegistrar;
 use ref_cast::RefCast;
 use schemars::JsonSchema;
 use serde::Deserialize;
@@ -68,7 +79,9 @@ type NexusApiDescription = ApiDescription<Arc<ServerContext>>;
  * Returns a description of the external nexus API
  */
 pub fn external_api() -> NexusApiDescription {
-    fn register_endpoints(api: &mut NexusApiDescription) -> Result<(), String> {
+    fn register_endpoints(
+        api: &mut EndpointRegistrar<'_, Arc<ServerContext>>,
+    ) -> Result<(), String> {
         api.register(organizations_get)?;
         api.register(organizations_post)?;
         api.register(organizations_get_organization)?;
@@ -79,7 +92,9 @@ pub fn external_api() -> NexusApiDescription {
         api.register(organization_projects_post)?;
         api.register(organization_projects_get_project)?;
         api.register(organization_projects_delete_project)?;
+        api.register(organization_projects_delete)?;
         api.register(organization_projects_put_project)?;
+        api.register(projects_get_project_by_id)?;
 
         api.register(project_disks_get)?;
         api.register(project_disks_post)?;
@@ -88,11 +103,13 @@ pub fn external_api() -> NexusApiDescription {
 
         api.register(project_instances_get)?;
         api.register(project_instances_post)?;
+        api.register(project_instances_count)?;
         api.register(project_instances_get_instance)?;
         api.register(project_instances_delete_instance)?;
         api.register(project_instances_instance_reboot)?;
         api.register(project_instances_instance_start)?;
         api.register(project_instances_instance_stop)?;
+        api.register(project_instances_instance_serial)?;
 
         api.register(instance_disks_get)?;
         api.register(instance_disks_get_disk)?;
@@ -144,7 +161,8 @@ pub fn external_api() -> NexusApiDescription {
     }
 
     let mut api = NexusApiDescription::new();
-    if let Err(err) = register_endpoints(&mut api) {
+    if let Err(err) = register_endpoints(&mut EndpointRegistrar::new(&mut api))
+    {
         panic!("failed to register entrypoints: {}", err);
     }
     api
@@ -241,16 +259,34 @@ async fn organizations_post(
 ) -> Result<HttpResponseCreated<Organization>, HttpError> {
     let apictx = rqctx.context();
     let nexus = &apictx.nexus;
+    let new_organization = new_organization.into_inner();
     let handler = async {
         let opctx = OpContext::for_external_api(&rqctx).await?;
-        let organization = nexus
-            .organization_create(&opctx, &new_organization.into_inner())
+        let organization: Organization = apictx
+            .idempotency_cache
+            .run(&rqctx, &opctx, &new_organization, async {
+                let organization = nexus
+                    .organization_create(&opctx, &new_organization)
+                    .await?;
+                Ok(organization.into())
+            })
             .await?;
-        Ok(HttpResponseCreated(organization.into()))
+        Ok(HttpResponseCreated(organization))
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
 
+/**
+ * Query parameters for fetching a single resource
+ */
+#[derive(Deserialize, JsonSchema)]
+struct GetQuery {
+    /**
+     * If true, pretty-print the response body (for debugging)
+     */
+    pretty: Option<bool>,
+}
+
 /**
  * Path parameters for Organization requests
  */
@@ -262,6 +298,9 @@ struct OrganizationPathParam {
 
 /**
  * Fetch a specific organization
+ *
+ * The response carries an `ETag` header.  If the request's `If-None-Match`
+ * header matches it, the response is "304 Not Modified" with no body.
  */
 #[endpoint {
     method = GET,
@@ -269,21 +308,32 @@ struct OrganizationPathParam {
 }]
 async fn organizations_get_organization(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    query_params: Query<GetQuery>,
     path_params: Path<OrganizationPathParam>,
-) -> Result<HttpResponseOk<Organization>, HttpError> {
+) -> Result<HttpResponseOkEtag<Organization>, HttpError> {
     let apictx = rqctx.context();
     let nexus = &apictx.nexus;
     let path = path_params.into_inner();
+    let pretty = query_params.into_inner().pretty.unwrap_or(false);
     let organization_name = &path.organization_name;
     let handler = async {
-        let organization = nexus.organization_fetch(&organization_name).await?;
-        Ok(HttpResponseOk(organization.into()))
+        let organization: Organization =
+            nexus.organization_fetch(&organization_name).await?.into();
+        http_response_ok_etag(&rqctx, &organization, pretty).await
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx
+        .external_latencies
+        .instrument_dropshot_handler_with_status(&rqctx, handler, |response| {
+            response.status()
+        })
+        .await
 }
 
 /**
  * Delete a specific organization.
+ *
+ * If the request has an `If-Unmodified-Since` header and the organization has
+ * been modified since then, this fails with "412 Precondition Failed".
  */
 #[endpoint {
      method = DELETE,
@@ -298,7 +348,8 @@ async fn organizations_delete_organization(
     let params = path_params.into_inner();
     let organization_name = &params.organization_name;
     let handler = async {
-        nexus.organization_delete(&organization_name).await?;
+        let unmodified_since = if_unmodified_since(&rqctx).await;
+        nexus.organization_delete(&organization_name, unmodified_since).await?;
         Ok(HttpResponseDeleted())
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
@@ -307,6 +358,9 @@ async fn organizations_delete_organization(
 /**
  * Update a specific organization.
  *
+ * If the request has an `If-Unmodified-Since` header and the organization has
+ * been modified since then, this fails with "412 Precondition Failed".
+ *
  * TODO-correctness: Is it valid for PUT to accept application/json that's a
  * subset of what the resource actually represents?  If not, is that a problem?
  * (HTTP may require that this be idempotent.)  If so, can we get around that
@@ -327,10 +381,12 @@ async fn organizations_put_organization(
     let path = path_params.into_inner();
     let organization_name = &path.organization_name;
     let handler = async {
+        let unmodified_since = if_unmodified_since(&rqctx).await;
         let new_organization = nexus
             .organization_update(
                 &organization_name,
                 &updated_organization.into_inner(),
+                unmodified_since,
             )
             .await?;
         Ok(HttpResponseOk(new_organization.into()))
@@ -340,6 +396,9 @@ async fn organizations_put_organization(
 
 /**
  * List all projects.
+ *
+ * If `search` is given, only projects whose name or description contains it
+ * (ignoring case) are listed.
  */
 #[endpoint {
      method = GET,
@@ -347,7 +406,7 @@ async fn organizations_put_organization(
  }]
 async fn organization_projects_get(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
-    query_params: Query<PaginatedByNameOrId>,
+    query_params: Query<PaginatedByNameIdOrModified>,
     path_params: Path<OrganizationPathParam>,
 ) -> Result<HttpResponseOk<ResultsPage<Project>>, HttpError> {
     let apictx = rqctx.context();
@@ -357,29 +416,46 @@ async fn organization_projects_get(
     let organization_name = &path.organization_name;
 
     let handler = async {
-        let params = ScanByNameOrId::from_query(&query)?;
-        let field = pagination_field_for_scan_params(params);
-        let projects = match field {
-            PagField::Id => {
-                let page_selector = data_page_params_nameid_id(&rqctx, &query)?;
+        let search = ScanByNameIdOrModified::from_query(&query)?.search();
+        let projects = match data_page_params_nameidmodified(&rqctx, &query)? {
+            NameIdOrModifiedPage::Id(page_selector) => {
                 nexus
-                    .projects_list_by_id(&organization_name, &page_selector)
+                    .projects_list_by_id(
+                        &organization_name,
+                        &page_selector,
+                        search,
+                    )
                     .await?
             }
 
-            PagField::Name => {
+            NameIdOrModifiedPage::Name(page_selector) => {
                 let page_selector =
-                    data_page_params_nameid_name(&rqctx, &query)?
-                        .map_name(|n| Name::ref_cast(n));
+                    page_selector.map_name(|n| Name::ref_cast(n));
                 nexus
-                    .projects_list_by_name(&organization_name, &page_selector)
+                    .projects_list_by_name(
+                        &organization_name,
+                        &page_selector,
+                        search,
+                    )
+                    .await?
+            }
+
+            NameIdOrModifiedPage::Modified(page_selector) => {
+                nexus
+                    .projects_list_by_modified(
+                        &organization_name,
+                        &page_selector,
+                        search,
+                    )
                     .await?
             }
         }
         .into_iter()
         .map(|p| p.into())
         .collect();
-        Ok(HttpResponseOk(ScanByNameOrId::results_page(&query, projects)?))
+        Ok(HttpResponseOk(ScanByNameIdOrModified::results_page(
+            &query, projects,
+        )?))
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
@@ -400,16 +476,19 @@ async fn organization_projects_post(
     let nexus = &apictx.nexus;
     let params = path_params.into_inner();
     let organization_name = &params.organization_name;
+    let new_project = new_project.into_inner();
     let handler = async {
         let opctx = OpContext::for_external_api(&rqctx).await?;
-        let project = nexus
-            .project_create(
-                &opctx,
-                &organization_name,
-                &new_project.into_inner(),
-            )
+        let project: Project = apictx
+            .idempotency_cache
+            .run(&rqctx, &opctx, &new_project, async {
+                let project = nexus
+                    .project_create(&opctx, &organization_name, &new_project)
+                    .await?;
+                Ok(project.into())
+            })
             .await?;
-        Ok(HttpResponseCreated(project.into()))
+        Ok(HttpResponseCreated(project))
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
@@ -427,6 +506,9 @@ struct ProjectPathParam {
 
 /**
  * Fetch a specific project
+ *
+ * The response carries an `ETag` header.  If the request's `If-None-Match`
+ * header matches it, the response is "304 Not Modified" with no body.
  */
 #[endpoint {
     method = GET,
@@ -434,23 +516,76 @@ struct ProjectPathParam {
 }]
 async fn organization_projects_get_project(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    query_params: Query<GetQuery>,
     path_params: Path<ProjectPathParam>,
-) -> Result<HttpResponseOk<Project>, HttpError> {
+) -> Result<HttpResponseOkEtag<Project>, HttpError> {
     let apictx = rqctx.context();
     let nexus = &apictx.nexus;
     let path = path_params.into_inner();
+    let pretty = query_params.into_inner().pretty.unwrap_or(false);
     let organization_name = &path.organization_name;
     let project_name = &path.project_name;
     let handler = async {
-        let project =
-            nexus.project_fetch(&organization_name, &project_name).await?;
-        Ok(HttpResponseOk(project.into()))
+        let project: Project = nexus
+            .project_fetch(&organization_name, &project_name)
+            .await?
+            .into();
+        http_response_ok_etag(&rqctx, &project, pretty).await
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx
+        .external_latencies
+        .instrument_dropshot_handler_with_status(&rqctx, handler, |response| {
+            response.status()
+        })
+        .await
+}
+
+/**
+ * Path parameters for Project requests by id
+ */
+#[derive(Deserialize, JsonSchema)]
+struct ProjectByIdPathParam {
+    /** The project's unique ID. */
+    id: Uuid,
+}
+
+/**
+ * Fetch a specific project by its ID, which (unlike its name) never changes
+ *
+ * The response carries an `ETag` header.  If the request's `If-None-Match`
+ * header matches it, the response is "304 Not Modified" with no body.
+ */
+#[endpoint {
+    method = GET,
+    path = "/by-id/projects/{id}",
+}]
+async fn projects_get_project_by_id(
+    rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    query_params: Query<GetQuery>,
+    path_params: Path<ProjectByIdPathParam>,
+) -> Result<HttpResponseOkEtag<Project>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let pretty = query_params.into_inner().pretty.unwrap_or(false);
+    let handler = async {
+        let project: Project =
+            nexus.project_fetch_by_id(&path.id).await?.into();
+        http_response_ok_etag(&rqctx, &project, pretty).await
+    };
+    apictx
+        .external_latencies
+        .instrument_dropshot_handler_with_status(&rqctx, handler, |response| {
+            response.status()
+        })
+        .await
 }
 
 /**
  * Delete a specific project.
+ *
+ * If the request has an `If-Unmodified-Since` header and the project has been
+ * modified since then, this fails with "412 Precondition Failed".
  */
 #[endpoint {
      method = DELETE,
@@ -466,15 +601,65 @@ async fn organization_projects_delete_project(
     let organization_name = &params.organization_name;
     let project_name = &params.project_name;
     let handler = async {
-        nexus.project_delete(&organization_name, &project_name).await?;
+        let unmodified_since = if_unmodified_since(&rqctx).await;
+        nexus
+            .project_delete(&organization_name, &project_name, unmodified_since)
+            .await?;
         Ok(HttpResponseDeleted())
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
 
+/**
+ * Delete several projects at once.
+ *
+ * Each project is deleted as though by its own delete request.  Failing to
+ * delete one project (say, because it doesn't exist) doesn't stop the rest
+ * from being deleted.  The response has the outcome for each project, in the
+ * order they were given.
+ *
+ * The number of projects that may be deleted in one request is limited, and
+ * naming more than that is an error.
+ */
+#[endpoint {
+     method = POST,
+     path = "/organizations/{organization_name}/projects-delete",
+ }]
+async fn organization_projects_delete(
+    rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    path_params: Path<OrganizationPathParam>,
+    delete_params: TypedBody<params::ProjectsDelete>,
+) -> Result<HttpResponseOk<Vec<ProjectDeleteResult>>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let organization_name = &path.organization_name;
+    let project_names: Vec<Name> =
+        delete_params.into_inner().names.into_iter().map(Name::from).collect();
+    let handler = async {
+        let results =
+            nexus.projects_delete(&organization_name, &project_names).await?;
+        let results = project_names
+            .into_iter()
+            .zip(results)
+            .map(|(name, result)| ProjectDeleteResult {
+                name: name.0,
+                error: result
+                    .err()
+                    .map(|e| HttpError::from(e).external_message),
+            })
+            .collect();
+        Ok(HttpResponseOk(results))
+    };
+    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+}
+
 /**
  * Update a specific project.
  *
+ * If the request has an `If-Unmodified-Since` header and the project has been
+ * modified since then, this fails with "412 Precondition Failed".
+ *
  * TODO-correctness: Is it valid for PUT to accept application/json that's a
  * subset of what the resource actually represents?  If not, is that a problem?
  * (HTTP may require that this be idempotent.)  If so, can we get around that
@@ -496,11 +681,13 @@ async fn organization_projects_put_project(
     let organization_name = &path.organization_name;
     let project_name = &path.project_name;
     let handler = async {
+        let unmodified_since = if_unmodified_since(&rqctx).await;
         let newproject = nexus
             .project_update(
                 &organization_name,
                 &project_name,
                 &updated_project.into_inner(),
+                unmodified_since,
             )
             .await?;
         Ok(HttpResponseOk(newproject.into()))
@@ -568,14 +755,21 @@ async fn project_disks_post(
     let project_name = &path.project_name;
     let new_disk_params = &new_disk.into_inner();
     let handler = async {
-        let disk = nexus
-            .project_create_disk(
-                &organization_name,
-                &project_name,
-                &new_disk_params,
-            )
+        let opctx = OpContext::for_external_api(&rqctx).await?;
+        let disk: Disk = apictx
+            .idempotency_cache
+            .run(&rqctx, &opctx, new_disk_params, async {
+                let disk = nexus
+                    .project_create_disk(
+                        &organization_name,
+                        &project_name,
+                        &new_disk_params,
+                    )
+                    .await?;
+                Ok(disk.into())
+            })
             .await?;
-        Ok(HttpResponseCreated(disk.into()))
+        Ok(HttpResponseCreated(disk))
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
@@ -592,6 +786,9 @@ struct DiskPathParam {
 
 /**
  * Fetch a single disk in a project.
+ *
+ * The response carries an `ETag` header.  If the request's `If-None-Match`
+ * header matches it, the response is "304 Not Modified" with no body.
  */
 #[endpoint {
      method = GET,
@@ -599,11 +796,13 @@ struct DiskPathParam {
  }]
 async fn project_disks_get_disk(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    query_params: Query<GetQuery>,
     path_params: Path<DiskPathParam>,
-) -> Result<HttpResponseOk<Disk>, HttpError> {
+) -> Result<HttpResponseOkEtag<Disk>, HttpError> {
     let apictx = rqctx.context();
     let nexus = &apictx.nexus;
     let path = path_params.into_inner();
+    let pretty = query_params.into_inner().pretty.unwrap_or(false);
     let organization_name = &path.organization_name;
     let project_name = &path.project_name;
     let disk_name = &path.disk_name;
@@ -611,9 +810,15 @@ async fn project_disks_get_disk(
         let (disk, _) = nexus
             .project_lookup_disk(&organization_name, &project_name, &disk_name)
             .await?;
-        Ok(HttpResponseOk(disk.into()))
+        let disk: Disk = disk.into();
+        http_response_ok_etag(&rqctx, &disk, pretty).await
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx
+        .external_latencies
+        .instrument_dropshot_handler_with_status(&rqctx, handler, |response| {
+            response.status()
+        })
+        .await
 }
 
 /**
@@ -687,6 +892,31 @@ async fn project_instances_get(
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
 
+/**
+ * Count instances in a project.
+ */
+#[endpoint {
+     method = GET,
+     path = "/organizations/{organization_name}/projects/{project_name}/instances-count",
+ }]
+async fn project_instances_count(
+    rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    path_params: Path<ProjectPathParam>,
+) -> Result<HttpResponseOk<InstanceCount>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let organization_name = &path.organization_name;
+    let project_name = &path.project_name;
+    let handler = async {
+        let count = nexus
+            .project_instances_count(&organization_name, &project_name)
+            .await?;
+        Ok(HttpResponseOk(InstanceCount { count }))
+    };
+    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+}
+
 /**
  * Create an instance in a project.
  *
@@ -714,14 +944,21 @@ async fn project_instances_post(
     let project_name = &path.project_name;
     let new_instance_params = &new_instance.into_inner();
     let handler = async {
-        let instance = nexus
-            .project_create_instance(
-                &organization_name,
-                &project_name,
-                &new_instance_params,
-            )
+        let opctx = OpContext::for_external_api(&rqctx).await?;
+        let instance: Instance = apictx
+            .idempotency_cache
+            .run(&rqctx, &opctx, new_instance_params, async {
+                let instance = nexus
+                    .project_create_instance(
+                        &organization_name,
+                        &project_name,
+                        &new_instance_params,
+                    )
+                    .await?;
+                Ok(instance.into())
+            })
             .await?;
-        Ok(HttpResponseCreated(instance.into()))
+        Ok(HttpResponseCreated(instance))
     };
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
@@ -738,6 +975,9 @@ struct InstancePathParam {
 
 /**
  * Get an instance in a project.
+ *
+ * The response carries an `ETag` header.  If the request's `If-None-Match`
+ * header matches it, the response is "304 Not Modified" with no body.
  */
 #[endpoint {
      method = GET,
@@ -745,11 +985,13 @@ struct InstancePathParam {
  }]
 async fn project_instances_get_instance(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    query_params: Query<GetQuery>,
     path_params: Path<InstancePathParam>,
-) -> Result<HttpResponseOk<Instance>, HttpError> {
+) -> Result<HttpResponseOkEtag<Instance>, HttpError> {
     let apictx = rqctx.context();
     let nexus = &apictx.nexus;
     let path = path_params.into_inner();
+    let pretty = query_params.into_inner().pretty.unwrap_or(false);
     let organization_name = &path.organization_name;
     let project_name = &path.project_name;
     let instance_name = &path.instance_name;
@@ -761,9 +1003,26 @@ async fn project_instances_get_instance(
                 &instance_name,
             )
             .await?;
-        Ok(HttpResponseOk(instance.into()))
+        let instance: Instance = instance.into();
+        http_response_ok_etag(&rqctx, &instance, pretty).await
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx
+        .external_latencies
+        .instrument_dropshot_handler_with_status(&rqctx, handler, |response| {
+            response.status()
+        })
+        .await
+}
+
+/**
+ * Query parameters for deleting an Instance
+ */
+#[derive(Deserialize, JsonSchema)]
+struct InstanceDeleteQuery {
+    /**
+     * If true, delete the instance regardless of its state
+     */
+    force: Option<bool>,
 }
 
 /**
@@ -775,11 +1034,13 @@ async fn project_instances_get_instance(
  }]
 async fn project_instances_delete_instance(
     rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    query_params: Query<InstanceDeleteQuery>,
     path_params: Path<InstancePathParam>,
 ) -> Result<HttpResponseDeleted, HttpError> {
     let apictx = rqctx.context();
     let nexus = &apictx.nexus;
     let path = path_params.into_inner();
+    let force = query_params.into_inner().force.unwrap_or(false);
     let organization_name = &path.organization_name;
     let project_name = &path.project_name;
     let instance_name = &path.instance_name;
@@ -789,6 +1050,7 @@ async fn project_instances_delete_instance(
                 &organization_name,
                 &project_name,
                 &instance_name,
+                force,
             )
             .await?;
         Ok(HttpResponseDeleted())
@@ -875,6 +1137,40 @@ async fn project_instances_instance_stop(
     apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
 }
 
+/**
+ * Get the serial console output of an instance.
+ */
+/*
+ * TODO-completeness This returns all of the output so far, one line per entry,
+ * rather than streaming it.
+ */
+#[endpoint {
+    method = GET,
+    path = "/organizations/{organization_name}/projects/{project_name}/instances/{instance_name}/serial",
+}]
+async fn project_instances_instance_serial(
+    rqctx: Arc<RequestContext<Arc<ServerContext>>>,
+    path_params: Path<InstancePathParam>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let organization_name = &path.organization_name;
+    let project_name = &path.project_name;
+    let instance_name = &path.instance_name;
+    let handler = async {
+        let output = nexus
+            .instance_serial_output(
+                &organization_name,
+                &project_name,
+                &instance_name,
+            )
+            .await?;
+        Ok(HttpResponseOk(output))
+    };
+    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+}
+
 /**
  * List disks attached to this instance.
  */
@@ -0,0 +1,390 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*!
+ * Support for the `Idempotency-Key` header on create requests
+ *
+ * A client that isn't sure whether its create request made it (say, because
+ * the connection dropped before the response arrived) can retry it safely by
+ * sending the same `Idempotency-Key` header both times.  The first request to
+ * complete successfully with a given key has its response cached; until the
+ * key expires, retries with that key get the cached response back instead of
+ * creating the resource again or failing with a conflict.
+ *
+ * Keys are scoped to the authenticated actor making the request and to the
+ * request's method and path, so the same key may be used by different users,
+ * or against different collections, without interfering.  Only successful
+ * responses are cached: a request that failed may be retried with the same key
+ * and will be attempted again.
+ *
+ * A retry is expected to carry the same body as the original request.  One
+ * that doesn't is almost certainly a client bug (reusing a key for a different
+ * request), so rather than replay a response to some other request, we reject
+ * it with "422 Unprocessable Entity".
+ *
+ * The cache is kept in memory, so the number of keys it holds at once is
+ * limited.  While it's full, requests with keys it doesn't already hold are
+ * refused with "503 Service Unavailable" rather than run without the
+ * protection the client asked for.  Expired keys are removed as new ones come
+ * in, so this clears up once the expiry window has passed.
+ */
+
+use super::super::ServerContext;
+use crate::context::OpContext;
+use dropshot::HttpError;
+use dropshot::RequestContext;
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use uuid::Uuid;
+
+/** Name of the request header carrying the idempotency key */
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/**
+ * Identifies a cached response: the authenticated actor (if any), request
+ * method and path, plus the key
+ */
+type CacheKey = (Option<Uuid>, String, String, String);
+
+/** Slot for a (possibly not-yet-completed) response to a keyed request */
+type CacheEntry = Arc<tokio::sync::Mutex<Option<CachedResponse>>>;
+
+struct CachedResponse {
+    completed: Instant,
+    request_hash: u64,
+    body: serde_json::Value,
+}
+
+/** Returns a hash of the JSON form of a request body */
+fn request_hash<P: Serialize>(params: &P) -> Result<u64, HttpError> {
+    let serialized = serde_json::to_vec(params).map_err(|e| {
+        HttpError::for_internal_error(format!(
+            "failed to serialize request body: {}",
+            e
+        ))
+    })?;
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&serialized);
+    Ok(hasher.finish())
+}
+
+/**
+ * Responses to create requests that carried an `Idempotency-Key` header
+ */
+pub struct IdempotencyCache {
+    expiry: Duration,
+    max_keys: usize,
+    entries: Mutex<BTreeMap<CacheKey, CacheEntry>>,
+}
+
+impl IdempotencyCache {
+    /**
+     * Creates a cache whose entries may be replayed for `expiry` after the
+     * request that created them completed, and which holds at most
+     * `max_keys` entries at once
+     */
+    pub fn new(expiry: Duration, max_keys: usize) -> IdempotencyCache {
+        IdempotencyCache {
+            expiry,
+            max_keys,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /**
+     * Runs `create` on behalf of the request described by `rqctx` and `opctx`,
+     * whose body is `params`, honoring its `Idempotency-Key` header (if any)
+     *
+     * If an earlier request from the same actor with the same method, path,
+     * and key completed successfully within the expiry window, `create` is not
+     * run and that request's result is returned instead (or, if that request
+     * had a different body, an error).  If such a request is still in
+     * progress, this waits for it to finish first.
+     */
+    pub async fn run<T, P, F>(
+        &self,
+        rqctx: &RequestContext<Arc<ServerContext>>,
+        opctx: &OpContext,
+        params: &P,
+        create: F,
+    ) -> Result<T, HttpError>
+    where
+        T: Serialize + DeserializeOwned,
+        P: Serialize,
+        F: Future<Output = Result<T, HttpError>>,
+    {
+        let cache_key = {
+            let request = rqctx.request.lock().await;
+            let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+                None => None,
+                Some(value) => Some(
+                    value
+                        .to_str()
+                        .ok()
+                        .filter(|key| !key.is_empty())
+                        .ok_or_else(|| {
+                            HttpError::for_bad_request(
+                                None,
+                                format!(
+                                    "header {:?} must be a non-empty \
+                                     printable ASCII string",
+                                    IDEMPOTENCY_KEY_HEADER
+                                ),
+                            )
+                        })?
+                        .to_string(),
+                ),
+            };
+            key.map(|key| {
+                (
+                    opctx.authn.actor().map(|actor| actor.0),
+                    request.method().to_string(),
+                    request.uri().path().to_string(),
+                    key,
+                )
+            })
+        };
+
+        match cache_key {
+            None => create.await,
+            Some(cache_key) => {
+                let request_hash = request_hash(params)?;
+                self.run_keyed(cache_key, request_hash, create).await
+            }
+        }
+    }
+
+    async fn run_keyed<T, F>(
+        &self,
+        cache_key: CacheKey,
+        request_hash: u64,
+        create: F,
+    ) -> Result<T, HttpError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T, HttpError>>,
+    {
+        let entry = {
+            let mut entries = self.entries.lock().unwrap();
+            self.purge_expired(&mut entries);
+            if !entries.contains_key(&cache_key)
+                && entries.len() >= self.max_keys
+            {
+                return Err(HttpError::for_unavail(
+                    None,
+                    format!(
+                        "too many outstanding {:?} keys",
+                        IDEMPOTENCY_KEY_HEADER
+                    ),
+                ));
+            }
+            Arc::clone(entries.entry(cache_key).or_default())
+        };
+
+        /*
+         * Holding the entry's lock while we create the resource means that a
+         * retry arriving while the original request is still in flight waits
+         * for it rather than racing it.
+         */
+        let mut cached = entry.lock().await;
+        if let Some(response) = &*cached {
+            if response.completed.elapsed() < self.expiry {
+                if response.request_hash != request_hash {
+                    return Err(HttpError::for_client_error(
+                        None,
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!(
+                            "{:?} was already used for a request with a \
+                             different body",
+                            IDEMPOTENCY_KEY_HEADER
+                        ),
+                    ));
+                }
+                return serde_json::from_value(response.body.clone()).map_err(
+                    |e| {
+                        HttpError::for_internal_error(format!(
+                            "failed to replay cached response: {}",
+                            e
+                        ))
+                    },
+                );
+            }
+        }
+
+        let result = create.await?;
+        let body = serde_json::to_value(&result).map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "failed to cache response: {}",
+                e
+            ))
+        })?;
+        *cached = Some(CachedResponse {
+            completed: Instant::now(),
+            request_hash,
+            body,
+        });
+        Ok(result)
+    }
+
+    /**
+     * Removes entries that have expired or whose request failed, skipping any
+     * that some request is still using
+     */
+    fn purge_expired(&self, entries: &mut BTreeMap<CacheKey, CacheEntry>) {
+        let expiry = self.expiry;
+        entries.retain(|_, entry| {
+            /*
+             * While we hold the map's lock, nobody can pick up a new reference
+             * to an entry, so if ours is the only one, nobody is using it (and
+             * so its lock is free).
+             */
+            if Arc::strong_count(entry) > 1 {
+                return true;
+            }
+            match entry.try_lock().as_deref() {
+                Ok(Some(response)) => response.completed.elapsed() < expiry,
+                Ok(None) | Err(_) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CacheKey;
+    use super::IdempotencyCache;
+    use dropshot::HttpError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn key(k: &str) -> CacheKey {
+        (None, String::from("POST"), String::from("/things"), String::from(k))
+    }
+
+    #[tokio::test]
+    async fn test_replay_until_expired() {
+        let calls = AtomicUsize::new(0);
+        let create = || async {
+            Ok::<_, HttpError>(calls.fetch_add(1, Ordering::SeqCst))
+        };
+
+        /* A retry with the same key replays the first result. */
+        let cache = IdempotencyCache::new(Duration::from_secs(3600), 100);
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 0);
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        /* A different key is a different request. */
+        assert_eq!(cache.run_keyed(key("b"), 0, create()).await.unwrap(), 1);
+        assert_eq!(cache.entries.lock().unwrap().len(), 2);
+
+        /* With no expiry window, nothing is replayed (or kept). */
+        let cache = IdempotencyCache::new(Duration::from_secs(0), 100);
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 2);
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 3);
+        assert_eq!(cache.run_keyed(key("b"), 0, create()).await.unwrap(), 4);
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failures_not_cached() {
+        let cache = IdempotencyCache::new(Duration::from_secs(3600), 100);
+        let error = cache
+            .run_keyed(key("a"), 0, async {
+                Err::<u32, _>(HttpError::for_unavail(None, "busy".to_string()))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(
+            cache.run_keyed(key("a"), 0, async { Ok(1) }).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            cache.run_keyed(key("a"), 0, async { Ok(2) }).await.unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_different_body_rejected() {
+        let cache = IdempotencyCache::new(Duration::from_secs(3600), 100);
+        assert_eq!(
+            cache.run_keyed(key("a"), 0, async { Ok(1) }).await.unwrap(),
+            1
+        );
+        let error =
+            cache.run_keyed(key("a"), 1, async { Ok(2) }).await.unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::UNPROCESSABLE_ENTITY);
+
+        /* The original request can still be retried. */
+        assert_eq!(
+            cache.run_keyed(key("a"), 0, async { Ok(3) }).await.unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_keys() {
+        let calls = AtomicUsize::new(0);
+        let create = || async {
+            Ok::<_, HttpError>(calls.fetch_add(1, Ordering::SeqCst))
+        };
+
+        let cache = IdempotencyCache::new(Duration::from_secs(3600), 2);
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 0);
+        assert_eq!(cache.run_keyed(key("b"), 0, create()).await.unwrap(), 1);
+
+        /* Once the cache is full, a new key is refused without running. */
+        let error = cache.run_keyed(key("c"), 0, create()).await.unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.entries.lock().unwrap().len(), 2);
+
+        /* Keys already in the cache are still replayed. */
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        /* Expired entries make room for new keys. */
+        let cache = IdempotencyCache::new(Duration::from_secs(0), 1);
+        assert_eq!(cache.run_keyed(key("a"), 0, create()).await.unwrap(), 2);
+        assert_eq!(cache.run_keyed(key("b"), 0, create()).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_keys_scoped_to_actor() {
+        let cache = IdempotencyCache::new(Duration::from_secs(3600), 100);
+        let (_, method, path, k) = key("a");
+        let actor1 =
+            (Some(Uuid::new_v4()), method.clone(), path.clone(), k.clone());
+        let actor2 = (Some(Uuid::new_v4()), method, path, k);
+        assert_eq!(
+            cache.run_keyed(actor1.clone(), 0, async { Ok(1) }).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            cache.run_keyed(actor2, 0, async { Ok(2) }).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            cache.run_keyed(key("a"), 0, async { Ok(3) }).await.unwrap(),
+            3
+        );
+        assert_eq!(
+            cache.run_keyed(actor1, 0, async { Ok(4) }).await.unwrap(),
+            1
+        );
+    }
+}
@@ -3,6 +3,9 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 pub mod console_api;
+pub mod etag;
 pub mod http_entrypoints;
+pub mod idempotency;
 pub mod params;
+pub mod preconditions;
 pub mod views;
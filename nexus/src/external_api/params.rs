@@ -50,6 +50,8 @@ pub struct OrganizationUpdate {
 pub struct ProjectCreate {
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
+    /** id for the new Project (chosen by the server if not specified) */
+    pub id: Option<Uuid>,
 }
 
 /**
@@ -62,6 +64,19 @@ pub struct ProjectUpdate {
     pub identity: IdentityMetadataUpdateParams,
 }
 
+/** Most projects that may be deleted by one [`ProjectsDelete`] request */
+pub const PROJECTS_DELETE_MAX: usize = 100;
+
+/**
+ * Parameters for deleting several [`Project`]s at once
+ */
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectsDelete {
+    /** names of the projects to delete */
+    pub names: Vec<Name>,
+}
+
 /*
  * INSTANCES
  */
@@ -0,0 +1,79 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*!
+ * Support for `If-Unmodified-Since` on updates and deletes
+ *
+ * A client that last read a resource at some time can send that time in an
+ * `If-Unmodified-Since` header on a PUT or DELETE.  If the resource has been
+ * modified since then, the request fails with "412 Precondition Failed" and
+ * the resource is left alone, so the client doesn't clobber (or delete) a
+ * version it has never seen.
+ *
+ * The check is made by the datastore as part of the update or delete itself,
+ * so a modification can't slip in between the check and the change.  HTTP
+ * dates only have one-second resolution, while we track modification times
+ * much more precisely, so a resource modified during the same second as the
+ * given time counts as unmodified.
+ *
+ * So far, only Organizations and Projects support this.
+ */
+
+use chrono::DateTime;
+use chrono::Utc;
+use dropshot::RequestContext;
+use http::header;
+
+/**
+ * Parses the value of an `If-Unmodified-Since` header
+ *
+ * Per RFC 7232, a value that isn't a valid HTTP-date is ignored, so this
+ * returns `None` in that case.
+ */
+fn parse_http_date(header_value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(header_value.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/**
+ * Returns the time in the `If-Unmodified-Since` header of the request
+ * described by `rqctx`, if it has a valid one
+ */
+pub async fn if_unmodified_since<C>(
+    rqctx: &RequestContext<C>,
+) -> Option<DateTime<Utc>>
+where
+    C: Send + Sync + 'static,
+{
+    let request = rqctx.request.lock().await;
+    request
+        .headers()
+        .get(header::IF_UNMODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_http_date)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_http_date;
+    use chrono::TimeZone;
+    use chrono::Utc;
+
+    #[test]
+    fn test_parse_http_date() {
+        let expected = Utc.ymd(1994, 11, 6).and_hms(8, 49, 37);
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_http_date(" Sun, 06 Nov 1994 08:49:37 GMT "),
+            Some(expected)
+        );
+        assert_eq!(parse_http_date(""), None);
+        assert_eq!(parse_http_date("yesterday"), None);
+        assert_eq!(parse_http_date("1994-11-06T08:49:37Z"), None);
+    }
+}
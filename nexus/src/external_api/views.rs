@@ -65,6 +65,27 @@ impl Into<Project> for model::Project {
     }
 }
 
+/**
+ * Outcome of deleting one of several [`Project`]s at once
+ */
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectDeleteResult {
+    /** name of the project */
+    pub name: Name,
+    /** why the project could not be deleted (if it wasn't) */
+    pub error: Option<String>,
+}
+
+/**
+ * Number of Instances in a [`Project`]
+ */
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceCount {
+    pub count: u64,
+}
+
 /*
  * VPCs
  */
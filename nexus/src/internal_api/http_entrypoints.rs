@@ -22,6 +22,7 @@ use dropshot::TypedBody;
 use omicron_common::api::internal::nexus::DiskRuntimeState;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use omicron_common::api::internal::nexus::ProducerEndpoint;
+use omicron_common::api::registration::EndpointRegistrar;
 use oximeter::types::ProducerResults;
 use oximeter_producer::{collect, ProducerIdPathParams};
 use schemars::JsonSchema;
@@ -35,7 +36,9 @@ type NexusApiDescription = ApiDescription<Arc<ServerContext>>;
  * Returns a description of the internal nexus API
  */
 pub fn internal_api() -> NexusApiDescription {
-    fn register_endpoints(api: &mut NexusApiDescription) -> Result<(), String> {
+    fn register_endpoints(
+        api: &mut EndpointRegistrar<'_, Arc<ServerContext>>,
+    ) -> Result<(), String> {
         api.register(cpapi_sled_agents_post)?;
         api.register(zpool_put)?;
         api.register(dataset_put)?;
@@ -48,7 +51,8 @@ pub fn internal_api() -> NexusApiDescription {
     }
 
     let mut api = NexusApiDescription::new();
-    if let Err(err) = register_endpoints(&mut api) {
+    if let Err(err) = register_endpoints(&mut EndpointRegistrar::new(&mut api))
+    {
         panic!("failed to register entrypoints: {}", err);
     }
     api
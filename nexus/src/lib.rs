@@ -26,6 +26,7 @@ pub mod internal_api; // public for testing
 mod nexus;
 mod saga_interface;
 mod sagas;
+mod sled_selection;
 
 pub use config::Config;
 pub use context::ServerContext;
@@ -35,6 +36,7 @@ pub use nexus::Nexus;
 pub use nexus::TestInterfaces;
 use slog::Logger;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[macro_use]
@@ -58,6 +60,28 @@ pub fn run_openapi_external() -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+/**
+ * Run the JSON Schema generator for the external API, which emits to stdout a
+ * JSON object mapping each type name to its JSON Schema.
+ *
+ * These are the same schemas that appear under "components/schemas" in the
+ * OpenAPI spec emitted by [`run_openapi_external()`].  References between them
+ * still use the OpenAPI form ("#/components/schemas/...").
+ */
+pub fn run_json_schemas_external() -> Result<(), String> {
+    let spec = external_api()
+        .openapi("Oxide Region API", "0.0.1")
+        .json()
+        .map_err(|e| e.to_string())?;
+    let schemas = spec
+        .get("components")
+        .and_then(|components| components.get("schemas"))
+        .cloned()
+        .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+    serde_json::to_writer_pretty(&mut std::io::stdout(), &schemas)
+        .map_err(|e| e.to_string())
+}
+
 pub fn run_openapi_internal() -> Result<(), String> {
     internal_api()
         .openapi("Nexus internal API", "0.0.1")
@@ -148,6 +172,28 @@ impl Server {
         }
     }
 
+    /**
+     * Gracefully shut down both HTTP servers
+     *
+     * Each server immediately stops accepting new connections and then waits
+     * for requests already in flight to complete.  If the servers have not
+     * finished within `timeout`, this returns an error without waiting any
+     * longer.
+     */
+    pub async fn close(self, timeout: Duration) -> Result<(), String> {
+        let shutdown = async {
+            let (external, internal) = futures::join!(
+                self.http_server_external.close(),
+                self.http_server_internal.close(),
+            );
+            external.map_err(|e| format!("external: {}", e))?;
+            internal.map_err(|e| format!("internal: {}", e))
+        };
+        tokio::time::timeout(timeout, shutdown).await.map_err(|_| {
+            format!("timed out after {:?} waiting for shutdown", timeout)
+        })?
+    }
+
     /**
      * Register the Nexus server as a metric producer with `oximeter.
      */
@@ -17,8 +17,12 @@ use crate::external_api::params;
 use crate::internal_api::params::{OximeterInfo, ZpoolPutRequest};
 use crate::saga_interface::SagaContext;
 use crate::sagas;
+use crate::sled_selection::SledOrder;
+use crate::sled_selection::SledSelector;
 use anyhow::Context;
 use async_trait::async_trait;
+use chrono::DateTime;
+use chrono::Utc;
 use futures::future::ready;
 use futures::StreamExt;
 use hex;
@@ -26,6 +30,7 @@ use ipnetwork::Ipv4Network;
 use ipnetwork::Ipv6Network;
 use lazy_static::lazy_static;
 use omicron_common::api::external;
+use omicron_common::api::external::http_pagination::ModifiedMarker;
 use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::DeleteResult;
@@ -50,6 +55,9 @@ use omicron_common::api::external::UpdateResult;
 use omicron_common::api::external::VpcFirewallRuleUpdateParams;
 use omicron_common::api::external::VpcFirewallRuleUpdateResult;
 use omicron_common::api::external::VpcRouterKind;
+use omicron_common::api::internal::ids::InstanceId;
+use omicron_common::api::internal::ids::ProjectId;
+use omicron_common::api::internal::ids::SledId;
 use omicron_common::api::internal::nexus;
 use omicron_common::api::internal::nexus::DiskRuntimeState;
 use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
@@ -61,8 +69,10 @@ use oximeter_producer::register;
 use rand::{rngs::StdRng, RngCore, SeedableRng};
 use sled_agent_client::Client as SledAgentClient;
 use slog::Logger;
+use std::collections::BTreeSet;
 use std::convert::TryInto;
 use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::sync::Arc;
 use std::time::Duration;
 use steno::SagaId;
@@ -101,6 +111,12 @@ pub trait TestInterfaces {
         &self,
         session: db::model::ConsoleSession,
     ) -> CreateResult<db::model::ConsoleSession>;
+
+    /**
+     * Returns how many Instances count towards the load of sled `sled_id`,
+     * including any that it's reserved for while they're being created.
+     */
+    async fn sled_load(&self, sled_id: &Uuid) -> Result<u64, Error>;
 }
 
 /**
@@ -127,8 +143,34 @@ pub struct Nexus {
 
     /** Task representing completion of recovered Sagas */
     recovery_task: std::sync::Mutex<Option<db::RecoveryTask>>,
+
+    /** how new Instance ids are chosen (random if `None`) */
+    instance_id_generator: std::sync::Mutex<Option<IdGenerator>>,
+
+    /** how new Project ids are chosen (random if `None`) */
+    project_id_generator: std::sync::Mutex<Option<IdGenerator>>,
+
+    /** how sleds are chosen for new Instances */
+    sled_selector: Box<dyn SledSelector>,
+
+    /** how many upcoming calls to `sled_free()` should fail */
+    sled_free_failures: std::sync::Mutex<usize>,
 }
 
+/**
+ * Source of ids for newly-created objects, used in place of random ids
+ */
+pub type IdGenerator = Box<dyn FnMut() -> Uuid + Send>;
+
+/**
+ * How many ids we try for a new Project (when the caller didn't pick one)
+ * before giving up
+ *
+ * With random ids, even one collision is astronomically unlikely.  This limit
+ * only matters when something's wrong with the ids being generated.
+ */
+const PROJECT_CREATE_ID_ATTEMPTS: usize = 3;
+
 /*
  * TODO Is it possible to make some of these operations more generic?  A
  * particularly good example is probably list() (or even lookup()), where
@@ -172,6 +214,10 @@ impl Nexus {
             db_datastore: Arc::clone(&db_datastore),
             sec_client: Arc::clone(&sec_client),
             recovery_task: std::sync::Mutex::new(None),
+            instance_id_generator: std::sync::Mutex::new(None),
+            project_id_generator: std::sync::Mutex::new(None),
+            sled_selector: config.placement.sled_selector.selector(),
+            sled_free_failures: std::sync::Mutex::new(0),
         };
 
         /* TODO-cleanup all the extra Arcs here seems wrong */
@@ -208,6 +254,26 @@ impl Nexus {
         Ok(())
     }
 
+    /**
+     * Adds a sled that isn't already known, failing if one with the same id
+     * is
+     *
+     * Sled agents register through [`Nexus::upsert_sled`] so that they can
+     * re-register after restarting.  This is for adding a sled on an agent's
+     * behalf (e.g., in tests), where reusing an id is a mistake that would
+     * otherwise clobber the existing sled's address.
+     */
+    pub async fn insert_sled(
+        &self,
+        id: Uuid,
+        address: SocketAddr,
+    ) -> Result<(), Error> {
+        info!(self.log, "adding sled"; "sled_uuid" => id.to_string());
+        let sled = db::model::Sled::new(id, address);
+        self.db_datastore.sled_insert(sled).await?;
+        Ok(())
+    }
+
     /// Upserts a Zpool into the database, updating it if it already exists.
     pub async fn upsert_zpool(
         &self,
@@ -458,17 +524,38 @@ impl Nexus {
         self.db_datastore.organizations_list_by_id(opctx, pagparams).await
     }
 
-    pub async fn organization_delete(&self, name: &Name) -> DeleteResult {
-        self.db_datastore.organization_delete(name).await
+    /**
+     * Deletes the named Organization
+     *
+     * If `unmodified_since` is given and the Organization has been modified
+     * since then, this fails with `Error::PreconditionFailed`.
+     */
+    pub async fn organization_delete(
+        &self,
+        name: &Name,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> DeleteResult {
+        self.db_datastore.organization_delete(name, unmodified_since).await
     }
 
+    /**
+     * Updates the named Organization
+     *
+     * If `unmodified_since` is given and the Organization has been modified
+     * since then, this fails with `Error::PreconditionFailed`.
+     */
     pub async fn organization_update(
         &self,
         name: &Name,
         new_params: &params::OrganizationUpdate,
+        unmodified_since: Option<DateTime<Utc>>,
     ) -> UpdateResult<db::model::Organization> {
         self.db_datastore
-            .organization_update(name, new_params.clone().into())
+            .organization_update(
+                name,
+                new_params.clone().into(),
+                unmodified_since,
+            )
             .await
     }
 
@@ -476,6 +563,26 @@ impl Nexus {
      * Projects
      */
 
+    /**
+     * Overrides how ids are chosen for newly-created Projects (when the caller
+     * doesn't choose), or restores the default (random ids) if `generator` is
+     * `None`
+     *
+     * This is intended for tests, particularly of what happens when a
+     * generated id is already in use.
+     */
+    pub fn set_project_id_generator(&self, generator: Option<IdGenerator>) {
+        *self.project_id_generator.lock().unwrap() = generator;
+    }
+
+    /** Chooses the id for a new Project. */
+    fn generate_project_id(&self) -> Uuid {
+        match &mut *self.project_id_generator.lock().unwrap() {
+            Some(generator) => generator(),
+            None => Uuid::new_v4(),
+        }
+    }
+
     pub async fn project_create(
         &self,
         opctx: &OpContext,
@@ -485,10 +592,56 @@ impl Nexus {
         let org =
             self.db_datastore.organization_lookup(organization_name).await?;
 
-        // Create a project.
-        let db_project = db::model::Project::new(org.id(), new_project.clone());
-        let db_project =
-            self.db_datastore.project_create(opctx, &org, db_project).await?;
+        // Create a project.  If the id we use conflicts with an existing
+        // project's (including a deleted one), then: if the caller chose the
+        // id, that's their problem; if we chose it, we try another one, up to
+        // a point.  Otherwise, it's the name that conflicts.
+        let mut attempts = 0;
+        let db_project = loop {
+            let project_id =
+                new_project.id.unwrap_or_else(|| self.generate_project_id());
+            let db_project = db::model::Project::new(
+                org.id(),
+                params::ProjectCreate {
+                    id: Some(project_id),
+                    ..new_project.clone()
+                },
+            );
+            match self
+                .db_datastore
+                .project_create(opctx, &org, db_project)
+                .await
+            {
+                Err(error @ Error::ObjectAlreadyExists { .. }) => {
+                    if !self.db_datastore.project_id_in_use(&project_id).await?
+                    {
+                        return Err(error);
+                    }
+                    if new_project.id.is_some() {
+                        return Err(Error::ObjectAlreadyExists {
+                            type_name: ResourceType::Project,
+                            object_name: project_id.to_string(),
+                        });
+                    }
+
+                    attempts += 1;
+                    warn!(self.log, "generated project id already in use";
+                        "project_id" => project_id.to_string(),
+                        "attempts" => attempts,
+                    );
+                    if attempts == PROJECT_CREATE_ID_ATTEMPTS {
+                        return Err(Error::ServiceUnavailable {
+                            internal_message: format!(
+                                "gave up creating project after {} attempts \
+                                 (every generated id was already in use)",
+                                attempts
+                            ),
+                        });
+                    }
+                }
+                result => break result?,
+            }
+        };
 
         // TODO: We probably want to have "project creation" and "default VPC
         // creation" co-located within a saga for atomicity.
@@ -527,17 +680,25 @@ impl Nexus {
         self.db_datastore.project_fetch(&organization_id, project_name).await
     }
 
+    pub async fn project_fetch_by_id(
+        &self,
+        project_id: &Uuid,
+    ) -> LookupResult<db::model::Project> {
+        self.db_datastore.project_fetch_by_id(project_id).await
+    }
+
     pub async fn projects_list_by_name(
         &self,
         organization_name: &Name,
         pagparams: &DataPageParams<'_, Name>,
+        search: Option<&str>,
     ) -> ListResultVec<db::model::Project> {
         let organization_id = self
             .db_datastore
             .organization_lookup_id_by_name(organization_name)
             .await?;
         self.db_datastore
-            .projects_list_by_name(&organization_id, pagparams)
+            .projects_list_by_name(&organization_id, pagparams, search)
             .await
     }
 
@@ -545,31 +706,103 @@ impl Nexus {
         &self,
         organization_name: &Name,
         pagparams: &DataPageParams<'_, Uuid>,
+        search: Option<&str>,
+    ) -> ListResultVec<db::model::Project> {
+        let organization_id = self
+            .db_datastore
+            .organization_lookup_id_by_name(organization_name)
+            .await?;
+        self.db_datastore
+            .projects_list_by_id(&organization_id, pagparams, search)
+            .await
+    }
+
+    pub async fn projects_list_by_modified(
+        &self,
+        organization_name: &Name,
+        pagparams: &DataPageParams<'_, ModifiedMarker>,
+        search: Option<&str>,
     ) -> ListResultVec<db::model::Project> {
         let organization_id = self
             .db_datastore
             .organization_lookup_id_by_name(organization_name)
             .await?;
-        self.db_datastore.projects_list_by_id(&organization_id, pagparams).await
+        self.db_datastore
+            .projects_list_by_modified(&organization_id, pagparams, search)
+            .await
     }
 
+    /**
+     * Deletes the named Project, which must not have any Instances
+     *
+     * If `unmodified_since` is given and the Project has been modified since
+     * then, this fails with `Error::PreconditionFailed`.
+     */
     pub async fn project_delete(
         &self,
         organization_name: &Name,
         project_name: &Name,
+        unmodified_since: Option<DateTime<Utc>>,
     ) -> DeleteResult {
         let organization_id = self
             .db_datastore
             .organization_lookup_id_by_name(organization_name)
             .await?;
-        self.db_datastore.project_delete(&organization_id, project_name).await
+        self.db_datastore
+            .project_delete(&organization_id, project_name, unmodified_since)
+            .await
+    }
+
+    /**
+     * Deletes each of the named projects in turn, as `project_delete()` would.
+     * Failing to delete one project doesn't stop the rest from being deleted.
+     * The result for each project is returned in the same order as the names.
+     *
+     * Naming more than `params::PROJECTS_DELETE_MAX` projects is an error, and
+     * nothing is deleted in that case.
+     */
+    pub async fn projects_delete(
+        &self,
+        organization_name: &Name,
+        project_names: &[Name],
+    ) -> Result<Vec<DeleteResult>, Error> {
+        if project_names.len() > params::PROJECTS_DELETE_MAX {
+            return Err(Error::InvalidRequest {
+                message: format!(
+                    "cannot delete more than {} projects at once (got {})",
+                    params::PROJECTS_DELETE_MAX,
+                    project_names.len()
+                ),
+            });
+        }
+
+        /* If the organization doesn't exist, there's no point going on. */
+        self.db_datastore
+            .organization_lookup_id_by_name(organization_name)
+            .await?;
+
+        let mut results = Vec::with_capacity(project_names.len());
+        for project_name in project_names {
+            results.push(
+                self.project_delete(organization_name, project_name, None)
+                    .await,
+            );
+        }
+        Ok(results)
     }
 
+    /**
+     * Updates the named Project
+     *
+     * If `unmodified_since` is given and the Project has been modified since
+     * then, this fails with `Error::PreconditionFailed`.
+     */
     pub async fn project_update(
         &self,
         organization_name: &Name,
         project_name: &Name,
         new_params: &params::ProjectUpdate,
+        unmodified_since: Option<DateTime<Utc>>,
     ) -> UpdateResult<db::model::Project> {
         let organization_id = self
             .db_datastore
@@ -580,6 +813,7 @@ impl Nexus {
                 &organization_id,
                 project_name,
                 new_params.clone().into(),
+                unmodified_since,
             )
             .await
     }
@@ -735,25 +969,40 @@ impl Nexus {
      * TODO-design This interface should not exist.  See
      * SagaContext::alloc_server().
      */
-    pub async fn sled_allocate(&self) -> Result<Uuid, Error> {
-        // TODO: replace this with a real allocation policy.
-        //
-        // This implementation always assigns the first sled (by ID order).
-        let pagparams = DataPageParams {
-            marker: None,
-            direction: dropshot::PaginationOrder::Ascending,
-            limit: std::num::NonZeroU32::new(1).unwrap(),
-        };
-        let sleds = self.db_datastore.sled_list(&pagparams).await?;
+    pub async fn sled_allocate(
+        &self,
+        instance_id: &Uuid,
+        avoid_sleds: &BTreeSet<Uuid>,
+    ) -> Result<Uuid, Error> {
+        let reservation = self
+            .db_datastore
+            .sled_allocate(*instance_id, &*self.sled_selector, avoid_sleds)
+            .await?;
+        Ok(reservation.sled_id)
+    }
 
-        sleds
-            .first()
-            .ok_or_else(|| Error::ServiceUnavailable {
-                internal_message: String::from(
-                    "no sleds available for new Instance",
-                ),
-            })
-            .map(|s| s.id())
+    /*
+     * Releases the sled reserved by `sled_allocate()` for `instance_id`.
+     */
+    pub async fn sled_free(&self, instance_id: &Uuid) -> DeleteResult {
+        {
+            let mut failures = self.sled_free_failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Error::unavail("injected sled_free() failure"));
+            }
+        }
+        self.db_datastore.sled_reservation_delete(instance_id).await
+    }
+
+    /**
+     * Makes the next `count` calls to `sled_free()` fail without releasing
+     * anything
+     *
+     * This is intended for tests of how sagas cope with that failure.
+     */
+    pub fn set_sled_free_failures(&self, count: usize) {
+        *self.sled_free_failures.lock().unwrap() = count;
     }
 
     pub async fn project_list_instances(
@@ -773,11 +1022,73 @@ impl Nexus {
         self.db_datastore.project_list_instances(&project_id, pagparams).await
     }
 
+    /**
+     * Returns the number of Instances in a Project, without listing them
+     */
+    pub async fn project_instances_count(
+        &self,
+        organization_name: &Name,
+        project_name: &Name,
+    ) -> Result<u64, Error> {
+        let organization_id = self
+            .db_datastore
+            .organization_lookup_id_by_name(organization_name)
+            .await?;
+        let project_id = self
+            .db_datastore
+            .project_lookup_id_by_name(&organization_id, project_name)
+            .await?;
+        self.db_datastore.project_instances_count(&project_id).await
+    }
+
+    /**
+     * Overrides how ids are chosen for newly-created Instances, or restores
+     * the default (random ids) if `generator` is `None`
+     *
+     * This is intended for tests that need to know ahead of time what ids the
+     * Instances they create will get.
+     */
+    pub fn set_instance_id_generator(&self, generator: Option<IdGenerator>) {
+        *self.instance_id_generator.lock().unwrap() = generator;
+    }
+
+    /** Chooses the id for a new Instance. */
+    pub fn generate_instance_id(&self) -> Uuid {
+        match &mut *self.instance_id_generator.lock().unwrap() {
+            Some(generator) => generator(),
+            None => Uuid::new_v4(),
+        }
+    }
+
     pub async fn project_create_instance(
         self: &Arc<Self>,
         organization_name: &Name,
         project_name: &Name,
         params: &params::InstanceCreate,
+    ) -> CreateResult<db::model::Instance> {
+        self.project_create_instance_avoiding(
+            organization_name,
+            project_name,
+            params,
+            &BTreeSet::new(),
+        )
+        .await
+    }
+
+    /**
+     * Like `project_create_instance()`, but the new Instance will not be
+     * placed on any of the sleds in `avoid_sleds`
+     *
+     * This is intended for spreading out Instances that shouldn't share a
+     * sled (e.g., for availability).  Creation fails if every sled is to be
+     * avoided.
+     */
+    pub async fn project_create_instance_avoiding(
+        self: &Arc<Self>,
+        organization_name: &Name,
+        project_name: &Name,
+        params: &params::InstanceCreate,
+        avoid_sleds: &BTreeSet<Uuid>,
     ) -> CreateResult<db::model::Instance> {
         let organization_id = self
             .db_datastore
@@ -788,9 +1099,31 @@ impl Nexus {
             .project_lookup_id_by_name(&organization_id, project_name)
             .await?;
 
+        /*
+         * Reject hardware that can't run anything before we go allocate a
+         * sled for it.
+         */
+        if params.ncpus.0 == 0 {
+            return Err(Error::InvalidValue {
+                label: String::from("ncpus"),
+                message: String::from("an instance needs at least one CPU"),
+            });
+        }
+        if params.memory.to_bytes() == 0 {
+            return Err(Error::InvalidValue {
+                label: String::from("memory"),
+                message: String::from("an instance needs nonzero memory"),
+            });
+        }
+
         let saga_params = Arc::new(sagas::ParamsInstanceCreate {
-            project_id,
+            project_id: ProjectId::from(project_id),
             create_params: params.clone(),
+            avoid_sleds: avoid_sleds
+                .iter()
+                .copied()
+                .map(SledId::from)
+                .collect(),
         });
 
         let saga_outputs = self
@@ -801,10 +1134,12 @@ impl Nexus {
             )
             .await?;
         /* TODO-error more context would be useful  */
-        let instance_id =
-            saga_outputs.lookup_output::<Uuid>("instance_id").map_err(|e| {
-                Error::InternalError { internal_message: e.to_string() }
-            })?;
+        let instance_id: Uuid = saga_outputs
+            .lookup_output::<InstanceId>("instance_id")
+            .map_err(|e| Error::InternalError {
+                internal_message: e.to_string(),
+            })?
+            .into();
         /*
          * TODO-correctness TODO-robustness TODO-design It's not quite correct
          * to take this instance id and look it up again.  It's possible that
@@ -861,6 +1196,7 @@ impl Nexus {
         organization_name: &Name,
         project_name: &Name,
         instance_name: &Name,
+        force: bool,
     ) -> DeleteResult {
         /*
          * TODO-robustness We need to figure out what to do with Destroyed
@@ -879,7 +1215,19 @@ impl Nexus {
             .db_datastore
             .instance_fetch_by_name(&project_id, instance_name)
             .await?;
-        self.db_datastore.project_delete_instance(&instance.id()).await
+        if force {
+            /*
+             * TODO-robustness A forced delete does not ask the sled agent to
+             * tear down the instance, so whatever resources it holds there are
+             * leaked.  Any state changes it reports later are ignored because
+             * the instance has already been deleted.
+             */
+            self.db_datastore
+                .project_delete_instance_force(&instance.id())
+                .await
+        } else {
+            self.db_datastore.project_delete_instance(&instance.id()).await
+        }
     }
 
     pub async fn project_lookup_instance(
@@ -904,13 +1252,16 @@ impl Nexus {
     fn check_runtime_change_allowed(
         &self,
         runtime: &nexus::InstanceRuntimeState,
+        requested: &InstanceStateRequested,
     ) -> Result<(), Error> {
         /*
          * Users are allowed to request a start or stop even if the instance is
          * already in the desired state (or moving to it), and we will issue a
          * request to the SA to make the state change in these cases in case the
          * runtime state we saw here was stale.  However, users are not allowed
-         * to change the state of an instance that's failed or destroyed.
+         * to change the state of an instance that's failed or destroyed.  An
+         * instance that's being repaired can only be booted, which the SA
+         * handles the same way as booting a stopped instance.
          */
         let allowed = match runtime.run_state {
             InstanceState::Creating => true,
@@ -920,7 +1271,10 @@ impl Nexus {
             InstanceState::Stopped => true,
             InstanceState::Rebooting => true,
 
-            InstanceState::Repairing => false,
+            InstanceState::Repairing => {
+                matches!(requested, InstanceStateRequested::Running)
+            }
+
             InstanceState::Failed => false,
             InstanceState::Destroyed => false,
         };
@@ -1005,7 +1359,10 @@ impl Nexus {
             )
             .await?;
 
-        self.check_runtime_change_allowed(&instance.runtime().clone().into())?;
+        self.check_runtime_change_allowed(
+            &instance.runtime().clone().into(),
+            &InstanceStateRequested::Reboot,
+        )?;
         self.instance_set_runtime(
             &instance,
             self.instance_sled(&instance).await?,
@@ -1034,7 +1391,10 @@ impl Nexus {
             )
             .await?;
 
-        self.check_runtime_change_allowed(&instance.runtime().clone().into())?;
+        self.check_runtime_change_allowed(
+            &instance.runtime().clone().into(),
+            &InstanceStateRequested::Running,
+        )?;
         self.instance_set_runtime(
             &instance,
             self.instance_sled(&instance).await?,
@@ -1063,7 +1423,10 @@ impl Nexus {
             )
             .await?;
 
-        self.check_runtime_change_allowed(&instance.runtime().clone().into())?;
+        self.check_runtime_change_allowed(
+            &instance.runtime().clone().into(),
+            &InstanceStateRequested::Stopped,
+        )?;
         self.instance_set_runtime(
             &instance,
             self.instance_sled(&instance).await?,
@@ -1124,6 +1487,27 @@ impl Nexus {
             .map(|_| ())
     }
 
+    /**
+     * Fetches the serial console output of the instance from the sled agent
+     * where it's running.
+     */
+    pub async fn instance_serial_output(
+        &self,
+        organization_name: &Name,
+        project_name: &Name,
+        instance_name: &Name,
+    ) -> LookupResult<Vec<String>> {
+        let instance = self
+            .project_lookup_instance(
+                organization_name,
+                project_name,
+                instance_name,
+            )
+            .await?;
+        let sa = self.instance_sled(&instance).await?;
+        sa.instance_serial_get(&instance.id()).await.map_err(Error::from)
+    }
+
     /**
      * Lists disks attached to the instance.
      */
@@ -2062,6 +2446,10 @@ impl Nexus {
     /**
      * Invoked by a sled agent to publish an updated runtime state for an
      * Instance.
+     *
+     * Notifications may arrive out of order.  An update whose generation
+     * number is not newer than the one we already have is stale, so it's
+     * dropped (successfully, since there's nothing for the caller to retry).
      */
     pub async fn notify_instance_updated(
         &self,
@@ -2290,6 +2678,22 @@ impl TestInterfaces for Nexus {
     ) -> CreateResult<db::model::ConsoleSession> {
         Ok(self.db_datastore.session_create(session).await?)
     }
+
+    async fn sled_load(&self, sled_id: &Uuid) -> Result<u64, Error> {
+        let candidates = self
+            .db_datastore
+            .sled_candidates(
+                &BTreeSet::new(),
+                SledOrder::ById,
+                NonZeroU32::new(u32::MAX).unwrap(),
+            )
+            .await?;
+        candidates
+            .iter()
+            .find(|candidate| candidate.id == *sled_id)
+            .map(|candidate| candidate.ninstances)
+            .ok_or_else(|| Error::not_found_by_id(ResourceType::Sled, sled_id))
+    }
 }
 
 lazy_static! {
@@ -10,7 +10,10 @@ use crate::db;
 use crate::external_api::params;
 use crate::Nexus;
 use omicron_common::api::external::Error;
+use omicron_common::api::internal::ids::InstanceId;
+use omicron_common::api::internal::ids::SledId;
 use sled_agent_client::Client as SledAgentClient;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::sync::Arc;
 use uuid::Uuid;
@@ -36,21 +39,50 @@ impl SagaContext {
     }
 
     /*
-     * TODO-design This interface should not exist.  Instead, sleds should be
-     * represented in the database.  Reservations will wind up writing to the
-     * database.  Allocating a server will thus be a saga action, complete with
-     * an undo action.  The only thing needed at this layer is a way to read and
-     * write to the database, which we already have.
+     * TODO-design This interface should not exist.  Sleds and their
+     * reservations are represented in the database (see
+     * `DataStore::sled_allocate()` and `DataStore::sled_reservation_create()`),
+     * so allocating a server could be done by saga actions that only need a
+     * way to read and write to the database, which we already have.
      *
      * Note: the parameters appear here (unused) to make sure callers make sure
      * to have them available.  They're not used now, but they will be in a real
      * implementation.
+     *
+     * The chosen sled is reserved for Instance `instance_id` until
+     * `free_server()` is called.  It's never one of `avoid_sleds` (an
+     * anti-affinity hint).  If every sled is to be avoided, this fails.
      */
     pub async fn alloc_server(
         &self,
         _params: &params::InstanceCreate,
-    ) -> Result<Uuid, Error> {
-        self.nexus.sled_allocate().await
+        instance_id: &InstanceId,
+        avoid_sleds: &BTreeSet<SledId>,
+    ) -> Result<SledId, Error> {
+        let avoid_sleds: BTreeSet<Uuid> =
+            avoid_sleds.iter().copied().map(Uuid::from).collect();
+        self.nexus
+            .sled_allocate(&Uuid::from(*instance_id), &avoid_sleds)
+            .await
+            .map(SledId::from)
+    }
+
+    /*
+     * Releases the sled reserved by `alloc_server()` for Instance
+     * `instance_id`, if any.
+     *
+     * TODO-design As with `alloc_server()`, this should not exist.
+     */
+    pub async fn free_server(
+        &self,
+        _params: &params::InstanceCreate,
+        instance_id: &InstanceId,
+    ) -> Result<(), Error> {
+        self.nexus.sled_free(&Uuid::from(*instance_id)).await
+    }
+
+    pub fn generate_instance_id(&self) -> InstanceId {
+        InstanceId::from(self.nexus.generate_instance_id())
     }
 
     pub fn datastore(&self) -> &db::DataStore {
@@ -59,8 +91,8 @@ impl SagaContext {
 
     pub async fn sled_client(
         &self,
-        sled_id: &Uuid,
+        sled_id: &SledId,
     ) -> Result<Arc<SledAgentClient>, Error> {
-        self.nexus.sled_client(sled_id).await
+        self.nexus.sled_client(&sled_id.0).await
     }
 }
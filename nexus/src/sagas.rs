@@ -20,15 +20,20 @@ use chrono::Utc;
 use lazy_static::lazy_static;
 use omicron_common::api::external::Generation;
 use omicron_common::api::external::InstanceState;
+use omicron_common::api::internal::ids::InstanceId;
+use omicron_common::api::internal::ids::ProjectId;
+use omicron_common::api::internal::ids::SledId;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use omicron_common::api::internal::sled_agent::InstanceHardware;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use steno::new_action_noop_undo;
 use steno::ActionContext;
 use steno::ActionError;
+use steno::ActionFunc;
 use steno::SagaTemplate;
 use steno::SagaTemplateBuilder;
 use steno::SagaTemplateGeneric;
@@ -66,8 +71,11 @@ fn all_templates(
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ParamsInstanceCreate {
-    pub project_id: Uuid,
+    pub project_id: ProjectId,
     pub create_params: params::InstanceCreate,
+    /** sleds that the new Instance must not be placed on */
+    #[serde(default)]
+    pub avoid_sleds: BTreeSet<SledId>,
 }
 
 #[derive(Debug)]
@@ -83,7 +91,7 @@ pub fn saga_instance_create() -> SagaTemplate<SagaInstanceCreate> {
     template_builder.append(
         "instance_id",
         "GenerateInstanceId",
-        new_action_noop_undo(sic_generate_uuid),
+        new_action_noop_undo(sic_generate_instance_id),
     );
 
     template_builder.append(
@@ -95,16 +103,24 @@ pub fn saga_instance_create() -> SagaTemplate<SagaInstanceCreate> {
     template_builder.append(
         "server_id",
         "AllocServer",
-        // TODO-robustness This still needs an undo action, and we should really
-        // keep track of resources and reservations, etc.  See the comment on
-        // SagaContext::alloc_server()
-        new_action_noop_undo(sic_alloc_server),
+        // TODO-robustness We should really keep track of resources, etc.
+        // See the comment on SagaContext::alloc_server()
+        ActionFunc::new_action(sic_alloc_server, sic_alloc_server_undo),
     );
 
     template_builder.append(
         "initial_runtime",
         "CreateInstanceRecord",
-        new_action_noop_undo(sic_create_instance_record),
+        ActionFunc::new_action(
+            sic_create_instance_record,
+            sic_delete_instance_record,
+        ),
+    );
+
+    template_builder.append(
+        "server_freed",
+        "FreeServer",
+        new_action_noop_undo(sic_free_server),
     );
 
     template_builder.append(
@@ -122,29 +138,46 @@ async fn sic_generate_uuid(
     Ok(Uuid::new_v4())
 }
 
+async fn sic_generate_instance_id(
+    sagactx: ActionContext<SagaInstanceCreate>,
+) -> Result<InstanceId, ActionError> {
+    Ok(sagactx.user_data().generate_instance_id())
+}
+
 async fn sic_alloc_server(
     sagactx: ActionContext<SagaInstanceCreate>,
-) -> Result<Uuid, ActionError> {
+) -> Result<SledId, ActionError> {
     let osagactx = sagactx.user_data();
     let params = sagactx.saga_params();
+    let instance_id = sagactx.lookup::<InstanceId>("instance_id")?;
     osagactx
-        .alloc_server(&params.create_params)
+        .alloc_server(&params.create_params, &instance_id, &params.avoid_sleds)
         .await
         .map_err(ActionError::action_failed)
 }
 
+async fn sic_alloc_server_undo(
+    sagactx: ActionContext<SagaInstanceCreate>,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params();
+    let instance_id = sagactx.lookup::<InstanceId>("instance_id")?;
+    osagactx.free_server(&params.create_params, &instance_id).await?;
+    Ok(())
+}
+
 async fn sic_create_instance_record(
     sagactx: ActionContext<SagaInstanceCreate>,
 ) -> Result<InstanceHardware, ActionError> {
     let osagactx = sagactx.user_data();
     let params = sagactx.saga_params();
-    let sled_uuid = sagactx.lookup::<Uuid>("server_id");
-    let instance_id = sagactx.lookup::<Uuid>("instance_id");
+    let sled_id = sagactx.lookup::<SledId>("server_id");
+    let instance_id = sagactx.lookup::<InstanceId>("instance_id")?;
     let propolis_uuid = sagactx.lookup::<Uuid>("propolis_id");
 
     let runtime = InstanceRuntimeState {
         run_state: InstanceState::Creating,
-        sled_uuid: sled_uuid?,
+        sled_uuid: sled_id?.into(),
         propolis_uuid: propolis_uuid?,
         hostname: params.create_params.hostname.clone(),
         memory: params.create_params.memory,
@@ -154,17 +187,30 @@ async fn sic_create_instance_record(
     };
 
     let new_instance = db::model::Instance::new(
-        instance_id?,
-        params.project_id,
+        instance_id.into(),
+        params.project_id.into(),
         &params.create_params,
         runtime.into(),
     );
 
-    let instance = osagactx
+    /*
+     * If the record can't be created, release the sled reserved for the
+     * Instance right away rather than leave it to AllocServer's undo action.
+     * Releasing it is idempotent, and that undo action tries again anyway, so
+     * report the original error even if this fails.
+     */
+    let instance = match osagactx
         .datastore()
         .project_create_instance(new_instance)
         .await
-        .map_err(ActionError::action_failed)?;
+    {
+        Ok(instance) => instance,
+        Err(error) => {
+            let _ =
+                osagactx.free_server(&params.create_params, &instance_id).await;
+            return Err(ActionError::action_failed(error));
+        }
+    };
 
     // TODO: Populate this with an appropriate NIC.
     // See also: instance_set_runtime in nexus.rs for a similar construction.
@@ -174,6 +220,59 @@ async fn sic_create_instance_record(
     })
 }
 
+/*
+ * The Instance record now accounts for its sled's load, so the sled no longer
+ * needs to be reserved for it.
+ *
+ * This is its own action, rather than part of CreateInstanceRecord, so that if
+ * it fails, unwinding the saga removes the record as well.  Otherwise the
+ * record would be left behind (since a failed action's own undo action isn't
+ * run), still counting toward the sled's load.
+ */
+async fn sic_free_server(
+    sagactx: ActionContext<SagaInstanceCreate>,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params();
+    let instance_id = sagactx.lookup::<InstanceId>("instance_id")?;
+    osagactx
+        .free_server(&params.create_params, &instance_id)
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn sic_delete_instance_record(
+    sagactx: ActionContext<SagaInstanceCreate>,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let instance_id: Uuid = sagactx.lookup::<InstanceId>("instance_id")?.into();
+    let initial_runtime =
+        sagactx.lookup::<InstanceHardware>("initial_runtime")?.runtime;
+
+    /*
+     * Instances can only be deleted once they're stopped or failed, so mark
+     * the record failed before deleting it.
+     *
+     * TODO-correctness If we're unwinding past this point because
+     * InstanceEnsure failed, it may have done so after the sled agent had
+     * already started the Instance (e.g., if recording the new runtime state
+     * failed), and nothing here tells the sled agent to stop it, so that
+     * Instance would be left running on its sled.
+     */
+    let failed_runtime = InstanceRuntimeState {
+        run_state: InstanceState::Failed,
+        gen: initial_runtime.gen.next(),
+        time_updated: Utc::now(),
+        ..initial_runtime
+    };
+    let datastore = osagactx.datastore();
+    datastore
+        .instance_update_runtime(&instance_id, &failed_runtime.into())
+        .await?;
+    datastore.project_delete_instance(&instance_id).await?;
+    Ok(())
+}
+
 async fn sic_instance_ensure(
     sagactx: ActionContext<SagaInstanceCreate>,
 ) -> Result<(), ActionError> {
@@ -186,12 +285,12 @@ async fn sic_instance_ensure(
             run_state:
                 sled_agent_client::types::InstanceStateRequested::Running,
         };
-    let instance_id = sagactx.lookup::<Uuid>("instance_id")?;
-    let sled_uuid = sagactx.lookup::<Uuid>("server_id")?;
+    let instance_id: Uuid = sagactx.lookup::<InstanceId>("instance_id")?.into();
+    let sled_id = sagactx.lookup::<SledId>("server_id")?;
     let initial_runtime =
         sagactx.lookup::<InstanceHardware>("initial_runtime")?;
     let sa = osagactx
-        .sled_client(&sled_uuid)
+        .sled_client(&sled_id)
         .await
         .map_err(ActionError::action_failed)?;
 
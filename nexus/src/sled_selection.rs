@@ -0,0 +1,160 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*!
+ * Strategies for choosing the sled on which to place a new Instance
+ */
+
+use serde::Deserialize;
+use serde::Serialize;
+use uuid::Uuid;
+
+/**
+ * A sled that could host a new Instance, along with how loaded it is
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SledCandidate {
+    pub id: Uuid,
+    /** number of (undeleted) Instances currently assigned to the sled */
+    pub ninstances: u64,
+}
+
+/**
+ * Order in which a [`SledSelector`] considers candidate sleds
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SledOrder {
+    /** by id */
+    ById,
+    /** by number of Instances, fewest first (then by id) */
+    ByLoad,
+}
+
+/**
+ * How many candidate sleds (the first ones in its preferred order) a
+ * [`SledSelector`] is given to choose among
+ */
+pub const SLED_CANDIDATES_MAX: u32 = 8;
+
+/**
+ * Chooses a sled for a new Instance
+ */
+pub trait SledSelector: Send + Sync {
+    /**
+     * Returns the order in which this selector prefers sleds, so that the
+     * database can sort the candidates and only load the best few
+     */
+    fn order(&self) -> SledOrder;
+
+    /**
+     * Returns the candidate sled that should host a new Instance, or `None` if
+     * there are no suitable sleds
+     *
+     * `candidates` are sorted as [`SledSelector::order()`] says.
+     */
+    fn select<'a>(
+        &self,
+        candidates: &'a [SledCandidate],
+    ) -> Option<&'a SledCandidate>;
+}
+
+/**
+ * Picks the first candidate sled (by id), regardless of load
+ */
+pub struct FirstFit;
+
+impl SledSelector for FirstFit {
+    fn order(&self) -> SledOrder {
+        SledOrder::ById
+    }
+
+    fn select<'a>(
+        &self,
+        candidates: &'a [SledCandidate],
+    ) -> Option<&'a SledCandidate> {
+        candidates.first()
+    }
+}
+
+/**
+ * Picks the candidate sled with the fewest Instances (the first such sled, by
+ * id, if there's a tie)
+ */
+pub struct LeastLoaded;
+
+impl SledSelector for LeastLoaded {
+    fn order(&self) -> SledOrder {
+        SledOrder::ByLoad
+    }
+
+    fn select<'a>(
+        &self,
+        candidates: &'a [SledCandidate],
+    ) -> Option<&'a SledCandidate> {
+        candidates.iter().min_by_key(|c| (c.ninstances, c.id))
+    }
+}
+
+/**
+ * Names a [`SledSelector`], for use in configuration
+ */
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SledSelectorKind {
+    FirstFit,
+    LeastLoaded,
+}
+
+impl SledSelectorKind {
+    pub fn selector(&self) -> Box<dyn SledSelector> {
+        match self {
+            SledSelectorKind::FirstFit => Box::new(FirstFit),
+            SledSelectorKind::LeastLoaded => Box::new(LeastLoaded),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FirstFit;
+    use super::LeastLoaded;
+    use super::SledCandidate;
+    use super::SledSelector;
+    use uuid::Uuid;
+
+    fn candidates(loads: &[u64]) -> Vec<SledCandidate> {
+        loads
+            .iter()
+            .enumerate()
+            .map(|(i, &ninstances)| SledCandidate {
+                id: Uuid::from_u128(i as u128 + 1),
+                ninstances,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_first_fit() {
+        assert_eq!(FirstFit.select(&[]), None);
+        assert_eq!(
+            FirstFit.select(&candidates(&[5, 0, 3])).map(|c| c.id),
+            Some(Uuid::from_u128(1))
+        );
+    }
+
+    #[test]
+    fn test_least_loaded() {
+        assert_eq!(LeastLoaded.select(&[]), None);
+        assert_eq!(
+            LeastLoaded.select(&candidates(&[5, 0, 3])).map(|c| c.id),
+            Some(Uuid::from_u128(2))
+        );
+
+        /* Ties go to the first sled by id. */
+        assert_eq!(
+            LeastLoaded.select(&candidates(&[4, 2, 2])).map(|c| c.id),
+            Some(Uuid::from_u128(2))
+        );
+    }
+}
@@ -10,6 +10,7 @@ use anyhow::Context;
 use dropshot::test_util::ClientTestContext;
 use std::convert::TryInto;
 use std::fmt::Debug;
+use std::time::Duration;
 
 /// Convenient way to make an outgoing HTTP request and verify various
 /// properties of the response for testing
@@ -41,6 +42,8 @@ use std::fmt::Debug;
 // - an HTTP request body (optional)
 // - an expected status code (optional)
 // - an expected response body type (optional)
+// - how long to keep retrying when the server says to try again later
+//   (optional)
 //
 // On top of all this, we want failures to be easy to debug.  For now, that
 // means we always log the outgoing request and incoming response.  With a bit
@@ -54,8 +57,11 @@ pub struct RequestBuilder<'a> {
     method: http::Method,
     uri: http::Uri,
     headers: http::HeaderMap<http::header::HeaderValue>,
-    body: hyper::Body,
+    // kept as bytes, rather than a hyper::Body, so that it can be re-sent
+    body: bytes::Bytes,
     error: Option<anyhow::Error>,
+    // total time we're willing to spend waiting as advised by Retry-After
+    retry_budget: Option<Duration>,
 
     expected_status: Option<http::StatusCode>,
     allowed_headers: Option<Vec<http::header::HeaderName>>,
@@ -76,14 +82,17 @@ impl<'a> RequestBuilder<'a> {
             method,
             uri,
             headers: http::HeaderMap::new(),
-            body: hyper::Body::empty(),
+            body: bytes::Bytes::new(),
+            retry_budget: None,
             expected_status: None,
             allowed_headers: Some(vec![
                 http::header::CACHE_CONTROL,
                 http::header::CONTENT_LENGTH,
                 http::header::CONTENT_TYPE,
                 http::header::DATE,
+                http::header::ETAG,
                 http::header::LOCATION,
+                http::header::RETRY_AFTER,
                 http::header::SET_COOKIE,
                 http::header::HeaderName::from_static("x-request-id"),
             ]),
@@ -123,8 +132,8 @@ impl<'a> RequestBuilder<'a> {
         });
         match new_body {
             Some(Err(error)) => self.error = Some(error),
-            Some(Ok(new_body)) => self.body = hyper::Body::from(new_body),
-            None => self.body = hyper::Body::empty(),
+            Some(Ok(new_body)) => self.body = bytes::Bytes::from(new_body),
+            None => self.body = bytes::Bytes::new(),
         };
         self
     }
@@ -142,6 +151,18 @@ impl<'a> RequestBuilder<'a> {
         self
     }
 
+    /// Retry the request if the server responds with "429 Too Many Requests"
+    /// or "503 Service Unavailable" and a `Retry-After` header, waiting as
+    /// long as the header says to
+    ///
+    /// We give up waiting once the next wait would take the total time spent
+    /// waiting past `max_wait`.  In that case, [`execute()`] checks the last
+    /// response received as usual.
+    pub fn retry_after(mut self, max_wait: Duration) -> Self {
+        self.retry_budget = Some(max_wait);
+        self
+    }
+
     /// Record a list of header names allowed in the response
     ///
     /// If this function is used, then [`execute()`] will check each header in
@@ -194,28 +215,46 @@ impl<'a> RequestBuilder<'a> {
             return Err(error);
         }
 
-        let mut builder =
-            http::Request::builder().method(self.method).uri(self.uri);
-        for (header_name, header_value) in &self.headers {
-            builder = builder.header(header_name, header_value);
-        }
-        let request =
-            builder.body(self.body).context("failed to construct request")?;
-
-        let time_before = chrono::offset::Utc::now().timestamp();
-        slog::info!(self.testctx.client_log,
-            "client request";
-            "method" => %request.method(),
-            "uri" => %request.uri(),
-            "body" => ?&request.body(),
-        );
+        let mut retry_budget = self.retry_budget.unwrap_or(Duration::ZERO);
+        let (time_before, mut response) = loop {
+            let mut builder = http::Request::builder()
+                .method(self.method.clone())
+                .uri(self.uri.clone());
+            for (header_name, header_value) in &self.headers {
+                builder = builder.header(header_name, header_value);
+            }
+            let request = builder
+                .body(hyper::Body::from(self.body.clone()))
+                .context("failed to construct request")?;
+
+            let time_before = chrono::offset::Utc::now().timestamp();
+            slog::info!(self.testctx.client_log,
+                "client request";
+                "method" => %request.method(),
+                "uri" => %request.uri(),
+                "body" => ?&request.body(),
+            );
 
-        let mut response = self
-            .testctx
-            .client
-            .request(request)
-            .await
-            .context("making request to server")?;
+            let response = self
+                .testctx
+                .client
+                .request(request)
+                .await
+                .context("making request to server")?;
+
+            match retry_after_delay(&response) {
+                Some(delay) if delay <= retry_budget => {
+                    slog::info!(self.testctx.client_log,
+                        "server asked us to retry";
+                        "status" => ?response.status(),
+                        "delay" => ?delay,
+                    );
+                    retry_budget -= delay;
+                    tokio::time::sleep(delay).await;
+                }
+                _ => break (time_before, response),
+            }
+        };
 
         // Check that we got the expected response code.
         let status = response.status();
@@ -350,6 +389,29 @@ impl<'a> RequestBuilder<'a> {
     }
 }
 
+/// Returns how long the server said to wait before retrying, if `response` is
+/// a "429 Too Many Requests" or "503 Service Unavailable" with a valid
+/// `Retry-After` header
+fn retry_after_delay(
+    response: &http::Response<hyper::Body>,
+) -> Option<Duration> {
+    if response.status() != http::StatusCode::TOO_MANY_REQUESTS
+        && response.status() != http::StatusCode::SERVICE_UNAVAILABLE
+    {
+        return None;
+    }
+
+    // The header holds either a number of seconds or an HTTP date.
+    let value = response.headers().get(http::header::RETRY_AFTER)?;
+    let value = value.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::offset::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
 fn parse_header_pair<K, V, KE, VE>(
     name: K,
     value: V,
@@ -517,6 +579,86 @@ pub mod dropshot_compat {
             .unwrap()
     }
 
+    /// Default limit on the number of pages fetched by [`iter_collection`]
+    pub const ITER_COLLECTION_MAX_PAGES: usize = 1000;
+
+    /// See [`dropshot::test_util::iter_collection`].
+    ///
+    /// Unlike that function, this panics after fetching
+    /// [`ITER_COLLECTION_MAX_PAGES`] pages, or as soon as the server hands back
+    /// the same page token twice in a row.  If the server ever handed back a
+    /// page token that didn't advance through the collection, the test fails
+    /// instead of paging forever.
+    pub async fn iter_collection<T>(
+        testctx: &ClientTestContext,
+        collection_url: &str,
+        initial_params: &str,
+        limit: usize,
+    ) -> (Vec<T>, usize)
+    where
+        T: Clone + DeserializeOwned,
+    {
+        iter_collection_max_pages(
+            testctx,
+            collection_url,
+            initial_params,
+            limit,
+            ITER_COLLECTION_MAX_PAGES,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Like [`iter_collection`], but with a caller-provided limit on the
+    /// number of pages, and returning an error when that limit is reached
+    pub async fn iter_collection_max_pages<T>(
+        testctx: &ClientTestContext,
+        collection_url: &str,
+        initial_params: &str,
+        limit: usize,
+        max_pages: usize,
+    ) -> Result<(Vec<T>, usize), anyhow::Error>
+    where
+        T: Clone + DeserializeOwned,
+    {
+        let mut page = objects_list_page::<T>(
+            testctx,
+            &format!("{}?limit={}&{}", collection_url, limit, initial_params),
+        )
+        .await;
+        assert!(page.items.len() <= limit);
+        let mut rv = page.items.clone();
+        let mut npages = 1;
+        let mut last_token = None;
+        while let Some(token) = page.next_page {
+            anyhow::ensure!(
+                last_token.as_ref() != Some(&token),
+                "listing \"{}\": page token {:?} returned twice in a row",
+                collection_url,
+                token
+            );
+            anyhow::ensure!(
+                npages < max_pages,
+                "listing \"{}\": gave up after {} pages",
+                collection_url,
+                npages
+            );
+            page = objects_list_page::<T>(
+                testctx,
+                &format!(
+                    "{}?limit={}&page_token={}",
+                    collection_url, limit, token
+                ),
+            )
+            .await;
+            assert!(page.items.len() <= limit);
+            rv.extend_from_slice(&page.items);
+            npages += 1;
+            last_token = Some(token);
+        }
+        Ok((rv, npages))
+    }
+
     /// See [`dropshot::test_util::objects_post`].
     pub async fn objects_post<S, T>(
         testctx: &ClientTestContext,
@@ -13,6 +13,8 @@ use dropshot::ConfigLogging;
 use dropshot::ConfigLoggingLevel;
 use omicron_common::api::external::IdentityMetadata;
 use omicron_common::api::internal::nexus::ProducerEndpoint;
+use omicron_sled_agent::common::nexus::NEXUS_TIMEOUT_MS_DEFAULT;
+use omicron_sled_agent::sim::SimMode;
 use omicron_test_utils::dev;
 use oximeter_collector::Oximeter;
 use oximeter_producer::Server as ProducerServer;
@@ -45,11 +47,10 @@ pub struct ControlPlaneTestContext {
 
 impl ControlPlaneTestContext {
     pub async fn teardown(mut self) {
-        self.server.http_server_external.close().await.unwrap();
-        self.server.http_server_internal.close().await.unwrap();
+        self.server.close(Duration::from_secs(30)).await.unwrap();
         self.database.cleanup().await.unwrap();
         self.clickhouse.cleanup().await.unwrap();
-        self.sled_agent.http_server.close().await.unwrap();
+        self.sled_agent.close().await.unwrap();
         self.oximeter.close().await.unwrap();
         self.producer.close().await.unwrap();
         self.logctx.cleanup_successful();
@@ -86,9 +87,43 @@ pub async fn test_setup(test_name: &str) -> ControlPlaneTestContext {
     test_setup_with_config(test_name, &mut config).await
 }
 
+/**
+ * Like [`test_setup()`], but the external API accepts request bodies of up to
+ * `max_bytes` rather than the dropshot default (1KiB).
+ */
+pub async fn test_setup_with_request_body_max_bytes(
+    test_name: &str,
+    max_bytes: usize,
+) -> ControlPlaneTestContext {
+    let mut config = load_test_config();
+    config.dropshot_external.request_body_max_bytes = max_bytes;
+    test_setup_with_config(test_name, &mut config).await
+}
+
+/**
+ * Like [`test_setup()`], but the sled agent simulates Instance and Disk
+ * transitions in the given mode rather than `SimMode::Explicit`.
+ */
+pub async fn test_setup_with_sim_mode(
+    test_name: &str,
+    sim_mode: SimMode,
+) -> ControlPlaneTestContext {
+    let mut config = load_test_config();
+    test_setup_with_config_and_sim_mode(test_name, &mut config, sim_mode).await
+}
+
 pub async fn test_setup_with_config(
     test_name: &str,
     config: &mut omicron_nexus::Config,
+) -> ControlPlaneTestContext {
+    test_setup_with_config_and_sim_mode(test_name, config, SimMode::Explicit)
+        .await
+}
+
+async fn test_setup_with_config_and_sim_mode(
+    test_name: &str,
+    config: &mut omicron_nexus::Config,
+    sim_mode: SimMode,
 ) -> ControlPlaneTestContext {
     let logctx = LogContext::new(test_name, &config.log);
     let rack_id = Uuid::parse_str(RACK_UUID).unwrap();
@@ -115,13 +150,14 @@ pub async fn test_setup_with_config(
 
     /* Set up a single sled agent. */
     let sa_id = Uuid::parse_str(SLED_AGENT_UUID).unwrap();
-    let sa = start_sled_agent(
+    let sa = start_sled_agent_with_sim_mode(
         logctx.log.new(o!(
             "component" => "omicron_sled_agent::sim::Server",
             "sled_id" => sa_id.to_string(),
         )),
         server.http_server_internal.local_addr(),
         sa_id,
+        sim_mode,
     )
     .await
     .unwrap();
@@ -162,17 +198,33 @@ pub async fn start_sled_agent(
     log: Logger,
     nexus_address: SocketAddr,
     id: Uuid,
+) -> Result<omicron_sled_agent::sim::Server, String> {
+    start_sled_agent_with_sim_mode(log, nexus_address, id, SimMode::Explicit)
+        .await
+}
+
+pub async fn start_sled_agent_with_sim_mode(
+    log: Logger,
+    nexus_address: SocketAddr,
+    id: Uuid,
+    sim_mode: SimMode,
 ) -> Result<omicron_sled_agent::sim::Server, String> {
     let config = omicron_sled_agent::sim::Config {
         id,
-        sim_mode: omicron_sled_agent::sim::SimMode::Explicit,
+        sim_mode,
+        sim_queue: omicron_sled_agent::sim::SimQueue::Coalescing,
+        boot_failure: None,
         nexus_address,
+        nexus_timeout_ms: NEXUS_TIMEOUT_MS_DEFAULT,
         dropshot: ConfigDropshot {
             bind_address: SocketAddr::new("127.0.0.1".parse().unwrap(), 0),
             ..Default::default()
         },
         /* TODO-cleanup this is unused */
         log: ConfigLogging::StderrTerminal { level: ConfigLoggingLevel::Debug },
+        watchdog: None,
+        capacity: None,
+        destroy_grace_ms: 0,
     };
 
     omicron_sled_agent::sim::Server::start(&config, &log).await
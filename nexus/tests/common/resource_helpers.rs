@@ -62,6 +62,7 @@ pub async fn create_project(
                 name: project_name.parse().unwrap(),
                 description: "a pier".to_string(),
             },
+            id: None,
         },
     )
     .authn_as(AuthnMode::PrivilegedUser)
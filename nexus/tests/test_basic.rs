@@ -9,25 +9,41 @@
  * TODO-coverage add test for racks, sleds
  */
 
-use dropshot::test_util::iter_collection;
+use dropshot::endpoint;
 use dropshot::test_util::object_get;
 use dropshot::test_util::objects_list_page;
 use dropshot::test_util::read_json;
 use dropshot::test_util::ClientTestContext;
+use dropshot::test_util::LogContext;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
 use dropshot::HttpErrorResponseBody;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
 use http::method::Method;
 use http::StatusCode;
 use omicron_common::api::external::IdentityMetadataCreateParams;
 use omicron_common::api::external::IdentityMetadataUpdateParams;
 use omicron_common::api::external::Name;
+use omicron_nexus::authn::external::spoof::HTTP_HEADER_OXIDE_AUTHN_SPOOF;
+use omicron_nexus::authn::TEST_USER_UUID_PRIVILEGED;
 use omicron_nexus::external_api::{
     params,
     views::{Project, Sled},
 };
+use schemars::JsonSchema;
 use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 use uuid::Uuid;
 
 pub mod common;
+use common::http_testing::dropshot_compat::iter_collection;
+use common::http_testing::dropshot_compat::iter_collection_max_pages;
+use common::http_testing::dropshot_compat::ITER_COLLECTION_MAX_PAGES;
 use common::http_testing::AuthnMode;
 use common::http_testing::NexusRequest;
 use common::http_testing::RequestBuilder;
@@ -35,6 +51,7 @@ use common::resource_helpers::create_organization;
 use common::resource_helpers::create_project;
 use common::start_sled_agent;
 use common::test_setup;
+use common::ControlPlaneTestContext;
 
 #[macro_use]
 extern crate slog;
@@ -76,8 +93,8 @@ async fn test_basic_failures() {
 
     /*
      * Error case: GET /organizations/test-org/projects/-invalid-name
-     * TODO-correctness is 400 the right error code here or is 404 more
-     * appropriate?
+     * A syntactically invalid name is a 400, not a 404, and the message says
+     * so.  See `test_invalid_names_in_path` for more cases.
      */
     let error = client
         .make_request(
@@ -89,8 +106,8 @@ async fn test_basic_failures() {
         .await
         .expect_err("expected error");
     assert_eq!(
-        "bad parameter in URL path: name must begin with an ASCII lowercase \
-         character",
+        "bad parameter in URL path: invalid name: must begin with an ASCII \
+         lowercase character",
         error.message
     );
 
@@ -153,8 +170,8 @@ async fn test_basic_failures() {
         .await
         .expect_err("expected error");
     assert_eq!(
-        "bad parameter in URL path: name contains invalid character: \"_\" \
-         (allowed characters are lowercase ASCII, digits, and \"-\")",
+        "bad parameter in URL path: invalid name: contains \"_\" (allowed \
+         characters are lowercase ASCII, digits, and \"-\")",
         error.message
     );
 
@@ -169,14 +186,104 @@ async fn test_basic_failures() {
         .await
         .expect_err("expected error");
     assert_eq!(
-        "bad parameter in URL path: name contains invalid character: \"_\" \
-         (allowed characters are lowercase ASCII, digits, and \"-\")",
+        "bad parameter in URL path: invalid name: contains \"_\" (allowed \
+         characters are lowercase ASCII, digits, and \"-\")",
+        error.message
+    );
+
+    /* Error case: fetch a sled using a path segment that's not a UUID. */
+    let error = client
+        .make_request(
+            Method::GET,
+            "/hardware/sleds/not-a-uuid",
+            None as Option<()>,
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .expect_err("expected error");
+    assert!(
+        error.message.starts_with("bad parameter in URL path: "),
+        "unexpected error message: {}",
         error.message
     );
 
     testctx.teardown().await;
 }
 
+/*
+ * Invalid names in URL paths are rejected with a 400 whose message says the
+ * name is invalid, no matter where in the path they appear, while valid names
+ * that don't match anything get a 404.
+ */
+#[tokio::test]
+async fn test_invalid_names_in_path() {
+    let testctx = test_setup("test_invalid_names_in_path").await;
+    let client = &testctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+
+    let long_name = format!("a{}", "b".repeat(63));
+    let invalid_names = vec![
+        ("-leading-dash", "must begin with an ASCII lowercase character"),
+        ("123abc", "must begin with an ASCII lowercase character"),
+        ("Uppercase", "must begin with an ASCII lowercase character"),
+        (
+            "under_score",
+            "contains \"_\" (allowed characters are lowercase ASCII, \
+             digits, and \"-\")",
+        ),
+        (
+            "camelCase",
+            "contains \"C\" (allowed characters are lowercase ASCII, \
+             digits, and \"-\")",
+        ),
+        ("trailing-", "cannot end with \"-\""),
+        (&long_name, "may contain at most 63 characters"),
+    ];
+    let url_templates = vec![
+        "/organizations/{}",
+        "/organizations/test-org/projects/{}",
+        "/organizations/test-org/projects/{}/instances",
+        "/organizations/test-org/projects/nonexistent/instances/{}",
+    ];
+
+    for template in &url_templates {
+        for (name, reason) in &invalid_names {
+            let url = template.replace("{}", name);
+            let error = client
+                .make_request(
+                    Method::GET,
+                    &url,
+                    None as Option<()>,
+                    StatusCode::BAD_REQUEST,
+                )
+                .await
+                .expect_err("expected error");
+            assert_eq!(
+                format!("bad parameter in URL path: invalid name: {}", reason),
+                error.message,
+                "GET {}",
+                url
+            );
+        }
+    }
+
+    /* A valid name that doesn't match anything is a different error. */
+    let error = client
+        .make_request(
+            Method::GET,
+            "/organizations/test-org/projects/valid-name",
+            None as Option<()>,
+            StatusCode::NOT_FOUND,
+        )
+        .await
+        .expect_err("expected error");
+    assert_eq!("not found: project with name \"valid-name\"", error.message);
+
+    testctx.teardown().await;
+}
+
 #[tokio::test]
 async fn test_projects_basic() {
     let testctx = test_setup("test_projects").await;
@@ -209,6 +316,7 @@ async fn test_projects_basic() {
                             "<auto-generated by test suite>",
                         ),
                     },
+                    id: None,
                 },
             )
             .authn_as(AuthnMode::PrivilegedUser)
@@ -418,6 +526,7 @@ async fn test_projects_basic() {
             name: "simproject1".parse().unwrap(),
             description: "a duplicate of simproject1".to_string(),
         },
+        id: None,
     };
     let error = NexusRequest::new(
         RequestBuilder::new(client, Method::POST, &projects_url)
@@ -456,8 +565,8 @@ async fn test_projects_basic() {
     .parsed_body::<HttpErrorResponseBody>()
     .unwrap();
     assert!(error.message.starts_with(
-        "unable to parse body: name contains invalid character: \"_\" \
-         (allowed characters are lowercase ASCII, digits, and \"-\""
+        "unable to parse body: invalid name: contains \"_\" (allowed \
+         characters are lowercase ASCII, digits, and \"-\""
     ));
 
     /*
@@ -468,6 +577,7 @@ async fn test_projects_basic() {
             name: "honor-roller".parse().unwrap(),
             description: "a soapbox racer".to_string(),
         },
+        id: None,
     };
     let project: Project =
         NexusRequest::objects_post(client, projects_url, &project_create)
@@ -641,6 +751,256 @@ async fn test_projects_list() {
     testctx.teardown().await;
 }
 
+#[tokio::test]
+async fn test_projects_list_by_modified() {
+    let testctx = test_setup("test_projects_list_by_modified").await;
+    let client = &testctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = "/organizations/test-org/projects";
+    for i in 0..5 {
+        create_project(&client, org_name, &format!("project{}", i)).await;
+    }
+
+    /*
+     * Update a couple of the projects, which should move them to the end of
+     * the list in order of time modified.
+     */
+    for name in &["project1", "project3"] {
+        client
+            .make_request(
+                Method::PUT,
+                &format!("{}/{}", projects_url, name),
+                Some(params::ProjectUpdate {
+                    identity: IdentityMetadataUpdateParams {
+                        name: None,
+                        description: Some(String::from("updated")),
+                    },
+                }),
+                StatusCode::OK,
+            )
+            .await
+            .expect("failed to update project");
+    }
+
+    /*
+     * Page through the projects in both directions, using a small page size so
+     * that we resume from a marker along the way.
+     */
+    let expected_names =
+        vec!["project0", "project2", "project4", "project1", "project3"];
+    let found_projects = iter_collection::<Project>(
+        &client,
+        projects_url,
+        "sort_by=modified-ascending",
+        2,
+    )
+    .await
+    .0;
+    assert_eq!(
+        expected_names,
+        found_projects
+            .iter()
+            .map(|p| p.identity.name.as_str())
+            .collect::<Vec<&str>>()
+    );
+    assert!(found_projects.windows(2).all(|pair| {
+        pair[0].identity.time_modified <= pair[1].identity.time_modified
+    }));
+
+    let mut found_projects = iter_collection::<Project>(
+        &client,
+        projects_url,
+        "sort_by=modified-descending",
+        2,
+    )
+    .await
+    .0;
+    found_projects.reverse();
+    assert_eq!(
+        expected_names,
+        found_projects
+            .iter()
+            .map(|p| p.identity.name.as_str())
+            .collect::<Vec<&str>>()
+    );
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_search() {
+    let testctx = test_setup("test_projects_search").await;
+    let client = &testctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = "/organizations/test-org/projects";
+    let projects = vec![
+        ("web-frontend", "serves the Website"),
+        ("web-backend", "API server"),
+        ("database", "Postgres for the WEB tier"),
+        ("batch", "nightly jobs"),
+        ("uptime", "aiming for 100% availability"),
+    ];
+    for (name, description) in &projects {
+        let project_create = params::ProjectCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: description.to_string(),
+            },
+            id: None,
+        };
+        NexusRequest::objects_post(client, projects_url, &project_create)
+            .authn_as(AuthnMode::PrivilegedUser)
+            .execute()
+            .await
+            .expect("failed to make request");
+    }
+
+    /*
+     * The search term may match the name or the description, ignoring case.
+     * It's carried along from page to page, so use a small page size to make
+     * sure that it's still applied after the first page.
+     */
+    let search = |query: &'static str| async move {
+        iter_collection::<Project>(&client, projects_url, query, 1)
+            .await
+            .0
+            .into_iter()
+            .map(|p| p.identity.name.to_string())
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(
+        search("search=web").await,
+        vec!["database", "web-backend", "web-frontend"]
+    );
+    assert_eq!(search("search=WEBSITE").await, vec!["web-frontend"]);
+    assert_eq!(
+        search("sort_by=name-descending&search=web").await,
+        vec!["web-frontend", "web-backend", "database"]
+    );
+    assert_eq!(search("sort_by=id-ascending&search=jobs").await, vec!["batch"]);
+    assert_eq!(
+        search("sort_by=modified-ascending&search=end").await,
+        vec!["web-frontend", "web-backend"]
+    );
+
+    /* The search term is matched literally, not as a pattern. */
+    assert_eq!(search("search=100%25").await, vec!["uptime"]);
+    assert!(search("search=_").await.is_empty());
+    assert!(search("search=nothing-matches").await.is_empty());
+
+    /* Without a search term, everything is listed. */
+    assert_eq!(search("").await.len(), projects.len());
+
+    testctx.teardown().await;
+}
+
+/*
+ * Paging through a collection gives up once it has fetched too many pages,
+ * which is what would happen if the server's page tokens didn't advance.
+ */
+#[tokio::test]
+async fn test_iter_collection_max_pages() {
+    let testctx = test_setup("test_iter_collection_max_pages").await;
+    let client = &testctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = "/organizations/test-org/projects";
+    for i in 0..5 {
+        create_project(&client, org_name, &format!("project{}", i)).await;
+    }
+
+    let (found, npages) = iter_collection_max_pages::<Project>(
+        &client,
+        projects_url,
+        "",
+        1,
+        ITER_COLLECTION_MAX_PAGES,
+    )
+    .await
+    .unwrap();
+    assert_eq!(found.len(), 5);
+    assert!(npages >= 5);
+
+    let error = iter_collection_max_pages::<Project>(
+        &client,
+        projects_url,
+        "",
+        1,
+        npages - 1,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "listing \"{}\": gave up after {} pages",
+            projects_url,
+            npages - 1
+        )
+    );
+
+    testctx.teardown().await;
+}
+
+/*
+ * A server that keeps handing back the same page token is caught as soon as
+ * the token repeats, without waiting for the page limit.  Nexus doesn't do
+ * this, so this uses a separate server whose one endpoint always does.
+ */
+#[tokio::test]
+async fn test_iter_collection_repeated_page_token() {
+    let config = common::load_test_config();
+    let logctx = LogContext::new(
+        "test_iter_collection_repeated_page_token",
+        &config.log,
+    );
+    let mut api = ApiDescription::new();
+    api.register(stuck_list).unwrap();
+    let log = logctx.log.new(o!());
+    let testctx =
+        TestContext::new(api, (), &config.dropshot_external, Some(logctx), log);
+
+    let error = iter_collection_max_pages::<u32>(
+        &testctx.client_testctx,
+        "/stuck",
+        "",
+        1,
+        ITER_COLLECTION_MAX_PAGES,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "listing \"/stuck\": page token \"stuck\" returned twice in a row"
+    );
+
+    testctx.teardown().await;
+}
+
+#[derive(Serialize, JsonSchema)]
+struct StuckPage {
+    items: Vec<u32>,
+    next_page: Option<String>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/stuck",
+}]
+async fn stuck_list(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<StuckPage>, HttpError> {
+    Ok(HttpResponseOk(StuckPage {
+        items: vec![0],
+        next_page: Some("stuck".to_string()),
+    }))
+}
+
 #[tokio::test]
 async fn test_sleds_list() {
     let testctx = test_setup("test_sleds_list").await;
@@ -678,6 +1038,85 @@ async fn test_sleds_list() {
     testctx.teardown().await;
 }
 
+#[tokio::test]
+async fn test_server_close_drains_requests() {
+    let testctx = test_setup("test_server_close_drains_requests").await;
+    let addr = testctx.server.http_server_external.local_addr();
+
+    /*
+     * Start a request, but hold back its body so that it's still in flight
+     * when the server starts closing.  With "Expect: 100-continue", the server
+     * tells us when the handler starts reading the body, so we know the
+     * request has been accepted and dispatched before we close the server.
+     */
+    let body = serde_json::to_vec(&params::OrganizationCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "slow-org".parse().unwrap(),
+            description: "created while closing".to_string(),
+        },
+    })
+    .unwrap();
+    let head = format!(
+        "POST /organizations HTTP/1.1\r\n\
+        host: {}\r\n\
+        {}: {}\r\n\
+        content-type: application/json\r\n\
+        content-length: {}\r\n\
+        expect: 100-continue\r\n\r\n",
+        addr,
+        HTTP_HEADER_OXIDE_AUTHN_SPOOF,
+        TEST_USER_UUID_PRIVILEGED,
+        body.len()
+    );
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let expected = b"HTTP/1.1 100 Continue\r\n\r\n";
+    let mut interim = vec![0; expected.len()];
+    stream.read_exact(&mut interim).await.unwrap();
+    assert_eq!(interim, expected);
+
+    let ControlPlaneTestContext {
+        server,
+        database,
+        clickhouse,
+        logctx,
+        sled_agent,
+        oximeter,
+        producer,
+        ..
+    } = testctx;
+    let close = tokio::spawn(server.close(Duration::from_secs(30)));
+
+    /* New connections are refused as soon as the server starts closing. */
+    let refused = async {
+        while TcpStream::connect(addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), refused)
+        .await
+        .expect("server still accepting connections while closing");
+
+    /* The request in flight still completes. */
+    stream.write_all(&body).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 201 Created\r\n"),
+        "unexpected response: {}",
+        response
+    );
+    close.await.unwrap().unwrap();
+
+    database.cleanup().await.unwrap();
+    clickhouse.cleanup().await.unwrap();
+    sled_agent.close().await.unwrap();
+    oximeter.close().await.unwrap();
+    producer.close().await.unwrap();
+    logctx.cleanup_successful();
+}
+
 async fn projects_list(
     client: &ClientTestContext,
     projects_url: &str,
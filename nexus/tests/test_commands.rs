@@ -13,6 +13,9 @@
  */
 
 use expectorate::assert_contents;
+use jsonschema::JSONSchema;
+use omicron_test_utils::dev::openapi::diff_operations;
+use omicron_test_utils::dev::openapi::OperationChange;
 use omicron_test_utils::dev::test_cmds::assert_exit_code;
 use omicron_test_utils::dev::test_cmds::error_for_enoent;
 use omicron_test_utils::dev::test_cmds::path_to_executable;
@@ -22,6 +25,7 @@ use omicron_test_utils::dev::test_cmds::EXIT_FAILURE;
 use omicron_test_utils::dev::test_cmds::EXIT_SUCCESS;
 use omicron_test_utils::dev::test_cmds::EXIT_USAGE;
 use openapiv3::OpenAPI;
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::PathBuf;
 use subprocess::Exec;
@@ -44,6 +48,153 @@ fn write_config(config: &str) -> PathBuf {
     file_path
 }
 
+/**
+ * Violations of the OpenAPI 3.0 JSON schema that [`check_openapi_schema()`]
+ * accepts, each given by the JSON pointer of the part of the document at fault
+ *
+ * "POST /login" builds its own response (a 200 or a 401, either one setting a
+ * cookie), so Dropshot documents no responses for it, while the specification
+ * requires at least one.
+ */
+const KNOWN_SCHEMA_VIOLATIONS: &[&str] = &["/paths/~1login/post/responses"];
+
+/**
+ * Validates an OpenAPI document against the official OpenAPI 3.0 JSON schema
+ * (see tests/schemas/README.md), panicking with every violation found other
+ * than those in [`KNOWN_SCHEMA_VIOLATIONS`].
+ *
+ * Deserializing into [`OpenAPI`] is more forgiving than the specification:
+ * for example, it accepts an operation with no responses.
+ */
+fn check_openapi_schema(text: &str) {
+    let doc: serde_json::Value =
+        serde_json::from_str(text).expect("stdout was not valid JSON");
+    let schema: serde_json::Value =
+        serde_json::from_str(include_str!("schemas/openapi-3.0.json"))
+            .expect("OpenAPI 3.0 schema was not valid JSON");
+    let validator = JSONSchema::compile(&schema)
+        .expect("failed to compile the OpenAPI 3.0 schema");
+    if let Err(errors) = validator.validate(&doc) {
+        let errors: Vec<String> = errors
+            .filter(|error| {
+                let path = error.instance_path.to_string();
+                !KNOWN_SCHEMA_VIOLATIONS.contains(&path.as_str())
+            })
+            .map(|error| format!("{}: {}", error.instance_path, error))
+            .collect();
+        if errors.is_empty() {
+            return;
+        }
+        panic!(
+            "document does not match the OpenAPI 3.0 schema:\n{}",
+            errors.join("\n")
+        );
+    }
+}
+
+/**
+ * Checks consistency rules of the OpenAPI 3.0 specification that can't be
+ * expressed in its JSON schema (see [`check_openapi_schema()`]).
+ *
+ * This walks the raw JSON and panics on the first violation of any of these
+ * rules:
+ *
+ * - every "$ref" resolves to an object that exists in the document
+ * - every operationId is unique across the document
+ * - every "{param}" in a path template is declared as a required path
+ *   parameter of each operation on that path, and vice versa
+ */
+fn check_openapi_references(text: &str) {
+    let doc: serde_json::Value =
+        serde_json::from_str(text).expect("stdout was not valid JSON");
+
+    check_refs(&doc, &doc);
+
+    let paths = doc["paths"].as_object().expect("missing \"paths\"");
+    let methods =
+        ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+    let mut operation_ids = BTreeSet::new();
+    for (path, item) in paths {
+        let template_params = path
+            .split('/')
+            .filter(|s| s.starts_with('{') && s.ends_with('}'))
+            .map(|s| &s[1..s.len() - 1])
+            .collect::<BTreeSet<&str>>();
+
+        for method in methods {
+            let operation = match item.get(method) {
+                Some(operation) => operation,
+                None => continue,
+            };
+            let label = format!("{} {}", method, path);
+
+            if let Some(operation_id) = operation["operationId"].as_str() {
+                assert!(
+                    operation_ids.insert(operation_id.to_string()),
+                    "{}: duplicate operationId \"{}\"",
+                    label,
+                    operation_id
+                );
+            }
+
+            let mut declared_params = BTreeSet::new();
+            let parameters = operation["parameters"].as_array();
+            for param in parameters.into_iter().flatten() {
+                if param["in"] != "path" {
+                    continue;
+                }
+                let name = param["name"].as_str().unwrap_or_else(|| {
+                    panic!("{}: path parameter has no name", label)
+                });
+                assert_eq!(
+                    param["required"], true,
+                    "{}: path parameter \"{}\" must be required",
+                    label, name
+                );
+                declared_params.insert(name);
+            }
+            assert_eq!(
+                template_params, declared_params,
+                "{}: path template parameters do not match declared path \
+                 parameters",
+                label
+            );
+        }
+    }
+}
+
+/**
+ * Recursively checks that every "$ref" in `value` resolves within `doc`.
+ */
+fn check_refs(doc: &serde_json::Value, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(reference) = map.get("$ref") {
+                let reference =
+                    reference.as_str().expect("\"$ref\" was not a string");
+                let pointer =
+                    reference.strip_prefix('#').unwrap_or_else(|| {
+                        panic!("unsupported non-local \"$ref\": {}", reference)
+                    });
+                assert!(
+                    doc.pointer(pointer).is_some(),
+                    "dangling \"$ref\": {}",
+                    reference
+                );
+            }
+            for v in map.values() {
+                check_refs(doc, v);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for v in items {
+                check_refs(doc, v);
+            }
+        }
+        _ => (),
+    }
+}
+
 /*
  * Tests
  */
@@ -122,6 +273,8 @@ fn test_nexus_openapi() {
      */
     let spec: OpenAPI = serde_json::from_str(&stdout_text)
         .expect("stdout was not valid OpenAPI");
+    check_openapi_schema(&stdout_text);
+    check_openapi_references(&stdout_text);
     assert_eq!(spec.openapi, "3.0.3");
     assert_eq!(spec.info.title, "Oxide Region API");
     assert_eq!(spec.info.version, "0.0.1");
@@ -132,18 +285,148 @@ fn test_nexus_openapi() {
     assert!(!spec.paths.is_empty());
     assert!(spec.paths.get("/organizations").is_some());
 
+    /*
+     * Endpoints marked `unpublished` are served (see test_console_api.rs) but
+     * must not appear in the spec.  "GET /login" is unpublished while
+     * "POST /login" is not.
+     */
+    let doc: serde_json::Value = serde_json::from_str(&stdout_text).unwrap();
+    for unpublished in &["/orgs/{path}", "/assets/{path}"] {
+        assert!(
+            spec.paths.get(*unpublished).is_none(),
+            "unpublished endpoint {} appeared in the spec",
+            unpublished
+        );
+    }
+    assert!(doc["paths"]["/login"].get("post").is_some());
+    assert!(doc["paths"]["/login"].get("get").is_none());
+
+    /*
+     * Fetching an instance can also answer 304 for a matching If-None-Match.
+     * Dropshot only lists the handler's 200, so the description says so.
+     */
+    let get_instance = &doc["paths"]["/organizations/{organization_name}/\
+        projects/{project_name}/instances/{instance_name}"]["get"];
+    assert!(get_instance["description"]
+        .as_str()
+        .unwrap()
+        .contains("304 Not Modified"));
+
+    /*
+     * The success response for an endpoint is derived from the handler's
+     * return type.  Check that listing projects documents a 200 that returns
+     * a page of projects.
+     */
+    let list_projects =
+        &doc["paths"]["/organizations/{organization_name}/projects"]["get"];
+    assert_eq!(
+        list_projects["responses"]["200"]["content"]["application/json"]
+            ["schema"]["$ref"],
+        "#/components/schemas/ProjectResultsPage"
+    );
+
     /*
      * Check for lint errors.
      */
     let errors = openapi_lint::validate(&spec);
     assert!(errors.is_empty(), "{}", errors.join("\n\n"));
 
+    /*
+     * Find any operations that were added, removed, or changed relative to the
+     * checked-in spec.  This has to happen before `assert_contents()`, which
+     * may overwrite that spec.
+     */
+    let committed = committed_nexus_spec();
+    let changes = diff_operations(&committed, &doc);
+
+    /*
+     * Report the changes now: if the checked-in spec is out of date,
+     * `assert_contents()` fails first, and this is the only place a breaking
+     * change would show up.
+     */
+    for change in &changes {
+        let kind = if change.is_breaking() { "breaking" } else { "compatible" };
+        eprintln!("API change ({}): {}", kind, change);
+    }
+
     /*
      * Confirm that the output hasn't changed. It's expected that we'll change
      * this file as the API evolves, but pay attention to the diffs to ensure
      * that the changes match your expectations.
      */
     assert_contents("../openapi/nexus.json", &stdout_text);
+
+    /*
+     * Even once the checked-in spec has been updated, changes that would break
+     * existing clients are flagged (once) so that they don't go unnoticed.
+     */
+    let breaking: Vec<_> = changes
+        .iter()
+        .filter(|change| change.is_breaking())
+        .map(|change| change.to_string())
+        .collect();
+    assert!(
+        breaking.is_empty(),
+        "breaking changes to the API:\n{}",
+        breaking.join("\n")
+    );
+}
+
+fn committed_nexus_spec() -> serde_json::Value {
+    let text = fs::read_to_string("../openapi/nexus.json")
+        .expect("failed to read checked-in OpenAPI spec");
+    serde_json::from_str(&text).expect("checked-in spec was not valid JSON")
+}
+
+#[test]
+fn test_nexus_openapi_removed_operation() {
+    let committed = committed_nexus_spec();
+    let mut modified = committed.clone();
+    modified["paths"]["/organizations"]
+        .as_object_mut()
+        .unwrap()
+        .remove("post")
+        .expect("expected \"POST /organizations\" in the spec");
+
+    let changes = diff_operations(&committed, &modified);
+    assert_eq!(
+        changes,
+        vec![OperationChange::Removed {
+            method: "post".to_string(),
+            path: "/organizations".to_string(),
+        }]
+    );
+    assert!(changes[0].is_breaking());
+}
+
+#[test]
+fn test_nexus_openapi_additive_change() {
+    /*
+     * A new optional parameter and a new response don't break clients, so
+     * they show up as a change but aren't flagged.
+     */
+    let committed = committed_nexus_spec();
+    let mut modified = committed.clone();
+    let operation = &mut modified["paths"]["/organizations"]["get"];
+    operation["parameters"].as_array_mut().unwrap().push(serde_json::json!({
+        "in": "query",
+        "name": "verbose",
+        "schema": { "type": "boolean" },
+        "style": "form"
+    }));
+    operation["responses"]["304"] =
+        serde_json::json!({ "description": "not modified" });
+
+    let changes = diff_operations(&committed, &modified);
+    assert_eq!(
+        changes,
+        vec![OperationChange::Changed {
+            method: "get".to_string(),
+            path: "/organizations".to_string(),
+            breaking: vec![],
+        }]
+    );
+    assert!(!changes[0].is_breaking());
 }
 
 #[test]
@@ -151,6 +434,8 @@ fn test_nexus_openapi_internal() {
     let (stdout_text, _) = run_command_with_arg("--openapi-internal");
     let spec: OpenAPI = serde_json::from_str(&stdout_text)
         .expect("stdout was not valid OpenAPI");
+    check_openapi_schema(&stdout_text);
+    check_openapi_references(&stdout_text);
 
     /*
      * Check for lint errors.
@@ -165,3 +450,26 @@ fn test_nexus_openapi_internal() {
      */
     assert_contents("../openapi/nexus-internal.json", &stdout_text);
 }
+
+#[test]
+fn test_nexus_json_schemas() {
+    let (stdout_text, _) = run_command_with_arg("--json-schemas");
+    let schemas: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&stdout_text)
+            .expect("stdout was not a JSON object");
+
+    /*
+     * Spot check the schema for project creation parameters.
+     */
+    let project_create =
+        schemas.get("ProjectCreate").expect("missing ProjectCreate schema");
+    assert_eq!(project_create["type"], "object");
+    let mut required: Vec<&str> = project_create["required"]
+        .as_array()
+        .expect("expected \"required\" to be an array")
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    required.sort_unstable();
+    assert_eq!(required, vec!["description", "name"]);
+}
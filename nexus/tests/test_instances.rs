@@ -6,17 +6,35 @@
  * Tests basic instance support in the API
  */
 
+use chrono::Utc;
 use http::method::Method;
 use http::StatusCode;
 use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::Generation;
 use omicron_common::api::external::IdentityMetadataCreateParams;
 use omicron_common::api::external::Instance;
 use omicron_common::api::external::InstanceCpuCount;
 use omicron_common::api::external::InstanceState;
+use omicron_common::api::internal::nexus::InstanceRuntimeState;
+use omicron_common::api::internal::sled_agent::InstanceEnsureBody;
+use omicron_common::api::internal::sled_agent::InstanceHardware;
+use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
+use omicron_common::api::internal::sled_agent::InstanceStateRequested;
 use omicron_nexus::TestInterfaces as _;
-use omicron_nexus::{external_api::params, Nexus};
+use omicron_nexus::{
+    external_api::{
+        params,
+        views::{InstanceCount, Project},
+    },
+    Nexus,
+};
+use omicron_sled_agent::sim::SimMode;
+use omicron_test_utils::dev::poll::{wait_for_condition, CondCheckError};
 use sled_agent_client::TestInterfaces as _;
+use std::collections::BTreeSet;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 use dropshot::test_util::object_delete;
@@ -27,9 +45,18 @@ use dropshot::test_util::read_json;
 use dropshot::test_util::ClientTestContext;
 
 pub mod common;
+use common::http_testing::dropshot_compat::iter_collection;
+use common::http_testing::RequestBuilder;
+use common::http_testing::TestResponse;
 use common::identity_eq;
 use common::resource_helpers::{create_organization, create_project};
+use common::start_sled_agent;
 use common::test_setup;
+use common::test_setup_with_sim_mode;
+use common::SLED_AGENT_UUID;
+
+#[macro_use]
+extern crate slog;
 
 static ORGANIZATION_NAME: &str = "test-org";
 static PROJECT_NAME: &str = "springfield-squidport";
@@ -75,6 +102,347 @@ async fn test_instances_access_before_create_returns_not_found() {
         error.message,
         "not found: instance with name \"just-rainsticks\""
     );
+
+    /* Ditto for each of the actions that operate on an instance. */
+    for action in &["start", "stop", "reboot"] {
+        let error = client
+            .make_request_error(
+                Method::POST,
+                &format!("{}/{}", instance_url, action),
+                StatusCode::NOT_FOUND,
+            )
+            .await;
+        assert_eq!(
+            error.message,
+            "not found: instance with name \"just-rainsticks\""
+        );
+    }
+
+    /* Ditto for listing a missing instance's disks. */
+    let error = client
+        .make_request_error(
+            Method::GET,
+            &format!("{}/disks", instance_url),
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "not found: instance with name \"just-rainsticks\""
+    );
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_list_paginated() {
+    let cptestctx = test_setup("test_instances_list_paginated").await;
+    let client = &cptestctx.external_client;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /* Create instances in an order other than their sorted order. */
+    let names =
+        ["instance-c", "instance-e", "instance-a", "instance-d", "instance-b"];
+    for name in &names {
+        let new_instance = params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: format!("instance {}", name),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: name.to_string(),
+        };
+        let _: Instance =
+            objects_post(&client, &url_instances, new_instance).await;
+    }
+
+    /*
+     * Page through the instances two at a time.  They should come back in
+     * increasing order of name.
+     */
+    let (found, _) =
+        iter_collection::<Instance>(&client, &url_instances, "", 2).await;
+    let mut expected = names.to_vec();
+    expected.sort_unstable();
+    assert_eq!(
+        found.iter().map(|i| i.identity.name.as_str()).collect::<Vec<&str>>(),
+        expected
+    );
+
+    /* Descending order should be the reverse. */
+    let (found, _) = iter_collection::<Instance>(
+        &client,
+        &url_instances,
+        "sort_by=name-descending",
+        2,
+    )
+    .await;
+    expected.reverse();
+    assert_eq!(
+        found.iter().map(|i| i.identity.name.as_str()).collect::<Vec<&str>>(),
+        expected
+    );
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_count() {
+    let cptestctx = test_setup("test_instances_count").await;
+    let client = &cptestctx.external_client;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let url_count = format!(
+        "/organizations/{}/projects/{}/instances-count",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /* An empty project has no instances. */
+    let count = object_get::<InstanceCount>(&client, &url_count).await;
+    assert_eq!(count.count, 0);
+
+    for i in 0..3 {
+        let name = format!("instance-{}", i);
+        let new_instance = params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: format!("instance {}", name),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: name.clone(),
+        };
+        let _: Instance =
+            objects_post(&client, &url_instances, new_instance).await;
+    }
+
+    let count = object_get::<InstanceCount>(&client, &url_count).await;
+    assert_eq!(count.count, 3);
+
+    /* Instances in other projects aren't counted. */
+    let _ = create_project(&client, ORGANIZATION_NAME, "other-project").await;
+    let count = object_get::<InstanceCount>(
+        &client,
+        &format!(
+            "/organizations/{}/projects/other-project/instances-count",
+            ORGANIZATION_NAME
+        ),
+    )
+    .await;
+    assert_eq!(count.count, 0);
+
+    /* Counting instances in a nonexistent project fails. */
+    let error = client
+        .make_request_error(
+            Method::GET,
+            &format!(
+                "/organizations/{}/projects/no-such-project/instances-count",
+                ORGANIZATION_NAME
+            ),
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "not found: project with name \"no-such-project\""
+    );
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_etag() {
+    let cptestctx = test_setup("test_instances_etag").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    /* Create a project and a running instance that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "just-rainsticks".parse().unwrap(),
+                description: String::from("sells rainsticks"),
+            },
+            ncpus: InstanceCpuCount(4),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: String::from("rainsticks"),
+        },
+    )
+    .await;
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+    instance_simulate(nexus, &instance.identity.id).await;
+
+    let etag_of = |response: &TestResponse| {
+        response
+            .headers
+            .get(http::header::ETAG)
+            .expect("response had no ETag")
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    /*
+     * A plain GET returns the instance along with its ETag.  The tag is weak,
+     * since it doesn't depend on whether the body was pretty-printed.
+     */
+    let response = RequestBuilder::new(client, Method::GET, &instance_url)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    let etag = etag_of(&response);
+    assert!(etag.starts_with("W/"));
+    let instance: Instance = response.parsed_body().unwrap();
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    /* The ETag is the same as long as the instance hasn't changed. */
+    let response = RequestBuilder::new(client, Method::GET, &instance_url)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(etag_of(&response), etag);
+
+    /* If the client already has the current version, it gets no body. */
+    let response = RequestBuilder::new(client, Method::GET, &instance_url)
+        .header(http::header::IF_NONE_MATCH, &etag)
+        .expect_status(Some(StatusCode::NOT_MODIFIED))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(etag_of(&response), etag);
+    assert!(response.body.is_empty());
+
+    /*
+     * Once the instance changes, the old ETag is stale and the client gets
+     * the whole (new) instance back, along with a new ETag.
+     */
+    instance_post(&client, &instance_url, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let response = RequestBuilder::new(client, Method::GET, &instance_url)
+        .header(http::header::IF_NONE_MATCH, &etag)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    let new_etag = etag_of(&response);
+    assert_ne!(new_etag, etag);
+    let instance: Instance = response.parsed_body().unwrap();
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_get_pretty() {
+    let cptestctx = test_setup("test_instances_get_pretty").await;
+    let client = &cptestctx.external_client;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    let _: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "just-rainsticks".parse().unwrap(),
+                description: String::from("sells rainsticks"),
+            },
+            ncpus: InstanceCpuCount(4),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: String::from("rainsticks"),
+        },
+    )
+    .await;
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+
+    /* By default, the body is compact. */
+    let compact = RequestBuilder::new(client, Method::GET, &instance_url)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    assert!(!compact.body.contains(&b'\n'));
+
+    /* With "?pretty=true", it's indented, but otherwise the same. */
+    let pretty = RequestBuilder::new(
+        client,
+        Method::GET,
+        &format!("{}?pretty=true", instance_url),
+    )
+    .expect_status(Some(StatusCode::OK))
+    .execute()
+    .await
+    .unwrap();
+    let pretty_body = std::str::from_utf8(&pretty.body).unwrap();
+    assert!(pretty_body.contains("\n  \"name\": \"just-rainsticks\""));
+    assert_eq!(
+        pretty.parsed_body::<serde_json::Value>().unwrap(),
+        compact.parsed_body::<serde_json::Value>().unwrap()
+    );
+    assert_eq!(
+        pretty.headers.get(http::header::ETAG),
+        compact.headers.get(http::header::ETAG)
+    );
+
+    /* A matching tag still gets "304 Not Modified" when pretty-printing. */
+    let etag = compact
+        .headers
+        .get(http::header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let response = RequestBuilder::new(
+        client,
+        Method::GET,
+        &format!("{}?pretty=true", instance_url),
+    )
+    .header(http::header::IF_NONE_MATCH, &etag)
+    .expect_status(Some(StatusCode::NOT_MODIFIED))
+    .execute()
+    .await
+    .unwrap();
+    assert!(response.body.is_empty());
+
+    /* "?pretty=false" is the same as the default. */
+    let response = RequestBuilder::new(
+        client,
+        Method::GET,
+        &format!("{}?pretty=false", instance_url),
+    )
+    .expect_status(Some(StatusCode::OK))
+    .execute()
+    .await
+    .unwrap();
+    assert_eq!(response.body, compact.body);
+
     cptestctx.teardown().await;
 }
 
@@ -170,6 +538,15 @@ async fn test_instances_create_reboot_halt() {
             > instance.runtime.time_run_state_updated
     );
 
+    let instance = instance_next;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance_next = instance_get(&client, &instance_url).await;
+    assert_eq!(instance_next.runtime.run_state, InstanceState::Stopping);
+    assert!(
+        instance_next.runtime.time_run_state_updated
+            > instance.runtime.time_run_state_updated
+    );
+
     let instance = instance_next;
     instance_simulate(nexus, &instance.identity.id).await;
     let instance_next = instance_get(&client, &instance_url).await;
@@ -257,6 +634,15 @@ async fn test_instances_create_reboot_halt() {
             > instance.runtime.time_run_state_updated
     );
 
+    let instance = instance_next;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance_next = instance_get(&client, &instance_url).await;
+    assert_eq!(instance_next.runtime.run_state, InstanceState::Stopping);
+    assert!(
+        instance_next.runtime.time_run_state_updated
+            > instance.runtime.time_run_state_updated
+    );
+
     let instance = instance_next;
     instance_simulate(nexus, &instance.identity.id).await;
     let instance_next = instance_get(&client, &instance_url).await;
@@ -306,7 +692,18 @@ async fn test_instances_create_reboot_halt() {
             > instance.runtime.time_run_state_updated
     );
 
-    /* TODO-coverage add a test to try to delete the project at this point. */
+    /* The project can't be deleted while the instance is still there. */
+    let project_url = format!(
+        "/organizations/{}/projects/{}",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    client
+        .make_request_error(
+            Method::DELETE,
+            &project_url,
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
 
     /* Delete the instance. */
     client
@@ -359,16 +756,14 @@ async fn test_instances_create_reboot_halt() {
 }
 
 #[tokio::test]
-async fn test_instances_delete_fails_when_running_succeeds_when_stopped() {
-    let cptestctx = test_setup(
-        "test_instances_delete_fails_when_running_succeeds_when_stopped",
-    )
-    .await;
+async fn test_instances_poke_over_http() {
+    let cptestctx = test_setup("test_instances_poke_over_http").await;
     let client = &cptestctx.external_client;
-    let apictx = &cptestctx.server.apictx;
-    let nexus = &apictx.nexus;
+    let sled_client = ClientTestContext::new(
+        cptestctx.sled_agent.http_server.local_addr(),
+        cptestctx.logctx.log.new(o!("component" => "sled agent client")),
+    );
 
-    // Create a project that we'll use for testing.
     create_organization(&client, ORGANIZATION_NAME).await;
     let url_instances = format!(
         "/organizations/{}/projects/{}/instances",
@@ -376,7 +771,10 @@ async fn test_instances_delete_fails_when_running_succeeds_when_stopped() {
     );
     let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
 
-    // Create an instance.
+    /*
+     * The test sled agent runs in explicit mode, so the new instance stays
+     * "starting" until something pokes it.
+     */
     let instance_url = format!("{}/just-rainsticks", url_instances);
     let new_instance = params::InstanceCreate {
         identity: IdentityMetadataCreateParams {
@@ -388,75 +786,518 @@ async fn test_instances_delete_fails_when_running_succeeds_when_stopped() {
         hostname: String::from("rainsticks"),
     };
     let instance: Instance =
-        objects_post(&client, &url_instances, new_instance.clone()).await;
+        objects_post(&client, &url_instances, new_instance).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
 
-    // Simulate the instance booting.
-    instance_simulate(nexus, &instance.identity.id).await;
-    let instance_next = instance_get(&client, &instance_url).await;
-    identity_eq(&instance.identity, &instance_next.identity);
-    assert_eq!(instance_next.runtime.run_state, InstanceState::Running);
+    sled_client
+        .make_request_no_body(
+            Method::POST,
+            &format!("/instances/{}/poke", instance.identity.id),
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .unwrap();
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
 
-    // Attempt to delete a running instance. This should fail.
-    let error = client
+    /* Poking an instance that the sled agent doesn't know about fails. */
+    sled_client
         .make_request_error(
-            Method::DELETE,
-            &instance_url,
-            StatusCode::BAD_REQUEST,
+            Method::POST,
+            &format!("/instances/{}/poke", Uuid::new_v4()),
+            StatusCode::NOT_FOUND,
         )
         .await;
-    assert_eq!(
-        error.message,
-        "instance cannot be deleted in state \"running\""
+
+    cptestctx.teardown().await;
+}
+
+/*
+ * With the sled agent in `SimMode::Explicit`, a test can ask which Instances
+ * have transitions waiting to be completed and complete exactly those.
+ */
+#[tokio::test]
+async fn test_instances_pending_transitions() {
+    let cptestctx = test_setup("test_instances_pending_transitions").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
     );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
 
-    // Stop the instance
-    let instance =
-        instance_post(&client, &instance_url, InstanceOp::Stop).await;
-    instance_simulate(nexus, &instance.identity.id).await;
-    let instance = instance_get(&client, &instance_url).await;
-    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+    /* Start two Instances.  Both are left waiting in "starting". */
+    let mut ids = Vec::new();
+    for name in &["pending-1", "pending-2"] {
+        let new_instance = params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: format!("instance {}", name),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: name.to_string(),
+        };
+        let instance: Instance =
+            objects_post(&client, &url_instances, new_instance).await;
+        assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+        ids.push(instance.identity.id);
+    }
+    ids.sort();
 
-    // Now deletion should succeed.
-    object_delete(&client, &instance_url).await;
+    let sa = nexus.instance_sled_by_id(&ids[0]).await.unwrap();
+    assert_eq!(sa.instances_pending().await, ids);
+
+    /* Step each pending Instance.  Afterwards, none are pending. */
+    for id in sa.instances_pending().await {
+        sa.instance_finish_transition(id).await;
+    }
+    assert!(sa.instances_pending().await.is_empty());
+    for name in &["pending-1", "pending-2"] {
+        let instance =
+            instance_get(&client, &format!("{}/{}", url_instances, name)).await;
+        assert_eq!(instance.runtime.run_state, InstanceState::Running);
+    }
 
     cptestctx.teardown().await;
 }
 
+/*
+ * A client can ask the sled agent to wait for a transition to finish.  If the
+ * client gives up first, the transition is cancelled and Nexus hears that the
+ * instance failed.
+ */
 #[tokio::test]
-async fn test_instances_invalid_creation_returns_bad_request() {
-    /*
-     * The rest of these examples attempt to create invalid instances.  We don't
-     * do exhaustive tests of the model here -- those are part of unit tests --
-     * but we exercise a few different types of errors to make sure those get
-     * passed through properly.
-     */
-    let cptestctx =
-        test_setup("test_instances_invalid_creation_returns_bad_request").await;
+async fn test_instances_wait_cancelled_over_http() {
+    let cptestctx = test_setup("test_instances_wait_cancelled_over_http").await;
     let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
     let url_instances = format!(
         "/organizations/{}/projects/{}/instances",
         ORGANIZATION_NAME, PROJECT_NAME
     );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
 
-    let error = client
-        .make_request_with_body(
-            Method::POST,
-            &url_instances,
-            "{".into(),
-            StatusCode::BAD_REQUEST,
-        )
-        .await
-        .unwrap_err();
-    assert!(error
-        .message
-        .starts_with("unable to parse body: EOF while parsing an object"));
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "impatient".parse().unwrap(),
+                description: "gives up".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "impatient".to_string(),
+        },
+    )
+    .await;
+    let id = instance.identity.id;
+    let instance_url = format!("{}/impatient", url_instances);
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
 
-    let request_body = r##"
-        {
-            "name": "an-instance",
-            "description": "will never exist",
-            "ncpus": -3,
-            "memory": 256,
+    /*
+     * The test sled agent runs in explicit mode, so nothing finishes the boot
+     * and this request waits until the client times out and hangs up.  (The
+     * sled agent already knows the instance, so `initial` is ignored.)
+     */
+    let body = InstanceEnsureBody {
+        initial: InstanceHardware {
+            runtime: InstanceRuntimeState {
+                run_state: InstanceState::Creating,
+                sled_uuid: Uuid::new_v4(),
+                propolis_uuid: Uuid::new_v4(),
+                ncpus: InstanceCpuCount(1),
+                memory: ByteCount::from_mebibytes_u32(256),
+                hostname: "impatient".to_string(),
+                gen: Generation::new(),
+                time_updated: Utc::now(),
+            },
+            nics: vec![],
+        },
+        target: InstanceRuntimeStateRequested {
+            run_state: InstanceStateRequested::Running,
+        },
+    };
+    let url = format!(
+        "http://{}/instances/{}?wait=true",
+        cptestctx.sled_agent.http_server.local_addr(),
+        id
+    );
+    let error = reqwest::Client::new()
+        .put(url)
+        .json(&body)
+        .timeout(Duration::from_millis(500))
+        .send()
+        .await
+        .expect_err("request for a stalled transition completed");
+    assert!(error.is_timeout());
+
+    /* Nexus hears that the cancelled boot left the instance failed. */
+    wait_for_condition::<_, std::convert::Infallible, _, _>(
+        || async {
+            let instance = instance_get(&client, &instance_url).await;
+            match instance.runtime.run_state {
+                InstanceState::Failed => Ok(()),
+                InstanceState::Starting => Err(CondCheckError::NotYet),
+                other => panic!("unexpected instance state: {}", other),
+            }
+        },
+        &Duration::from_millis(50),
+        &Duration::from_secs(30),
+    )
+    .await
+    .expect("instance did not fail after its boot was cancelled");
+    let sa = nexus.instance_sled_by_id(&id).await.unwrap();
+    assert!(sa.instances_pending().await.is_empty());
+
+    cptestctx.teardown().await;
+}
+
+/*
+ * With the sled agent simulating transitions on its own, an instance created
+ * through the API reaches "running" without any help from the test.  This
+ * covers the whole path from the API through Nexus to the sled agent and back.
+ */
+#[tokio::test]
+async fn test_instance_create_runs_with_auto_sim() {
+    let cptestctx = test_setup_with_sim_mode(
+        "test_instance_create_runs_with_auto_sim",
+        SimMode::Auto,
+    )
+    .await;
+    let client = &cptestctx.external_client;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+    let new_instance = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(4),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: "rainsticks".to_string(),
+    };
+    let instance: Instance =
+        objects_post(&client, &url_instances, new_instance).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+
+    let instance = wait_for_condition::<_, std::convert::Infallible, _, _>(
+        || async {
+            let instance = instance_get(&client, &instance_url).await;
+            match instance.runtime.run_state {
+                InstanceState::Running => Ok(instance),
+                InstanceState::Starting => Err(CondCheckError::NotYet),
+                other => panic!("unexpected instance state: {}", other),
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(30),
+    )
+    .await
+    .expect("instance did not reach \"running\"");
+    assert_eq!(instance.identity.name, "just-rainsticks");
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_delete_fails_when_running_succeeds_when_stopped() {
+    let cptestctx = test_setup(
+        "test_instances_delete_fails_when_running_succeeds_when_stopped",
+    )
+    .await;
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx;
+    let nexus = &apictx.nexus;
+
+    // Create a project that we'll use for testing.
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    // Create an instance.
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+    let new_instance = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(4),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: String::from("rainsticks"),
+    };
+    let instance: Instance =
+        objects_post(&client, &url_instances, new_instance.clone()).await;
+
+    // Simulate the instance booting.
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance_next = instance_get(&client, &instance_url).await;
+    identity_eq(&instance.identity, &instance_next.identity);
+    assert_eq!(instance_next.runtime.run_state, InstanceState::Running);
+
+    // Attempt to delete a running instance. This should fail.
+    let error = client
+        .make_request_error(
+            Method::DELETE,
+            &instance_url,
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "instance cannot be deleted in state \"running\""
+    );
+
+    // Stop the instance
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+
+    // Now deletion should succeed.
+    object_delete(&client, &instance_url).await;
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_project_delete_empty() {
+    let cptestctx = test_setup("test_project_delete_empty").await;
+    let client = &cptestctx.external_client;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    let project_url = format!(
+        "/organizations/{}/projects/{}",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+
+    /* A project with no instances can be deleted right away. */
+    object_delete(&client, &project_url).await;
+    client
+        .make_request_error(Method::GET, &project_url, StatusCode::NOT_FOUND)
+        .await;
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_project_delete_with_running_instance() {
+    let cptestctx =
+        test_setup("test_project_delete_with_running_instance").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    let project_url = format!(
+        "/organizations/{}/projects/{}",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let instance_url = format!("{}/instances/just-rainsticks", project_url);
+    let instance = project_delete_test_instance(&client, &project_url).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    /*
+     * Deleting the project is refused, and neither the project nor the
+     * instance is affected.
+     */
+    let error = client
+        .make_request_error(
+            Method::DELETE,
+            &project_url,
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "project cannot be deleted while it has instances (1 remaining)"
+    );
+    let _: Project = object_get(&client, &project_url).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_project_delete_with_stopped_instance() {
+    let cptestctx =
+        test_setup("test_project_delete_with_stopped_instance").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    let project_url = format!(
+        "/organizations/{}/projects/{}",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let instance_url = format!("{}/instances/just-rainsticks", project_url);
+    let instance = project_delete_test_instance(&client, &project_url).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+
+    /* A stopped instance still keeps the project from being deleted. */
+    let error = client
+        .make_request_error(
+            Method::DELETE,
+            &project_url,
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "project cannot be deleted while it has instances (1 remaining)"
+    );
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+
+    /* Once the instance is deleted, so can the project be. */
+    object_delete(&client, &instance_url).await;
+    object_delete(&client, &project_url).await;
+    client
+        .make_request_error(Method::GET, &project_url, StatusCode::NOT_FOUND)
+        .await;
+
+    cptestctx.teardown().await;
+}
+
+async fn project_delete_test_instance(
+    client: &ClientTestContext,
+    project_url: &str,
+) -> Instance {
+    let new_instance = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(4),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: String::from("rainsticks"),
+    };
+    objects_post(&client, &format!("{}/instances", project_url), new_instance)
+        .await
+}
+
+#[tokio::test]
+async fn test_instances_force_delete_starting() {
+    let cptestctx = test_setup("test_instances_force_delete_starting").await;
+    let client = &cptestctx.external_client;
+
+    // Create a project that we'll use for testing.
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    // Create an instance.  We never simulate it booting, so it stays in
+    // "starting".
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "just-rainsticks".parse().unwrap(),
+                description: "sells rainsticks".to_string(),
+            },
+            ncpus: InstanceCpuCount(4),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: String::from("rainsticks"),
+        },
+    )
+    .await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+
+    // A normal delete is refused, as is an explicitly graceful one.
+    for url in &[instance_url.clone(), format!("{}?force=false", instance_url)]
+    {
+        let error = client
+            .make_request_error(Method::DELETE, url, StatusCode::BAD_REQUEST)
+            .await;
+        assert_eq!(
+            error.message,
+            "instance cannot be deleted in state \"starting\""
+        );
+    }
+
+    // A forced delete succeeds right away, without waiting for the instance
+    // to stop.
+    object_delete(&client, &format!("{}?force=true", instance_url)).await;
+    let error = client
+        .make_request_error(Method::GET, &instance_url, StatusCode::NOT_FOUND)
+        .await;
+    assert_eq!(
+        error.message,
+        "not found: instance with name \"just-rainsticks\""
+    );
+    let instances = instances_list(&client, &url_instances).await;
+    assert_eq!(instances.len(), 0);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_invalid_creation_returns_bad_request() {
+    /*
+     * The rest of these examples attempt to create invalid instances.  We don't
+     * do exhaustive tests of the model here -- those are part of unit tests --
+     * but we exercise a few different types of errors to make sure those get
+     * passed through properly.
+     */
+    let cptestctx =
+        test_setup("test_instances_invalid_creation_returns_bad_request").await;
+    let client = &cptestctx.external_client;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+
+    let error = client
+        .make_request_with_body(
+            Method::POST,
+            &url_instances,
+            "{".into(),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+    assert!(error
+        .message
+        .starts_with("unable to parse body: EOF while parsing an object"));
+
+    let request_body = r##"
+        {
+            "name": "an-instance",
+            "description": "will never exist",
+            "ncpus": -3,
+            "memory": 256,
             "hostname": "localhost",
         }
     "##;
@@ -464,14 +1305,856 @@ async fn test_instances_invalid_creation_returns_bad_request() {
         .make_request_with_body(
             Method::POST,
             &url_instances,
-            request_body.into(),
-            StatusCode::BAD_REQUEST,
+            request_body.into(),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+    assert!(error
+        .message
+        .starts_with("unable to parse body: invalid value: integer `-3`"));
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_create_invalid_hardware() {
+    let cptestctx = test_setup("test_instances_create_invalid_hardware").await;
+    let client = &cptestctx.external_client;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    let new_instance = |ncpus: u16, memory_mib: u32| params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(ncpus),
+        memory: ByteCount::from_mebibytes_u32(memory_mib),
+        hostname: String::from("rainsticks"),
+    };
+
+    /* An instance with no CPUs is rejected. */
+    let error = client
+        .make_request_error_body(
+            Method::POST,
+            &url_instances,
+            new_instance(0, 256),
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "unsupported value for \"ncpus\": an instance needs at least one CPU"
+    );
+
+    /* So is an instance with no memory. */
+    let error = client
+        .make_request_error_body(
+            Method::POST,
+            &url_instances,
+            new_instance(1, 0),
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    assert_eq!(
+        error.message,
+        "unsupported value for \"memory\": an instance needs nonzero memory"
+    );
+
+    /* Neither attempt created anything. */
+    assert!(instances_list(&client, &url_instances).await.is_empty());
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_create_failure_unwinds() {
+    let cptestctx = test_setup("test_instances_create_failure_unwinds").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /*
+     * Register a sled whose agent isn't listening.  Sled allocation picks the
+     * first sled by id, so using the nil UUID ensures that the new instance
+     * lands on this one and the saga fails when it tries to start the
+     * instance.
+     */
+    let dead_address = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    nexus.insert_sled(Uuid::nil(), dead_address).await.unwrap();
+
+    /* Adding the same sled again fails rather than replacing its address. */
+    let error = nexus.insert_sled(Uuid::nil(), dead_address).await.unwrap_err();
+    assert!(matches!(error, Error::ObjectAlreadyExists { .. }));
+
+    let new_instance = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(4),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: "rainsticks".to_string(),
+    };
+    client
+        .make_request_error_body(
+            Method::POST,
+            &url_instances,
+            new_instance.clone(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .await;
+
+    /*
+     * The saga should have unwound, removing the instance record it created.
+     */
+    let instances = instances_list(&client, &url_instances).await;
+    assert_eq!(instances.len(), 0);
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+    client
+        .make_request_error(Method::GET, &instance_url, StatusCode::NOT_FOUND)
+        .await;
+    assert_eq!(nexus.sled_load(&Uuid::nil()).await.unwrap(), 0);
+
+    /*
+     * The saga's final state should be visible through the saga endpoints.
+     */
+    let mut response = client
+        .make_request_with_body(
+            Method::GET,
+            "/sagas",
+            "".into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let sagas: serde_json::Value = read_json(&mut response).await;
+    let failed_sagas = sagas["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|saga| saga["state"]["state"] == "failed")
+        .collect::<Vec<_>>();
+    assert_eq!(failed_sagas.len(), 1);
+    let saga_url =
+        format!("/sagas/{}", failed_sagas[0]["id"].as_str().unwrap());
+    let mut response = client
+        .make_request_with_body(
+            Method::GET,
+            &saga_url,
+            "".into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let saga: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(saga["state"]["state"], "failed");
+    assert_eq!(saga["state"]["error_node_name"], "instance_ensure");
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_create_failure_frees_sled() {
+    let cptestctx =
+        test_setup("test_instances_create_failure_frees_sled").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+    let sled_id: Uuid = SLED_AGENT_UUID.parse().unwrap();
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    assert_eq!(nexus.sled_load(&sled_id).await.unwrap(), 0);
+
+    /* Once an instance has been created, its record holds the sled. */
+    let new_instance = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(4),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: "rainsticks".to_string(),
+    };
+    let _: Instance =
+        objects_post(&client, &url_instances, new_instance.clone()).await;
+    assert_eq!(nexus.sled_load(&sled_id).await.unwrap(), 1);
+
+    /*
+     * Creating another instance with the same name fails after a sled has
+     * been allocated for it, when the saga tries to create its record.
+     * Unwinding the saga releases that sled again.
+     */
+    client
+        .make_request_error_body(
+            Method::POST,
+            &url_instances,
+            new_instance,
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    assert_eq!(nexus.sled_load(&sled_id).await.unwrap(), 1);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_create_free_failure_unwinds() {
+    let cptestctx =
+        test_setup("test_instances_create_free_failure_unwinds").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+    let sled_id: Uuid = SLED_AGENT_UUID.parse().unwrap();
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /*
+     * Releasing the sled's reservation happens after the instance record has
+     * been created.  If that fails, unwinding the saga must remove the record
+     * too, rather than leave it behind in "creating".
+     */
+    nexus.set_sled_free_failures(1);
+    let new_instance = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "just-rainsticks".parse().unwrap(),
+            description: "sells rainsticks".to_string(),
+        },
+        ncpus: InstanceCpuCount(4),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: "rainsticks".to_string(),
+    };
+    client
+        .make_request_error_body(
+            Method::POST,
+            &url_instances,
+            new_instance,
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .await;
+
+    assert!(instances_list(&client, &url_instances).await.is_empty());
+    let instance_url = format!("{}/just-rainsticks", url_instances);
+    client
+        .make_request_error(Method::GET, &instance_url, StatusCode::NOT_FOUND)
+        .await;
+    assert_eq!(nexus.sled_load(&sled_id).await.unwrap(), 0);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_transitions_update_time_modified() {
+    let cptestctx =
+        test_setup("test_instances_transitions_update_time_modified").await;
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx;
+    let nexus = &apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    let instance_url = format!("{}/canoe", url_instances);
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "canoe".parse().unwrap(),
+                description: "paddles".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "canoe".to_string(),
+        },
+    )
+    .await;
+    let id = instance.identity.id;
+    instance_simulate(nexus, &id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+    let running = instance.identity.time_modified;
+
+    /*
+     * Stop and then start the instance.  Each runtime state change should be
+     * reflected in the instance's "time_modified".
+     */
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Stop).await;
+    assert!(instance.identity.time_modified > running);
+    let stopping = instance.identity.time_modified;
+    instance_simulate(nexus, &id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+    assert!(instance.identity.time_modified > stopping);
+    let stopped = instance.identity.time_modified;
+
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Start).await;
+    assert!(instance.identity.time_modified > stopped);
+    let starting = instance.identity.time_modified;
+    instance_simulate(nexus, &id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+    assert!(instance.identity.time_modified > starting);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_serial_output() {
+    let cptestctx = test_setup("test_instances_serial_output").await;
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx;
+    let nexus = &apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /* Create an instance.  The simulated sled agent starts booting it. */
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "kayak".parse().unwrap(),
+                description: "console output".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "kayak".to_string(),
+        },
+    )
+    .await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+    let id = instance.identity.id;
+    let sa = nexus.instance_sled_by_id(&id).await.unwrap();
+    let booting = sa.instance_serial_output(id).await;
+    assert!(!booting.is_empty());
+
+    /*
+     * Once the instance is running, its output should have grown, and what we
+     * saw while it was booting should still be there.
+     */
+    instance_simulate(nexus, &id).await;
+    let instance =
+        instance_get(&client, &format!("{}/kayak", url_instances)).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+    let running = sa.instance_serial_output(id).await;
+    assert!(running.len() > booting.len());
+    assert_eq!(&running[..booting.len()], &booting[..]);
+
+    /* Nexus serves the same output from the sled agent. */
+    let serial: Vec<String> =
+        object_get(&client, &format!("{}/kayak/serial", url_instances)).await;
+    assert_eq!(serial, running);
+
+    /* There's no output for an instance that doesn't exist. */
+    RequestBuilder::new(
+        client,
+        Method::GET,
+        &format!("{}/canoe/serial", url_instances),
+    )
+    .expect_status(Some(StatusCode::NOT_FOUND))
+    .execute()
+    .await
+    .unwrap();
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_deterministic_ids() {
+    let cptestctx = test_setup("test_instances_deterministic_ids").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /* Hand out ids from a sequence, so that we know what to expect. */
+    let mut next_id: u128 = 1;
+    nexus.set_instance_id_generator(Some(Box::new(move || {
+        let id = Uuid::from_u128(next_id);
+        next_id += 1;
+        id
+    })));
+
+    let mut ids = Vec::new();
+    for name in ["kayak", "canoe"] {
+        let instance: Instance = objects_post(
+            &client,
+            &url_instances,
+            params::InstanceCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: name.parse().unwrap(),
+                    description: "predictable".to_string(),
+                },
+                ncpus: InstanceCpuCount(1),
+                memory: ByteCount::from_mebibytes_u32(256),
+                hostname: name.to_string(),
+            },
+        )
+        .await;
+        ids.push(instance.identity.id);
+    }
+    assert_eq!(ids, vec![Uuid::from_u128(1), Uuid::from_u128(2)]);
+    let instance =
+        instance_get(&client, &format!("{}/canoe", url_instances)).await;
+    assert_eq!(instance.identity.id, Uuid::from_u128(2));
+
+    /* Without a generator, ids are random again. */
+    nexus.set_instance_id_generator(None);
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "raft".parse().unwrap(),
+                description: "unpredictable".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "raft".to_string(),
+        },
+    )
+    .await;
+    assert!(!ids.contains(&instance.identity.id));
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_stale_updates_ignored() {
+    let cptestctx = test_setup("test_instances_stale_updates_ignored").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    /*
+     * Create an instance.  The simulated sled agent doesn't finish booting it
+     * until we tell it to, so nothing else will update its state under us.
+     */
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "kayak".parse().unwrap(),
+                description: "out of order".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "kayak".to_string(),
+        },
+    )
+    .await;
+    let id = instance.identity.id;
+    let instance_url = format!("{}/kayak", url_instances);
+    let stored_runtime = || async {
+        let runtime: InstanceRuntimeState = nexus
+            .datastore()
+            .instance_fetch(&id)
+            .await
+            .unwrap()
+            .runtime()
+            .clone()
+            .into();
+        runtime
+    };
+    let initial = stored_runtime().await;
+    assert_eq!(initial.run_state, InstanceState::Starting);
+
+    /*
+     * The sled agent reports that the instance is running, then that it's
+     * stopping, but the reports arrive in the opposite order.  The later one
+     * (with the higher generation) must win.
+     */
+    let running = InstanceRuntimeState {
+        run_state: InstanceState::Running,
+        gen: initial.gen.next(),
+        time_updated: Utc::now(),
+        ..initial.clone()
+    };
+    let stopping = InstanceRuntimeState {
+        run_state: InstanceState::Stopping,
+        gen: running.gen.next(),
+        time_updated: Utc::now(),
+        ..initial.clone()
+    };
+    nexus.notify_instance_updated(&id, &stopping).await.unwrap();
+    nexus.notify_instance_updated(&id, &running).await.unwrap();
+    /*
+     * The database keeps timestamps only to the microsecond, so the stored
+     * state may differ from the one we sent in "time_updated".
+     */
+    assert!(stored_runtime().await.equivalent_ignoring_time(&stopping));
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopping);
+
+    /* An update with the same generation as the stored one is dropped too. */
+    let duplicate = InstanceRuntimeState {
+        run_state: InstanceState::Running,
+        ..stopping.clone()
+    };
+    nexus.notify_instance_updated(&id, &duplicate).await.unwrap();
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopping);
+
+    /* A newer one is applied. */
+    let stopped = InstanceRuntimeState {
+        run_state: InstanceState::Stopped,
+        gen: stopping.gen.next(),
+        time_updated: Utc::now(),
+        ..initial.clone()
+    };
+    nexus.notify_instance_updated(&id, &stopped).await.unwrap();
+    assert!(stored_runtime().await.equivalent_ignoring_time(&stopped));
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_sled_partition() {
+    let cptestctx = test_setup("test_instances_sled_partition").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "island".parse().unwrap(),
+                description: "cut off".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "island".to_string(),
+        },
+    )
+    .await;
+    let id = instance.identity.id;
+    let instance_url = format!("{}/island", url_instances);
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+
+    /*
+     * Partition the sled from Nexus and let the instance finish booting.  Nexus
+     * doesn't hear about it.
+     */
+    let sa = nexus.instance_sled_by_id(&id).await.unwrap();
+    sa.sled_set_partitioned(true).await;
+    instance_simulate(nexus, &id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+
+    /* Once the partition heals, the queued update is delivered. */
+    sa.sled_set_partitioned(false).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    cptestctx.teardown().await;
+}
+
+/*
+ * While an instance's sled is powered off, Nexus can't change the instance's
+ * state.  The request fails as retryable, and nothing changes until the sled
+ * comes back.
+ */
+#[tokio::test]
+async fn test_instances_sled_powered_off() {
+    let cptestctx = test_setup("test_instances_sled_powered_off").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "blackout".parse().unwrap(),
+                description: "lost power".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "blackout".to_string(),
+        },
+    )
+    .await;
+    let id = instance.identity.id;
+    let instance_url = format!("{}/blackout", url_instances);
+    instance_simulate(nexus, &id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    let sa = nexus.instance_sled_by_id(&id).await.unwrap();
+    sa.sled_set_powered_on(false).await;
+    client
+        .make_request_error(
+            Method::POST,
+            &format!("{}/stop", instance_url),
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    /* Once the sled is back, the same request works. */
+    sa.sled_set_powered_on(true).await;
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Stop).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopping);
+
+    cptestctx.teardown().await;
+}
+
+/*
+ * An instance that's being repaired can be booted again, but not stopped or
+ * rebooted.  Nexus must agree with the sled agent about this.
+ */
+#[tokio::test]
+async fn test_instances_boot_while_repairing() {
+    let cptestctx = test_setup("test_instances_boot_while_repairing").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let url_instances = format!(
+        "/organizations/{}/projects/{}/instances",
+        ORGANIZATION_NAME, PROJECT_NAME
+    );
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+
+    let instance: Instance = objects_post(
+        &client,
+        &url_instances,
+        params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "mender".parse().unwrap(),
+                description: "in the shop".to_string(),
+            },
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_mebibytes_u32(256),
+            hostname: "mender".to_string(),
+        },
+    )
+    .await;
+    let id = instance.identity.id;
+    let instance_url = format!("{}/mender", url_instances);
+    instance_simulate(nexus, &id).await;
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Stop).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopping);
+    instance_simulate(nexus, &id).await;
+
+    /*
+     * Take the instance out of service.  The sled agent reports the new state
+     * to Nexus before this returns.
+     */
+    let sa = nexus.instance_sled_by_id(&id).await.unwrap();
+    sa.instance_repair(id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Repairing);
+
+    for action in &["stop", "reboot"] {
+        client
+            .make_request_error(
+                Method::POST,
+                &format!("{}/{}", instance_url, action),
+                StatusCode::BAD_REQUEST,
+            )
+            .await;
+    }
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Repairing);
+
+    let instance =
+        instance_post(&client, &instance_url, InstanceOp::Start).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Starting);
+    instance_simulate(nexus, &id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_instances_anti_affinity() {
+    let cptestctx = test_setup("test_instances_anti_affinity").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    /* Create a project that we'll use for testing. */
+    create_organization(&client, ORGANIZATION_NAME).await;
+    let _ = create_project(&client, ORGANIZATION_NAME, PROJECT_NAME).await;
+    let organization_name = ORGANIZATION_NAME.parse().unwrap();
+    let project_name = PROJECT_NAME.parse().unwrap();
+
+    /* Start a second sled agent so that there's a choice of sleds. */
+    let sa_id = Uuid::new_v4();
+    let sa = start_sled_agent(
+        cptestctx.logctx.log.new(o!("sled_id" => sa_id.to_string())),
+        cptestctx.server.http_server_internal.local_addr(),
+        sa_id,
+    )
+    .await
+    .unwrap();
+
+    let create_params = |name: &str| params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: "replica".to_string(),
+        },
+        ncpus: InstanceCpuCount(1),
+        memory: ByteCount::from_mebibytes_u32(256),
+        hostname: name.to_string(),
+    };
+
+    /*
+     * Create one replica, then a second one that must avoid the first one's
+     * sled.  They should land on different sleds.
+     */
+    let first = nexus
+        .project_create_instance_avoiding(
+            &organization_name,
+            &project_name,
+            &create_params("replica-1"),
+            &BTreeSet::new(),
+        )
+        .await
+        .unwrap();
+    let first_sled = first.runtime().sled_uuid;
+    let second = nexus
+        .project_create_instance_avoiding(
+            &organization_name,
+            &project_name,
+            &create_params("replica-2"),
+            &vec![first_sled].into_iter().collect(),
+        )
+        .await
+        .unwrap();
+    let second_sled = second.runtime().sled_uuid;
+    assert_ne!(first_sled, second_sled);
+
+    /* A third replica that must avoid both sleds has nowhere to go. */
+    let error = nexus
+        .project_create_instance_avoiding(
+            &organization_name,
+            &project_name,
+            &create_params("replica-3"),
+            &vec![first_sled, second_sled].into_iter().collect(),
         )
         .await
         .unwrap_err();
-    assert!(error
-        .message
-        .starts_with("unable to parse body: invalid value: integer `-3`"));
+    assert!(
+        error.to_string().contains("no sleds available for new Instance"),
+        "unexpected error: {}",
+        error
+    );
+
+    sa.close().await.unwrap();
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_sled_agent_close() {
+    let cptestctx = test_setup("test_sled_agent_close").await;
+
+    let sa_id = Uuid::new_v4();
+    let sa = start_sled_agent(
+        cptestctx.logctx.log.new(o!("sled_id" => sa_id.to_string())),
+        cptestctx.server.http_server_internal.local_addr(),
+        sa_id,
+    )
+    .await
+    .unwrap();
+    let sled_agent = Arc::clone(&sa.sled_agent);
+    let address = sa.http_server.local_addr();
+
+    /*
+     * Give the sled agent a background task to stop and some notifications
+     * to hold back.
+     */
+    sled_agent.start_watchdog(omicron_sled_agent::sim::ConfigWatchdog {
+        threshold_ms: 100,
+        action: omicron_sled_agent::sim::WatchdogAction::Warn,
+    });
+    sled_agent.set_partitioned(true).await;
+    assert_eq!(sled_agent.sim_tasks_running(), 1);
+
+    /*
+     * Once closed, the sled agent no longer accepts connections, its
+     * background tasks are gone, and what it was holding back was delivered.
+     */
+    sa.close().await.unwrap();
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+    assert_eq!(sled_agent.sim_tasks_running(), 0);
+    assert!(!sled_agent.partitioned());
 
     cptestctx.teardown().await;
 }
@@ -480,7 +2163,17 @@ async fn instance_get(
     client: &ClientTestContext,
     instance_url: &str,
 ) -> Instance {
-    object_get::<Instance>(client, instance_url).await
+    /*
+     * We can't use `object_get()` here because it doesn't expect the ETag
+     * header on the response.
+     */
+    RequestBuilder::new(client, Method::GET, instance_url)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap()
 }
 
 async fn instances_list(
@@ -2,14 +2,33 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use omicron_nexus::external_api::views::Project;
+use omicron_nexus::external_api::views::{Project, ProjectDeleteResult};
 
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
 use dropshot::test_util::object_get;
 use dropshot::test_util::objects_list_page;
+use dropshot::test_util::read_json;
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::Method;
+use http::StatusCode;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::IdentityMetadataUpdateParams;
+use omicron_common::api::external::Name;
+use omicron_common::api::external::DEFAULT_DESCRIPTION;
+use omicron_nexus::external_api::params;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 pub mod common;
+use common::http_testing::{
+    AuthnMode, NexusRequest, RequestBuilder, TestResponse,
+};
 use common::resource_helpers::{create_organization, create_project};
 use common::test_setup;
+use common::test_setup_with_request_body_max_bytes;
 
 extern crate slog;
 
@@ -68,3 +87,860 @@ async fn test_projects() {
 
     cptestctx.teardown().await;
 }
+
+#[tokio::test]
+async fn test_projects_large_request_body() {
+    let max_bytes = 4096;
+    let cptestctx = test_setup_with_request_body_max_bytes(
+        "test_projects_large_request_body",
+        max_bytes,
+    )
+    .await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = format!("/organizations/{}/projects", org_name);
+
+    /*
+     * A body larger than the default limit (1KiB) but within the configured
+     * one is accepted.
+     */
+    let description = "a".repeat(2048);
+    let project: Project = NexusRequest::objects_post(
+        client,
+        &projects_url,
+        &params::ProjectCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "springfield-squidport".parse().unwrap(),
+                description: description.clone(),
+            },
+            id: None,
+        },
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap();
+    assert_eq!(project.identity.description, description);
+
+    /* A body larger than the configured limit is still rejected. */
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, http::Method::POST, &projects_url)
+            .body(Some(&params::ProjectCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "cairo-airport".parse().unwrap(),
+                    description: "a".repeat(max_bytes),
+                },
+                id: None,
+            }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap();
+    assert!(
+        error.message.contains("exceeded maximum size"),
+        "unexpected error message: {}",
+        error.message
+    );
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_empty_description() {
+    let cptestctx = test_setup("test_projects_empty_description").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = format!("/organizations/{}/projects", org_name);
+
+    /* A project created without a description gets the default one. */
+    let project: Project = NexusRequest::objects_post(
+        client,
+        &projects_url,
+        &params::ProjectCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "springfield-squidport".parse().unwrap(),
+                description: String::new(),
+            },
+            id: None,
+        },
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap();
+    assert_eq!(project.identity.description, DEFAULT_DESCRIPTION);
+
+    let project_url = format!("{}/springfield-squidport", projects_url);
+    let project: Project = object_get(&client, &project_url).await;
+    assert_eq!(project.identity.description, DEFAULT_DESCRIPTION);
+
+    /*
+     * Leaving the description out of an update keeps it as is, while updating
+     * it to an empty one gets the default one too.
+     */
+    let project_update = |description: Option<&str>| params::ProjectUpdate {
+        identity: IdentityMetadataUpdateParams {
+            name: None,
+            description: description.map(String::from),
+        },
+    };
+    client
+        .make_request(
+            Method::PUT,
+            &project_url,
+            Some(project_update(Some("a squidport"))),
+            StatusCode::OK,
+        )
+        .await
+        .expect("failed to update project");
+    client
+        .make_request(
+            Method::PUT,
+            &project_url,
+            Some(project_update(None)),
+            StatusCode::OK,
+        )
+        .await
+        .expect("failed to update project");
+    let project: Project = object_get(&client, &project_url).await;
+    assert_eq!(project.identity.description, "a squidport");
+    let mut response = client
+        .make_request(
+            Method::PUT,
+            &project_url,
+            Some(project_update(Some(""))),
+            StatusCode::OK,
+        )
+        .await
+        .expect("failed to update project");
+    let project: Project = read_json(&mut response).await;
+    assert_eq!(project.identity.description, DEFAULT_DESCRIPTION);
+    let project: Project = object_get(&client, &project_url).await;
+    assert_eq!(project.identity.description, DEFAULT_DESCRIPTION);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_get_by_id() {
+    let cptestctx = test_setup("test_projects_get_by_id").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let project_id =
+        create_project(&client, &org_name, "springfield-squidport")
+            .await
+            .identity
+            .id;
+    let by_id_url = format!("/by-id/projects/{}", project_id);
+
+    let project: Project = object_get(&client, &by_id_url).await;
+    assert_eq!(project.identity.id, project_id);
+    assert_eq!(project.identity.name, "springfield-squidport");
+
+    /* Rename the project.  Its id, and so its by-id URL, stays the same. */
+    let project_update = params::ProjectUpdate {
+        identity: IdentityMetadataUpdateParams {
+            name: Some("cairo-airport".parse().unwrap()),
+            description: None,
+        },
+    };
+    let mut response = client
+        .make_request(
+            Method::PUT,
+            "/organizations/test-org/projects/springfield-squidport",
+            Some(project_update),
+            StatusCode::OK,
+        )
+        .await
+        .expect("failed to rename project");
+    let project: Project = read_json(&mut response).await;
+    assert_eq!(project.identity.id, project_id);
+
+    let project: Project = object_get(&client, &by_id_url).await;
+    assert_eq!(project.identity.id, project_id);
+    assert_eq!(project.identity.name, "cairo-airport");
+
+    /* The old name no longer resolves, but an unknown id is a 404 too. */
+    client
+        .make_request_error(
+            Method::GET,
+            "/organizations/test-org/projects/springfield-squidport",
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    let bogus_id = uuid::Uuid::new_v4();
+    let error = client
+        .make_request_error(
+            Method::GET,
+            &format!("/by-id/projects/{}", bogus_id),
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    assert_eq!(
+        format!("not found: project with id \"{}\"", bogus_id),
+        error.message
+    );
+
+    /* Deleted projects can't be found by id either. */
+    client
+        .make_request_no_body(
+            Method::DELETE,
+            "/organizations/test-org/projects/cairo-airport",
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .expect("failed to delete project");
+    client
+        .make_request_error(Method::GET, &by_id_url, StatusCode::NOT_FOUND)
+        .await;
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_idempotency_key() {
+    let cptestctx = test_setup("test_projects_idempotency_key").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = format!("/organizations/{}/projects", org_name);
+    let project_create = params::ProjectCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "springfield-squidport".parse().unwrap(),
+            description: "a project".to_string(),
+        },
+        id: None,
+    };
+    let post = |key: &str, status: StatusCode| {
+        NexusRequest::new(
+            RequestBuilder::new(client, Method::POST, &projects_url)
+                .header("idempotency-key", key)
+                .body(Some(&project_create))
+                .expect_status(Some(status)),
+        )
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+    };
+
+    /*
+     * Posting twice with the same key creates the project once, and the retry
+     * gets back exactly the same response as the original request.
+     */
+    let first = post("create-squidport", StatusCode::CREATED).await.unwrap();
+    let second = post("create-squidport", StatusCode::CREATED).await.unwrap();
+    assert_eq!(first.body, second.body);
+    let project: Project = first.parsed_body().unwrap();
+    assert_eq!(project.identity.name, "springfield-squidport");
+
+    let projects = objects_list_page::<Project>(client, &projects_url).await;
+    assert_eq!(projects.items.len(), 1);
+    assert_eq!(projects.items[0].identity.id, project.identity.id);
+
+    /* Reusing the key for a different request is an error. */
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &projects_url)
+            .header("idempotency-key", "create-squidport")
+            .body(Some(&params::ProjectCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "cairo-airport".parse().unwrap(),
+                    description: "a project".to_string(),
+                },
+                id: None,
+            }))
+            .expect_status(Some(StatusCode::UNPROCESSABLE_ENTITY)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "\"idempotency-key\" was already used for a request with a different \
+         body"
+    );
+
+    /* A different key is a new request, which conflicts with the first. */
+    let error: HttpErrorResponseBody =
+        post("create-squidport-again", StatusCode::BAD_REQUEST)
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(
+        error.message,
+        "already exists: project \"springfield-squidport\""
+    );
+
+    /* Keys are scoped to the collection they were used against. */
+    create_organization(&client, "test-org2").await;
+    let project2: Project = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            "/organizations/test-org2/projects",
+        )
+        .header("idempotency-key", "create-squidport")
+        .body(Some(&project_create))
+        .expect_status(Some(StatusCode::CREATED)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_ne!(project2.identity.id, project.identity.id);
+
+    /* An empty key is rejected outright. */
+    post("", StatusCode::BAD_REQUEST).await.unwrap();
+    let projects = objects_list_page::<Project>(client, &projects_url).await;
+    assert_eq!(projects.items.len(), 1);
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_create_with_id() {
+    let cptestctx = test_setup("test_projects_create_with_id").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = format!("/organizations/{}/projects", org_name);
+    let project_create = |name: &str, id: uuid::Uuid| params::ProjectCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: "imported".to_string(),
+        },
+        id: Some(id),
+    };
+
+    /* A project created with an id gets that id. */
+    let id = uuid::Uuid::new_v4();
+    let project: Project = NexusRequest::objects_post(
+        client,
+        &projects_url,
+        &project_create("springfield-squidport", id),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap();
+    assert_eq!(project.identity.id, id);
+    let project: Project =
+        object_get(&client, &format!("/by-id/projects/{}", id)).await;
+    assert_eq!(project.identity.name, "springfield-squidport");
+
+    /* Another project can't have the same id, even with a different name. */
+    let error = project_create_error(
+        client,
+        &projects_url,
+        &project_create("cairo-airport", id),
+        StatusCode::BAD_REQUEST,
+    )
+    .await;
+    assert_eq!(error.message, format!("already exists: project \"{}\"", id));
+
+    /* A name conflict is still reported as such. */
+    let error = project_create_error(
+        client,
+        &projects_url,
+        &project_create("springfield-squidport", uuid::Uuid::new_v4()),
+        StatusCode::BAD_REQUEST,
+    )
+    .await;
+    assert_eq!(
+        error.message,
+        "already exists: project \"springfield-squidport\""
+    );
+
+    /* Ids of deleted projects can't be reused either. */
+    client
+        .make_request_no_body(
+            Method::DELETE,
+            &format!("{}/springfield-squidport", projects_url),
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .expect("failed to delete project");
+    let error = project_create_error(
+        client,
+        &projects_url,
+        &project_create("cairo-airport", id),
+        StatusCode::BAD_REQUEST,
+    )
+    .await;
+    assert_eq!(error.message, format!("already exists: project \"{}\"", id));
+
+    let projects = objects_list_page::<Project>(client, &projects_url).await;
+    assert!(projects.items.is_empty());
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_if_unmodified_since() {
+    let cptestctx = test_setup("test_projects_if_unmodified_since").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let project =
+        create_project(&client, &org_name, "springfield-squidport").await;
+    let project_url =
+        format!("/organizations/{}/projects/springfield-squidport", org_name);
+
+    let http_date =
+        |t: DateTime<Utc>| t.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let stale = http_date(Utc.ymd(1994, 11, 6).and_hms(8, 49, 37));
+    let updates = params::ProjectUpdate {
+        identity: IdentityMetadataUpdateParams {
+            name: None,
+            description: Some("updated".to_string()),
+        },
+    };
+
+    /*
+     * If the project has been modified since the given time, neither an update
+     * nor a delete goes through.
+     */
+    let error: HttpErrorResponseBody =
+        RequestBuilder::new(client, Method::PUT, &project_url)
+            .header(http::header::IF_UNMODIFIED_SINCE, &stale)
+            .body(Some(&updates))
+            .expect_status(Some(StatusCode::PRECONDITION_FAILED))
+            .execute()
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(error.error_code, Some(String::from("PreconditionFailed")));
+    RequestBuilder::new(client, Method::DELETE, &project_url)
+        .header(http::header::IF_UNMODIFIED_SINCE, &stale)
+        .expect_status(Some(StatusCode::PRECONDITION_FAILED))
+        .execute()
+        .await
+        .unwrap();
+    let unchanged: Project = object_get(&client, &project_url).await;
+    assert_eq!(unchanged.identity.description, project.identity.description);
+    assert_eq!(
+        unchanged.identity.time_modified,
+        project.identity.time_modified
+    );
+
+    /*
+     * If it hasn't been, the update goes through.  HTTP dates only go down to
+     * the second, so the project's own modification time counts as
+     * unmodified.
+     */
+    let fresh = http_date(project.identity.time_modified);
+    let updated: Project =
+        RequestBuilder::new(client, Method::PUT, &project_url)
+            .header(http::header::IF_UNMODIFIED_SINCE, &fresh)
+            .body(Some(&updates))
+            .expect_status(Some(StatusCode::OK))
+            .execute()
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(updated.identity.description, "updated");
+
+    /* Likewise for the delete, given a time after that update. */
+    let fresh = http_date(updated.identity.time_modified);
+    RequestBuilder::new(client, Method::DELETE, &project_url)
+        .header(http::header::IF_UNMODIFIED_SINCE, &fresh)
+        .expect_status(Some(StatusCode::NO_CONTENT))
+        .execute()
+        .await
+        .unwrap();
+    RequestBuilder::new(client, Method::GET, &project_url)
+        .expect_status(Some(StatusCode::NOT_FOUND))
+        .execute()
+        .await
+        .unwrap();
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_etag() {
+    let cptestctx = test_setup("test_projects_etag").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let project =
+        create_project(&client, &org_name, "springfield-squidport").await;
+    let project_url =
+        format!("/organizations/{}/projects/springfield-squidport", org_name);
+    let project_id_url = format!("/by-id/projects/{}", project.identity.id);
+
+    let etag_of = |response: &TestResponse| {
+        response
+            .headers
+            .get(http::header::ETAG)
+            .expect("response had no ETag")
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    /*
+     * A plain GET returns the project along with its ETag.  Fetching it by id
+     * returns the same body, so it gets the same tag.
+     */
+    let response = RequestBuilder::new(client, Method::GET, &project_url)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    let etag = etag_of(&response);
+    let fetched: Project = response.parsed_body().unwrap();
+    assert_eq!(fetched.identity.id, project.identity.id);
+    let response = RequestBuilder::new(client, Method::GET, &project_id_url)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(etag_of(&response), etag);
+
+    /* If the client already has the current version, it gets no body. */
+    for url in [&project_url, &project_id_url] {
+        let response = RequestBuilder::new(client, Method::GET, url)
+            .header(http::header::IF_NONE_MATCH, &etag)
+            .expect_status(Some(StatusCode::NOT_MODIFIED))
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(etag_of(&response), etag);
+        assert!(response.body.is_empty());
+    }
+
+    /*
+     * Once the project changes, the old ETag is stale and the client gets the
+     * whole (new) project back, along with a new ETag.
+     */
+    let updates = params::ProjectUpdate {
+        identity: IdentityMetadataUpdateParams {
+            name: None,
+            description: Some("updated".to_string()),
+        },
+    };
+    RequestBuilder::new(client, Method::PUT, &project_url)
+        .body(Some(&updates))
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    let response = RequestBuilder::new(client, Method::GET, &project_url)
+        .header(http::header::IF_NONE_MATCH, &etag)
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+    assert_ne!(etag_of(&response), etag);
+    let updated: Project = response.parsed_body().unwrap();
+    assert_eq!(updated.identity.description, "updated");
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_get_pretty() {
+    let cptestctx = test_setup("test_projects_get_pretty").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let project =
+        create_project(&client, &org_name, "springfield-squidport").await;
+    let project_url =
+        format!("/organizations/{}/projects/springfield-squidport", org_name);
+    let project_id_url = format!("/by-id/projects/{}", project.identity.id);
+
+    /*
+     * Like the other single-resource GETs, fetching a project (by name or by
+     * id) pretty-prints the body on request, without changing its content or
+     * its ETag.
+     */
+    for url in [&project_url, &project_id_url] {
+        let compact = RequestBuilder::new(client, Method::GET, url)
+            .expect_status(Some(StatusCode::OK))
+            .execute()
+            .await
+            .unwrap();
+        assert!(!compact.body.contains(&b'\n'));
+        let pretty = RequestBuilder::new(
+            client,
+            Method::GET,
+            &format!("{}?pretty=true", url),
+        )
+        .expect_status(Some(StatusCode::OK))
+        .execute()
+        .await
+        .unwrap();
+        let pretty_body = std::str::from_utf8(&pretty.body).unwrap();
+        assert!(pretty_body.contains("\n  \"name\": \"springfield-squidport\""));
+        assert_eq!(
+            pretty.parsed_body::<serde_json::Value>().unwrap(),
+            compact.parsed_body::<serde_json::Value>().unwrap()
+        );
+        assert_eq!(
+            pretty.headers.get(http::header::ETAG),
+            compact.headers.get(http::header::ETAG)
+        );
+    }
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_bulk_delete() {
+    let cptestctx = test_setup("test_projects_bulk_delete").await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    for project_name in
+        &["cairo-airport", "springfield-squidport", "ogdenville"]
+    {
+        create_project(&client, &org_name, project_name).await;
+    }
+
+    /*
+     * Delete two of the projects, along with one that doesn't exist.  The
+     * missing one fails without stopping the others from being deleted.
+     */
+    let names: Vec<Name> =
+        ["springfield-squidport", "north-haverbrook", "ogdenville"]
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+    let results: Vec<ProjectDeleteResult> = RequestBuilder::new(
+        client,
+        Method::POST,
+        &format!("/organizations/{}/projects-delete", org_name),
+    )
+    .body(Some(&params::ProjectsDelete { names: names.clone() }))
+    .expect_status(Some(StatusCode::OK))
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        results.iter().map(|r| r.name.clone()).collect::<Vec<_>>(),
+        names
+    );
+    assert_eq!(results[0].error, None);
+    assert_eq!(
+        results[1].error.as_deref(),
+        Some("not found: project with name \"north-haverbrook\"")
+    );
+    assert_eq!(results[2].error, None);
+
+    /* Only the project we didn't ask to delete is left. */
+    let projects = objects_list_page::<Project>(
+        client,
+        &format!("/organizations/{}/projects", org_name),
+    )
+    .await
+    .items;
+    assert_eq!(projects.len(), 1);
+    assert_eq!(projects[0].identity.name, "cairo-airport");
+
+    /* If the organization doesn't exist, nothing is attempted. */
+    RequestBuilder::new(
+        client,
+        Method::POST,
+        "/organizations/no-such-org/projects-delete",
+    )
+    .body(Some(&params::ProjectsDelete { names }))
+    .expect_status(Some(StatusCode::NOT_FOUND))
+    .execute()
+    .await
+    .unwrap();
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_bulk_delete_too_many() {
+    /* Make sure the request isn't rejected just for being large. */
+    let cptestctx = test_setup_with_request_body_max_bytes(
+        "test_projects_bulk_delete_too_many",
+        16384,
+    )
+    .await;
+    let client = &cptestctx.external_client;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    create_project(&client, &org_name, "springfield-squidport").await;
+    let delete = |count: usize| params::ProjectsDelete {
+        names: std::iter::once("springfield-squidport".parse().unwrap())
+            .chain(
+                (1..count).map(|i| format!("project-{}", i).parse().unwrap()),
+            )
+            .collect(),
+    };
+    let post = |count: usize, status: StatusCode| {
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/organizations/{}/projects-delete", org_name),
+        )
+        .body(Some(&delete(count)))
+        .expect_status(Some(status))
+        .execute()
+    };
+
+    /* Asking to delete too many projects at once deletes none of them. */
+    let error: HttpErrorResponseBody =
+        post(params::PROJECTS_DELETE_MAX + 1, StatusCode::BAD_REQUEST)
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(
+        error.message,
+        format!(
+            "cannot delete more than {} projects at once (got {})",
+            params::PROJECTS_DELETE_MAX,
+            params::PROJECTS_DELETE_MAX + 1
+        )
+    );
+    let projects_url = format!("/organizations/{}/projects", org_name);
+    let projects = objects_list_page::<Project>(client, &projects_url).await;
+    assert_eq!(projects.items.len(), 1);
+
+    /* Asking to delete exactly the limit is fine. */
+    let results: Vec<ProjectDeleteResult> =
+        post(params::PROJECTS_DELETE_MAX, StatusCode::OK)
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(results.len(), params::PROJECTS_DELETE_MAX);
+    assert_eq!(results[0].error, None);
+    let projects = objects_list_page::<Project>(client, &projects_url).await;
+    assert!(projects.items.is_empty());
+
+    cptestctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_projects_generated_id_collision() {
+    let cptestctx = test_setup("test_projects_generated_id_collision").await;
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx.nexus;
+
+    let org_name = "test-org";
+    create_organization(&client, &org_name).await;
+    let projects_url = format!("/organizations/{}/projects", org_name);
+    let taken_id = create_project(&client, &org_name, "springfield-squidport")
+        .await
+        .identity
+        .id;
+
+    /*
+     * If every id Nexus comes up with is taken, it tries a few before giving
+     * up with an error that's worth retrying.
+     */
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let generator_attempts = Arc::clone(&attempts);
+    nexus.set_project_id_generator(Some(Box::new(move || {
+        generator_attempts.fetch_add(1, Ordering::SeqCst);
+        taken_id
+    })));
+    let project_create = |name: &str| params::ProjectCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: "colliding".to_string(),
+        },
+        id: None,
+    };
+    let error = project_create_error(
+        client,
+        &projects_url,
+        &project_create("cairo-airport"),
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+    .await;
+    assert_eq!(error.error_code, Some(String::from("ServiceNotAvailable")));
+    assert!(attempts.load(Ordering::SeqCst) > 1);
+    RequestBuilder::new(
+        client,
+        Method::GET,
+        &format!("{}/cairo-airport", projects_url),
+    )
+    .expect_status(Some(StatusCode::NOT_FOUND))
+    .execute()
+    .await
+    .unwrap();
+
+    /* If a later id is free, the project gets created with that one. */
+    let free_id = uuid::Uuid::new_v4();
+    let mut ids = vec![taken_id, free_id].into_iter();
+    nexus.set_project_id_generator(Some(Box::new(move || {
+        ids.next().unwrap_or_else(uuid::Uuid::new_v4)
+    })));
+    let project: Project = NexusRequest::objects_post(
+        client,
+        &projects_url,
+        &project_create("cairo-airport"),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap();
+    assert_eq!(project.identity.id, free_id);
+
+    nexus.set_project_id_generator(None);
+    cptestctx.teardown().await;
+}
+
+/**
+ * Makes an authenticated request to create a project, expecting it to fail
+ * with status `status`, and returns the error
+ */
+async fn project_create_error(
+    client: &ClientTestContext,
+    projects_url: &str,
+    params: &params::ProjectCreate,
+    status: StatusCode,
+) -> HttpErrorResponseBody {
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, projects_url)
+            .body(Some(params))
+            .expect_status(Some(status)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap()
+}
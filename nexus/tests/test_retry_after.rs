@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Tests for the test suite's handling of `Retry-After`
+
+pub mod common;
+
+use common::http_testing::RequestBuilder;
+use dropshot::endpoint;
+use dropshot::test_util::LogContext;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::HttpErrorResponseBody;
+use http::Method;
+use http::Response;
+use http::StatusCode;
+use hyper::Body;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+#[macro_use]
+extern crate slog;
+
+/// Tests that a request that's rate-limited on the first attempt succeeds once
+/// we've waited as long as the server told us to
+#[tokio::test]
+async fn test_retry_after_honored() {
+    let testctx = start_busy_server("test_retry_after_honored", 1).await;
+    let start = Instant::now();
+    let response =
+        RequestBuilder::new(&testctx.client_testctx, Method::GET, "/busy")
+            .retry_after(Duration::from_secs(5))
+            .expect_status(Some(StatusCode::OK))
+            .execute()
+            .await
+            .unwrap();
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(response.parsed_body::<String>().unwrap(), "done");
+    assert_eq!(
+        testctx.server.app_private().nrequests.load(Ordering::SeqCst),
+        2
+    );
+    testctx.teardown().await;
+}
+
+/// Tests that we give up (and report the rate-limited response) if the server
+/// asks us to wait longer than we're willing to
+#[tokio::test]
+async fn test_retry_after_cap() {
+    let testctx = start_busy_server("test_retry_after_cap", 1).await;
+    let error: HttpErrorResponseBody =
+        RequestBuilder::new(&testctx.client_testctx, Method::GET, "/busy")
+            .retry_after(Duration::from_millis(500))
+            .expect_status(Some(StatusCode::TOO_MANY_REQUESTS))
+            .execute()
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(error.message, "slow down");
+    assert_eq!(
+        testctx.server.app_private().nrequests.load(Ordering::SeqCst),
+        1
+    );
+
+    // Without a cap, we don't retry at all.
+    RequestBuilder::new(&testctx.client_testctx, Method::GET, "/busy")
+        .expect_status(Some(StatusCode::TOO_MANY_REQUESTS))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(
+        testctx.server.app_private().nrequests.load(Ordering::SeqCst),
+        2
+    );
+    testctx.teardown().await;
+}
+
+/// State for a server that rate-limits the first `nbusy` requests it receives
+struct BusyServerState {
+    nbusy: usize,
+    nrequests: AtomicUsize,
+}
+
+async fn start_busy_server(
+    test_name: &str,
+    nbusy: usize,
+) -> TestContext<BusyServerState> {
+    let config = common::load_test_config();
+    let logctx = LogContext::new(test_name, &config.log);
+
+    let mut api = ApiDescription::new();
+    api.register(busy_get).unwrap_or_else(|error| {
+        panic!("failed to register busy_get: {:#}", error)
+    });
+
+    let server_state =
+        BusyServerState { nbusy, nrequests: AtomicUsize::new(0) };
+    let log = logctx.log.new(o!());
+    TestContext::new(
+        api,
+        server_state,
+        &config.dropshot_external,
+        Some(logctx),
+        log,
+    )
+}
+
+#[endpoint {
+    method = GET,
+    path = "/busy",
+}]
+async fn busy_get(
+    rqctx: Arc<dropshot::RequestContext<BusyServerState>>,
+) -> Result<Response<Body>, dropshot::HttpError> {
+    let state = rqctx.context();
+    let nprevious = state.nrequests.fetch_add(1, Ordering::SeqCst);
+    if nprevious < state.nbusy {
+        let error = HttpErrorResponseBody {
+            request_id: rqctx.request_id.clone(),
+            error_code: None,
+            message: String::from("slow down"),
+        };
+        return Ok(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::RETRY_AFTER, "1")
+            .body(serde_json::to_string(&error).unwrap().into())?);
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string("done").unwrap().into())?)
+}
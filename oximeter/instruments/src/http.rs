@@ -161,12 +161,35 @@ impl LatencyTracker {
         R: HttpResponse,
         H: Future<Output = Result<R, HttpError>>,
         T: ServerContext,
+    {
+        self.instrument_dropshot_handler_with_status(context, handler, |_| {
+            R::metadata().success.unwrap()
+        })
+        .await
+    }
+
+    /// Instrument the given Dropshot endpoint handler function, whose successful responses may
+    /// have different status codes.
+    ///
+    /// This is like [`Self::instrument_dropshot_handler`], for handlers whose response type doesn't
+    /// determine the status code (e.g., one that may return either "200 OK" or "304 Not
+    /// Modified"). `status` returns the status code of a successful response.
+    pub async fn instrument_dropshot_handler_with_status<T, H, R, S>(
+        &self,
+        context: &RequestContext<T>,
+        handler: H,
+        status: S,
+    ) -> Result<R, HttpError>
+    where
+        H: Future<Output = Result<R, HttpError>>,
+        T: ServerContext,
+        S: FnOnce(&R) -> StatusCode,
     {
         let start = Instant::now();
         let result = handler.await;
         let latency = start.elapsed();
         let status_code = match result {
-            Ok(_) => R::metadata().success.unwrap(),
+            Ok(ref response) => status(response),
             Err(ref e) => e.status_code,
         };
         let request = context.request.lock().await;
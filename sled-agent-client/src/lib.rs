@@ -283,6 +283,31 @@ impl From<omicron_common::api::external::MacAddr> for types::MacAddr {
         Self(s.0.to_string())
     }
 }
+
+impl Client {
+    /**
+     * Fetches the serial console output of the given Instance
+     *
+     * So far only the simulated sled agent provides this, so it's not part of
+     * the generated client.
+     */
+    pub async fn instance_serial_get(
+        &self,
+        id: &Uuid,
+    ) -> anyhow::Result<Vec<String>> {
+        let url = format!("{}/instances/{}/serial", self.baseurl(), id);
+        let output = self
+            .client()
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(output)
+    }
+}
+
 /**
  * Exposes additional [`Client`] interfaces for use by the test suite. These
  * are bonus endpoints, not generated in the real client.
@@ -291,6 +316,12 @@ impl From<omicron_common::api::external::MacAddr> for types::MacAddr {
 pub trait TestInterfaces {
     async fn instance_finish_transition(&self, id: Uuid);
     async fn disk_finish_transition(&self, id: Uuid);
+    async fn instance_repair(&self, id: Uuid);
+    async fn instance_serial_output(&self, id: Uuid) -> Vec<String>;
+    async fn instance_last_error(&self, id: Uuid) -> Option<String>;
+    async fn instances_pending(&self) -> Vec<Uuid>;
+    async fn sled_set_powered_on(&self, powered_on: bool);
+    async fn sled_set_partitioned(&self, partitioned: bool);
 }
 
 #[async_trait]
@@ -316,4 +347,79 @@ impl TestInterfaces for Client {
             .await
             .expect("disk_finish_transition() failed unexpectedly");
     }
+
+    async fn instance_repair(&self, id: Uuid) {
+        let baseurl = self.baseurl();
+        let client = self.client();
+        let url = format!("{}/instances/{}/repair", baseurl, id);
+        client
+            .post(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .expect("instance_repair() failed unexpectedly");
+    }
+
+    async fn instance_serial_output(&self, id: Uuid) -> Vec<String> {
+        self.instance_serial_get(&id)
+            .await
+            .expect("instance_serial_output() failed unexpectedly")
+    }
+
+    async fn instance_last_error(&self, id: Uuid) -> Option<String> {
+        let baseurl = self.baseurl();
+        let client = self.client();
+        let url = format!("{}/instances/{}/last-error", baseurl, id);
+        client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .expect("instance_last_error() failed unexpectedly")
+            .json()
+            .await
+            .expect("instance_last_error() returned unexpected body")
+    }
+
+    async fn instances_pending(&self) -> Vec<Uuid> {
+        let baseurl = self.baseurl();
+        let client = self.client();
+        let url = format!("{}/pending-instances", baseurl);
+        client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .expect("instances_pending() failed unexpectedly")
+            .json()
+            .await
+            .expect("instances_pending() returned unexpected body")
+    }
+
+    async fn sled_set_powered_on(&self, powered_on: bool) {
+        let baseurl = self.baseurl();
+        let client = self.client();
+        let url = format!("{}/power", baseurl);
+        let power_state = if powered_on { "on" } else { "off" };
+        client
+            .put(url)
+            .json(&power_state)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .expect("sled_set_powered_on() failed unexpectedly");
+    }
+
+    async fn sled_set_partitioned(&self, partitioned: bool) {
+        let baseurl = self.baseurl();
+        let client = self.client();
+        let url = format!("{}/partition", baseurl);
+        client
+            .put(url)
+            .json(&partitioned)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .expect("sled_set_partitioned() failed unexpectedly");
+    }
 }
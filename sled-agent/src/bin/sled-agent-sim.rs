@@ -15,7 +15,8 @@ use dropshot::ConfigLogging;
 use dropshot::ConfigLoggingLevel;
 use omicron_common::cmd::fatal;
 use omicron_common::cmd::CmdError;
-use omicron_sled_agent::sim::{run_server, Config, SimMode};
+use omicron_sled_agent::common::nexus::NEXUS_TIMEOUT_MS_DEFAULT;
+use omicron_sled_agent::sim::{run_server, Config, SimMode, SimQueue};
 use std::net::SocketAddr;
 use structopt::StructOpt;
 use uuid::Uuid;
@@ -67,12 +68,18 @@ async fn do_run() -> Result<(), CmdError> {
     let config = Config {
         id: args.uuid,
         sim_mode: args.sim_mode,
+        sim_queue: SimQueue::Coalescing,
+        boot_failure: None,
         nexus_address: args.nexus_addr,
+        nexus_timeout_ms: NEXUS_TIMEOUT_MS_DEFAULT,
         dropshot: ConfigDropshot {
             bind_address: args.sled_agent_addr,
             ..Default::default()
         },
         log: ConfigLogging::StderrTerminal { level: ConfigLoggingLevel::Info },
+        watchdog: None,
+        capacity: None,
+        destroy_grace_ms: 0,
     };
 
     run_server(&config).await.map_err(CmdError::Failure)
@@ -14,7 +14,8 @@ use omicron_sled_agent::bootstrap::{
     config::Config as BootstrapConfig, server as bootstrap_server,
 };
 use omicron_sled_agent::{
-    common::vlan::VlanID, config::Config as SledConfig, server as sled_server,
+    common::nexus::NEXUS_TIMEOUT_MS_DEFAULT, common::vlan::VlanID,
+    config::Config as SledConfig, server as sled_server,
 };
 use std::net::SocketAddr;
 use structopt::StructOpt;
@@ -68,6 +69,11 @@ enum Args {
 
         #[structopt(long = "vlan")]
         vlan: Option<VlanID>,
+
+        /// Index of this sled's share of the rack secret (required, and
+        /// different for every sled in the rack).
+        #[structopt(long = "share-index")]
+        share_index: u8,
     },
 }
 
@@ -98,6 +104,7 @@ async fn do_run() -> Result<(), CmdError> {
             sled_agent_addr,
             nexus_addr,
             vlan,
+            share_index,
         } => {
             // Configure and run the Bootstrap server.
             let config = BootstrapConfig {
@@ -109,6 +116,9 @@ async fn do_run() -> Result<(), CmdError> {
                 log: ConfigLogging::StderrTerminal {
                     level: ConfigLoggingLevel::Info,
                 },
+                share_retry: Default::default(),
+                share_index,
+                discovery: Default::default(),
             };
             let boot_server = bootstrap_server::Server::start(&config)
                 .await
@@ -119,6 +129,7 @@ async fn do_run() -> Result<(), CmdError> {
             let config = SledConfig {
                 id: uuid,
                 nexus_address: nexus_addr,
+                nexus_timeout_ms: NEXUS_TIMEOUT_MS_DEFAULT,
                 dropshot: ConfigDropshot {
                     bind_address: sled_agent_addr,
                     ..Default::default()
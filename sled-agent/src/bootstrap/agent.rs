@@ -6,20 +6,26 @@
 
 use super::client::types as bootstrap_types;
 use super::client::Client as BootstrapClient;
+use super::config::ConfigDiscovery;
+use super::config::ConfigShareRetry;
 use super::discovery;
 use super::spdm::SpdmError;
-use super::views::ShareResponse;
+use super::views::{BootstrapState, BootstrapStatus, ShareResponse};
 use omicron_common::api::external::Error as ExternalError;
 use omicron_common::backoff::{
-    internal_service_policy, retry_notify, BackoffError,
+    exponential_policy, internal_service_policy, retry_notify, Backoff,
+    BackoffError,
 };
 use omicron_common::packaging::sha256_digest;
 
 use slog::Logger;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Seek, SeekFrom};
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
 use tar::Archive;
 use thiserror::Error;
 
@@ -52,6 +58,11 @@ pub enum BootstrapError {
 
     #[error("Not enough peers to unlock storage")]
     NotEnoughPeers,
+
+    #[error(
+        "Collected {collected} shares, need {threshold} to unlock storage"
+    )]
+    NotEnoughShares { collected: usize, threshold: usize },
 }
 
 impl From<BootstrapError> for ExternalError {
@@ -65,12 +76,123 @@ pub(crate) struct Agent {
     /// Debug log
     log: Logger,
     peer_monitor: discovery::PeerMonitor,
+    share_retry: ConfigShareRetry,
+    /// Index of this sled's share of the rack secret
+    share_index: u8,
+    /// Progress towards establishing trust quorum
+    status: Mutex<BootstrapStatus>,
+}
+
+/// Longest delay between attempts at a share request to a single peer
+const MAX_SHARE_RETRY_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Sends a share request to a single peer, retrying up to the limit in
+/// `retry` (with exponential backoff) so that a transient failure, like a
+/// dropped connection, doesn't count against the peer.
+async fn request_share_with_retry(
+    log: &Logger,
+    agent: &BootstrapClient,
+    retry: &ConfigShareRetry,
+) -> Result<bootstrap_types::ShareResponse, anyhow::Error> {
+    let request = bootstrap_types::ShareRequest { identity: vec![] };
+    let mut backoff = exponential_policy(
+        retry.initial_backoff(),
+        MAX_SHARE_RETRY_BACKOFF,
+        2.0,
+        0.0,
+    );
+    let mut attempt = 1;
+    loop {
+        match agent.api_request_share(&request).await {
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < retry.max_attempts => {
+                let delay = backoff
+                    .next_backoff()
+                    .expect("share request backoff policy gave up");
+                info!(
+                    log,
+                    "Bootstrap: Failed to share request with peer \
+                     (attempt {} of {}, will retry after {:?}): {:?}",
+                    attempt,
+                    retry.max_attempts,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Requests a share from each of `agents` in turn, recording progress in
+/// `status`.
+///
+/// Shares are told apart by their index, so a share returned by more than one
+/// peer (or a peer returning this sled's own share, at `index`) is only
+/// counted once.  Once every peer has responded, `status` moves to
+/// [`BootstrapState::Unlocked`] if enough shares were collected to reach its
+/// threshold; if not, it stays in [`BootstrapState::Collecting`] and
+/// [`BootstrapError::NotEnoughShares`] is returned.  If a peer fails to
+/// respond, the error is returned, leaving `status` reflecting the shares
+/// collected before then.
+async fn collect_shares(
+    log: &Logger,
+    index: u8,
+    agents: &[BootstrapClient],
+    retry: &ConfigShareRetry,
+    status: &Mutex<BootstrapStatus>,
+) -> Result<(), BootstrapError> {
+    {
+        let mut status = status.lock().unwrap();
+        status.state = BootstrapState::Collecting;
+        status.shares_collected = 1;
+    }
+    let mut indexes = BTreeSet::from([index]);
+    for agent in agents {
+        let share = request_share_with_retry(log, agent, retry).await?;
+        if !indexes.insert(share.index) {
+            warn!(
+                log,
+                "Bootstrap: Ignoring duplicate share with index {}",
+                share.index
+            );
+            continue;
+        }
+        info!(log, "Bootstrap: Shared request with peer");
+        status.lock().unwrap().shares_collected += 1;
+    }
+    let mut status = status.lock().unwrap();
+    if status.shares_collected < status.threshold {
+        return Err(BootstrapError::NotEnoughShares {
+            collected: status.shares_collected,
+            threshold: status.threshold,
+        });
+    }
+    status.state = BootstrapState::Unlocked;
+    Ok(())
 }
 
 impl Agent {
-    pub fn new(log: Logger) -> Result<Self, BootstrapError> {
-        let peer_monitor = discovery::PeerMonitor::new(&log)?;
-        Ok(Agent { log, peer_monitor })
+    pub fn new(
+        log: Logger,
+        share_retry: ConfigShareRetry,
+        share_index: u8,
+        discovery: &ConfigDiscovery,
+    ) -> Result<Self, BootstrapError> {
+        let peer_monitor = discovery::PeerMonitor::new(&log, discovery)?;
+        let status = Mutex::new(BootstrapStatus {
+            state: BootstrapState::Discovering,
+            shares_collected: 1,
+            threshold: UNLOCK_THRESHOLD,
+        });
+        Ok(Agent { log, peer_monitor, share_retry, share_index, status })
+    }
+
+    /// Reports progress towards establishing trust quorum.
+    pub fn status(&self) -> BootstrapStatus {
+        self.status.lock().unwrap().clone()
     }
 
     /// Implements the "request share" API.
@@ -81,10 +203,10 @@ impl Agent {
         // TODO-correctness: Validate identity, return whatever
         // information is necessary to establish trust quorum.
         //
-        // This current implementation is a placeholder.
+        // This current implementation is a placeholder, apart from the index.
         info!(&self.log, "request_share, received identity: {:x?}", identity);
 
-        Ok(ShareResponse { shared_secret: vec![] })
+        Ok(ShareResponse { shared_secret: vec![], index: self.share_index })
     }
 
     /// Communicates with peers, sharing secrets, until the rack has been
@@ -96,6 +218,11 @@ impl Agent {
         retry_notify(
             internal_service_policy(),
             || async {
+                {
+                    let mut status = self.status.lock().unwrap();
+                    status.state = BootstrapState::Discovering;
+                    status.shares_collected = 1;
+                }
                 let other_agents = self.peer_monitor.addrs().await;
                 info!(&self.log, "Bootstrap: Communicating with peers: {:?}", other_agents);
 
@@ -144,19 +271,12 @@ impl Agent {
                         )
                     })
                     .collect();
-                for agent in &other_agents {
-                    agent
-                        .api_request_share(&bootstrap_types::ShareRequest {
-                            identity: vec![],
-                        })
-                        .await
-                        .map_err(|e| {
-                            info!(&self.log, "Bootstrap: Failed to share request with peer: {:?}", e);
-                            BackoffError::Transient(BootstrapError::Api(e))
-                        })?;
-                        info!(&self.log, "Bootstrap: Shared request with peer");
-                }
-                Ok(())
+                collect_shares(&self.log, self.share_index, &other_agents, &self.share_retry, &self.status)
+                    .await
+                    .map_err(|e| {
+                        info!(&self.log, "Bootstrap: Failed to collect shares from peers: {:?}", e);
+                        BackoffError::Transient(e)
+                    })
             },
             |error, duration| {
                 warn!(
@@ -322,3 +442,216 @@ impl Agent {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use omicron_test_utils::dev::test_setup_log;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Pretends to be a bootstrap agent that drops the first `drops`
+    /// connections it accepts without responding, then answers one share
+    /// request with the share at `index`.
+    async fn flaky_peer(listener: TcpListener, drops: usize, index: u8) {
+        for _ in 0..drops {
+            let (sock, _) = listener.accept().await.unwrap();
+            drop(sock);
+        }
+
+        let (mut sock, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        loop {
+            let mut buf = [0u8; 1024];
+            let n = sock.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before request was read");
+            request.extend_from_slice(&buf[..n]);
+
+            let text = String::from_utf8_lossy(&request);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let content_length = text[..end]
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| {
+                        name.eq_ignore_ascii_case("content-length")
+                    })
+                    .map_or(0, |(_, value)| value.trim().parse().unwrap());
+                if request.len() >= end + 4 + content_length {
+                    break;
+                }
+            }
+        }
+
+        let body =
+            format!(r#"{{"shared_secret":[1,2,3],"index":{}}}"#, index);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n\
+             content-type: application/json\r\n\
+             content-length: {}\r\n\
+             connection: close\r\n\
+             \r\n\
+             {}",
+            body.len(),
+            body
+        );
+        sock.write_all(response.as_bytes()).await.unwrap();
+    }
+
+    async fn request_share_from_flaky_peer(
+        log: &Logger,
+        drops: usize,
+        retry: &ConfigShareRetry,
+    ) -> Result<bootstrap_types::ShareResponse, anyhow::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = tokio::spawn(flaky_peer(listener, drops, 1));
+        let client =
+            BootstrapClient::new(&format!("http://{}", addr), log.clone());
+        let result = request_share_with_retry(log, &client, retry).await;
+        peer.abort();
+        result
+    }
+
+    /// Starts one `flaky_peer` for each `(drops, index)` entry in `specs`
+    /// (dropping that many connections, then returning the share at that
+    /// index), returning the peers' tasks and clients for them.
+    async fn start_peers(
+        log: &Logger,
+        specs: &[(usize, u8)],
+    ) -> (Vec<tokio::task::JoinHandle<()>>, Vec<BootstrapClient>) {
+        let mut peers = Vec::new();
+        let mut clients = Vec::new();
+        for &(drops, index) in specs {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            peers.push(tokio::spawn(flaky_peer(listener, drops, index)));
+            clients.push(BootstrapClient::new(
+                &format!("http://{}", addr),
+                log.clone(),
+            ));
+        }
+        (peers, clients)
+    }
+
+    #[tokio::test]
+    async fn test_request_share_retries_dropped_connection() {
+        let logctx =
+            test_setup_log("test_request_share_retries_dropped_connection");
+        let retry =
+            ConfigShareRetry { max_attempts: 3, initial_backoff_ms: 10 };
+
+        let response = request_share_from_flaky_peer(&logctx.log, 1, &retry)
+            .await
+            .unwrap();
+        assert_eq!(response.shared_secret, vec![1, 2, 3]);
+        assert_eq!(response.index, 1);
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_collect_shares_status() {
+        let logctx = test_setup_log("test_collect_shares_status");
+        let retry =
+            ConfigShareRetry { max_attempts: 1, initial_backoff_ms: 10 };
+        let status = Mutex::new(BootstrapStatus {
+            state: BootstrapState::Discovering,
+            shares_collected: 1,
+            threshold: 3,
+        });
+
+        // The first peer answers, but the second doesn't, so we're left
+        // partway through collecting.
+        let (peers, clients) =
+            start_peers(&logctx.log, &[(0, 1), (1, 2)]).await;
+        let result =
+            collect_shares(&logctx.log, 0, &clients, &retry, &status).await;
+        assert!(result.is_err());
+        assert_eq!(
+            *status.lock().unwrap(),
+            BootstrapStatus {
+                state: BootstrapState::Collecting,
+                shares_collected: 2,
+                threshold: 3,
+            }
+        );
+        peers.iter().for_each(|peer| peer.abort());
+
+        // This time, both peers answer.
+        let (peers, clients) =
+            start_peers(&logctx.log, &[(0, 1), (0, 2)]).await;
+        collect_shares(&logctx.log, 0, &clients, &retry, &status)
+            .await
+            .unwrap();
+        assert_eq!(
+            *status.lock().unwrap(),
+            BootstrapStatus {
+                state: BootstrapState::Unlocked,
+                shares_collected: 3,
+                threshold: 3,
+            }
+        );
+        peers.iter().for_each(|peer| peer.abort());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_collect_shares_dedupes_index() {
+        let logctx = test_setup_log("test_collect_shares_dedupes_index");
+        let retry =
+            ConfigShareRetry { max_attempts: 1, initial_backoff_ms: 10 };
+        let status = Mutex::new(BootstrapStatus {
+            state: BootstrapState::Discovering,
+            shares_collected: 1,
+            threshold: 3,
+        });
+
+        // Both peers return the same share, which only counts once, leaving
+        // us short of the threshold.
+        let (peers, clients) =
+            start_peers(&logctx.log, &[(0, 1), (0, 1)]).await;
+        let result =
+            collect_shares(&logctx.log, 0, &clients, &retry, &status).await;
+        assert!(matches!(
+            result,
+            Err(BootstrapError::NotEnoughShares { collected: 2, threshold: 3 })
+        ));
+        assert_eq!(
+            *status.lock().unwrap(),
+            BootstrapStatus {
+                state: BootstrapState::Collecting,
+                shares_collected: 2,
+                threshold: 3,
+            }
+        );
+        peers.iter().for_each(|peer| peer.abort());
+
+        // A peer returning our own share doesn't count either.
+        let (peers, clients) =
+            start_peers(&logctx.log, &[(0, 0), (0, 1)]).await;
+        let result =
+            collect_shares(&logctx.log, 0, &clients, &retry, &status).await;
+        assert!(matches!(
+            result,
+            Err(BootstrapError::NotEnoughShares { collected: 2, threshold: 3 })
+        ));
+        peers.iter().for_each(|peer| peer.abort());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_request_share_gives_up() {
+        let logctx = test_setup_log("test_request_share_gives_up");
+        let retry =
+            ConfigShareRetry { max_attempts: 2, initial_backoff_ms: 10 };
+
+        // Both attempts land on dropped connections.
+        let result =
+            request_share_from_flaky_peer(&logctx.log, 2, &retry).await;
+        assert!(result.is_err());
+
+        logctx.cleanup_successful();
+    }
+}
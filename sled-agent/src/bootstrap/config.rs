@@ -6,10 +6,13 @@
  * Interfaces for working with bootstrap agent configuration
  */
 
+use super::multicast::Ipv6MulticastScope;
 use dropshot::ConfigDropshot;
 use dropshot::ConfigLogging;
 use serde::Deserialize;
 use serde::Serialize;
+use std::net::Ipv6Addr;
+use std::time::Duration;
 use uuid::Uuid;
 
 /**
@@ -20,4 +23,77 @@ pub struct Config {
     pub id: Uuid,
     pub dropshot: ConfigDropshot,
     pub log: ConfigLogging,
+    #[serde(default)]
+    pub share_retry: ConfigShareRetry,
+    /**
+     * index of this sled's share of the rack secret
+     *
+     * This has no default: shares are told apart by their index, so every
+     * sled in the rack needs a different one.
+     */
+    pub share_index: u8,
+    #[serde(default)]
+    pub discovery: ConfigDiscovery,
+}
+
+/**
+ * How persistently a bootstrap agent retries a share request to a single peer
+ * before giving up on that peer (for this round)
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigShareRetry {
+    /** total number of attempts, including the first */
+    pub max_attempts: u32,
+    /** delay before the first retry; doubled for each later retry */
+    pub initial_backoff_ms: u64,
+}
+
+impl ConfigShareRetry {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+}
+
+impl Default for ConfigShareRetry {
+    fn default() -> Self {
+        ConfigShareRetry { max_attempts: 3, initial_backoff_ms: 250 }
+    }
+}
+
+/**
+ * How a bootstrap agent announces itself to, and discovers, its peers
+ *
+ * Agents only discover peers that use the same multicast group, so separate
+ * groups can be used to run isolated discovery domains on one network.
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ConfigDiscovery {
+    /** IPv6 multicast group to announce on and listen to */
+    pub group: Ipv6Addr,
+    /** UDP port used for announcements */
+    pub port: u16,
+    /** index of the interface to use (0 means any interface) */
+    pub interface: u32,
+    /** how often to announce ourselves to the group */
+    pub announce_interval_ms: u64,
+}
+
+impl ConfigDiscovery {
+    pub fn announce_interval(&self) -> Duration {
+        Duration::from_millis(self.announce_interval_ms)
+    }
+}
+
+impl Default for ConfigDiscovery {
+    /** Uses the link-local all-nodes group, ff02::1 */
+    fn default() -> Self {
+        let scope = Ipv6MulticastScope::LinkLocal.first_hextet();
+        ConfigDiscovery {
+            group: Ipv6Addr::new(scope, 0, 0, 0, 0, 0, 0, 0x1),
+            port: 7645,
+            interface: 0,
+            announce_interval_ms: 5000,
+        }
+    }
 }
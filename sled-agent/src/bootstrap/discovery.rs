@@ -4,12 +4,14 @@
 
 //! Sled announcement and discovery.
 
+use super::config::ConfigDiscovery;
 use super::multicast;
 use slog::Logger;
 use std::collections::HashSet;
 use std::io;
 use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
@@ -21,33 +23,50 @@ pub struct PeerMonitor {
     _worker: JoinHandle<()>,
 }
 
+/// Returns the announcement sent (and expected) by sleds in `group`.
+///
+/// Including the group means that a sled only records announcements from its
+/// own group, even if the host delivers traffic for other groups on the same
+/// port to our listener.
+fn announcement(group: &Ipv6Addr) -> Vec<u8> {
+    // Let this message be a reminder that this content is *not*
+    // encrypted, authenticated, or otherwise verified. We're just using
+    // it as a starting point for swapping addresses.
+    format!(
+        "We've been trying to reach you about your car's extended warranty \
+         ({})",
+        group
+    )
+    .into_bytes()
+}
+
 async fn monitor_worker(
     log: Logger,
     address: SocketAddrV6,
+    interval: Duration,
     sender: UdpSocket,
     listener: UdpSocket,
     sleds: Arc<Mutex<HashSet<SocketAddr>>>,
 ) {
-    // Let this message be a reminder that this content is *not*
-    // encrypted, authenticated, or otherwise verified. We're just using
-    // it as a starting point for swapping addresses.
-    let message =
-        b"We've been trying to reach you about your car's extended warranty";
+    let message = announcement(address.ip());
     loop {
         let mut buf = vec![0u8; 128];
         tokio::select! {
-            _ = tokio::time::sleep(tokio::time::Duration::from_millis(5000)) => {
+            _ = tokio::time::sleep(interval) => {
                 info!(log, "Bootstrap Peer Monitor: Broadcasting our own address: {}", address);
-                if let Err(e) = sender.try_send_to(message, address.into()) {
+                if let Err(e) = sender.try_send_to(&message, address.into()) {
                     warn!(log, "PeerMonitor failed to broadcast: {}", e);
                 }
             }
             result = listener.recv_from(&mut buf) => {
                 match result {
-                    Ok((_, addr)) => {
+                    Ok((len, addr)) if buf[..len] == message[..] => {
                         info!(log, "Bootstrap Peer Monitor: Successfully received an address: {}", addr);
                         sleds.lock().await.insert(addr);
                     },
+                    Ok((_, addr)) => {
+                        debug!(log, "Bootstrap Peer Monitor: Ignoring announcement from another group: {}", addr);
+                    },
                     Err(e) => warn!(log, "PeerMonitor failed to receive: {}", e),
                 }
             }
@@ -56,29 +75,49 @@ async fn monitor_worker(
 }
 
 impl PeerMonitor {
-    /// Creates a new [`PeerMonitor`].
-    // TODO: Address, port, interface, etc, probably should be
-    // configuration options.
-    pub fn new(log: &Logger) -> Result<Self, io::Error> {
-        let scope = multicast::Ipv6MulticastScope::LinkLocal.first_hextet();
-        let address = SocketAddrV6::new(
-            Ipv6Addr::new(scope, 0, 0, 0, 0, 0, 0, 0x1),
-            7645,
-            0,
-            0,
-        );
+    /// Creates a new [`PeerMonitor`], which announces itself to (and
+    /// discovers peers in) the multicast group described by `config`.
+    pub fn new(
+        log: &Logger,
+        config: &ConfigDiscovery,
+    ) -> Result<Self, io::Error> {
         let loopback = false;
-        let interface = 0;
+        PeerMonitor::start(log, config, loopback)
+    }
+
+    fn start(
+        log: &Logger,
+        config: &ConfigDiscovery,
+        loopback: bool,
+    ) -> Result<Self, io::Error> {
+        if !config.group.is_multicast() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "discovery group {} is not a multicast address",
+                    config.group
+                ),
+            ));
+        }
+        let address = SocketAddrV6::new(config.group, config.port, 0, 0);
         let (sender, listener) =
-            multicast::new_ipv6_udp_pair(&address, loopback, interface)?;
+            multicast::new_ipv6_udp_pair(&address, loopback, config.interface)?;
 
         let sleds = Arc::new(Mutex::new(HashSet::new()));
         let sleds_for_worker = sleds.clone();
         let log = log.clone();
+        let interval = config.announce_interval();
 
         let worker = tokio::task::spawn(async move {
-            monitor_worker(log, address, sender, listener, sleds_for_worker)
-                .await
+            monitor_worker(
+                log,
+                address,
+                interval,
+                sender,
+                listener,
+                sleds_for_worker,
+            )
+            .await
         });
 
         Ok(PeerMonitor { sleds, _worker: worker })
@@ -91,3 +130,121 @@ impl PeerMonitor {
         self.sleds.lock().await.iter().map(|addr| *addr).collect()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use omicron_test_utils::dev::test_setup_log;
+
+    fn config(group: u16) -> ConfigDiscovery {
+        let scope = multicast::Ipv6MulticastScope::LinkLocal.first_hextet();
+        ConfigDiscovery {
+            group: Ipv6Addr::new(scope, 0, 0, 0, 0, 0, 0, group),
+            announce_interval_ms: 50,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether `listener` discovers `announcer`.
+    ///
+    /// Only the announcer's traffic is looped back to this host, so anything
+    /// `listener` discovers came from `announcer`.
+    async fn discovers(
+        log: &Logger,
+        announcer: &ConfigDiscovery,
+        listener: &ConfigDiscovery,
+    ) -> bool {
+        let _announcer = PeerMonitor::start(log, announcer, true).unwrap();
+        let listener = PeerMonitor::start(log, listener, false).unwrap();
+        for _ in 0..20 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if !listener.addrs().await.is_empty() {
+                return true;
+            }
+        }
+        false
+    }
+
+    // NOTE: These tests are ignored by default, for the same reason as the
+    // test in the "multicast" module.  They can still be run locally with:
+    //
+    // $ cargo test -p omicron-sled-agent -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_discovery_same_group() {
+        let logctx = test_setup_log("test_discovery_same_group");
+        assert!(discovers(&logctx.log, &config(0x1), &config(0x1)).await);
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_discovery_different_groups() {
+        let logctx = test_setup_log("test_discovery_different_groups");
+        assert!(!discovers(&logctx.log, &config(0x1), &config(0x2)).await);
+        logctx.cleanup_successful();
+    }
+
+    // Unlike the tests above, this one doesn't need multicast: it hands the
+    // worker sockets bound to the loopback address and sends announcements to
+    // its listener directly.
+    #[tokio::test]
+    async fn test_discovery_ignores_other_groups_loopback() {
+        let logctx =
+            test_setup_log("test_discovery_ignores_other_groups_loopback");
+        let localhost = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        let listener = UdpSocket::bind(localhost).await.unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+        let sender = UdpSocket::bind(localhost).await.unwrap();
+
+        let ours = config(0x1);
+        let theirs = config(0x2);
+        let address = SocketAddrV6::new(ours.group, ours.port, 0, 0);
+        let sleds = Arc::new(Mutex::new(HashSet::new()));
+        let worker = tokio::task::spawn(monitor_worker(
+            logctx.log.clone(),
+            address,
+            Duration::from_secs(3600),
+            sender,
+            listener,
+            sleds.clone(),
+        ));
+
+        // Announce from one socket as a member of another group, and from
+        // another as a member of ours.
+        let stranger = UdpSocket::bind(localhost).await.unwrap();
+        let peer = UdpSocket::bind(localhost).await.unwrap();
+        stranger
+            .send_to(&announcement(&theirs.group), listener_addr)
+            .await
+            .unwrap();
+        peer.send_to(&announcement(&ours.group), listener_addr).await.unwrap();
+
+        // Datagrams on the loopback interface arrive in order, so once the
+        // peer shows up, the stranger's announcement has been handled too.
+        let peer_addr = peer.local_addr().unwrap();
+        for _ in 0..100 {
+            if sleds.lock().await.contains(&peer_addr) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let found = sleds.lock().await.clone();
+        assert_eq!(found, [peer_addr].iter().cloned().collect());
+
+        worker.abort();
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_discovery_rejects_unicast_group() {
+        let logctx = test_setup_log("test_discovery_rejects_unicast_group");
+        let config = ConfigDiscovery {
+            group: Ipv6Addr::LOCALHOST,
+            ..Default::default()
+        };
+        let error = PeerMonitor::new(&logctx.log, &config).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        logctx.cleanup_successful();
+    }
+}
@@ -31,22 +31,26 @@ use dropshot::HttpResponseOk;
 use dropshot::RequestContext;
 use dropshot::TypedBody;
 use omicron_common::api::external::Error as ExternalError;
+use omicron_common::api::registration::EndpointRegistrar;
 use std::sync::Arc;
 
 use super::agent::Agent;
-use super::{params::ShareRequest, views::ShareResponse};
+use super::params::ShareRequest;
+use super::views::{BootstrapStatus, ShareResponse};
 
 /// Returns a description of the bootstrap agent API
 pub(crate) fn ba_api() -> ApiDescription<Arc<Agent>> {
     fn register_endpoints(
-        api: &mut ApiDescription<Arc<Agent>>,
+        api: &mut EndpointRegistrar<'_, Arc<Agent>>,
     ) -> Result<(), String> {
         api.register(api_request_share)?;
+        api.register(api_status)?;
         Ok(())
     }
 
     let mut api = ApiDescription::new();
-    if let Err(err) = register_endpoints(&mut api) {
+    if let Err(err) = register_endpoints(&mut EndpointRegistrar::new(&mut api))
+    {
         panic!("failed to register entrypoints: {}", err);
     }
     api
@@ -70,3 +74,15 @@ async fn api_request_share(
             .map_err(|e| ExternalError::from(e))?,
     ))
 }
+
+/// Reports progress towards establishing trust quorum.
+#[endpoint {
+    method = GET,
+    path = "/status",
+}]
+async fn api_status(
+    rqctx: Arc<RequestContext<Arc<Agent>>>,
+) -> Result<HttpResponseOk<BootstrapStatus>, HttpError> {
+    let bootstrap_agent = rqctx.context();
+    Ok(HttpResponseOk(bootstrap_agent.status()))
+}
@@ -10,6 +10,7 @@ use p256::elliptic_curve::subtle::ConstantTimeEq;
 use p256::{NonZeroScalar, ProjectivePoint, Scalar, SecretKey};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use vsss_rs::{Feldman, FeldmanVerifier, Share};
 
 /// A `RackSecret` is a shared secret used to perform a "rack-level" unlock.
@@ -69,6 +70,83 @@ impl Verifier {
     pub fn verify(&self, share: &Share) -> bool {
         self.verifier.verify(share)
     }
+
+    /// Returns whether `secret` is the secret whose shares this verifies.
+    ///
+    /// The first Feldman commitment is the generator times the secret.
+    pub fn verify_secret(&self, secret: &RackSecret) -> bool {
+        match self.verifier.commitments.get(0) {
+            Some(commitment) => (self.verifier.generator * secret.as_ref())
+                .ct_eq(commitment)
+                .into(),
+            None => false,
+        }
+    }
+}
+
+/// Describes errors which may occur while reconstructing a [`RackSecret`] with
+/// [`RackSecret::combine`].
+#[derive(Error, Debug)]
+pub enum CombineError {
+    #[error(
+        "Only {valid} valid shares, need {threshold} (skipped invalid shares \
+         {skipped:?})"
+    )]
+    NotEnoughShares { threshold: usize, valid: usize, skipped: Vec<u8> },
+
+    #[error("Error combining shares: {0:?}")]
+    Combine(vsss_rs::Error),
+
+    #[error("Reconstructed secret does not match the verifier")]
+    Mismatch,
+}
+
+/// A [`RackSecret`] reconstructed by [`RackSecret::combine`], along with which
+/// shares went into it
+pub struct Combined {
+    pub secret: RackSecret,
+    /// identifiers of the shares that were combined, in the order given
+    pub share_ids: Vec<u8>,
+    /// identifiers of the shares that failed verification and were left out
+    pub skipped: Vec<u8>,
+}
+
+/// The shares of a split [`RackSecret`], along with the parameters of the
+/// split.
+///
+/// Whoever collects shares to reconstruct the secret needs to know how many
+/// are enough; that's the `threshold`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Shares {
+    shares: Vec<Share>,
+    threshold: usize,
+    total: usize,
+}
+
+impl Shares {
+    /// Gathers `shares` of a secret that was split into `total` shares, any
+    /// `threshold` of which recover it
+    ///
+    /// This is for whoever collects shares one at a time (e.g., from peers
+    /// during rack unlock) and wants to [`RackSecret::combine`] them.
+    pub fn new(shares: Vec<Share>, threshold: usize, total: usize) -> Shares {
+        Shares { shares, threshold, total }
+    }
+
+    /// The shares themselves
+    pub fn shares(&self) -> &[Share] {
+        &self.shares
+    }
+
+    /// How many of the shares must be combined to recover the secret
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// How many shares the secret was split into
+    pub fn total(&self) -> usize {
+        self.total
+    }
 }
 
 impl RackSecret {
@@ -85,11 +163,14 @@ impl RackSecret {
         &self,
         threshold: usize,
         total_shares: usize,
-    ) -> Result<(Vec<Share>, Verifier), vsss_rs::Error> {
+    ) -> Result<(Shares, Verifier), vsss_rs::Error> {
         let mut rng = OsRng::default();
         let (shares, verifier) = Feldman { t: threshold, n: total_shares }
             .split_secret(*self.as_ref(), None, &mut rng)?;
-        Ok((shares, Verifier { verifier }))
+        Ok((
+            Shares { shares, threshold, total: total_shares },
+            Verifier { verifier },
+        ))
     }
 
     /// Combine a set of shares and return a RackSecret
@@ -104,6 +185,53 @@ impl RackSecret {
         let sk = SecretKey::from(nzs);
         Ok(RackSecret { secret: sk.to_secret_scalar() })
     }
+
+    /// Reconstruct a secret from `shares`, checking both the shares and the
+    /// result against `verifier`
+    ///
+    /// Shares that fail verification are skipped, and the next valid ones are
+    /// used instead, until `shares.threshold()` valid shares are found. Unlike
+    /// [`RackSecret::combine_shares`], this reports which shares were used and
+    /// which were skipped, for auditing, and won't return a secret that
+    /// doesn't match the verifier.
+    pub fn combine(
+        shares: &Shares,
+        verifier: &Verifier,
+    ) -> Result<Combined, CombineError> {
+        let threshold = shares.threshold();
+        let mut valid = Vec::with_capacity(threshold);
+        let mut skipped = Vec::new();
+        for share in shares.shares() {
+            if valid.len() == threshold {
+                break;
+            }
+            if verifier.verify(share) {
+                valid.push(share.clone());
+            } else {
+                skipped.push(share.identifier());
+            }
+        }
+        if valid.len() < threshold {
+            return Err(CombineError::NotEnoughShares {
+                threshold,
+                valid: valid.len(),
+                skipped,
+            });
+        }
+
+        let secret =
+            RackSecret::combine_shares(threshold, shares.total(), &valid)
+                .map_err(CombineError::Combine)?;
+        if !verifier.verify_secret(&secret) {
+            return Err(CombineError::Mismatch);
+        }
+
+        Ok(Combined {
+            secret,
+            share_ids: valid.iter().map(|s| s.identifier()).collect(),
+            skipped,
+        })
+    }
 }
 
 impl AsRef<Scalar> for RackSecret {
@@ -146,7 +274,113 @@ mod tests {
     fn create_and_verify() {
         let secret = RackSecret::new();
         let (shares, verifier) = secret.split(3, 5).unwrap();
-        verify(&secret, &verifier, &shares);
+        verify(&secret, &verifier, shares.shares());
+    }
+
+    #[test]
+    fn combine_reports_shares_used() {
+        let secret = RackSecret::new();
+        let (shares, verifier) = secret.split(3, 5).unwrap();
+        let all = shares.shares();
+
+        // Only the first three shares (the threshold) are used.
+        let subset = Shares::new(
+            vec![
+                all[4].clone(),
+                all[1].clone(),
+                all[2].clone(),
+                all[0].clone(),
+            ],
+            shares.threshold(),
+            shares.total(),
+        );
+        let combined = RackSecret::combine(&subset, &verifier).unwrap();
+        assert_eq!(combined.secret, secret);
+        assert_eq!(
+            combined.share_ids,
+            vec![all[4].identifier(), all[1].identifier(), all[2].identifier()]
+        );
+        assert!(combined.skipped.is_empty());
+        assert!(verifier.verify_secret(&combined.secret));
+
+        // Too few shares can't be combined.
+        let too_few = Shares::new(subset.shares()[..2].to_vec(), 3, 5);
+        assert!(matches!(
+            RackSecret::combine(&too_few, &verifier),
+            Err(CombineError::NotEnoughShares { threshold: 3, valid: 2, .. })
+        ));
+
+        // Shares of a different secret don't pass verification.
+        let (other_shares, other_verifier) =
+            RackSecret::new().split(3, 5).unwrap();
+        match RackSecret::combine(&other_shares, &verifier) {
+            Err(CombineError::NotEnoughShares {
+                valid: 0, skipped, ..
+            }) => {
+                assert_eq!(skipped.len(), 5);
+            }
+            _ => panic!("combined shares of a different secret"),
+        }
+        assert!(!other_verifier.verify_secret(&secret));
+    }
+
+    #[test]
+    fn combine_skips_invalid_shares() {
+        let secret = RackSecret::new();
+        let (shares, verifier) = secret.split(3, 5).unwrap();
+        let (other_shares, _) = RackSecret::new().split(3, 5).unwrap();
+        let all = shares.shares();
+        let bad = &other_shares.shares()[0];
+
+        // The bad share is skipped, and the next valid share takes its place.
+        let mixed = Shares::new(
+            vec![
+                all[0].clone(),
+                bad.clone(),
+                all[1].clone(),
+                all[2].clone(),
+                all[3].clone(),
+            ],
+            shares.threshold(),
+            shares.total(),
+        );
+        let combined = RackSecret::combine(&mixed, &verifier).unwrap();
+        assert_eq!(combined.secret, secret);
+        assert_eq!(
+            combined.share_ids,
+            vec![all[0].identifier(), all[1].identifier(), all[2].identifier()]
+        );
+        assert_eq!(combined.skipped, vec![bad.identifier()]);
+
+        // Skipping leaves too few valid shares.
+        let short = Shares::new(
+            vec![all[0].clone(), bad.clone(), all[1].clone()],
+            shares.threshold(),
+            shares.total(),
+        );
+        match RackSecret::combine(&short, &verifier) {
+            Err(CombineError::NotEnoughShares {
+                valid: 2, skipped, ..
+            }) => {
+                assert_eq!(skipped, vec![bad.identifier()]);
+            }
+            _ => panic!("combined too few valid shares"),
+        }
+    }
+
+    #[test]
+    fn shares_remember_split_parameters() {
+        let secret = RackSecret::new();
+        let (shares, _) = secret.split(3, 5).unwrap();
+        assert_eq!(shares.threshold(), 3);
+        assert_eq!(shares.total(), 5);
+        assert_eq!(shares.shares().len(), 5);
+
+        // The parameters survive serialization along with the shares.
+        let shares_s = bincode::serialize(&shares).unwrap();
+        let shares2: Shares = bincode::deserialize(&shares_s).unwrap();
+        assert_eq!(shares2.threshold(), 3);
+        assert_eq!(shares2.total(), 5);
     }
 
     #[test]
@@ -160,7 +394,7 @@ mod tests {
         let secret = RackSecret::new();
         let (shares, verifier) = secret.split(3, 5).unwrap();
         let verifier_s = bincode::serialize(&verifier).unwrap();
-        let shares_s = bincode::serialize(&shares).unwrap();
+        let shares_s = bincode::serialize(shares.shares()).unwrap();
 
         let shares2: Vec<Share> = bincode::deserialize(&shares_s).unwrap();
         let verifier2: Verifier = bincode::deserialize(&verifier_s).unwrap();
@@ -28,8 +28,15 @@ impl Server {
             "component" => "Agent",
             "server" => config.id.clone().to_string()
         ));
-        let bootstrap_agent =
-            Arc::new(Agent::new(ba_log).map_err(|e| e.to_string())?);
+        let bootstrap_agent = Arc::new(
+            Agent::new(
+                ba_log,
+                config.share_retry.clone(),
+                config.share_index,
+                &config.discovery,
+            )
+            .map_err(|e| e.to_string())?,
+        );
 
         let ba = Arc::clone(&bootstrap_agent);
         let dropshot_log = log.new(o!("component" => "dropshot"));
@@ -3,6 +3,8 @@
 use spdm::{requester::RequesterError, responder::ResponderError};
 use thiserror::Error;
 
+use super::{SpdmVersion, VersionRange};
+
 /// Describes errors that arise from use of the SPDM protocol library
 #[derive(Error, Debug)]
 pub enum SpdmError {
@@ -17,6 +19,17 @@ pub enum SpdmError {
 
     #[error("invalid state transition: expected {expected}, got {got}")]
     InvalidState { expected: &'static str, got: &'static str },
+
+    #[error(
+        "version negotiation failed: we support {ours}, peer offered {theirs:?}"
+    )]
+    VersionMismatch { ours: VersionRange, theirs: Vec<SpdmVersion> },
+
+    #[error("frame exceeds maximum length of {max} bytes")]
+    FrameTooLarge { len: Option<usize>, max: usize },
+
+    #[error("malformed {0} message")]
+    Malformed(&'static str),
 }
 
 impl From<RequesterError> for SpdmError {
@@ -2,27 +2,115 @@
 //! algorithms, and credentials.
 //!
 //! Sled agents run the SPDM protocol over a tokio TCP stream with a 2 byte size
-//! header for framing.
+//! header for framing.  Tests can run it over an in-memory stream instead (see
+//! [`Transport::pair`]).
 
 mod error;
 mod requester;
 mod responder;
 
+use std::fmt;
 use std::io::{Error, ErrorKind};
 
 use bytes::{Bytes, BytesMut};
 use futures::{SinkExt, StreamExt};
 use slog::Logger;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::{
+    Framed, LengthDelimitedCodec, LengthDelimitedCodecError,
+};
 
 // 2^16 - 2 bytes for a header
 const MAX_BUF_SIZE: usize = 65534;
 
 pub use error::SpdmError;
 
+/// An SPDM protocol version (e.g., 1.1)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpdmVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl SpdmVersion {
+    pub const fn new(major: u8, minor: u8) -> SpdmVersion {
+        SpdmVersion { major, minor }
+    }
+
+    /// Decodes the SPDMVersion field at the start of every SPDM message,
+    /// which holds the major version in the high nibble and the minor version
+    /// in the low nibble.
+    fn from_header(byte: u8) -> SpdmVersion {
+        SpdmVersion::new(byte >> 4, byte & 0xf)
+    }
+
+    /// Decodes one version number entry of a VERSION response.  The major
+    /// and minor versions are the top two nibbles; we ignore the update and
+    /// alpha versions.
+    fn from_entry(entry: u16) -> SpdmVersion {
+        SpdmVersion::new((entry >> 12) as u8, ((entry >> 8) & 0xf) as u8)
+    }
+}
+
+impl fmt::Display for SpdmVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The (inclusive) range of SPDM versions that a requester or responder is
+/// willing to use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: SpdmVersion,
+    pub max: SpdmVersion,
+}
+
+impl VersionRange {
+    pub fn contains(&self, version: SpdmVersion) -> bool {
+        self.min <= version && version <= self.max
+    }
+}
+
+impl Default for VersionRange {
+    /// All published 1.x versions of the protocol
+    fn default() -> Self {
+        VersionRange {
+            min: SpdmVersion::new(1, 0),
+            max: SpdmVersion::new(1, 2),
+        }
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.min, self.max)
+    }
+}
+
+/// Returns the versions listed in a VERSION response.
+///
+/// The response is a 4 byte SPDM header, a reserved byte, a count of version
+/// number entries, and then the entries themselves (2 bytes each, little
+/// endian).
+fn parse_version_response(msg: &[u8]) -> Result<Vec<SpdmVersion>, SpdmError> {
+    let count = *msg.get(5).ok_or(SpdmError::Malformed("VERSION"))? as usize;
+    let entries =
+        msg.get(6..6 + 2 * count).ok_or(SpdmError::Malformed("VERSION"))?;
+    Ok(entries
+        .chunks_exact(2)
+        .map(|e| SpdmVersion::from_entry(u16::from_le_bytes([e[0], e[1]])))
+        .collect())
+}
+
+/// A byte stream that a [`Transport`] can carry frames over
+trait ByteStream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> ByteStream for T {}
+
 pub struct Transport {
-    framed: Framed<TcpStream, LengthDelimitedCodec>,
+    framed: Framed<Box<dyn ByteStream>, LengthDelimitedCodec>,
+    max_frame_len: usize,
 }
 
 impl Transport {
@@ -30,28 +118,188 @@ impl Transport {
     #[allow(dead_code)]
     pub const HEADER_LEN: usize = 2;
 
+    /// The largest payload whose length fits in the header
+    pub const MAX_FRAME_LEN: usize = (1 << (8 * Self::HEADER_LEN)) - 1;
+
     #[allow(dead_code)]
     pub fn new(sock: TcpStream) -> Transport {
+        Transport::with_max_frame_len(sock, MAX_BUF_SIZE)
+    }
+
+    /// Creates a transport that refuses to send or receive frames with more
+    /// than `max_frame_len` bytes of payload.
+    ///
+    /// On receipt, the length prefix is checked before anything is read into
+    /// memory, so a peer can't make us allocate more than this.  A limit
+    /// above [`Transport::MAX_FRAME_LEN`] is lowered to that, since no larger
+    /// frame can be framed anyway.
+    #[allow(dead_code)]
+    pub fn with_max_frame_len(
+        sock: TcpStream,
+        max_frame_len: usize,
+    ) -> Transport {
+        Transport::from_stream(Box::new(sock), max_frame_len)
+    }
+
+    /// Returns a pair of transports connected to each other in memory, each
+    /// refusing to send or receive frames with more than `max_frame_len`
+    /// bytes of payload.
+    ///
+    /// This lets tests exercise whatever runs over a transport without real
+    /// sockets.
+    #[cfg(test)]
+    pub fn pair(max_frame_len: usize) -> (Transport, Transport) {
+        let (stream1, stream2) =
+            tokio::io::duplex(Self::HEADER_LEN + max_frame_len);
+        (
+            Transport::from_stream(Box::new(stream1), max_frame_len),
+            Transport::from_stream(Box::new(stream2), max_frame_len),
+        )
+    }
+
+    fn from_stream(
+        stream: Box<dyn ByteStream>,
+        max_frame_len: usize,
+    ) -> Transport {
+        let max_frame_len = max_frame_len.min(Self::MAX_FRAME_LEN);
         Transport {
             framed: LengthDelimitedCodec::builder()
                 .length_field_length(Self::HEADER_LEN)
-                .new_framed(sock),
+                .max_frame_length(max_frame_len)
+                .new_framed(stream),
+            max_frame_len,
         }
     }
 
     pub async fn send(&mut self, data: &[u8]) -> Result<(), SpdmError> {
+        if data.len() > self.max_frame_len {
+            return Err(SpdmError::FrameTooLarge {
+                len: Some(data.len()),
+                max: self.max_frame_len,
+            });
+        }
         let data = Bytes::copy_from_slice(data);
         self.framed.send(data).await.map_err(|e| e.into())
     }
 
     pub async fn recv(&mut self, log: &Logger) -> Result<BytesMut, SpdmError> {
-        if let Some(rsp) = self.framed.next().await {
-            let rsp = rsp?;
-            debug!(log, "Received {:x?}", &rsp[..]);
-            Ok(rsp)
-        } else {
-            Err(Error::new(ErrorKind::ConnectionAborted, "SPDM channel closed")
-                .into())
+        match self.framed.next().await {
+            Some(Ok(rsp)) => {
+                debug!(log, "Received {:x?}", &rsp[..]);
+                Ok(rsp)
+            }
+            // The codec reports an oversized length prefix as invalid data
+            // wrapping its own error type.  (It doesn't tell us what the
+            // length was.)  Any other invalid data is passed through as is.
+            Some(Err(e))
+                if e.kind() == ErrorKind::InvalidData
+                    && e.get_ref().map_or(false, |inner| {
+                        inner.is::<LengthDelimitedCodecError>()
+                    }) =>
+            {
+                Err(SpdmError::FrameTooLarge {
+                    len: None,
+                    max: self.max_frame_len,
+                })
+            }
+            Some(Err(e)) => Err(e.into()),
+            None => Err(Error::new(
+                ErrorKind::ConnectionAborted,
+                "SPDM channel closed",
+            )
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::Drain;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    pub(super) fn test_log() -> Logger {
+        let decorator = slog_term::TermDecorator::new().build();
+        let drain = slog_term::FullFormat::new(decorator).build().fuse();
+        let drain = slog_async::Async::new(drain).build().fuse();
+        slog::Logger::root(drain, o!("component" => "spdm"))
+    }
+
+    /// Returns a pair of connected transports, with the given maximum frame
+    /// lengths.
+    async fn transport_pair(
+        max_frame_len1: usize,
+        max_frame_len2: usize,
+    ) -> (Transport, Transport) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sock1, accepted) =
+            tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (sock2, _) = accepted.unwrap();
+        (
+            Transport::with_max_frame_len(sock1.unwrap(), max_frame_len1),
+            Transport::with_max_frame_len(sock2, max_frame_len2),
+        )
+    }
+
+    #[tokio::test]
+    async fn frames_within_limit() {
+        let log = test_log();
+        let (mut t1, mut t2) = transport_pair(64, 64).await;
+        let data = [0xau8; 64];
+        t1.send(&data).await.unwrap();
+        assert_eq!(&t2.recv(&log).await.unwrap()[..], &data[..]);
+    }
+
+    #[tokio::test]
+    async fn frames_in_memory() {
+        let log = test_log();
+        let (mut t1, mut t2) = Transport::pair(64);
+        t1.send(&[0xau8; 64]).await.unwrap();
+        t2.send(&[0xbu8; 32]).await.unwrap();
+        assert_eq!(&t2.recv(&log).await.unwrap()[..], &[0xau8; 64][..]);
+        assert_eq!(&t1.recv(&log).await.unwrap()[..], &[0xbu8; 32][..]);
+
+        // Once one end goes away, the other sees the channel close.
+        drop(t1);
+        assert!(t2.recv(&log).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_over_limit() {
+        let (mut t1, _t2) = transport_pair(64, 64).await;
+        match t1.send(&[0u8; 65]).await {
+            Err(SpdmError::FrameTooLarge { len: Some(65), max: 64 }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn limit_clamped_to_header() {
+        let (mut t1, _t2) =
+            transport_pair(Transport::MAX_FRAME_LEN + 1000, 64).await;
+        assert_eq!(Transport::MAX_FRAME_LEN, 65535);
+        assert_eq!(t1.max_frame_len, Transport::MAX_FRAME_LEN);
+
+        // A frame that doesn't fit the header is refused before sending.
+        let data = vec![0u8; Transport::MAX_FRAME_LEN + 1];
+        match t1.send(&data).await {
+            Err(SpdmError::FrameTooLarge { len: Some(len), max })
+                if len == Transport::MAX_FRAME_LEN + 1
+                    && max == Transport::MAX_FRAME_LEN => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_over_limit() {
+        let log = test_log();
+        let (mut t1, mut t2) = transport_pair(MAX_BUF_SIZE, 64).await;
+        t1.send(&[0u8; 65]).await.unwrap();
+        match t2.recv(&log).await {
+            Err(SpdmError::FrameTooLarge { len: None, max: 64 }) => (),
+            result => panic!("unexpected result: {:?}", result),
         }
     }
 }
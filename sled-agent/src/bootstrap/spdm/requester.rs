@@ -8,7 +8,10 @@ use spdm::{
     Transcript,
 };
 
-use super::{SpdmError, Transport, MAX_BUF_SIZE};
+use super::{
+    parse_version_response, SpdmError, SpdmVersion, Transport, VersionRange,
+    MAX_BUF_SIZE,
+};
 
 // A `Ctx` contains shared types for use by a requester task
 struct Ctx {
@@ -16,15 +19,17 @@ struct Ctx {
     log: Logger,
     transport: Transport,
     transcript: Transcript,
+    versions: VersionRange,
 }
 
 impl Ctx {
-    fn new(log: Logger, transport: Transport) -> Ctx {
+    fn new(log: Logger, transport: Transport, versions: VersionRange) -> Ctx {
         Ctx {
             buf: [0u8; MAX_BUF_SIZE],
             log,
             transport,
             transcript: Transcript::new(),
+            versions,
         }
     }
 
@@ -41,6 +46,14 @@ impl Ctx {
         let rsp = self.transport.recv(&self.log).await?;
         debug!(self.log, "Requester received VERSION");
 
+        let offered = parse_version_response(&rsp[..])?;
+        if !offered.iter().any(|v| self.versions.contains(*v)) {
+            return Err(SpdmError::VersionMismatch {
+                ours: self.versions,
+                theirs: offered,
+            });
+        }
+
         state.handle_msg(&rsp[..], &mut self.transcript).map_err(|e| e.into())
     }
 
@@ -68,6 +81,19 @@ impl Ctx {
 
         let rsp = self.transport.recv(&self.log).await?;
         debug!(self.log, "Requester received CAPABILITIES");
+
+        // CAPABILITIES is the first response made with the version that the
+        // responder settled on.  Make sure it's one we're willing to use.
+        let selected = SpdmVersion::from_header(
+            *rsp.first().ok_or(SpdmError::Malformed("CAPABILITIES"))?,
+        );
+        if !self.versions.contains(selected) {
+            return Err(SpdmError::VersionMismatch {
+                ours: self.versions,
+                theirs: vec![selected],
+            });
+        }
+
         state.handle_msg(&rsp, &mut self.transcript).map_err(|e| e.into())
     }
 
@@ -117,9 +143,16 @@ impl Ctx {
 /// The protocol operates over a TCP stream framed with a 2 byte size
 /// header. Requesters and Responders are decoupled from whether the endpoint of
 /// a socket is a TCP client or server.
+///
+/// Negotiation fails with [`SpdmError::VersionMismatch`] unless the responder
+/// offers at least one version in `versions`, and then uses one of those.
 #[allow(dead_code)]
-pub async fn run(log: Logger, transport: Transport) -> Result<(), SpdmError> {
-    let mut ctx = Ctx::new(log, transport);
+pub async fn run(
+    log: Logger,
+    transport: Transport,
+    versions: VersionRange,
+) -> Result<(), SpdmError> {
+    let mut ctx = Ctx::new(log, transport, versions);
 
     info!(ctx.log, "Requester starting version negotiation");
     let state = ctx.negotiate_version().await?;
@@ -140,18 +173,40 @@ pub async fn run(log: Logger, transport: Transport) -> Result<(), SpdmError> {
 mod tests {
     use std::net::SocketAddr;
 
-    use slog::Drain;
     use tokio::net::{TcpListener, TcpStream};
 
     use super::super::responder;
+    use super::super::tests::test_log;
     use super::*;
 
+    /// Runs a responder and a requester against each other, returning what
+    /// each side returned.
+    async fn negotiate(
+        requester_versions: VersionRange,
+        responder_versions: VersionRange,
+    ) -> (Result<(), SpdmError>, Result<(), SpdmError>) {
+        let log = test_log();
+        let log2 = log.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (sock, _) = listener.accept().await.unwrap();
+            let transport = Transport::new(sock);
+            responder::run(log, transport, responder_versions).await
+        });
+
+        let sock = TcpStream::connect(addr).await.unwrap();
+        let transport = Transport::new(sock);
+        let requester_result = run(log2, transport, requester_versions).await;
+
+        (requester_result, handle.await.unwrap())
+    }
+
     #[tokio::test]
     async fn negotiation() {
-        let decorator = slog_term::TermDecorator::new().build();
-        let drain = slog_term::FullFormat::new(decorator).build().fuse();
-        let drain = slog_async::Async::new(drain).build().fuse();
-        let log = slog::Logger::root(drain, o!("component" => "spdm"));
+        let log = test_log();
         let log2 = log.clone();
 
         let addr: SocketAddr = "127.0.0.1:9999".parse().unwrap();
@@ -160,13 +215,95 @@ mod tests {
         let handle = tokio::spawn(async move {
             let (sock, _) = listener.accept().await.unwrap();
             let transport = Transport::new(sock);
-            responder::run(log, transport).await.unwrap();
+            responder::run(log, transport, VersionRange::default())
+                .await
+                .unwrap();
         });
 
         let sock = TcpStream::connect(addr).await.unwrap();
         let transport = Transport::new(sock);
-        run(log2, transport).await.unwrap();
+        run(log2, transport, VersionRange::default()).await.unwrap();
+
+        handle.await.unwrap();
+    }
 
+    #[tokio::test]
+    async fn negotiation_in_memory() {
+        let log = test_log();
+        let log2 = log.clone();
+        let (transport1, transport2) = Transport::pair(MAX_BUF_SIZE);
+
+        let handle = tokio::spawn(async move {
+            responder::run(log, transport1, VersionRange::default()).await
+        });
+        run(log2, transport2, VersionRange::default()).await.unwrap();
+
+        handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn negotiation_requester_version_mismatch() {
+        let v2 = SpdmVersion::new(2, 0);
+        let requester_versions = VersionRange { min: v2, max: v2 };
+        let (requester_result, responder_result) =
+            negotiate(requester_versions, VersionRange::default()).await;
+
+        match requester_result {
+            Err(SpdmError::VersionMismatch { ours, theirs }) => {
+                assert_eq!(ours, requester_versions);
+                assert!(!theirs.is_empty());
+                assert!(theirs.iter().all(|v| v.major == 1));
+            }
+            result => panic!("unexpected requester result: {:?}", result),
+        }
+
+        // The responder sees the requester hang up.
+        assert!(responder_result.is_err());
+    }
+
+    #[tokio::test]
+    async fn negotiation_responder_version_mismatch() {
+        let v2 = SpdmVersion::new(2, 0);
+        let responder_versions = VersionRange { min: v2, max: v2 };
+        let (requester_result, responder_result) =
+            negotiate(VersionRange::default(), responder_versions).await;
+
+        match responder_result {
+            Err(SpdmError::VersionMismatch { ours, theirs }) => {
+                assert_eq!(ours, responder_versions);
+                assert_eq!(theirs.len(), 1);
+                assert!(VersionRange::default().contains(theirs[0]));
+            }
+            result => panic!("unexpected responder result: {:?}", result),
+        }
+        assert!(requester_result.is_err());
+    }
+
+    #[tokio::test]
+    async fn negotiation_responder_selects_unoffered_version() {
+        let log = test_log();
+        let (mut responder, requester) = Transport::pair(MAX_BUF_SIZE);
+
+        // A responder that offers only 1.1 in its VERSION response, but then
+        // answers GET_CAPABILITIES as if it had settled on 2.0.
+        let handle = tokio::spawn(async move {
+            let log = test_log();
+            responder.recv(&log).await.unwrap();
+            responder
+                .send(&[0x10, 0x04, 0, 0, 0, 1, 0x00, 0x11])
+                .await
+                .unwrap();
+            responder.recv(&log).await.unwrap();
+            responder.send(&[0x20, 0x61, 0, 0]).await.unwrap();
+        });
+
+        match run(log, requester, VersionRange::default()).await {
+            Err(SpdmError::VersionMismatch { ours, theirs }) => {
+                assert_eq!(ours, VersionRange::default());
+                assert_eq!(theirs, vec![SpdmVersion::new(2, 0)]);
+            }
+            result => panic!("unexpected requester result: {:?}", result),
+        }
         handle.await.unwrap();
     }
 }
@@ -4,7 +4,7 @@ use spdm::msgs::capabilities::{Capabilities, RspFlags};
 use spdm::responder::{self, algorithms, capabilities, id_auth};
 use spdm::Transcript;
 
-use super::{SpdmError, Transport, MAX_BUF_SIZE};
+use super::{SpdmError, SpdmVersion, Transport, VersionRange, MAX_BUF_SIZE};
 
 // A `Ctx` contains shared types for use by a responder task
 struct Ctx {
@@ -12,15 +12,17 @@ struct Ctx {
     log: Logger,
     transport: Transport,
     transcript: Transcript,
+    versions: VersionRange,
 }
 
 impl Ctx {
-    fn new(log: Logger, transport: Transport) -> Ctx {
+    fn new(log: Logger, transport: Transport, versions: VersionRange) -> Ctx {
         Ctx {
             buf: [0u8; MAX_BUF_SIZE],
             log,
             transport,
             transcript: Transcript::new(),
+            versions,
         }
     }
 
@@ -57,6 +59,19 @@ impl Ctx {
         };
 
         let req = self.transport.recv(&self.log).await?;
+
+        // GET_CAPABILITIES is the first request made with the version that
+        // the requester selected from our VERSION response.
+        let selected = SpdmVersion::from_header(
+            *req.first().ok_or(SpdmError::Malformed("GET_CAPABILITIES"))?,
+        );
+        if !self.versions.contains(selected) {
+            return Err(SpdmError::VersionMismatch {
+                ours: self.versions,
+                theirs: vec![selected],
+            });
+        }
+
         let (data, transition) = state.handle_msg(
             supported,
             &req[..],
@@ -116,9 +131,16 @@ impl Ctx {
 /// The protocol operates over a TCP stream framed with a 2 byte size
 /// header. Requesters and Responders are decoupled from whether the endpoint of
 /// a socket is a TCP client or server.
+///
+/// Negotiation fails with [`SpdmError::VersionMismatch`] unless the requester
+/// selects a version in `versions`.
 #[allow(dead_code)]
-pub async fn run(log: Logger, transport: Transport) -> Result<(), SpdmError> {
-    let mut ctx = Ctx::new(log, transport);
+pub async fn run(
+    log: Logger,
+    transport: Transport,
+    versions: VersionRange,
+) -> Result<(), SpdmError> {
+    let mut ctx = Ctx::new(log, transport, versions);
 
     info!(ctx.log, "Responder starting version negotiation");
     let state = ctx.negotiate_version().await?;
@@ -12,4 +12,29 @@ use serde::{Deserialize, Serialize};
 pub struct ShareResponse {
     // TODO-completeness: format TBD; currently opaque.
     pub shared_secret: Vec<u8>,
+    /// Index of this share among the shares of the rack secret.  Two responses
+    /// with the same index carry the same share.
+    pub index: u8,
+}
+
+/// Where a bootstrap agent is in establishing trust quorum.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BootstrapState {
+    /// Looking for enough peers to start collecting shares.
+    Discovering,
+    /// Requesting shares from peers.
+    Collecting,
+    /// Enough shares have been collected to reconstruct the rack secret.
+    Unlocked,
+}
+
+/// Progress of a bootstrap agent towards establishing trust quorum.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BootstrapStatus {
+    pub state: BootstrapState,
+    /// Number of shares collected so far, including this sled's own.
+    pub shares_collected: usize,
+    /// Number of shares needed to reconstruct the rack secret.
+    pub threshold: usize,
 }
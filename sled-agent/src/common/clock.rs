@@ -0,0 +1,77 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sources of the current time, for timestamping state changes
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::sync::Mutex;
+
+/// Reports the current time.
+///
+/// Code that records when something happened takes one of these, rather than
+/// calling [`Utc::now()`] directly, so that tests can control what time it
+/// is.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's real-time clock.
+#[derive(Debug)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    /// Returns a clock stopped at `start`.
+    pub fn new(start: DateTime<Utc>) -> ManualClock {
+        ManualClock { now: Mutex::new(start) }
+    }
+
+    /// Moves the clock to `now`.  (This may move it backwards.)
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Moves the clock forward by `delta`.
+    pub fn advance(&self, delta: chrono::Duration) {
+        *self.now.lock().unwrap() += delta;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clock, ManualClock};
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_manual_clock() {
+        let start = Utc.ymd(2021, 12, 1).and_hms(0, 0, 0);
+        let clock = ManualClock::new(start);
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::seconds(90));
+        assert_eq!(clock.now(), Utc.ymd(2021, 12, 1).and_hms(0, 1, 30));
+
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
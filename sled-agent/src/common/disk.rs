@@ -4,12 +4,13 @@
 
 //! Describes the states of network-attached storage.
 
+use super::clock::{Clock, RealClock};
 use crate::params::DiskStateRequested;
-use chrono::Utc;
 use omicron_common::api::external::DiskState;
 use omicron_common::api::external::Error;
 use omicron_common::api::internal::nexus::DiskRuntimeState;
 use propolis_client::api::DiskAttachmentState as PropolisDiskState;
+use std::sync::Arc;
 use uuid::Uuid;
 
 /// Action to be taken on behalf of state transition.
@@ -26,11 +27,22 @@ pub enum Action {
 pub struct DiskStates {
     current: DiskRuntimeState,
     desired: Option<DiskStateRequested>,
+    /// source of the timestamps recorded with each state change
+    clock: Arc<dyn Clock>,
 }
 
 impl DiskStates {
     pub fn new(current: DiskRuntimeState) -> Self {
-        DiskStates { current, desired: None }
+        DiskStates::new_with_clock(current, Arc::new(RealClock))
+    }
+
+    /// Like [`DiskStates::new()`], but timestamps state changes using `clock`
+    /// rather than the real time.
+    pub fn new_with_clock(
+        current: DiskRuntimeState,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        DiskStates { current, desired: None, clock }
     }
 
     /// Returns the current disk state.
@@ -59,20 +71,60 @@ impl DiskStates {
         None
     }
 
+    /// Checks whether the disk can move from its current state to the
+    /// requested "target" state, returning an error describing why not if it
+    /// can't.
+    ///
+    /// All of the rules about which requests are legal live here, so that the
+    /// rest of this type only has to carry out requests that pass.
+    pub fn validate_request(
+        &self,
+        target: &DiskStateRequested,
+    ) -> Result<(), Error> {
+        let current = &self.current.disk_state;
+        let message = match (target, current) {
+            // A disk can only be attached to one instance at a time.
+            (
+                DiskStateRequested::Attached(requested),
+                DiskState::Attaching(attached) | DiskState::Attached(attached),
+            ) if requested != attached => format!(
+                "cannot attach disk to instance {}: disk is {} to instance {}",
+                requested, current, attached
+            ),
+            (
+                DiskStateRequested::Attached(requested),
+                DiskState::Detaching(_)
+                | DiskState::Destroyed
+                | DiskState::Faulted,
+            ) => format!(
+                "cannot attach disk to instance {}: disk is {}",
+                requested, current
+            ),
+            (
+                DiskStateRequested::Detached,
+                DiskState::Destroyed | DiskState::Faulted,
+            ) => format!("cannot detach disk: disk is {}", current),
+            _ => return Ok(()),
+        };
+        Err(Error::InvalidRequest { message })
+    }
+
     /// Attempts to move from the current state to the requested "target" state.
     ///
     /// On success, returns the action, if any, which is necessary to carry
-    /// out this state transition.
+    /// out this state transition.  See [`DiskStates::validate_request`] for
+    /// which requests fail.
     pub fn request_transition(
         &mut self,
         target: &DiskStateRequested,
     ) -> Result<Option<Action>, Error> {
-        match target {
+        self.validate_request(target)?;
+        Ok(match target {
             DiskStateRequested::Detached => self.request_detach(),
             DiskStateRequested::Attached(uuid) => self.request_attach(*uuid),
             DiskStateRequested::Destroyed => self.request_destroy(),
             DiskStateRequested::Faulted => self.request_fault(),
-        }
+        })
     }
 
     // Transitions to a new DiskState value, updating the timestamp and
@@ -88,17 +140,20 @@ impl DiskStates {
         self.current = DiskRuntimeState {
             disk_state: next,
             gen: self.current.gen.next(),
-            time_updated: Utc::now(),
+            time_updated: self.clock.now(),
         };
         self.desired = desired;
     }
 
-    fn request_detach(&mut self) -> Result<Option<Action>, Error> {
+    // The request_*() functions below carry out requests that have already
+    // passed validate_request().
+
+    fn request_detach(&mut self) -> Option<Action> {
         match self.current.disk_state {
             // Already detached or can detach immediately.
             DiskState::Creating | DiskState::Detached => {
                 self.transition(DiskState::Detached, None);
-                return Ok(None);
+                None
             }
             // Currently attached - enter detached through detaching.
             DiskState::Attaching(uuid)
@@ -108,81 +163,162 @@ impl DiskStates {
                     DiskState::Detaching(uuid),
                     Some(DiskStateRequested::Detached),
                 );
-                return Ok(Some(Action::Detach(uuid)));
+                Some(Action::Detach(uuid))
             }
-            // Cannot detach.
             DiskState::Destroyed | DiskState::Faulted => {
-                return Err(Error::InvalidRequest {
-                    message: format!(
-                        "cannot detach from {}",
-                        self.current.disk_state
-                    ),
-                });
+                unreachable!("detach from {}", self.current.disk_state)
             }
-        };
+        }
     }
 
-    fn request_attach(&mut self, uuid: Uuid) -> Result<Option<Action>, Error> {
+    fn request_attach(&mut self, uuid: Uuid) -> Option<Action> {
         match self.current.disk_state {
-            // Currently attached - only legal to attach to current ID
-            // (which is a no-op anyway).
-            DiskState::Attaching(id) | DiskState::Attached(id) => {
-                if uuid != id {
-                    return Err(Error::InvalidRequest {
-                        message: "disk is already attached".to_string(),
-                    });
-                }
-                return Ok(None);
-            }
+            // Currently attached to this instance - nothing to do.
+            DiskState::Attaching(_) | DiskState::Attached(_) => None,
             // Not attached - enter attached through attaching.
             DiskState::Creating | DiskState::Detached => {
                 self.transition(
                     DiskState::Attaching(uuid),
                     Some(DiskStateRequested::Attached(uuid)),
                 );
-                return Ok(Some(Action::Attach(uuid)));
+                Some(Action::Attach(uuid))
             }
-            // Cannot attach.
             DiskState::Detaching(_)
             | DiskState::Destroyed
             | DiskState::Faulted => {
-                return Err(Error::InvalidRequest {
-                    message: format!(
-                        "cannot attach from {}",
-                        self.current.disk_state
-                    ),
-                });
+                unreachable!("attach from {}", self.current.disk_state)
             }
         }
     }
 
-    fn request_destroy(&mut self) -> Result<Option<Action>, Error> {
+    fn request_destroy(&mut self) -> Option<Action> {
         if self.current.disk_state.is_attached() {
             let id = *self.current.disk_state.attached_instance_id().unwrap();
             self.transition(
                 DiskState::Detaching(id),
                 Some(DiskStateRequested::Destroyed),
             );
-            return Ok(Some(Action::Detach(id)));
+            Some(Action::Detach(id))
         } else {
             self.transition(DiskState::Destroyed, None);
-            return Ok(Some(Action::Destroy));
+            Some(Action::Destroy)
         }
     }
 
-    fn request_fault(&mut self) -> Result<Option<Action>, Error> {
+    fn request_fault(&mut self) -> Option<Action> {
         if self.current.disk_state.is_attached() {
             let id = *self.current.disk_state.attached_instance_id().unwrap();
             self.transition(
                 DiskState::Detaching(id),
                 Some(DiskStateRequested::Faulted),
             );
-            return Ok(Some(Action::Detach(id)));
+            Some(Action::Detach(id))
         } else {
             self.transition(DiskState::Faulted, None);
             // Unlike "destroy", no action necessary to identify that
             // the attached disk is now faulted.
-            return Ok(None);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Action, DiskStates};
+    use crate::params::DiskStateRequested;
+    use chrono::Utc;
+    use omicron_common::api::external::{DiskState, Error, Generation};
+    use omicron_common::api::internal::nexus::DiskRuntimeState;
+
+    fn make_disk(disk_state: DiskState) -> DiskStates {
+        DiskStates::new(DiskRuntimeState {
+            disk_state,
+            gen: Generation::new(),
+            time_updated: Utc::now(),
+        })
+    }
+
+    fn expect_invalid(error: Error, expected_message: &str) {
+        match error {
+            Error::InvalidRequest { message } => {
+                assert_eq!(message, expected_message)
+            }
+            _ => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_attach() {
+        let instance_id = uuid::Uuid::new_v4();
+        let mut disk = make_disk(DiskState::Detached);
+        let target = DiskStateRequested::Attached(instance_id);
+        disk.validate_request(&target).unwrap();
+        let action = disk.request_transition(&target).unwrap();
+        assert!(
+            matches!(action, Some(Action::Attach(id)) if id == instance_id)
+        );
+        assert_eq!(
+            disk.current().disk_state,
+            DiskState::Attaching(instance_id)
+        );
+        assert_eq!(disk.desired(), &Some(target.clone()));
+
+        // Asking again for the same instance is fine, and does nothing.
+        let gen = disk.current().gen;
+        assert!(disk.request_transition(&target).unwrap().is_none());
+        assert_eq!(disk.current().gen, gen);
+    }
+
+    #[test]
+    fn test_attach_to_wrong_instance() {
+        let attached_id = uuid::Uuid::new_v4();
+        let other_id = uuid::Uuid::new_v4();
+        let target = DiskStateRequested::Attached(other_id);
+
+        for state in [
+            DiskState::Attaching(attached_id),
+            DiskState::Attached(attached_id),
+        ] {
+            let mut disk = make_disk(state.clone());
+            let expected = format!(
+                "cannot attach disk to instance {}: disk is {} to instance {}",
+                other_id, state, attached_id
+            );
+            expect_invalid(
+                disk.validate_request(&target).unwrap_err(),
+                &expected,
+            );
+            expect_invalid(
+                disk.request_transition(&target).unwrap_err(),
+                &expected,
+            );
+            assert_eq!(disk.current().disk_state, state);
+            assert_eq!(disk.current().gen, Generation::new());
+        }
+    }
+
+    #[test]
+    fn test_invalid_from_unusable_states() {
+        let instance_id = uuid::Uuid::new_v4();
+
+        let mut disk = make_disk(DiskState::Detaching(instance_id));
+        expect_invalid(
+            disk.request_transition(&DiskStateRequested::Attached(instance_id))
+                .unwrap_err(),
+            &format!(
+                "cannot attach disk to instance {}: disk is detaching",
+                instance_id
+            ),
+        );
+
+        for state in [DiskState::Destroyed, DiskState::Faulted] {
+            let mut disk = make_disk(state.clone());
+            expect_invalid(
+                disk.request_transition(&DiskStateRequested::Detached)
+                    .unwrap_err(),
+                &format!("cannot detach disk: disk is {}", state),
+            );
+            assert_eq!(disk.current().disk_state, state);
         }
     }
 }
@@ -4,13 +4,14 @@
 
 //! Describes the states of VM instances.
 
-use chrono::Utc;
+use super::clock::{Clock, RealClock};
 use omicron_common::api::external::Error;
 use omicron_common::api::external::InstanceState;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
 use omicron_common::api::internal::sled_agent::InstanceStateRequested;
 use propolis_client::api::InstanceState as PropolisInstanceState;
+use std::sync::Arc;
 
 /// Action to be taken on behalf of state transition.
 #[derive(Clone, Debug, PartialEq)]
@@ -58,11 +59,28 @@ pub struct InstanceStates {
 
     // Desired state, which we will attempt to poke the VM towards.
     desired: Option<InstanceRuntimeStateRequested>,
+
+    // Source of the timestamps recorded with each state change.
+    clock: Arc<dyn Clock>,
 }
 
 impl InstanceStates {
     pub fn new(current: InstanceRuntimeState) -> Self {
-        InstanceStates { current, desired: None }
+        InstanceStates::new_with_clock(current, Arc::new(RealClock))
+    }
+
+    /// Like [`InstanceStates::new()`], but timestamps state changes using
+    /// `clock` rather than the real time.
+    pub fn new_with_clock(
+        current: InstanceRuntimeState,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        InstanceStates { current, desired: None, clock }
+    }
+
+    /// Returns the clock used to timestamp state changes.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
     }
 
     /// Returns the current instance state.
@@ -149,7 +167,7 @@ impl InstanceStates {
     ) {
         self.current.run_state = next;
         self.current.gen = self.current.gen.next();
-        self.current.time_updated = Utc::now();
+        self.current.time_updated = self.clock.now();
         self.desired = desired
             .map(|run_state| InstanceRuntimeStateRequested { run_state });
     }
@@ -160,10 +178,12 @@ impl InstanceStates {
             InstanceState::Running
             | InstanceState::Starting
             | InstanceState::Rebooting => return Ok(None),
-            // Valid states for a running request
+            // Valid states for a running request.  An instance that's being
+            // repaired boots the same way as any other stopped instance.
             InstanceState::Creating
             | InstanceState::Stopping
-            | InstanceState::Stopped => {
+            | InstanceState::Stopped
+            | InstanceState::Repairing => {
                 self.transition(
                     InstanceState::Starting,
                     Some(InstanceStateRequested::Running),
@@ -171,9 +191,7 @@ impl InstanceStates {
                 return Ok(Some(Action::Run));
             }
             // Invalid states for a running request
-            InstanceState::Repairing
-            | InstanceState::Failed
-            | InstanceState::Destroyed => {
+            InstanceState::Failed | InstanceState::Destroyed => {
                 return Err(Error::InvalidRequest {
                     message: format!(
                         "cannot run instance in state \"{}\"",
@@ -313,6 +331,21 @@ mod test {
         verify_state(&instance, State::Starting, Some(Requested::Running));
     }
 
+    #[test]
+    fn test_running_from_repairing() {
+        let mut instance = make_instance();
+        assert_eq!(None, instance.observe_transition(&Observed::Repairing));
+        verify_state(&instance, State::Repairing, None);
+
+        assert_eq!(
+            Action::Run,
+            instance.request_transition(Requested::Running).unwrap().unwrap()
+        );
+        verify_state(&instance, State::Starting, Some(Requested::Running));
+        assert_eq!(None, instance.observe_transition(&Observed::Running));
+        verify_state(&instance, State::Running, None);
+    }
+
     #[test]
     fn test_reboot() {
         let mut instance = make_instance();
@@ -4,6 +4,8 @@
 
 //! Code shared between the "real" and "sim" Sled Agents.
 
+pub mod clock;
 pub mod disk;
 pub mod instance;
+pub mod nexus;
 pub mod vlan;
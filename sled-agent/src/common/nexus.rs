@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Support for talking to Nexus
+
+use std::time::Duration;
+
+/// Default timeout (in milliseconds) for requests to Nexus
+pub const NEXUS_TIMEOUT_MS_DEFAULT: u64 = 60_000;
+
+/// Returns an HTTP client for use by a `NexusClient`, whose requests fail if
+/// they haven't completed within `timeout`.
+///
+/// Without a timeout, a hung Nexus would block whatever is waiting on the
+/// request indefinitely.  A request that times out produces an error that
+/// converts to a (retryable) [`Error::ServiceUnavailable`].
+///
+/// [`Error::ServiceUnavailable`]: omicron_common::api::external::Error::ServiceUnavailable
+pub fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::ClientBuilder::new()
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .build()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::http_client;
+    use nexus_client::types::SledAgentStartupInfo;
+    use nexus_client::Client as NexusClient;
+    use omicron_common::api::external::Error;
+    use omicron_test_utils::dev::test_setup_log;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_request_times_out() {
+        let logctx = test_setup_log("test_request_times_out");
+
+        /*
+         * Stand in for a Nexus that accepts connections but never responds to
+         * anything sent over them.
+         */
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut connections = Vec::new();
+            loop {
+                let (connection, _) = listener.accept().await.unwrap();
+                connections.push(connection);
+            }
+        });
+
+        let nexus_client = NexusClient::new_with_client(
+            &format!("http://{}", address),
+            http_client(Duration::from_millis(100)),
+            logctx.log.clone(),
+        );
+        let request = nexus_client.cpapi_sled_agents_post(
+            &Uuid::new_v4(),
+            &SledAgentStartupInfo { sa_address: "127.0.0.1:0".to_string() },
+        );
+        let error = tokio::time::timeout(Duration::from_secs(30), request)
+            .await
+            .expect("request to unresponsive Nexus hung")
+            .expect_err("request to unresponsive Nexus succeeded");
+        let error = Error::from(error);
+        assert!(error.retryable(), "unexpected error: {:?}", error);
+
+        server.abort();
+        logctx.cleanup_successful();
+    }
+}
@@ -8,6 +8,7 @@ use crate::common::vlan::VlanID;
 use dropshot::ConfigDropshot;
 use dropshot::ConfigLogging;
 use std::net::SocketAddr;
+use std::time::Duration;
 use uuid::Uuid;
 
 /// Configuration for a sled agent
@@ -17,6 +18,8 @@ pub struct Config {
     pub id: Uuid,
     /// IP address and TCP port for Nexus instance
     pub nexus_address: SocketAddr,
+    /// How long (in milliseconds) to wait for requests to Nexus to complete
+    pub nexus_timeout_ms: u64,
     /// Configuration for the sled agent dropshot server
     pub dropshot: ConfigDropshot,
     /// Configuration for the sled agent debug log
@@ -24,3 +27,9 @@ pub struct Config {
     /// Optional VLAN ID to be used for tagging guest VNICs.
     pub vlan: Option<VlanID>,
 }
+
+impl Config {
+    pub fn nexus_timeout(&self) -> Duration {
+        Duration::from_millis(self.nexus_timeout_ms)
+    }
+}
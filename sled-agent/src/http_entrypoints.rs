@@ -15,6 +15,7 @@ use dropshot::TypedBody;
 use omicron_common::api::internal::nexus::DiskRuntimeState;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use omicron_common::api::internal::sled_agent::InstanceEnsureBody;
+use omicron_common::api::registration::EndpointRegistrar;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use std::sync::Arc;
@@ -26,14 +27,17 @@ type SledApiDescription = ApiDescription<SledAgent>;
 
 /// Returns a description of the sled agent API
 pub fn api() -> SledApiDescription {
-    fn register_endpoints(api: &mut SledApiDescription) -> Result<(), String> {
+    fn register_endpoints(
+        api: &mut EndpointRegistrar<'_, SledAgent>,
+    ) -> Result<(), String> {
         api.register(instance_put)?;
         api.register(disk_put)?;
         Ok(())
     }
 
     let mut api = SledApiDescription::new();
-    if let Err(err) = register_endpoints(&mut api) {
+    if let Err(err) = register_endpoints(&mut EndpointRegistrar::new(&mut api))
+    {
         panic!("failed to register entrypoints: {}", err);
     }
     api
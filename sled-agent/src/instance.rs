@@ -220,6 +220,8 @@ struct InstanceInner {
     // Internal State management
     state: InstanceStates,
     running_state: Option<RunningState>,
+    // Set if [`Instance::start`] returned an error.
+    start_failed: bool,
 
     // Connection to Nexus
     nexus_client: Arc<NexusClient>,
@@ -422,6 +424,7 @@ impl Instance {
             vlan,
             state: InstanceStates::new(initial.runtime),
             running_state: None,
+            start_failed: false,
             nexus_client,
         };
 
@@ -431,9 +434,24 @@ impl Instance {
     }
 
     /// Begins the execution of the instance's service (Propolis).
+    ///
+    /// A failure is recorded on the instance, so that later calls to
+    /// [`Instance::transition`] report it instead of waiting for a start that
+    /// will never finish.
     pub async fn start(&self, ticket: InstanceTicket) -> Result<(), Error> {
         let mut inner = self.inner.lock().await;
+        let result = self.start_locked(&mut inner, ticket).await;
+        if result.is_err() {
+            inner.start_failed = true;
+        }
+        result
+    }
 
+    async fn start_locked(
+        &self,
+        inner: &mut InstanceInner,
+        ticket: InstanceTicket,
+    ) -> Result<(), Error> {
         // Create the VNIC which will be attached to the zone.
         //
         // It would be preferable to use the UUID of the instance as a component
@@ -621,14 +639,23 @@ impl Instance {
     ///
     /// Returns the new state after starting the transition.
     ///
-    /// # Panics
-    ///
-    /// This method may panic if it has been invoked before [`Instance::start`].
+    /// `InstanceManager::ensure` releases its own lock before calling
+    /// [`Instance::start`], so a concurrent request for the same instance can
+    /// get here first.  That request fails with a retryable error, leaving the
+    /// state alone, rather than recording a transition that the instance can't
+    /// carry out yet.  If [`Instance::start`] already failed, there is nothing
+    /// to wait for, and the request fails with a non-retryable error instead.
     pub async fn transition(
         &self,
         target: InstanceRuntimeStateRequested,
     ) -> Result<InstanceRuntimeState, Error> {
         let mut inner = self.inner.lock().await;
+        if inner.start_failed {
+            return Err(Error::internal_error("instance failed to start"));
+        }
+        if inner.running_state.is_none() {
+            return Err(Error::unavail("instance is still starting"));
+        }
         if let Some(action) =
             inner.state.request_transition(target.run_state)?
         {
@@ -1099,9 +1126,6 @@ mod test {
 
     #[tokio::test]
     #[serial_test::serial]
-    #[should_panic(
-        expected = "Propolis client should be initialized before usage"
-    )]
     async fn transition_before_start() {
         let log = logger();
         let nic_id_allocator = IdAllocator::new();
@@ -1117,12 +1141,60 @@ mod test {
         )
         .unwrap();
 
-        // Trying to transition before the instance has been initialized will
-        // result in a panic.
-        inst.transition(InstanceRuntimeStateRequested {
-            run_state: InstanceStateRequested::Running,
-        })
-        .await
+        // Trying to transition before the instance has been initialized fails
+        // with a retryable error, and doesn't change the instance's state.
+        let error = inst
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::ServiceUnavailable { .. }));
+        assert!(error.retryable());
+        assert_eq!(
+            inst.inner.lock().await.state.current().run_state,
+            InstanceState::Creating
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn transition_after_failed_start() {
+        let log = logger();
+        let nic_id_allocator = IdAllocator::new();
+        let nexus_client = MockNexusClient::default();
+
+        let inst = Instance::new(
+            log.clone(),
+            test_uuid(),
+            nic_id_allocator,
+            new_initial_instance(),
+            None,
+            Arc::new(nexus_client),
+        )
         .unwrap();
+
+        // Fail the start before any resources have been created.
+        let dladm_find_physical_ctx = MockDladm::find_physical_context();
+        dladm_find_physical_ctx
+            .expect()
+            .times(1)
+            .returning(|| Err(Error::internal_error("no physical link")));
+        inst.start(InstanceTicket::null(test_uuid())).await.unwrap_err();
+
+        // Once the start has failed, transitions fail without asking the
+        // caller to retry, and don't change the instance's state.
+        let error = inst
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InternalError { .. }));
+        assert!(!error.retryable());
+        assert_eq!(
+            inst.inner.lock().await.state.current().run_state,
+            InstanceState::Creating
+        );
     }
 }
@@ -173,6 +173,10 @@ impl InstanceManager {
         // Additionally, this makes it possible to manage the "instance_ticket",
         // which might need to grab the lock to remove the instance during
         // teardown.
+        //
+        // A concurrent call for the same instance may therefore reach
+        // "transition" before this "start" does; "transition" rejects that
+        // call with a retryable error.
         if let Some(instance_ticket) = maybe_instance_ticket {
             instance.start(instance_ticket).await?;
         }
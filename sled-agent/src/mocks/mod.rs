@@ -13,6 +13,11 @@ use uuid::Uuid;
 mock! {
     pub NexusClient {
         pub fn new(server_addr: &str, log: Logger) -> Self;
+        pub fn new_with_client(
+            server_addr: &str,
+            client: reqwest::Client,
+            log: Logger,
+        ) -> Self;
         pub async fn cpapi_sled_agents_post(
             &self,
             id: &Uuid,
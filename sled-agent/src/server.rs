@@ -7,6 +7,7 @@
 use super::config::Config;
 use super::http_entrypoints::api as http_api;
 use super::sled_agent::SledAgent;
+use crate::common::nexus;
 
 use omicron_common::backoff::{
     internal_service_policy, retry_notify, BackoffError,
@@ -36,8 +37,9 @@ impl Server {
         info!(log, "setting up sled agent server");
 
         let client_log = log.new(o!("component" => "NexusClient"));
-        let nexus_client = Arc::new(NexusClient::new(
+        let nexus_client = Arc::new(NexusClient::new_with_client(
             &format!("http://{}", config.nexus_address),
+            nexus::http_client(config.nexus_timeout()),
             client_log,
         ));
 
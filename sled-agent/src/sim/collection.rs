@@ -7,6 +7,8 @@
  */
 
 use super::config::SimMode;
+use super::config::SimQueue;
+use crate::common::clock::{Clock, RealClock};
 
 use futures::channel::mpsc::Receiver;
 use futures::channel::mpsc::Sender;
@@ -14,14 +16,52 @@ use futures::lock::Mutex;
 use futures::stream::StreamExt;
 use nexus_client::Client as NexusClient;
 use omicron_common::api::external::Error;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
 use slog::Logger;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
 use super::simulatable::Simulatable;
 
+/**
+ * Decides, using a seedable random number generator, whether each simulated
+ * transition that can fail (see [`Simulatable::transition_can_fail`]) does so
+ */
+#[derive(Debug)]
+pub struct SimFailures {
+    probability: f64,
+    rng: StdRng,
+}
+
+impl SimFailures {
+    /**
+     * Returns a `SimFailures` that fails transitions with the given
+     * `probability`, which must be between 0 and 1.  The same `seed` always
+     * produces the same sequence of outcomes.
+     */
+    pub fn new(probability: f64, seed: u64) -> Result<SimFailures, String> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(format!(
+                "failure probability must be between 0 and 1 (found {})",
+                probability
+            ));
+        }
+        Ok(SimFailures { probability, rng: StdRng::seed_from_u64(seed) })
+    }
+
+    /** Returns whether the next transition that can fail should fail. */
+    fn should_fail(&mut self) -> bool {
+        self.rng.gen_bool(self.probability)
+    }
+}
+
 /**
  * Simulates an object of type `S: Simulatable`.
  *
@@ -36,6 +76,10 @@ struct SimObject<S: Simulatable> {
     log: Logger,
     /// Tx-side of a channel used to notify when async state changes begin
     channel_tx: Option<Sender<()>>,
+    /// How notifications on `channel_tx` are queued
+    queue: SimQueue,
+    /// When the object began its current asynchronous transition, if any
+    time_transition_started: Option<Instant>,
 }
 
 /**
@@ -70,14 +114,23 @@ impl<S: Simulatable> SimObject<S> {
     fn new_simulated_auto(
         initial_state: &S::CurrentState,
         log: Logger,
+        queue: SimQueue,
+        clock: Arc<dyn Clock>,
+        config: S::Config,
     ) -> (SimObject<S>, Receiver<()>) {
         info!(log, "created"; "initial_state" => ?initial_state);
-        let (tx, rx) = futures::channel::mpsc::channel(SIM_CHANNEL_BUFFER_SIZE);
+        let buffer_size = match queue {
+            SimQueue::Coalescing => SIM_CHANNEL_BUFFER_SIZE,
+            SimQueue::Bounded(size) => size,
+        };
+        let (tx, rx) = futures::channel::mpsc::channel(buffer_size);
         (
             SimObject {
-                object: S::new(initial_state.clone()),
+                object: S::new(initial_state.clone(), clock, config),
                 log,
                 channel_tx: Some(tx),
+                queue,
+                time_transition_started: None,
             },
             rx,
         )
@@ -92,12 +145,16 @@ impl<S: Simulatable> SimObject<S> {
     fn new_simulated_explicit(
         initial_state: &S::CurrentState,
         log: Logger,
+        clock: Arc<dyn Clock>,
+        config: S::Config,
     ) -> SimObject<S> {
         info!(log, "created"; "initial_state" => ?initial_state);
         SimObject {
-            object: S::new(initial_state.clone()),
+            object: S::new(initial_state.clone(), clock, config),
             log,
             channel_tx: None,
+            queue: SimQueue::Coalescing,
+            time_transition_started: None,
         }
     }
 
@@ -105,6 +162,11 @@ impl<S: Simulatable> SimObject<S> {
      * Begin a transition to the requested object state `target`.  On success,
      * returns whatever requested state change was dropped (because it was
      * replaced), if any.  This is mainly used for testing.
+     *
+     * With [`SimQueue::Bounded`], this fails with
+     * [`Error::ServiceUnavailable`] if the background task's queue is full.
+     * The requested transition has still been recorded in that case and will
+     * be simulated when the background task catches up.
      */
     fn transition(
         &mut self,
@@ -134,7 +196,9 @@ impl<S: Simulatable> SimObject<S> {
          *     All we need to guarantee in the first place is that the receiver
          *     will receive a message at least once after this function is
          *     invoked.  If there's already a message in the buffer, we don't
-         *     need to do anything else to achieve that.
+         *     need to do anything else to achieve that.  The exception is
+         *     `SimQueue::Bounded`, where the caller asked to be told about
+         *     this.
          *
          * (2) We fail to send the message because the channel is disconnected.
          *     This would be a programmer error -- the contract between us and
@@ -148,22 +212,46 @@ impl<S: Simulatable> SimObject<S> {
          *     but that's not currently the case.
          */
         if self.object.desired().is_some() {
+            self.time_transition_started = Some(Instant::now());
             if let Some(ref mut tx) = self.channel_tx {
                 let result = tx.try_send(());
                 if let Err(error) = result {
                     assert!(!error.is_disconnected());
                     assert!(error.is_full());
+                    if let SimQueue::Bounded(_) = self.queue {
+                        return Err(Error::unavail(
+                            "simulated transition queue is full",
+                        ));
+                    }
                 }
             }
+        } else {
+            self.time_transition_started = None;
         }
 
         Ok(dropped)
     }
 
     fn transition_finish(&mut self) {
+        self.transition_finish_or_fail(false)
+    }
+
+    /**
+     * Like `transition_finish()`, but if `fail` is true and the object says
+     * that the transition in progress can fail, fail it instead.
+     */
+    fn transition_finish_or_fail(&mut self, fail: bool) {
         let current = self.object.current().clone();
         let desired = self.object.desired().clone();
-        let action = self.object.execute_desired_transition();
+        let action = if fail && self.object.transition_can_fail() {
+            info!(self.log, "simulating failed transition");
+            self.object.fail_transition();
+            None
+        } else {
+            self.object.execute_desired_transition()
+        };
+        self.time_transition_started =
+            self.object.desired().as_ref().map(|_| Instant::now());
         info!(self.log, "simulated transition finish";
             "state_before" => ?current,
             "requested_state" => ?desired,
@@ -172,8 +260,56 @@ impl<S: Simulatable> SimObject<S> {
             "action" => ?action,
         );
     }
+
+    /** Abandons the transition in progress, if any. */
+    fn transition_cancel(&mut self) {
+        let current = self.object.current().clone();
+        let desired = self.object.desired().clone();
+        self.object.cancel_transition();
+        self.time_transition_started = None;
+        info!(self.log, "simulated transition cancelled";
+            "state_before" => ?current,
+            "requested_state" => ?desired,
+            "state_after" => ?self.object.current(),
+        );
+    }
 }
 
+/**
+ * Cancels the transition in progress for object `id` (see
+ * [`SimCollection::sim_cancel()`]) when dropped, unless disarmed first
+ */
+struct CancelOnDrop<S: Simulatable + 'static> {
+    collection: Option<Arc<SimCollection<S>>>,
+    id: Uuid,
+}
+
+impl<S: Simulatable + 'static> Drop for CancelOnDrop<S> {
+    fn drop(&mut self) {
+        if let Some(collection) = self.collection.take() {
+            let id = self.id;
+            tokio::spawn(async move {
+                collection.sim_cancel(id).await;
+            });
+        }
+    }
+}
+
+/**
+ * Decides whether a new object may be created in a [`SimCollection`]
+ *
+ * This is given the new object's initial state and the current states of the
+ * objects that already exist in the collection.
+ */
+pub type SimAdmission<S> = Box<
+    dyn Fn(
+            &<S as Simulatable>::CurrentState,
+            &[&<S as Simulatable>::CurrentState],
+        ) -> Result<(), Error>
+        + Send
+        + Sync,
+>;
+
 /**
  * A collection of `Simulatable` objects, each represented by a `SimObject`
  *
@@ -187,8 +323,38 @@ pub struct SimCollection<S: Simulatable> {
     log: Logger,
     /** simulation mode: automatic (timer-based) or explicit (using an API) */
     sim_mode: SimMode,
+    /** how simulated transitions are queued in `SimMode::Auto` */
+    sim_queue: SimQueue,
+    /** decides which simulated transitions fail, if any */
+    failures: Option<std::sync::Mutex<SimFailures>>,
+    /** decides whether new objects may be created (see `sim_set_admission()`) */
+    admission: std::sync::Mutex<Option<SimAdmission<S>>>,
+    /**
+     * while simulating a network partition from Nexus (see
+     * `sim_set_partitioned()`), the latest undelivered state of each object
+     * whose state has changed; `None` when not partitioned
+     */
+    partitioned: std::sync::Mutex<Option<BTreeMap<Uuid, S::CurrentState>>>,
+    /** whether automatic simulation is paused (see `sim_pause()`) */
+    paused_tx: watch::Sender<bool>,
+    /** kept so that `paused_tx` always has a receiver */
+    paused_rx: watch::Receiver<bool>,
+    /** signalled each time any object's asynchronous transition ends */
+    finished_tx: watch::Sender<()>,
+    /** kept so that `finished_tx` always has a receiver */
+    finished_rx: watch::Receiver<()>,
     /** list of objects being simulated */
     objects: Mutex<BTreeMap<Uuid, SimObject<S>>>,
+    /**
+     * background tasks simulating transitions in `SimMode::Auto` (see
+     * `sim_step()`), indexed by a unique id for each task (not the object's
+     * id, since an object may be re-created while its old task is exiting)
+     */
+    tasks: std::sync::Mutex<BTreeMap<Uuid, JoinHandle<()>>>,
+    /** timestamps state changes of new objects (see `sim_set_clock()`) */
+    clock: std::sync::Mutex<Arc<dyn Clock>>,
+    /** how new objects are simulated (see `sim_set_config()`) */
+    config: std::sync::Mutex<S::Config>,
 }
 
 impl<S: Simulatable + 'static> SimCollection<S> {
@@ -197,51 +363,221 @@ impl<S: Simulatable + 'static> SimCollection<S> {
         nexus_client: Arc<NexusClient>,
         log: Logger,
         sim_mode: SimMode,
+        sim_queue: SimQueue,
+        failures: Option<SimFailures>,
     ) -> SimCollection<S> {
+        let (paused_tx, paused_rx) = watch::channel(false);
+        let (finished_tx, finished_rx) = watch::channel(());
         SimCollection {
             nexus_client,
             log,
             sim_mode,
+            sim_queue,
+            failures: failures.map(std::sync::Mutex::new),
+            admission: std::sync::Mutex::new(None),
+            partitioned: std::sync::Mutex::new(None),
+            paused_tx,
+            paused_rx,
+            finished_tx,
+            finished_rx,
             objects: Mutex::new(BTreeMap::new()),
+            tasks: std::sync::Mutex::new(BTreeMap::new()),
+            clock: std::sync::Mutex::new(Arc::new(RealClock)),
+            config: std::sync::Mutex::new(S::Config::default()),
+        }
+    }
+
+    /**
+     * Sets the clock used to timestamp state changes of objects created from
+     * now on.  Objects that already exist keep using the clock they were
+     * created with.  By default, this is the real clock.
+     */
+    pub fn sim_set_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
+    /**
+     * Sets how objects created from now on are simulated.  Objects that
+     * already exist keep the configuration they were created with.
+     */
+    pub fn sim_set_config(&self, config: S::Config) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /**
+     * Installs a check that `sim_ensure()` runs before creating any new object.
+     * If the check fails, the object is not created and `sim_ensure()` returns
+     * the check's error.  Objects that already exist are unaffected.
+     */
+    pub fn sim_set_admission(&self, admission: Option<SimAdmission<S>>) {
+        *self.admission.lock().unwrap() = admission;
+    }
+
+    /**
+     * Starts or stops simulating a network partition between this collection
+     * and Nexus.
+     *
+     * While partitioned, no notifications are sent to Nexus.  Instead, the
+     * latest state of each object that changed is queued and then delivered
+     * when the partition heals.  (Nexus ignores updates older than what it
+     * already has, so it's fine if a queued update races with a newer one.)
+     */
+    pub async fn sim_set_partitioned(&self, partitioned: bool) {
+        let pending = {
+            let mut current = self.partitioned.lock().unwrap();
+            if partitioned {
+                current.get_or_insert_with(BTreeMap::new);
+                return;
+            }
+            match current.take() {
+                None => return,
+                Some(pending) => pending,
+            }
+        };
+
+        info!(self.log, "partition healed";
+            "pending_updates" => pending.len());
+        for (id, state) in pending {
+            if let Err(error) = S::notify(&self.nexus_client, &id, state).await
+            {
+                warn!(self.log, "failed to notify nexus of state change";
+                    "id" => %id,
+                    "error" => ?error,
+                );
+            }
+        }
+    }
+
+    /** Returns whether we're simulating a partition from Nexus. */
+    pub fn sim_partitioned(&self) -> bool {
+        self.partitioned.lock().unwrap().is_some()
+    }
+
+    /**
+     * Notifies Nexus that object `id` is now in state `new_state`, or queues
+     * the notification if we're simulating a partition from Nexus.
+     */
+    async fn notify(
+        &self,
+        id: &Uuid,
+        new_state: S::CurrentState,
+    ) -> Result<(), Error> {
+        if let Some(pending) = &mut *self.partitioned.lock().unwrap() {
+            debug!(self.log, "partitioned from nexus: queueing notification";
+                "id" => %id);
+            pending.insert(*id, new_state);
+            return Ok(());
         }
+
+        S::notify(&self.nexus_client, id, new_state).await
     }
 
     /**
      * Body of the background task (one per `SimObject`) that simulates
      * asynchronous transitions.  Each time we read a message from the object's
-     * channel, we sleep for a bit and then invoke `poke()` to complete whatever
+     * channel, we sleep for a bit (or however long the object says its
+     * transition takes) and then invoke `poke()` to complete whatever
      * transition is currently outstanding.
      *
-     * This is only used for `SimMode::Auto`.
+     * This is only used for `SimMode::Auto`.  The task exits once the object's
+     * channel is closed, which happens when the object is destroyed (see
+     * `sim_poke()`), or when it's aborted by `sim_shutdown()`.
      */
     async fn sim_step(&self, id: Uuid, mut rx: Receiver<()>) {
+        let mut paused = self.paused_rx.clone();
         while rx.next().await.is_some() {
-            tokio::time::sleep(Duration::from_millis(1500)).await;
+            let delay = self
+                .sim_inspect(&id, |o| o.transition_delay())
+                .await
+                .flatten()
+                .unwrap_or(Duration::from_millis(1500));
+            tokio::time::sleep(delay).await;
+            while *paused.borrow() {
+                if paused.changed().await.is_err() {
+                    return;
+                }
+            }
             self.sim_poke(id).await;
         }
     }
 
+    /** Returns how many background simulation tasks are still running. */
+    pub fn sim_tasks_running(&self) -> usize {
+        self.tasks.lock().unwrap().len()
+    }
+
+    /**
+     * Stops all background simulation tasks and waits for them to exit.
+     *
+     * The tasks each hold a reference to the collection, so they don't go away
+     * on their own when the collection's owner does.  Use this when tearing
+     * down a simulated sled agent so that its tasks don't outlive it.  After
+     * this, objects in `SimMode::Auto` no longer finish their transitions on
+     * their own.
+     */
+    pub async fn sim_shutdown(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        info!(self.log, "shutting down simulation"; "ntasks" => tasks.len());
+        for (_, task) in tasks {
+            task.abort();
+            /* The task was either cancelled or had already finished. */
+            let _ = task.await;
+        }
+    }
+
+    /**
+     * Pauses the automatic simulation of asynchronous transitions.  Objects
+     * stay in whatever state they're in (and transitions requested while
+     * paused are not completed) until `sim_resume()` is called.
+     *
+     * This has no effect on `SimMode::Explicit`, where transitions only
+     * happen when requested through `sim_poke()`.
+     */
+    pub fn sim_pause(&self) {
+        info!(self.log, "pausing simulation");
+        self.paused_tx.send(true).unwrap();
+    }
+
+    /**
+     * Resumes the automatic simulation of asynchronous transitions after
+     * `sim_pause()`.  Any transitions that were held while paused complete
+     * right away.
+     */
+    pub fn sim_resume(&self) {
+        info!(self.log, "resuming simulation");
+        self.paused_tx.send(false).unwrap();
+    }
+
     /**
      * Complete a desired asynchronous state transition for object `id`.
      * This is invoked either by `sim_step()` (if the simulation mode is
      * `SimMode::Auto`) or `instance_finish_transition` (if the simulation mode
      * is `SimMode::Api).
+     *
+     * Returns the object's resulting state, or `None` (doing nothing) if there
+     * is no such object.
      */
-    pub async fn sim_poke(&self, id: Uuid) {
+    pub async fn sim_poke(&self, id: Uuid) -> Option<S::CurrentState> {
         let (new_state, to_destroy) = {
             /*
-             * The object must be present in `objects` because it only gets
-             * removed when it comes to rest in the "Destroyed" state, but we
-             * can only get here if there's an asynchronous state transition
-             * desired.
+             * When we're invoked from `sim_step()`, the object must be present
+             * in `objects` because it only gets removed when it comes to rest
+             * in the "Destroyed" state, but we can only get here if there's an
+             * asynchronous state transition desired.  Explicit pokes may name
+             * any object, though.
              *
              * We do as little as possible with the lock held.  In particular,
              * we want to finish this work before calling out to notify the
              * nexus.
              */
             let mut objects = self.objects.lock().await;
-            let mut object = objects.remove(&id).unwrap();
-            object.transition_finish();
+            let mut object = objects.remove(&id)?;
+            let fail = object.object.transition_can_fail()
+                && self
+                    .failures
+                    .as_ref()
+                    .map_or(false, |f| f.lock().unwrap().should_fail());
+            object.transition_finish_or_fail(fail);
             let after = object.object.current().clone();
             if object.object.desired().is_none()
                 && object.object.ready_to_destroy()
@@ -252,28 +588,192 @@ impl<S: Simulatable + 'static> SimCollection<S> {
                 (after, None)
             }
         };
+        self.finished_tx.send(()).unwrap();
 
         /*
          * Notify Nexus that the object's state has changed.
-         * TODO-robustness: If this fails, we need to put it on some list of
+         * TODO-robustness: If this fails (other than because of a simulated
+         * partition, which queues it), we need to put it on some list of
          * updates to retry later.
          */
-        S::notify(&self.nexus_client, &id, new_state).await.unwrap();
+        if let Err(error) = self.notify(&id, new_state.clone()).await {
+            warn!(self.log, "failed to notify nexus of state change";
+                "id" => %id,
+                "error" => ?error,
+            );
+        }
 
         /*
          * If the object came to rest destroyed, complete any async cleanup
          * needed now.
-         * TODO-debug It would be nice to have visibility into objects that
-         * are cleaning up in case we have to debug resource leaks here.
-         * TODO-correctness Is it a problem that nobody waits on the background
-         * task?  If we did it here, we'd deadlock, since we're invoked from the
-         * background task.
+         * Closing the channel makes the object's background task exit, and
+         * the task removes itself from `tasks` as it does.  We can't wait for
+         * it here, since we're invoked from the background task, but
+         * `sim_shutdown()` can be used to make sure that no tasks remain.
          */
         if let Some(destroyed_object) = to_destroy {
             if let Some(mut tx) = destroyed_object.channel_tx {
                 tx.close_channel();
             }
         }
+
+        Some(new_state)
+    }
+
+    /**
+     * Invokes `f` on the simulated object identified by `id` and returns the
+     * result, or `None` if no such object exists.
+     */
+    pub async fn sim_inspect<T>(
+        &self,
+        id: &Uuid,
+        f: impl FnOnce(&S) -> T,
+    ) -> Option<T> {
+        let objects = self.objects.lock().await;
+        objects.get(id).map(|o| f(&o.object))
+    }
+
+    /**
+     * Returns the id and current state of each object for which `f` returns
+     * true, in order of id.  All objects are examined at once, so the result
+     * reflects a single point in time.
+     */
+    pub async fn sim_select(
+        &self,
+        f: impl Fn(&S) -> bool,
+    ) -> Vec<(Uuid, S::CurrentState)> {
+        let objects = self.objects.lock().await;
+        objects
+            .iter()
+            .filter(|(_, o)| f(&o.object))
+            .map(|(id, o)| (*id, o.object.current().clone()))
+            .collect()
+    }
+
+    /**
+     * Returns the ids of objects with an asynchronous transition in progress,
+     * in order.  In `SimMode::Explicit`, these are the objects that
+     * `sim_poke()` would move along.
+     */
+    pub async fn sim_pending(&self) -> Vec<Uuid> {
+        let objects = self.objects.lock().await;
+        objects
+            .iter()
+            .filter(|(_, o)| o.object.desired().is_some())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /**
+     * Returns the ids of objects that have been partway through an
+     * asynchronous transition for longer than `threshold`.  Transitions that
+     * are supposed to take longer than usual (see
+     * [`Simulatable::transition_delay()`]) get that much extra time.
+     */
+    pub async fn sim_stalled(&self, threshold: Duration) -> Vec<Uuid> {
+        let objects = self.objects.lock().await;
+        objects
+            .iter()
+            .filter(|(_, o)| {
+                let threshold =
+                    threshold + o.object.transition_delay().unwrap_or_default();
+                o.time_transition_started
+                    .map_or(false, |t| t.elapsed() >= threshold)
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /**
+     * Modifies object `id` using `f`, outside of the usual transition
+     * machinery, and notifies Nexus of the resulting state.
+     *
+     * This is intended for recovering objects that are stuck.  Returns `None`
+     * if there is no such object.
+     */
+    pub async fn sim_force_update(
+        &self,
+        id: Uuid,
+        f: impl FnOnce(&mut S),
+    ) -> Option<Result<(), Error>> {
+        let new_state = {
+            let mut objects = self.objects.lock().await;
+            let object = objects.get_mut(&id)?;
+            f(&mut object.object);
+            if object.object.desired().is_none() {
+                object.time_transition_started = None;
+            }
+            info!(object.log, "forced update";
+                "current" => ?object.object.current(),
+                "desired" => ?object.object.desired(),
+            );
+            object.object.current().clone()
+        };
+
+        Some(self.notify(&id, new_state).await)
+    }
+
+    /**
+     * Abandons the asynchronous transition in progress for object `id`, if
+     * any, leaving the object in whatever state its `Simulatable`
+     * implementation considers well-defined (e.g., "failed" for an Instance),
+     * and notifies Nexus of the resulting state.  Returns that state, or
+     * `None` if there is no such object.
+     */
+    pub async fn sim_cancel(&self, id: Uuid) -> Option<S::CurrentState> {
+        let new_state = {
+            let mut objects = self.objects.lock().await;
+            let object = objects.get_mut(&id)?;
+            if object.object.desired().is_none() {
+                return Some(object.object.current().clone());
+            }
+            object.transition_cancel();
+            object.object.current().clone()
+        };
+        self.finished_tx.send(()).unwrap();
+
+        if let Err(error) = self.notify(&id, new_state.clone()).await {
+            warn!(self.log, "failed to notify nexus of state change";
+                "id" => %id,
+                "error" => ?error,
+            );
+        }
+        Some(new_state)
+    }
+
+    /**
+     * Like `sim_ensure()`, but waits for any asynchronous transition that
+     * results to finish.  Returns the state at which the object came to rest,
+     * or `None` if it was destroyed.
+     *
+     * If the returned future is dropped before the transition finishes (e.g.,
+     * because the client that requested it went away), the transition is
+     * cancelled with `sim_cancel()` rather than left to complete on its own.
+     */
+    pub async fn sim_ensure_complete(
+        self: &Arc<Self>,
+        id: &Uuid,
+        current: S::CurrentState,
+        target: S::RequestedState,
+    ) -> Result<Option<S::CurrentState>, Error> {
+        let mut finished = self.finished_rx.clone();
+        self.sim_ensure(id, current, target).await?;
+        let mut guard =
+            CancelOnDrop { collection: Some(Arc::clone(self)), id: *id };
+        let rv = loop {
+            let state = self
+                .sim_inspect(id, |o| {
+                    o.desired().is_none().then(|| o.current().clone())
+                })
+                .await;
+            match state {
+                None => break None,
+                Some(Some(state)) => break Some(state),
+                Some(None) => finished.changed().await.unwrap(),
+            }
+        };
+        guard.collection = None;
+        Ok(rv)
     }
 
     /**
@@ -302,22 +802,55 @@ impl<S: Simulatable + 'static> SimCollection<S> {
         let maybe_current_object = objects.remove(id);
         let (mut object, is_new) = {
             if let Some(current_object) = maybe_current_object {
+                /*
+                 * `current` only describes a new object, so it's ignored here.
+                 * A request for the state that the object is already headed
+                 * to (e.g., "running" for a freshly created Instance that's
+                 * now "starting") is a no-op in `SimObject::transition()`, so
+                 * the transition isn't simulated a second time.
+                 */
                 (current_object, false)
             } else {
+                if let Some(admit) = &*self.admission.lock().unwrap() {
+                    let existing: Vec<_> =
+                        objects.values().map(|o| o.object.current()).collect();
+                    admit(&current, &existing)?;
+                }
+
                 /* Create a new SimObject */
                 let idc = *id;
                 let log = self.log.new(o!("id" => idc.to_string()));
+                let clock = Arc::clone(&*self.clock.lock().unwrap());
+                let config = self.config.lock().unwrap().clone();
 
                 if let SimMode::Auto = self.sim_mode {
-                    let (object, rx) =
-                        SimObject::new_simulated_auto(&current, log);
+                    let (object, rx) = SimObject::new_simulated_auto(
+                        &current,
+                        log,
+                        self.sim_queue,
+                        clock,
+                        config,
+                    );
+                    /*
+                     * Hold the lock on `tasks` until the handle is recorded,
+                     * so that the task can't try to remove itself first.
+                     */
                     let selfc = Arc::clone(self);
-                    tokio::spawn(async move {
+                    let task_id = Uuid::new_v4();
+                    let mut tasks = self.tasks.lock().unwrap();
+                    let task = tokio::spawn(async move {
                         selfc.sim_step(idc, rx).await;
+                        selfc.tasks.lock().unwrap().remove(&task_id);
                     });
+                    tasks.insert(task_id, task);
                     (object, true)
                 } else {
-                    (SimObject::new_simulated_explicit(&current, log), true)
+                    (
+                        SimObject::new_simulated_explicit(
+                            &current, log, clock, config,
+                        ),
+                        true,
+                    )
                 }
             }
         };
@@ -333,14 +866,22 @@ impl<S: Simulatable + 'static> SimCollection<S> {
 
 #[cfg(test)]
 mod test {
+    use crate::common::clock::{Clock, ManualClock, RealClock};
+    use crate::common::instance::InstanceStates;
     use crate::params::DiskStateRequested;
+    use crate::sim::collection::SimCollection;
+    use crate::sim::collection::SimFailures;
     use crate::sim::collection::SimObject;
+    use crate::sim::config::SimMode;
+    use crate::sim::config::SimQueue;
     use crate::sim::disk::SimDisk;
     use crate::sim::instance::SimInstance;
+    use crate::sim::instance::SimInstanceConfig;
     use crate::sim::simulatable::Simulatable;
-    use chrono::Utc;
+    use chrono::{TimeZone, Utc};
     use dropshot::test_util::LogContext;
     use futures::channel::mpsc::Receiver;
+    use nexus_client::Client as NexusClient;
     use omicron_common::api::external::ByteCount;
     use omicron_common::api::external::DiskState;
     use omicron_common::api::external::Error;
@@ -352,24 +893,54 @@ mod test {
     use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
     use omicron_common::api::internal::sled_agent::InstanceStateRequested;
     use omicron_test_utils::dev::test_setup_log;
+    use propolis_client::api::InstanceState as PropolisInstanceState;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /** How `make_instance()` sets up a simulated Instance */
+    struct InstanceOptions {
+        queue: SimQueue,
+        run_state: InstanceState,
+        clock: Arc<dyn Clock>,
+        config: SimInstanceConfig,
+    }
+
+    impl Default for InstanceOptions {
+        fn default() -> Self {
+            InstanceOptions {
+                queue: SimQueue::Coalescing,
+                run_state: InstanceState::Creating,
+                clock: Arc::new(RealClock),
+                config: SimInstanceConfig::default(),
+            }
+        }
+    }
 
     fn make_instance(
         logctx: &LogContext,
+        options: InstanceOptions,
     ) -> (SimObject<SimInstance>, Receiver<()>) {
+        let InstanceOptions { queue, run_state, clock, config } = options;
         let initial_runtime = {
             InstanceRuntimeState {
-                run_state: InstanceState::Creating,
+                run_state,
                 sled_uuid: uuid::Uuid::new_v4(),
                 propolis_uuid: uuid::Uuid::new_v4(),
                 ncpus: InstanceCpuCount(2),
                 memory: ByteCount::from_mebibytes_u32(512),
                 hostname: "myvm".to_string(),
                 gen: Generation::new(),
-                time_updated: Utc::now(),
+                time_updated: clock.now(),
             }
         };
 
-        SimObject::new_simulated_auto(&initial_runtime, logctx.log.new(o!()))
+        SimObject::new_simulated_auto(
+            &initial_runtime,
+            logctx.log.new(o!()),
+            queue,
+            clock,
+            config,
+        )
     }
 
     fn make_disk(
@@ -384,13 +955,20 @@ mod test {
             }
         };
 
-        SimObject::new_simulated_auto(&initial_runtime, logctx.log.new(o!()))
+        SimObject::new_simulated_auto(
+            &initial_runtime,
+            logctx.log.new(o!()),
+            SimQueue::Coalescing,
+            Arc::new(RealClock),
+            (),
+        )
     }
 
     #[tokio::test]
     async fn test_sim_instance_creating_to_stop() {
         let logctx = test_setup_log("test_sim_instance_creating_to_stop");
-        let (mut instance, mut rx) = make_instance(&logctx);
+        let (mut instance, mut rx) =
+            make_instance(&logctx, InstanceOptions::default());
         let r1 = instance.object.current().clone();
 
         info!(logctx.log, "new instance"; "run_state" => ?r1.run_state);
@@ -404,9 +982,7 @@ mod test {
         assert!(instance.object.desired().is_none());
         instance.transition_finish();
         assert!(instance.object.desired().is_none());
-        assert_eq!(&r1.time_updated, &instance.object.current().time_updated);
-        assert_eq!(&r1.run_state, &instance.object.current().run_state);
-        assert_eq!(r1.gen, instance.object.current().gen);
+        assert_eq!(&r1, instance.object.current());
         assert!(rx.try_next().is_err());
 
         /*
@@ -445,6 +1021,139 @@ mod test {
         }
     }
 
+    /**
+     * Tests that with a destroy grace period, a stopped SimInstance that's
+     * asked to be destroyed stays stopped until the transition is finished.
+     */
+    #[tokio::test]
+    async fn test_sim_instance_destroy_grace() {
+        let logctx = test_setup_log("test_sim_instance_destroy_grace");
+        let grace = std::time::Duration::from_secs(30);
+        let (mut instance, mut rx) = make_instance(
+            &logctx,
+            InstanceOptions {
+                run_state: InstanceState::Stopped,
+                config: SimInstanceConfig { destroy_grace: grace },
+                ..Default::default()
+            },
+        );
+        let rprev = instance.object.current().clone();
+        assert_eq!(instance.object.transition_delay(), None);
+
+        let destroy = InstanceRuntimeStateRequested {
+            run_state: InstanceStateRequested::Destroyed,
+        };
+        let dropped = instance.transition(destroy.clone()).unwrap();
+        assert!(dropped.is_none());
+        assert!(rx.try_next().is_ok());
+        let rnext = instance.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert_eq!(rnext.run_state, InstanceState::Stopped);
+        assert_eq!(
+            instance.object.desired().as_ref().map(|d| d.run_state),
+            Some(InstanceStateRequested::Destroyed)
+        );
+        assert_eq!(instance.object.transition_delay(), Some(grace));
+
+        /* Asking again doesn't restart the grace period. */
+        let dropped = instance.transition(destroy.clone()).unwrap();
+        assert!(dropped.is_none());
+        assert!(rx.try_next().is_err());
+        assert_eq!(instance.object.current(), &rnext);
+
+        instance.transition_finish();
+        let rnext = instance.object.current().clone();
+        assert_eq!(rnext.run_state, InstanceState::Destroyed);
+        assert!(instance.object.desired().is_none());
+        assert_eq!(instance.object.transition_delay(), None);
+        assert!(instance.object.ready_to_destroy());
+
+        /* Any other request during the grace period cancels the destroy. */
+        let (mut instance, mut rx) = make_instance(
+            &logctx,
+            InstanceOptions {
+                run_state: InstanceState::Stopped,
+                config: SimInstanceConfig { destroy_grace: grace },
+                ..Default::default()
+            },
+        );
+        instance.transition(destroy).unwrap();
+        assert!(rx.try_next().is_ok());
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        assert_eq!(
+            instance.object.current().run_state,
+            InstanceState::Starting
+        );
+        assert_eq!(instance.object.transition_delay(), None);
+        instance.transition_finish();
+        assert_eq!(instance.object.current().run_state, InstanceState::Running);
+
+        logctx.cleanup_successful();
+    }
+
+    /**
+     * Tests that an instance's state changes are timestamped using the clock
+     * it was created with.
+     */
+    #[tokio::test]
+    async fn test_sim_instance_timestamps() {
+        let logctx = test_setup_log("test_sim_instance_timestamps");
+        let t0 = Utc.ymd(2021, 12, 1).and_hms(0, 0, 0);
+        let clock = Arc::new(ManualClock::new(t0));
+        let (mut instance, mut rx) = make_instance(
+            &logctx,
+            InstanceOptions {
+                clock: Arc::clone(&clock) as Arc<dyn Clock>,
+                ..Default::default()
+            },
+        );
+        assert_eq!(instance.object.current().time_updated, t0);
+
+        /* Starting the instance is stamped with the time it was requested. */
+        clock.advance(chrono::Duration::seconds(5));
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        assert!(rx.try_next().is_ok());
+        let current = instance.object.current();
+        assert_eq!(current.run_state, InstanceState::Starting);
+        assert_eq!(current.time_updated, t0 + chrono::Duration::seconds(5));
+
+        /* ... and finishing the transition, with the time it finished. */
+        clock.advance(chrono::Duration::seconds(10));
+        instance.transition_finish();
+        let current = instance.object.current();
+        assert_eq!(current.run_state, InstanceState::Running);
+        assert_eq!(current.time_updated, t0 + chrono::Duration::seconds(15));
+
+        /* Nothing changes the timestamp if the clock doesn't move. */
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Stopped,
+            })
+            .unwrap();
+        assert!(rx.try_next().is_ok());
+        instance.transition_finish();
+        let current = instance.object.current();
+        assert_eq!(current.run_state, InstanceState::Stopped);
+        assert_eq!(current.time_updated, t0 + chrono::Duration::seconds(15));
+
+        /* Failures are stamped from the same clock. */
+        clock.advance(chrono::Duration::seconds(1));
+        instance.object.fail(String::from("simulated"));
+        let current = instance.object.current();
+        assert_eq!(current.run_state, InstanceState::Failed);
+        assert_eq!(current.time_updated, t0 + chrono::Duration::seconds(16));
+
+        logctx.cleanup_successful();
+    }
+
     /**
      * Tests a SimInstance which transitions to running and is subsequently destroyed.
      * This test observes an intermediate transition through "stopping" to
@@ -453,7 +1162,8 @@ mod test {
     #[tokio::test]
     async fn test_sim_instance_running_then_destroyed() {
         let logctx = test_setup_log("test_sim_instance_running_then_destroyed");
-        let (mut instance, mut rx) = make_instance(&logctx);
+        let (mut instance, mut rx) =
+            make_instance(&logctx, InstanceOptions::default());
         let r1 = instance.object.current().clone();
 
         info!(logctx.log, "new instance"; "run_state" => ?r1.run_state);
@@ -467,9 +1177,7 @@ mod test {
         assert!(instance.object.desired().is_none());
         instance.transition_finish();
         assert!(instance.object.desired().is_none());
-        assert_eq!(&r1.time_updated, &instance.object.current().time_updated);
-        assert_eq!(&r1.run_state, &instance.object.current().run_state);
-        assert_eq!(r1.gen, instance.object.current().gen);
+        assert_eq!(&r1, instance.object.current());
         assert!(rx.try_next().is_err());
 
         /*
@@ -504,7 +1212,7 @@ mod test {
         rprev = rnext;
         instance.transition_finish();
         let rnext = instance.object.current().clone();
-        assert_eq!(rprev.gen, rnext.gen);
+        assert_eq!(rprev, rnext);
 
         /*
          * If we transition again to "Running", the process should complete
@@ -520,9 +1228,7 @@ mod test {
         assert!(instance.object.desired().is_none());
         assert!(rx.try_next().is_err());
         let rnext = instance.object.current().clone();
-        assert_eq!(rnext.gen, rprev.gen);
-        assert_eq!(rnext.time_updated, rprev.time_updated);
-        assert_eq!(rnext.run_state, rprev.run_state);
+        assert_eq!(rnext, rprev);
         rprev = rnext;
 
         /*
@@ -555,13 +1261,114 @@ mod test {
         rprev = rnext;
         instance.transition_finish();
         let rnext = instance.object.current().clone();
-        assert_eq!(rprev.gen, rnext.gen);
+        assert_eq!(rprev, rnext);
+    }
+
+    /**
+     * Tests that a SimInstance being repaired can be booted again, going
+     * through "starting" just like a stopped Instance.
+     */
+    #[tokio::test]
+    async fn test_sim_instance_repairing_to_running() {
+        let logctx = test_setup_log("test_sim_instance_repairing_to_running");
+        let (mut instance, mut rx) = make_instance(
+            &logctx,
+            InstanceOptions {
+                run_state: InstanceState::Repairing,
+                ..Default::default()
+            },
+        );
+        let r1 = instance.object.current().clone();
+        assert_eq!(r1.run_state, InstanceState::Repairing);
+        assert!(r1.run_state.is_stopped());
+
+        let dropped = instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        assert!(dropped.is_none());
+        assert!(instance.object.desired().is_some());
+        assert!(rx.try_next().is_ok());
+        let r2 = instance.object.current().clone();
+        assert!(r2.gen > r1.gen);
+        assert_eq!(r2.run_state, InstanceState::Starting);
+
+        instance.transition_finish();
+        let r3 = instance.object.current().clone();
+        assert!(r3.gen > r2.gen);
+        assert!(instance.object.desired().is_none());
+        assert_eq!(r3.run_state, InstanceState::Running);
+        logctx.cleanup_successful();
+    }
+
+    /**
+     * Tests that destroying an Instance in automatic mode ends its background
+     * task on its own, without `sim_shutdown()`.
+     */
+    #[tokio::test]
+    async fn test_sim_instance_destroy_ends_task() {
+        let logctx = test_setup_log("test_sim_instance_destroy_ends_task");
+        let nexus_client = Arc::new(NexusClient::new(
+            "http://127.0.0.1:0",
+            logctx.log.new(o!()),
+        ));
+        let collection = Arc::new(SimCollection::<SimInstance>::new(
+            nexus_client,
+            logctx.log.new(o!()),
+            SimMode::Auto,
+            SimQueue::Coalescing,
+            None,
+        ));
+
+        /* Creating the Instance starts its task. */
+        let id = uuid::Uuid::new_v4();
+        let current = make_instance(&logctx, InstanceOptions::default())
+            .0
+            .object
+            .current()
+            .clone();
+        collection
+            .sim_ensure(
+                &id,
+                current.clone(),
+                InstanceRuntimeStateRequested {
+                    run_state: InstanceStateRequested::Running,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(collection.sim_tasks_running(), 1);
+        let task = {
+            let mut tasks = collection.tasks.lock().unwrap();
+            let task_id = *tasks.keys().next().unwrap();
+            tasks.remove(&task_id).unwrap()
+        };
+
+        /* Once the Instance comes to rest destroyed, the task exits. */
+        collection
+            .sim_ensure(
+                &id,
+                current,
+                InstanceRuntimeStateRequested {
+                    run_state: InstanceStateRequested::Destroyed,
+                },
+            )
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(30), task)
+            .await
+            .expect("simulation task did not exit")
+            .expect("simulation task failed");
+        assert!(collection.sim_inspect(&id, |_| ()).await.is_none());
+        logctx.cleanup_successful();
     }
 
     #[tokio::test]
     async fn test_sim_instance_preempt_transition() {
         let logctx = test_setup_log("test_sim_instance_preempt_transition");
-        let (mut instance, mut rx) = make_instance(&logctx);
+        let (mut instance, mut rx) =
+            make_instance(&logctx, InstanceOptions::default());
         let r1 = instance.object.current().clone();
 
         info!(logctx.log, "new instance"; "run_state" => ?r1.run_state);
@@ -575,9 +1382,7 @@ mod test {
         assert!(instance.object.desired().is_none());
         instance.transition_finish();
         assert!(instance.object.desired().is_none());
-        assert_eq!(&r1.time_updated, &instance.object.current().time_updated);
-        assert_eq!(&r1.run_state, &instance.object.current().run_state);
-        assert_eq!(r1.gen, instance.object.current().gen);
+        assert_eq!(&r1, instance.object.current());
         assert!(rx.try_next().is_err());
 
         /*
@@ -635,7 +1440,7 @@ mod test {
         rprev = rnext;
         instance.transition_finish();
         let rnext = instance.object.current().clone();
-        assert_eq!(rprev.gen, rnext.gen);
+        assert_eq!(rprev, rnext);
 
         logctx.cleanup_successful();
     }
@@ -650,7 +1455,8 @@ mod test {
         /*
          * Get an initial instance up to "Running".
          */
-        let (mut instance, _rx) = make_instance(&logctx);
+        let (mut instance, _rx) =
+            make_instance(&logctx, InstanceOptions::default());
         let r1 = instance.object.current().clone();
 
         info!(logctx.log, "new instance"; "run_state" => ?r1.run_state);
@@ -699,6 +1505,18 @@ mod test {
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
+        assert!(rnext.gen > rprev.gen);
+        assert!(rnext.time_updated > rprev.time_updated);
+        assert_eq!(rnext.run_state, InstanceState::Stopping);
+        assert!(instance.object.desired().is_some());
+        instance.transition_finish();
+        let (rprev, rnext) = (rnext, instance.object.current().clone());
+
+        // Chrono doesn't give us enough precision, so sleep a bit
+        if cfg!(windows) {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
         assert!(rnext.gen > rprev.gen);
         assert!(rnext.time_updated > rprev.time_updated);
         assert_eq!(rnext.run_state, InstanceState::Starting);
@@ -711,7 +1529,7 @@ mod test {
         assert!(instance.object.desired().is_none());
 
         /*
-         * Begin a reboot.  Then, while it's still "Stopping", begin another
+         * Begin a reboot.  Then, while it's still "Rebooting", begin another
          * reboot.  This should go through exactly one reboot sequence, as the
          * second reboot is totally superfluous.
          */
@@ -733,6 +1551,9 @@ mod test {
         assert_eq!(rnext.run_state, InstanceState::Rebooting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
+        assert_eq!(rnext.run_state, InstanceState::Stopping);
+        instance.transition_finish();
+        let rnext = instance.object.current().clone();
         assert_eq!(rnext.run_state, InstanceState::Starting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
@@ -757,6 +1578,9 @@ mod test {
         assert_eq!(rnext.run_state, InstanceState::Rebooting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
+        assert_eq!(rnext.run_state, InstanceState::Stopping);
+        instance.transition_finish();
+        let rnext = instance.object.current().clone();
         assert_eq!(rnext.run_state, InstanceState::Starting);
         assert!(instance
             .transition(InstanceRuntimeStateRequested {
@@ -768,6 +1592,9 @@ mod test {
         assert_eq!(rnext.run_state, InstanceState::Rebooting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
+        assert_eq!(rnext.run_state, InstanceState::Stopping);
+        instance.transition_finish();
+        let rnext = instance.object.current().clone();
         assert_eq!(rnext.run_state, InstanceState::Starting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
@@ -811,6 +1638,9 @@ mod test {
         assert_eq!(rnext.run_state, InstanceState::Rebooting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
+        assert_eq!(rnext.run_state, InstanceState::Stopping);
+        instance.transition_finish();
+        let rnext = instance.object.current().clone();
         assert_eq!(rnext.run_state, InstanceState::Starting);
         instance.transition_finish();
         let rnext = instance.object.current().clone();
@@ -831,6 +1661,247 @@ mod test {
         logctx.cleanup_successful();
     }
 
+    /**
+     * Tests that a `SimInstance` goes through the same states (with the same
+     * generation numbers) as the real sled agent's Instances do for a
+     * start/reboot/stop cycle.  Both are built on `InstanceStates`, so this
+     * checks that the simulator makes up the same observed states that a
+     * real Propolis reports, and tests see the same semantics either way.
+     */
+    #[tokio::test]
+    async fn test_sim_instance_matches_real_transitions() {
+        let logctx =
+            test_setup_log("test_sim_instance_matches_real_transitions");
+        let (mut instance, _rx) =
+            make_instance(&logctx, InstanceOptions::default());
+        let mut real = InstanceStates::new(instance.object.current().clone());
+
+        fn snapshot(
+            current: &InstanceRuntimeState,
+            desired: &Option<InstanceRuntimeStateRequested>,
+        ) -> (InstanceState, Generation, Option<InstanceStateRequested>)
+        {
+            let desired = desired.as_ref().map(|d| d.run_state);
+            (current.run_state, current.gen, desired)
+        }
+
+        /*
+         * For each request, the states that Propolis reports to a real sled
+         * agent while carrying it out
+         */
+        let cycle = vec![
+            (
+                InstanceStateRequested::Running,
+                vec![PropolisInstanceState::Running],
+            ),
+            (
+                InstanceStateRequested::Reboot,
+                vec![
+                    PropolisInstanceState::Stopping,
+                    PropolisInstanceState::Starting,
+                    PropolisInstanceState::Running,
+                ],
+            ),
+            (
+                InstanceStateRequested::Stopped,
+                vec![PropolisInstanceState::Stopped],
+            ),
+        ];
+
+        let mut sim_states = vec![snapshot(
+            instance.object.current(),
+            instance.object.desired(),
+        )];
+        let mut real_states = vec![snapshot(real.current(), real.desired())];
+        for (run_state, observations) in &cycle {
+            let run_state = *run_state;
+            instance
+                .transition(InstanceRuntimeStateRequested { run_state })
+                .unwrap();
+            sim_states.push(snapshot(
+                instance.object.current(),
+                instance.object.desired(),
+            ));
+            while instance.object.desired().is_some() {
+                instance.transition_finish();
+                sim_states.push(snapshot(
+                    instance.object.current(),
+                    instance.object.desired(),
+                ));
+            }
+
+            real.request_transition(run_state).unwrap();
+            real_states.push(snapshot(real.current(), real.desired()));
+            for observed in observations {
+                real.observe_transition(observed);
+                real_states.push(snapshot(real.current(), real.desired()));
+            }
+        }
+
+        assert_eq!(sim_states, real_states);
+        assert_eq!(
+            sim_states.iter().map(|s| s.0).collect::<Vec<_>>(),
+            vec![
+                InstanceState::Creating,
+                InstanceState::Starting,
+                InstanceState::Running,
+                InstanceState::Rebooting,
+                InstanceState::Stopping,
+                InstanceState::Starting,
+                InstanceState::Running,
+                InstanceState::Stopping,
+                InstanceState::Stopped,
+            ]
+        );
+
+        logctx.cleanup_successful();
+    }
+
+    /**
+     * Tests that with a bounded queue, requesting a transition while the
+     * background task's queue is full is reported to the caller.
+     */
+    #[tokio::test]
+    async fn test_sim_instance_bounded_queue_full() {
+        let logctx = test_setup_log("test_sim_instance_bounded_queue_full");
+        let (mut instance, mut rx) = make_instance(
+            &logctx,
+            InstanceOptions {
+                queue: SimQueue::Bounded(0),
+                ..Default::default()
+            },
+        );
+
+        /*
+         * The first asynchronous transition fills the queue (a buffer of 0
+         * still holds one message for our sender).
+         */
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        assert_eq!(
+            instance.object.current().run_state,
+            InstanceState::Starting
+        );
+
+        /*
+         * Another asynchronous transition cannot be queued.  The transition
+         * itself has still been recorded.
+         */
+        let error = instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Stopped,
+            })
+            .unwrap_err();
+        assert!(matches!(error, Error::ServiceUnavailable { .. }));
+        assert_eq!(
+            instance.object.current().run_state,
+            InstanceState::Stopping
+        );
+
+        /*
+         * Once the background task drains the queue, transitions can be
+         * queued again.
+         */
+        assert!(rx.try_next().unwrap().is_some());
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        assert_eq!(
+            instance.object.current().run_state,
+            InstanceState::Starting
+        );
+
+        logctx.cleanup_successful();
+    }
+
+    /**
+     * Boots an Instance and finishes the boot, failing it if `failures` says
+     * to.  Returns the resulting state.
+     */
+    fn boot_with_failures(
+        logctx: &LogContext,
+        failures: &mut SimFailures,
+    ) -> InstanceState {
+        let (mut instance, _rx) =
+            make_instance(&logctx, InstanceOptions::default());
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        assert!(instance.object.transition_can_fail());
+        instance.transition_finish_or_fail(failures.should_fail());
+        assert!(instance.object.desired().is_none());
+        instance.object.current().run_state
+    }
+
+    #[tokio::test]
+    async fn test_sim_instance_boot_failures() {
+        let logctx = test_setup_log("test_sim_instance_boot_failures");
+
+        assert!(SimFailures::new(-0.1, 0).is_err());
+        assert!(SimFailures::new(1.1, 0).is_err());
+
+        /* With probability 0, every boot succeeds. */
+        let mut failures = SimFailures::new(0.0, 0).unwrap();
+        for _ in 0..10 {
+            assert_eq!(
+                boot_with_failures(&logctx, &mut failures),
+                InstanceState::Running
+            );
+        }
+
+        /* With probability 1, every boot fails. */
+        let mut failures = SimFailures::new(1.0, 0).unwrap();
+        for _ in 0..10 {
+            assert_eq!(
+                boot_with_failures(&logctx, &mut failures),
+                InstanceState::Failed
+            );
+        }
+
+        /*
+         * With a fixed seed, outcomes are reproducible.  (We also check that
+         * this seed produces both outcomes so that this test is meaningful.)
+         */
+        let outcomes = |seed| {
+            let mut failures = SimFailures::new(0.5, seed).unwrap();
+            (0..32)
+                .map(|_| boot_with_failures(&logctx, &mut failures))
+                .collect::<Vec<_>>()
+        };
+        let first = outcomes(1234);
+        assert_eq!(first, outcomes(1234));
+        assert!(first.contains(&InstanceState::Running));
+        assert!(first.contains(&InstanceState::Failed));
+
+        /* Transitions other than booting never fail. */
+        let mut failures = SimFailures::new(1.0, 0).unwrap();
+        let (mut instance, _rx) =
+            make_instance(&logctx, InstanceOptions::default());
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Running,
+            })
+            .unwrap();
+        instance.transition_finish();
+        instance
+            .transition(InstanceRuntimeStateRequested {
+                run_state: InstanceStateRequested::Stopped,
+            })
+            .unwrap();
+        assert!(!instance.object.transition_can_fail());
+        instance.transition_finish_or_fail(failures.should_fail());
+        assert_eq!(instance.object.current().run_state, InstanceState::Stopped);
+
+        logctx.cleanup_successful();
+    }
+
     /**
      * Tests basic usage of `SimDisk`.  This is somewhat less exhaustive than
      * the analogous tests for `SimInstance` because much of that functionality
@@ -927,7 +1998,14 @@ mod test {
             .transition(DiskStateRequested::Attached(id2.clone()))
             .unwrap_err();
         if let Error::InvalidRequest { message } = error {
-            assert_eq!("disk is already attached", message);
+            assert_eq!(
+                format!(
+                    "cannot attach disk to instance {}: disk is attached to \
+                     instance {}",
+                    id2, id
+                ),
+                message
+            );
         } else {
             panic!("unexpected error type");
         }
@@ -995,7 +2073,13 @@ mod test {
             .transition(DiskStateRequested::Attached(id.clone()))
             .unwrap_err();
         if let Error::InvalidRequest { message } = error {
-            assert_eq!("cannot attach from detaching", message);
+            assert_eq!(
+                format!(
+                    "cannot attach disk to instance {}: disk is detaching",
+                    id
+                ),
+                message
+            );
         } else {
             panic!("unexpected error type");
         }
@@ -1004,4 +2088,172 @@ mod test {
 
         logctx.cleanup_successful();
     }
+
+    #[tokio::test]
+    async fn test_sim_disk_detach_then_reattach() {
+        let logctx = test_setup_log("test_sim_disk_detach_then_reattach");
+        let (mut disk, mut rx) = make_disk(&logctx, DiskState::Creating);
+        let r1 = disk.object.current().clone();
+
+        info!(logctx.log, "new disk"; "disk_state" => ?r1.disk_state);
+        assert_eq!(r1.disk_state, DiskState::Creating);
+        assert_eq!(r1.gen, Generation::new());
+
+        /*
+         * There's no asynchronous transition going on yet so a
+         * transition_finish() shouldn't change anything.
+         */
+        assert!(disk.object.desired().is_none());
+        disk.transition_finish();
+        assert!(disk.object.desired().is_none());
+        assert_eq!(&r1, disk.object.current());
+        assert!(rx.try_next().is_err());
+
+        /* Attaching goes through the async process. */
+        let id = uuid::Uuid::new_v4();
+        let mut rprev = r1;
+        let dropped =
+            disk.transition(DiskStateRequested::Attached(id)).unwrap();
+        assert!(dropped.is_none());
+        assert!(disk.object.desired().is_some());
+        assert!(rx.try_next().is_ok());
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert!(rnext.time_updated >= rprev.time_updated);
+        assert_eq!(rnext.disk_state, DiskState::Attaching(id));
+        rprev = rnext;
+
+        disk.transition_finish();
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert!(disk.object.desired().is_none());
+        assert!(rx.try_next().is_err());
+        assert_eq!(rnext.disk_state, DiskState::Attached(id));
+        rprev = rnext;
+
+        /* So does detaching. */
+        let dropped = disk.transition(DiskStateRequested::Detached).unwrap();
+        assert!(dropped.is_none());
+        assert!(rx.try_next().is_ok());
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert_eq!(rnext.disk_state, DiskState::Detaching(id));
+        rprev = rnext;
+
+        disk.transition_finish();
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert!(disk.object.desired().is_none());
+        assert_eq!(rnext.disk_state, DiskState::Detached);
+        rprev = rnext;
+
+        /*
+         * Once detached, the disk can be attached again, either to the same
+         * instance or to a different one.
+         */
+        let id2 = uuid::Uuid::new_v4();
+        assert_ne!(id, id2);
+        for next_id in &[id, id2] {
+            disk.transition(DiskStateRequested::Attached(*next_id)).unwrap();
+            assert!(rx.try_next().is_ok());
+            let rnext = disk.object.current().clone();
+            assert!(rnext.gen > rprev.gen);
+            assert_eq!(rnext.disk_state, DiskState::Attaching(*next_id));
+            disk.transition_finish();
+            assert_eq!(
+                disk.object.current().disk_state,
+                DiskState::Attached(*next_id)
+            );
+
+            disk.transition(DiskStateRequested::Detached).unwrap();
+            assert!(rx.try_next().is_ok());
+            disk.transition_finish();
+            rprev = disk.object.current().clone();
+            assert_eq!(rprev.disk_state, DiskState::Detached);
+        }
+
+        /* Detaching a detached disk completes immediately. */
+        disk.transition(DiskStateRequested::Detached).unwrap();
+        assert!(disk.object.desired().is_none());
+        assert!(rx.try_next().is_err());
+        assert_eq!(disk.object.current().disk_state, DiskState::Detached);
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_sim_disk_preempt_transition() {
+        let logctx = test_setup_log("test_sim_disk_preempt_transition");
+        let (mut disk, mut rx) = make_disk(&logctx, DiskState::Detached);
+        let r1 = disk.object.current().clone();
+        assert_eq!(r1.disk_state, DiskState::Detached);
+
+        /*
+         * Start attaching.  While we're still in "Attaching", ask for the disk
+         * to be detached instead.  The attach request is dropped, we go
+         * straight to "Detaching", and completing the transition takes us to
+         * "Detached".
+         */
+        let id = uuid::Uuid::new_v4();
+        let dropped =
+            disk.transition(DiskStateRequested::Attached(id)).unwrap();
+        assert!(dropped.is_none());
+        assert!(rx.try_next().is_ok());
+        let rprev = disk.object.current().clone();
+        assert_eq!(rprev.disk_state, DiskState::Attaching(id));
+
+        let dropped = disk.transition(DiskStateRequested::Detached).unwrap();
+        assert_eq!(dropped, Some(DiskStateRequested::Attached(id)));
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert!(rnext.time_updated >= rprev.time_updated);
+        assert_eq!(rnext.disk_state, DiskState::Detaching(id));
+        assert_eq!(
+            disk.object.desired().as_ref(),
+            Some(&DiskStateRequested::Detached)
+        );
+        let rprev = rnext;
+
+        /*
+         * The background task only needs to hear about this once: the second
+         * notification was coalesced with the first.
+         */
+        assert!(rx.try_next().is_err());
+
+        disk.transition_finish();
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert!(disk.object.desired().is_none());
+        assert_eq!(rnext.disk_state, DiskState::Detached);
+        let rprev = rnext;
+        disk.transition_finish();
+        assert_eq!(&rprev, disk.object.current());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_sim_disk_cancel_transition() {
+        let logctx = test_setup_log("test_sim_disk_cancel_transition");
+        let (mut disk, _rx) = make_disk(&logctx, DiskState::Detached);
+
+        /* With no transition in progress, cancelling does nothing. */
+        let r1 = disk.object.current().clone();
+        disk.transition_cancel();
+        assert_eq!(&r1, disk.object.current());
+
+        /* A cancelled attach leaves the disk faulted. */
+        let id = uuid::Uuid::new_v4();
+        disk.transition(DiskStateRequested::Attached(id)).unwrap();
+        let rprev = disk.object.current().clone();
+        disk.transition_cancel();
+        let rnext = disk.object.current().clone();
+        assert!(rnext.gen > rprev.gen);
+        assert!(disk.object.desired().is_none());
+        assert_eq!(rnext.disk_state, DiskState::Faulted);
+        disk.transition_finish();
+        assert_eq!(&rnext, disk.object.current());
+
+        logctx.cleanup_successful();
+    }
 }
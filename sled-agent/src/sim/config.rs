@@ -6,6 +6,7 @@
  * Interfaces for working with sled agent configuration
  */
 
+use crate::common::nexus::NEXUS_TIMEOUT_MS_DEFAULT;
 use dropshot::ConfigDropshot;
 use dropshot::ConfigLogging;
 use serde::Deserialize;
@@ -33,6 +34,85 @@ pub enum SimMode {
     Explicit,
 }
 
+/**
+ * How a [`SledAgent`](`super::sled_agent::SledAgent`) in [`SimMode::Auto`]
+ * queues up work for the background tasks that simulate asynchronous
+ * transitions
+ */
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SimQueue {
+    /**
+     * At most one notification is ever pending.  A transition requested while
+     * one is pending is coalesced into it, since the background task always
+     * acts on the latest state anyway.
+     */
+    Coalescing,
+
+    /**
+     * Up to the given number of notifications are buffered (in addition to
+     * one per sender).  Requesting a transition while the buffer is full is
+     * reported to the caller as an error.  This is useful for observing
+     * backpressure under load.
+     */
+    Bounded(usize),
+}
+
+impl Default for SimQueue {
+    fn default() -> Self {
+        SimQueue::Coalescing
+    }
+}
+
+/**
+ * Configuration for simulating Instances that fail to boot
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigBootFailure {
+    /** probability (between 0 and 1) that any given boot fails */
+    pub probability: f64,
+    /** seed for the random number generator, for reproducibility */
+    pub seed: u64,
+}
+
+/**
+ * What the simulated sled agent's watchdog does with an Instance that has been
+ * in an intermediate state (e.g., "starting") for too long
+ */
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum WatchdogAction {
+    /** Log a warning and leave the Instance alone. */
+    Warn,
+    /** Log a warning and move the Instance to the "failed" state. */
+    Fail,
+}
+
+/**
+ * Configuration for the watchdog that looks for stalled Instance transitions
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigWatchdog {
+    /**
+     * how long (in milliseconds) an Instance may be in an intermediate state
+     * before the watchdog flags it.  This is also how often the watchdog runs.
+     */
+    pub threshold_ms: u64,
+    /** what to do with Instances that the watchdog flags */
+    pub action: WatchdogAction,
+}
+
+/**
+ * Resources that the simulated sled can allocate to Instances
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigCapacity {
+    /** maximum number of Instances */
+    pub instances: u32,
+    /** total number of CPUs across all Instances */
+    pub cpus: u64,
+    /** total memory (in bytes) across all Instances */
+    pub memory_bytes: u64,
+}
+
 /**
  * Configuration for a sled agent
  */
@@ -42,10 +122,39 @@ pub struct Config {
     pub id: Uuid,
     /** how to simulate asynchronous Instance and Disk transitions */
     pub sim_mode: SimMode,
+    /** how to queue simulated transitions (only used for `SimMode::Auto`) */
+    #[serde(default)]
+    pub sim_queue: SimQueue,
     /** IP address and TCP port for Nexus instance to register with */
     pub nexus_address: SocketAddr,
+    /**
+     * how long (in milliseconds) to wait for requests to Nexus to complete
+     * (60 seconds if not specified)
+     */
+    #[serde(default = "nexus_timeout_ms_default")]
+    pub nexus_timeout_ms: u64,
     /** configuration for the sled agent dropshot server */
     pub dropshot: ConfigDropshot,
     /** configuration for the sled agent debug log */
     pub log: ConfigLogging,
+    /**
+     * make some Instance boots fail (only used for `SimMode::Auto`; disabled
+     * if not specified)
+     */
+    pub boot_failure: Option<ConfigBootFailure>,
+    /** stalled Instance watchdog (disabled if not specified) */
+    pub watchdog: Option<ConfigWatchdog>,
+    /** resources available to Instances (unlimited if not specified) */
+    pub capacity: Option<ConfigCapacity>,
+    /**
+     * how long (in milliseconds) a stopped Instance that's asked to be
+     * destroyed stays "stopped" first (only used for `SimMode::Auto`;
+     * destroyed right away if not specified)
+     */
+    #[serde(default)]
+    pub destroy_grace_ms: u64,
+}
+
+fn nexus_timeout_ms_default() -> u64 {
+    NEXUS_TIMEOUT_MS_DEFAULT
 }
@@ -6,6 +6,7 @@
  * Simulated sled agent implementation
  */
 
+use crate::common::clock::Clock;
 use crate::params::DiskStateRequested;
 use crate::sim::simulatable::Simulatable;
 use async_trait::async_trait;
@@ -35,9 +36,14 @@ impl Simulatable for SimDisk {
     type CurrentState = DiskRuntimeState;
     type RequestedState = DiskStateRequested;
     type Action = DiskAction;
+    type Config = ();
 
-    fn new(current: DiskRuntimeState) -> Self {
-        SimDisk { state: DiskStates::new(current) }
+    fn new(
+        current: DiskRuntimeState,
+        clock: Arc<dyn Clock>,
+        _config: (),
+    ) -> Self {
+        SimDisk { state: DiskStates::new_with_clock(current, clock) }
     }
 
     fn request_transition(
@@ -68,6 +74,12 @@ impl Simulatable for SimDisk {
         }
     }
 
+    fn cancel_transition(&mut self) {
+        if self.state.desired().is_some() {
+            self.state.observe_transition(&PropolisDiskState::Faulted);
+        }
+    }
+
     fn generation(&self) -> Generation {
         self.state.current().gen
     }
@@ -13,17 +13,22 @@ use dropshot::HttpError;
 use dropshot::HttpResponseOk;
 use dropshot::HttpResponseUpdatedNoContent;
 use dropshot::Path;
+use dropshot::Query;
 use dropshot::RequestContext;
 use dropshot::TypedBody;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ResourceType;
 use omicron_common::api::internal::nexus::DiskRuntimeState;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use omicron_common::api::internal::sled_agent::InstanceEnsureBody;
+use omicron_common::api::registration::EndpointRegistrar;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use std::sync::Arc;
 use uuid::Uuid;
 
 use super::sled_agent::SledAgent;
+use super::sled_agent::SledPowerState;
 
 type SledApiDescription = ApiDescription<Arc<SledAgent>>;
 
@@ -31,16 +36,25 @@ type SledApiDescription = ApiDescription<Arc<SledAgent>>;
  * Returns a description of the sled agent API
  */
 pub fn api() -> SledApiDescription {
-    fn register_endpoints(api: &mut SledApiDescription) -> Result<(), String> {
+    fn register_endpoints(
+        api: &mut EndpointRegistrar<'_, Arc<SledAgent>>,
+    ) -> Result<(), String> {
+        api.register(instances_pending_get)?;
         api.register(instance_put)?;
         api.register(instance_poke_post)?;
+        api.register(instance_repair_post)?;
+        api.register(instance_serial_get)?;
+        api.register(instance_last_error_get)?;
         api.register(disk_put)?;
         api.register(disk_poke_post)?;
+        api.register(power_put)?;
+        api.register(partition_put)?;
         Ok(())
     }
 
     let mut api = SledApiDescription::new();
-    if let Err(err) = register_endpoints(&mut api) {
+    if let Err(err) = register_endpoints(&mut EndpointRegistrar::new(&mut api))
+    {
         panic!("failed to register entrypoints: {}", err);
     }
     api
@@ -54,6 +68,42 @@ struct InstancePathParam {
     instance_id: Uuid,
 }
 
+/**
+ * Lists the ids of Instances with a simulated transition in progress, which are
+ * the ones that `instance_poke_post` would move along.  (Only the simulated
+ * sled agent provides this.)
+ */
+#[endpoint {
+    method = GET,
+    path = "/pending-instances",
+}]
+async fn instances_pending_get(
+    rqctx: Arc<RequestContext<Arc<SledAgent>>>,
+) -> Result<HttpResponseOk<Vec<Uuid>>, HttpError> {
+    let sa = rqctx.context();
+    Ok(HttpResponseOk(sa.instances_pending().await))
+}
+
+/**
+ * Query parameters for `instance_put`
+ */
+#[derive(Deserialize, JsonSchema)]
+struct InstancePutQuery {
+    /**
+     * If true, respond only once the requested transition has finished.  If
+     * the client goes away before then, the transition is cancelled and the
+     * Instance ends up "failed".  (Only the simulated sled agent accepts
+     * this.)
+     */
+    #[serde(default)]
+    wait: bool,
+}
+
+/**
+ * Ensures that an Instance is in the requested state.  With `wait`, this
+ * reports the state that the Instance came to rest in, or 404 if it was
+ * destroyed along the way.
+ */
 #[endpoint {
     method = PUT,
     path = "/instances/{instance_id}",
@@ -61,17 +111,44 @@ struct InstancePathParam {
 async fn instance_put(
     rqctx: Arc<RequestContext<Arc<SledAgent>>>,
     path_params: Path<InstancePathParam>,
+    query_params: Query<InstancePutQuery>,
     body: TypedBody<InstanceEnsureBody>,
 ) -> Result<HttpResponseOk<InstanceRuntimeState>, HttpError> {
     let sa = rqctx.context();
     let instance_id = path_params.into_inner().instance_id;
     let body_args = body.into_inner();
-    Ok(HttpResponseOk(
-        sa.instance_ensure(instance_id, body_args.initial, body_args.target)
+    if !query_params.into_inner().wait {
+        return Ok(HttpResponseOk(
+            sa.instance_ensure(
+                instance_id,
+                body_args.initial,
+                body_args.target,
+            )
             .await?,
-    ))
+        ));
+    }
+
+    /*
+     * Dropshot drops this future if the client disconnects, which cancels the
+     * transition (see `SledAgent::instance_ensure_complete()`).
+     */
+    sa.instance_ensure_complete(
+        instance_id,
+        body_args.initial,
+        body_args.target,
+    )
+    .await?
+    .map(HttpResponseOk)
+    .ok_or_else(|| {
+        Error::not_found_by_id(ResourceType::Instance, &instance_id).into()
+    })
 }
 
+/**
+ * Completes the simulated transition in progress for an Instance.  This is
+ * mainly useful in `SimMode::Explicit`, where nothing else does.  (Only the
+ * simulated sled agent provides this.)
+ */
 #[endpoint {
     method = POST,
     path = "/instances/{instance_id}/poke",
@@ -82,10 +159,54 @@ async fn instance_poke_post(
 ) -> Result<HttpResponseUpdatedNoContent, HttpError> {
     let sa = rqctx.context();
     let instance_id = path_params.into_inner().instance_id;
-    sa.instance_poke(instance_id).await;
+    sa.instance_poke(instance_id).await?;
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+/**
+ * Moves an Instance to "repairing", as though an operator had taken it out of
+ * service.  (Only the simulated sled agent provides this.)
+ */
+#[endpoint {
+    method = POST,
+    path = "/instances/{instance_id}/repair",
+}]
+async fn instance_repair_post(
+    rqctx: Arc<RequestContext<Arc<SledAgent>>>,
+    path_params: Path<InstancePathParam>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let instance_id = path_params.into_inner().instance_id;
+    sa.instance_repair(instance_id).await?;
     Ok(HttpResponseUpdatedNoContent())
 }
 
+#[endpoint {
+    method = GET,
+    path = "/instances/{instance_id}/serial",
+}]
+async fn instance_serial_get(
+    rqctx: Arc<RequestContext<Arc<SledAgent>>>,
+    path_params: Path<InstancePathParam>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    let sa = rqctx.context();
+    let instance_id = path_params.into_inner().instance_id;
+    Ok(HttpResponseOk(sa.instance_serial(instance_id).await?))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/instances/{instance_id}/last-error",
+}]
+async fn instance_last_error_get(
+    rqctx: Arc<RequestContext<Arc<SledAgent>>>,
+    path_params: Path<InstancePathParam>,
+) -> Result<HttpResponseOk<Option<String>>, HttpError> {
+    let sa = rqctx.context();
+    let instance_id = path_params.into_inner().instance_id;
+    Ok(HttpResponseOk(sa.instance_last_error(instance_id).await?))
+}
+
 /**
  * Path parameters for Disk requests (sled agent API)
  */
@@ -126,6 +247,32 @@ async fn disk_poke_post(
 ) -> Result<HttpResponseUpdatedNoContent, HttpError> {
     let sa = rqctx.context();
     let disk_id = path_params.into_inner().disk_id;
-    sa.disk_poke(disk_id).await;
+    sa.disk_poke(disk_id).await?;
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/power",
+}]
+async fn power_put(
+    rqctx: Arc<RequestContext<Arc<SledAgent>>>,
+    body: TypedBody<SledPowerState>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    sa.set_power_state(body.into_inner());
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/partition",
+}]
+async fn partition_put(
+    rqctx: Arc<RequestContext<Arc<SledAgent>>>,
+    body: TypedBody<bool>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    sa.set_partitioned(body.into_inner()).await;
     Ok(HttpResponseUpdatedNoContent())
 }
@@ -19,16 +19,132 @@ use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
 use omicron_common::api::internal::sled_agent::InstanceStateRequested;
 use propolis_client::api::InstanceState as PropolisInstanceState;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
+use crate::common::clock::Clock;
 use crate::common::instance::{Action as InstanceAction, InstanceStates};
 
+/**
+ * Settings for how Instances are simulated
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SimInstanceConfig {
+    /**
+     * how long a stopped Instance that's asked to be destroyed stays
+     * "stopped" before it's actually destroyed (zero to destroy it right away)
+     */
+    pub destroy_grace: Duration,
+}
+
 /**
  * Simulated Instance (virtual machine), as created by the external Oxide API
  */
 #[derive(Debug)]
 pub struct SimInstance {
     state: InstanceStates,
+    config: SimInstanceConfig,
+    /**
+     * request to destroy this (stopped) Instance that's waiting out
+     * `config.destroy_grace`, if any
+     */
+    pending_destroy: Option<InstanceRuntimeStateRequested>,
+    /** synthetic serial console output, one entry per line */
+    serial: Vec<String>,
+    /** why the Instance most recently went to "failed", if it ever has */
+    last_error: Option<String>,
+}
+
+impl SimInstance {
+    /** Returns the serial console output produced by this Instance so far. */
+    pub fn serial_output(&self) -> &[String] {
+        &self.serial
+    }
+
+    /**
+     * Returns why the Instance most recently went to "failed", if it ever has.
+     * This is kept even if the Instance is later restarted.
+     */
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /**
+     * Moves the Instance directly to "failed", abandoning any transition in
+     * progress, and records `reason` as its last error.
+     */
+    pub fn fail(&mut self, reason: String) {
+        self.last_error = Some(reason);
+        self.force_state(InstanceState::Failed);
+    }
+
+    /**
+     * Moves the Instance directly to "repairing", as though an operator had
+     * taken it out of service, abandoning any transition in progress.
+     */
+    pub fn repair(&mut self) {
+        self.force_state(InstanceState::Repairing);
+    }
+
+    /**
+     * Moves the Instance directly to `run_state`, abandoning any transition in
+     * progress (including a pending destroy).
+     */
+    fn force_state(&mut self, run_state: InstanceState) {
+        self.pending_destroy = None;
+        let previous = self.state.current().run_state;
+        let mut current = self.state.current().clone();
+        current.run_state = run_state;
+        current.gen = current.gen.next();
+        let clock = Arc::clone(self.state.clock());
+        current.time_updated = clock.now();
+        self.state = InstanceStates::new_with_clock(current, clock);
+        self.emit_serial(previous);
+    }
+
+    /**
+     * Records a request to destroy this stopped Instance once the destroy
+     * grace period is over.  The run state stays the same until then, but we
+     * still bump the generation number so that the request is seen as a
+     * change (and the grace period starts).
+     */
+    fn request_destroy_after_grace(
+        &mut self,
+        target: &InstanceRuntimeStateRequested,
+    ) {
+        if self.pending_destroy.is_some() {
+            return;
+        }
+        let mut current = self.state.current().clone();
+        current.gen = current.gen.next();
+        let clock = Arc::clone(self.state.clock());
+        current.time_updated = clock.now();
+        self.state = InstanceStates::new_with_clock(current, clock);
+        self.pending_destroy = Some(target.clone());
+    }
+
+    /**
+     * Appends canned console output appropriate for the Instance having just
+     * entered its current run state.
+     */
+    fn emit_serial(&mut self, previous: InstanceState) {
+        let current = self.state.current().run_state;
+        if current == previous {
+            return;
+        }
+        let lines: &[&str] = match current {
+            InstanceState::Starting => {
+                &["SeaBIOS (version simulated)", "Booting from Hard Disk..."]
+            }
+            InstanceState::Running => &[
+                "[    0.000000] Linux version simulated",
+                "[    1.000000] systemd[1]: Reached target Multi-User System.",
+            ],
+            InstanceState::Stopping => &["reboot: Power down"],
+            _ => &[],
+        };
+        self.serial.extend(lines.iter().map(|l| l.to_string()));
+    }
 }
 
 #[async_trait]
@@ -36,21 +152,55 @@ impl Simulatable for SimInstance {
     type CurrentState = InstanceRuntimeState;
     type RequestedState = InstanceRuntimeStateRequested;
     type Action = InstanceAction;
+    type Config = SimInstanceConfig;
 
-    fn new(current: InstanceRuntimeState) -> Self {
-        SimInstance { state: InstanceStates::new(current) }
+    fn new(
+        current: InstanceRuntimeState,
+        clock: Arc<dyn Clock>,
+        config: SimInstanceConfig,
+    ) -> Self {
+        SimInstance {
+            state: InstanceStates::new_with_clock(current, clock),
+            config,
+            pending_destroy: None,
+            serial: Vec::new(),
+            last_error: None,
+        }
     }
 
     fn request_transition(
         &mut self,
         target: &InstanceRuntimeStateRequested,
     ) -> Result<Option<InstanceAction>, Error> {
-        self.state.request_transition(target.run_state)
+        let previous = self.state.current().run_state;
+        if target.run_state == InstanceStateRequested::Destroyed
+            && self.config.destroy_grace > Duration::ZERO
+            && previous.is_stopped()
+            && previous != InstanceState::Destroyed
+            && self.state.desired().is_none()
+        {
+            self.request_destroy_after_grace(target);
+            return Ok(None);
+        }
+
+        /* Any other request supersedes a pending destroy. */
+        self.pending_destroy = None;
+        let rv = self.state.request_transition(target.run_state);
+        self.emit_serial(previous);
+        rv
     }
 
     fn execute_desired_transition(&mut self) -> Option<InstanceAction> {
-        if matches!(self.state.current().run_state, InstanceState::Rebooting) {
-            self.state.observe_transition(&PropolisInstanceState::Starting)
+        let previous = self.state.current().run_state;
+        let rv = if self.pending_destroy.take().is_some() {
+            /*
+             * The grace period is over.  Nothing else can have happened to the
+             * Instance in the meantime (that would have cancelled the pending
+             * destroy), so it's still stopped and can be destroyed right away.
+             */
+            self.state
+                .request_transition(InstanceStateRequested::Destroyed)
+                .expect("failed to destroy stopped instance")
         } else if let Some(desired) = self.state.desired() {
             // These operations would typically be triggered via responses from
             // Propolis, but for a simulated sled agent, this does not exist.
@@ -67,11 +217,60 @@ impl Simulatable for SimInstance {
                 InstanceStateRequested::Destroyed => {
                     PropolisInstanceState::Destroyed
                 }
-                _ => panic!("Unexpected desired state: {}", desired.run_state),
+                // Like Propolis, report that the Instance is stopping and then
+                // that it's starting back up.
+                InstanceStateRequested::Reboot => {
+                    if previous == InstanceState::Rebooting {
+                        PropolisInstanceState::Stopping
+                    } else {
+                        PropolisInstanceState::Starting
+                    }
+                }
             };
             self.state.observe_transition(&observed)
         } else {
             None
+        };
+        self.emit_serial(previous);
+        rv
+    }
+
+    fn transition_delay(&self) -> Option<Duration> {
+        self.pending_destroy.as_ref().map(|_| self.config.destroy_grace)
+    }
+
+    fn transition_can_fail(&self) -> bool {
+        self.state.current().run_state == InstanceState::Starting
+            && matches!(
+                self.state.desired(),
+                Some(InstanceRuntimeStateRequested {
+                    run_state: InstanceStateRequested::Running
+                })
+            )
+    }
+
+    fn fail_transition(&mut self) {
+        let reason = format!(
+            "simulated failure while {}",
+            self.state.current().run_state
+        );
+        self.fail(reason);
+    }
+
+    fn cancel_transition(&mut self) {
+        /* A stopped Instance waiting to be destroyed can just stay stopped. */
+        if self.pending_destroy.take().is_some() {
+            return;
+        }
+
+        /*
+         * We can't know how far along the Instance got, so the only state we
+         * can honestly report is "failed".
+         */
+        if let Some(desired) = self.state.desired() {
+            let reason =
+                format!("transition to {} cancelled", desired.run_state);
+            self.fail(reason);
         }
     }
 
@@ -84,7 +283,11 @@ impl Simulatable for SimInstance {
     }
 
     fn desired(&self) -> &Option<Self::RequestedState> {
-        self.state.desired()
+        if self.pending_destroy.is_some() {
+            &self.pending_destroy
+        } else {
+            self.state.desired()
+        }
     }
 
     fn ready_to_destroy(&self) -> bool {
@@ -15,5 +15,9 @@ mod server;
 mod simulatable;
 mod sled_agent;
 
-pub use config::{Config, SimMode};
+pub use config::{
+    Config, ConfigBootFailure, ConfigCapacity, ConfigWatchdog, SimMode,
+    SimQueue, WatchdogAction,
+};
 pub use server::{run_server, Server};
+pub use sled_agent::SledPowerState;
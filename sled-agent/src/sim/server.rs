@@ -6,9 +6,12 @@
 * Library interface to the sled agent
  */
 
+use super::collection::SimFailures;
 use super::config::Config;
+use super::config::SimMode;
 use super::http_entrypoints::api as http_api;
 use super::sled_agent::SledAgent;
+use crate::common::nexus;
 
 use nexus_client::Client as NexusClient;
 use omicron_common::backoff::{
@@ -16,6 +19,7 @@ use omicron_common::backoff::{
 };
 use slog::Logger;
 use std::sync::Arc;
+use std::time::Duration;
 
 /**
  * Packages up a [`SledAgent`], running the sled agent API under a Dropshot
@@ -26,6 +30,8 @@ pub struct Server {
     pub sled_agent: Arc<SledAgent>,
     /** dropshot server for the API */
     pub http_server: dropshot::HttpServer<Arc<SledAgent>>,
+    /** debug log */
+    log: Logger,
 }
 
 impl Server {
@@ -39,8 +45,9 @@ impl Server {
         info!(log, "setting up sled agent server");
 
         let client_log = log.new(o!("component" => "NexusClient"));
-        let nexus_client = Arc::new(NexusClient::new(
+        let nexus_client = Arc::new(NexusClient::new_with_client(
             &format!("http://{}", config.nexus_address),
+            nexus::http_client(Duration::from_millis(config.nexus_timeout_ms)),
             client_log,
         ));
 
@@ -48,12 +55,30 @@ impl Server {
             "component" => "SledAgent",
             "server" => config.id.clone().to_string()
         ));
+        let boot_failures = match (&config.boot_failure, config.sim_mode) {
+            (Some(boot_failure), SimMode::Auto) => Some(
+                SimFailures::new(boot_failure.probability, boot_failure.seed)
+                    .map_err(|e| format!("configuring boot failures: {}", e))?,
+            ),
+            _ => None,
+        };
         let sled_agent = Arc::new(SledAgent::new_simulated_with_id(
             &config.id,
             config.sim_mode,
+            config.sim_queue,
+            boot_failures,
             sa_log,
             Arc::clone(&nexus_client),
         ));
+        if let Some(watchdog) = &config.watchdog {
+            sled_agent.start_watchdog(watchdog.clone());
+        }
+        sled_agent.set_capacity(config.capacity.clone());
+        if let SimMode::Auto = config.sim_mode {
+            sled_agent.set_destroy_grace(Duration::from_millis(
+                config.destroy_grace_ms,
+            ));
+        }
 
         let sa = Arc::clone(&sled_agent);
         let dropshot_log = log.new(o!("component" => "dropshot"));
@@ -98,7 +123,7 @@ impl Server {
         )
         .await
         .expect("Expected an infinite retry loop contacting Nexus");
-        Ok(Server { sled_agent, http_server })
+        Ok(Server { sled_agent, http_server, log: log.clone() })
     }
 
     /**
@@ -111,6 +136,45 @@ impl Server {
     pub async fn wait_for_finish(self) -> Result<(), String> {
         self.http_server.await
     }
+
+    /**
+     * Shut down the server, stopping each part of it in turn
+     *
+     * This first stops accepting requests and waits for those already in
+     * flight to complete, so that nothing starts new simulated transitions.
+     * Then it stops the background tasks simulating transitions (and the
+     * watchdog).  Finally, it delivers any notifications to Nexus that were
+     * being held back by a simulated network partition so that Nexus sees the
+     * last state of each object.
+     *
+     * The simulation is stopped even if closing the HTTP server fails, in
+     * which case that error is returned afterwards.
+     */
+    pub async fn close(self) -> Result<(), String> {
+        let log = self.log;
+        info!(log, "shutting down: closing HTTP server");
+        let result = self
+            .http_server
+            .close()
+            .await
+            .map_err(|error| format!("closing HTTP server: {}", error));
+        stop_simulation(&log, &self.sled_agent).await;
+        result
+    }
+}
+
+/**
+ * Stop the simulation behind a server whose HTTP server has already stopped
+ * (see [`Server::close()`])
+ */
+async fn stop_simulation(log: &Logger, sled_agent: &SledAgent) {
+    info!(log, "shutting down: stopping simulation");
+    sled_agent.shutdown().await;
+    if sled_agent.partitioned() {
+        info!(log, "shutting down: flushing pending notifications");
+        sled_agent.set_partitioned(false).await;
+    }
+    info!(log, "shut down");
 }
 
 /**
@@ -122,7 +186,24 @@ pub async fn run_server(config: &Config) -> Result<(), String> {
         .to_logger("sled-agent")
         .map_err(|message| format!("initializing logger: {}", message))?;
 
-    let server = Server::start(config, &log).await?;
+    let mut server = Server::start(config, &log).await?;
     info!(log, "sled agent started successfully");
-    server.wait_for_finish().await
+    tokio::select! {
+        result = &mut server.http_server => {
+            /*
+             * The HTTP server stopped on its own.  There's nothing left to
+             * close there, but the simulation still needs to be stopped.
+             */
+            error!(log, "HTTP server exited unexpectedly");
+            stop_simulation(&log, &server.sled_agent).await;
+            result
+        }
+        signal = tokio::signal::ctrl_c() => {
+            signal.map_err(|error| {
+                format!("waiting for shutdown signal: {}", error)
+            })?;
+            info!(log, "received shutdown signal");
+            server.close().await
+        }
+    }
 }
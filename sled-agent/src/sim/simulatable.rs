@@ -2,12 +2,14 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::common::clock::Clock;
 use async_trait::async_trait;
 use nexus_client::Client as NexusClient;
 use omicron_common::api::external::Error;
 use omicron_common::api::external::Generation;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 /**
@@ -67,8 +69,17 @@ pub trait Simulatable: fmt::Debug + Send + Sync {
     /// Generated in response to a state change, either requested or observed.
     type Action: Send + Clone + fmt::Debug;
 
-    /// Creates a new Simulatable object.
-    fn new(current: Self::CurrentState) -> Self;
+    /// Settings that affect how objects of this type are simulated.  The
+    /// default value must simulate them the same way as always.
+    type Config: Send + Clone + Default + fmt::Debug;
+
+    /// Creates a new Simulatable object, whose state changes are timestamped
+    /// using `clock`.
+    fn new(
+        current: Self::CurrentState,
+        clock: Arc<dyn Clock>,
+        config: Self::Config,
+    ) -> Self;
 
     /// Requests that the simulated object transition to a new target.
     ///
@@ -86,6 +97,29 @@ pub trait Simulatable: fmt::Debug + Send + Sync {
     /// altering the resource into a desired state.
     fn execute_desired_transition(&mut self) -> Option<Self::Action>;
 
+    /// Returns how long the transition in progress should take to complete
+    /// when simulated automatically, if it should take something other than
+    /// the usual time.
+    fn transition_delay(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns true if the transition in progress is one that the simulation
+    /// may make fail (e.g., an Instance booting).
+    fn transition_can_fail(&self) -> bool {
+        false
+    }
+
+    /// Completes the transition in progress by failing it, in place of
+    /// `execute_desired_transition()`.  This is only invoked when
+    /// `transition_can_fail()` returns true.
+    fn fail_transition(&mut self) {}
+
+    /// Abandons the transition in progress, if any, leaving the object in a
+    /// well-defined state rather than letting the transition complete.  This
+    /// is used when whoever requested the transition has gone away.
+    fn cancel_transition(&mut self);
+
     /// Returns the generation number for the current state.
     fn generation(&self) -> Generation;
 
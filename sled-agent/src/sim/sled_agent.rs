@@ -6,21 +6,51 @@
  * Simulated sled agent implementation
  */
 
+use crate::common::clock::Clock;
 use crate::params::DiskStateRequested;
 use nexus_client::Client as NexusClient;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use omicron_common::api::external::ResourceType;
 use omicron_common::api::internal::nexus::DiskRuntimeState;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use omicron_common::api::internal::sled_agent::InstanceHardware;
 use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
 use slog::Logger;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
+use super::collection::SimAdmission;
 use super::collection::SimCollection;
+use super::collection::SimFailures;
+use super::config::ConfigCapacity;
+use super::config::ConfigWatchdog;
 use super::config::SimMode;
+use super::config::SimQueue;
+use super::config::WatchdogAction;
 use super::disk::SimDisk;
 use super::instance::SimInstance;
+use super::instance::SimInstanceConfig;
+use super::simulatable::Simulatable;
+
+/**
+ * Simulated power state of the sled itself (as opposed to that of the
+ * Instances on it)
+ */
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SledPowerState {
+    On,
+    Off,
+}
 
 /**
  * Simulates management of the control plane on a sled
@@ -39,6 +69,14 @@ pub struct SledAgent {
     instances: Arc<SimCollection<SimInstance>>,
     /** collection of simulated disks, indexed by disk uuid */
     disks: Arc<SimCollection<SimDisk>>,
+    /** debug log */
+    log: Logger,
+    /** whether the sled is powered on */
+    power_state: Mutex<SledPowerState>,
+    /** resources available to Instances (unlimited if `None`) */
+    capacity: Mutex<Option<ConfigCapacity>>,
+    /** background task started by `start_watchdog()`, if any */
+    watchdog: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl SledAgent {
@@ -50,10 +88,15 @@ impl SledAgent {
     pub fn new_simulated_with_id(
         id: &Uuid,
         sim_mode: SimMode,
+        sim_queue: SimQueue,
+        boot_failures: Option<SimFailures>,
         log: Logger,
         ctlsc: Arc<NexusClient>,
     ) -> SledAgent {
-        info!(&log, "created simulated sled agent"; "sim_mode" => ?sim_mode);
+        info!(&log, "created simulated sled agent";
+            "sim_mode" => ?sim_mode,
+            "sim_queue" => ?sim_queue,
+        );
 
         let instance_log = log.new(o!("kind" => "instances"));
         let disk_log = log.new(o!("kind" => "disks"));
@@ -64,12 +107,84 @@ impl SledAgent {
                 Arc::clone(&ctlsc),
                 instance_log,
                 sim_mode,
+                sim_queue,
+                boot_failures,
             )),
             disks: Arc::new(SimCollection::new(
                 Arc::clone(&ctlsc),
                 disk_log,
                 sim_mode,
+                sim_queue,
+                None,
             )),
+            log,
+            power_state: Mutex::new(SledPowerState::On),
+            capacity: Mutex::new(None),
+            watchdog: Mutex::new(None),
+        }
+    }
+
+    /** Returns the resources available to Instances, if limited. */
+    pub fn capacity(&self) -> Option<ConfigCapacity> {
+        self.capacity.lock().unwrap().clone()
+    }
+
+    /**
+     * Limits the resources available to Instances on this sled (or removes
+     * the limit, if `capacity` is `None`).  Once a new Instance would take the
+     * sled past any of these limits, requests to create it fail.  Instances
+     * that already exist are left alone, even if they exceed a new limit.
+     */
+    pub fn set_capacity(&self, capacity: Option<ConfigCapacity>) {
+        info!(self.log, "setting capacity"; "capacity" => ?capacity);
+        *self.capacity.lock().unwrap() = capacity.clone();
+        let sled_id = self.id;
+        let admission = capacity.map(|capacity| -> SimAdmission<SimInstance> {
+            Box::new(move |new, existing| {
+                check_capacity(sled_id, &capacity, new, existing)
+            })
+        });
+        self.instances.sim_set_admission(admission);
+    }
+
+    /** Returns the simulated power state of the sled. */
+    pub fn power_state(&self) -> SledPowerState {
+        *self.power_state.lock().unwrap()
+    }
+
+    /**
+     * Sets the simulated power state of the sled.  While the sled is off,
+     * requests to ensure the state of Instances and Disks fail.  Objects that
+     * already exist are left alone.
+     */
+    pub fn set_power_state(&self, power_state: SledPowerState) {
+        info!(self.log, "setting power state"; "power_state" => ?power_state);
+        *self.power_state.lock().unwrap() = power_state;
+    }
+
+    /**
+     * Starts or stops simulating a network partition between this sled and
+     * Nexus.  While partitioned, Instance and Disk state changes are not
+     * reported to Nexus; the latest state of each is reported once the
+     * partition heals.
+     */
+    pub async fn set_partitioned(&self, partitioned: bool) {
+        info!(self.log, "setting partitioned"; "partitioned" => partitioned);
+        self.instances.sim_set_partitioned(partitioned).await;
+        self.disks.sim_set_partitioned(partitioned).await;
+    }
+
+    /** Returns whether we're simulating a partition from Nexus. */
+    pub fn partitioned(&self) -> bool {
+        self.instances.sim_partitioned()
+    }
+
+    fn check_powered_on(&self) -> Result<(), Error> {
+        match self.power_state() {
+            SledPowerState::On => Ok(()),
+            SledPowerState::Off => {
+                Err(Error::unavail(&format!("sled {} is powered off", self.id)))
+            }
         }
     }
 
@@ -84,12 +199,46 @@ impl SledAgent {
         initial_hardware: InstanceHardware,
         target: InstanceRuntimeStateRequested,
     ) -> Result<InstanceRuntimeState, Error> {
+        self.check_powered_on()?;
         Ok(self
             .instances
             .sim_ensure(&instance_id, initial_hardware.runtime, target)
             .await?)
     }
 
+    /**
+     * Like `instance_ensure()`, but waits for the Instance to reach the
+     * requested state.  Returns the final state, or `None` if the Instance
+     * was destroyed.
+     *
+     * If the returned future is dropped first, the transition is cancelled
+     * as with `instance_cancel()`.
+     */
+    pub async fn instance_ensure_complete(
+        self: &Arc<Self>,
+        instance_id: Uuid,
+        initial_hardware: InstanceHardware,
+        target: InstanceRuntimeStateRequested,
+    ) -> Result<Option<InstanceRuntimeState>, Error> {
+        self.check_powered_on()?;
+        self.instances
+            .sim_ensure_complete(&instance_id, initial_hardware.runtime, target)
+            .await
+    }
+
+    /**
+     * Abandons any state transition in progress for the given Instance.  An
+     * Instance that was partway through a transition ends up "failed".
+     */
+    pub async fn instance_cancel(
+        &self,
+        instance_id: Uuid,
+    ) -> Result<InstanceRuntimeState, Error> {
+        self.instances.sim_cancel(instance_id).await.ok_or_else(|| {
+            Error::not_found_by_id(ResourceType::Instance, &instance_id)
+        })
+    }
+
     /**
      * Idempotently ensures that the given API Disk (described by `api_disk`)
      * is attached (or not) as specified.  This simulates disk attach and
@@ -101,14 +250,862 @@ impl SledAgent {
         initial_state: DiskRuntimeState,
         target: DiskStateRequested,
     ) -> Result<DiskRuntimeState, Error> {
+        self.check_powered_on()?;
         Ok(self.disks.sim_ensure(&disk_id, initial_state, target).await?)
     }
 
-    pub async fn instance_poke(&self, id: Uuid) {
-        self.instances.sim_poke(id).await;
+    /**
+     * Returns the synthetic serial console output of the given Instance.
+     */
+    pub async fn instance_serial(
+        &self,
+        instance_id: Uuid,
+    ) -> Result<Vec<String>, Error> {
+        self.instances
+            .sim_inspect(&instance_id, |i| i.serial_output().to_vec())
+            .await
+            .ok_or_else(|| {
+                Error::not_found_by_id(ResourceType::Instance, &instance_id)
+            })
+    }
+
+    /**
+     * Returns why the given Instance most recently went to "failed", if it
+     * ever has.
+     */
+    pub async fn instance_last_error(
+        &self,
+        instance_id: Uuid,
+    ) -> Result<Option<String>, Error> {
+        self.instances
+            .sim_inspect(&instance_id, |i| i.last_error().map(String::from))
+            .await
+            .ok_or_else(|| {
+                Error::not_found_by_id(ResourceType::Instance, &instance_id)
+            })
+    }
+
+    /**
+     * Starts a background task that periodically runs
+     * [`SledAgent::instance_watchdog_check`].  The task exits once the
+     * SledAgent has been dropped or shut down (see [`SledAgent::shutdown`]).
+     */
+    pub fn start_watchdog(self: &Arc<Self>, config: ConfigWatchdog) {
+        let sa = Arc::downgrade(self);
+        let period = Duration::from_millis(config.threshold_ms.max(1));
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match sa.upgrade() {
+                    Some(sa) => {
+                        sa.instance_watchdog_check(&config).await;
+                    }
+                    None => break,
+                }
+            }
+        });
+        if let Some(old) = self.watchdog.lock().unwrap().replace(task) {
+            old.abort();
+        }
+    }
+
+    /**
+     * Looks for Instances that have been in an intermediate state (e.g.,
+     * "starting") for longer than the configured threshold, logs a warning
+     * for each one, and takes the configured action.  Returns the ids of the
+     * Instances found.
+     */
+    pub async fn instance_watchdog_check(
+        &self,
+        config: &ConfigWatchdog,
+    ) -> Vec<Uuid> {
+        let threshold = Duration::from_millis(config.threshold_ms);
+        let stalled = self.instances.sim_stalled(threshold).await;
+        for id in &stalled {
+            warn!(self.log, "instance stalled in intermediate state";
+                "instance_id" => %id,
+                "threshold_ms" => config.threshold_ms,
+                "action" => ?config.action,
+            );
+            if let WatchdogAction::Fail = config.action {
+                let result = self
+                    .instances
+                    .sim_force_update(*id, |i| {
+                        let reason = format!(
+                            "stalled in state {} for more than {} ms",
+                            i.current().run_state,
+                            config.threshold_ms
+                        );
+                        i.fail(reason)
+                    })
+                    .await;
+                if let Some(Err(error)) = result {
+                    warn!(self.log, "failed to report failed instance";
+                        "instance_id" => %id,
+                        "error" => ?error,
+                    );
+                }
+            }
+        }
+        stalled
+    }
+
+    /**
+     * Pauses the simulation of asynchronous Instance and Disk transitions
+     * until `resume()` is called.  (This only matters for `SimMode::Auto`.)
+     */
+    pub fn pause(&self) {
+        self.instances.sim_pause();
+        self.disks.sim_pause();
+    }
+
+    /** Resumes simulation after `pause()`. */
+    pub fn resume(&self) {
+        self.instances.sim_resume();
+        self.disks.sim_resume();
+    }
+
+    /**
+     * Stops the watchdog (if any) and the background tasks that simulate
+     * Instance and Disk transitions (see [`SimCollection::sim_shutdown()`]).
+     */
+    pub async fn shutdown(&self) {
+        let watchdog = self.watchdog.lock().unwrap().take();
+        if let Some(watchdog) = watchdog {
+            watchdog.abort();
+            /* The task was either cancelled or had already finished. */
+            let _ = watchdog.await;
+        }
+        self.instances.sim_shutdown().await;
+        self.disks.sim_shutdown().await;
+    }
+
+    /**
+     * Sets how long a stopped Instance that's asked to be destroyed stays
+     * "stopped" before it's actually destroyed.  This applies to Instances
+     * created from now on.  (In `SimMode::Explicit`, the Instance is instead
+     * destroyed whenever it's next poked.)
+     */
+    pub fn set_destroy_grace(&self, grace: Duration) {
+        info!(self.log, "setting destroy grace period"; "grace" => ?grace);
+        self.instances
+            .sim_set_config(SimInstanceConfig { destroy_grace: grace });
+    }
+
+    /**
+     * Sets the clock used to timestamp state changes of Instances and Disks
+     * created from now on (see [`SimCollection::sim_set_clock()`]).
+     */
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.instances.sim_set_clock(Arc::clone(&clock));
+        self.disks.sim_set_clock(clock);
+    }
+
+    /**
+     * Returns how many background simulation tasks (including the watchdog)
+     * are still running.
+     */
+    pub fn sim_tasks_running(&self) -> usize {
+        /*
+         * The watchdog only exits on its own once the SledAgent is gone, so
+         * it's running for as long as we have it.
+         */
+        let watchdog = usize::from(self.watchdog.lock().unwrap().is_some());
+        watchdog
+            + self.instances.sim_tasks_running()
+            + self.disks.sim_tasks_running()
+    }
+
+    /**
+     * Completes the transition in progress (if any) for the given Instance,
+     * returning its resulting state.
+     */
+    pub async fn instance_poke(
+        &self,
+        id: Uuid,
+    ) -> Result<InstanceRuntimeState, Error> {
+        self.instances
+            .sim_poke(id)
+            .await
+            .ok_or_else(|| Error::not_found_by_id(ResourceType::Instance, &id))
+    }
+
+    /**
+     * Moves the given Instance to "repairing" (see [`SimInstance::repair()`])
+     * and notifies Nexus of its new state.
+     */
+    pub async fn instance_repair(&self, id: Uuid) -> Result<(), Error> {
+        self.instances.sim_force_update(id, |i| i.repair()).await.ok_or_else(
+            || Error::not_found_by_id(ResourceType::Instance, &id),
+        )?
+    }
+
+    /**
+     * Returns the id and current state of each Instance whose state is one of
+     * `states`, in order of id
+     */
+    pub async fn instances_in_states(
+        &self,
+        states: &[InstanceState],
+    ) -> Vec<(Uuid, InstanceRuntimeState)> {
+        self.instances
+            .sim_select(|instance| {
+                states.contains(&instance.current().run_state)
+            })
+            .await
+    }
+
+    /**
+     * Returns the ids of Instances with a transition in progress, which are
+     * the ones that `instance_poke()` would move along.
+     */
+    pub async fn instances_pending(&self) -> Vec<Uuid> {
+        self.instances.sim_pending().await
+    }
+
+    /**
+     * Completes the transition in progress (if any) for the given Disk,
+     * returning its resulting state.
+     */
+    pub async fn disk_poke(&self, id: Uuid) -> Result<DiskRuntimeState, Error> {
+        self.disks
+            .sim_poke(id)
+            .await
+            .ok_or_else(|| Error::not_found_by_id(ResourceType::Disk, &id))
+    }
+}
+
+/**
+ * Checks whether a new Instance (described by `new`) fits on a sled with the
+ * given capacity alongside the `existing` Instances
+ */
+fn check_capacity(
+    sled_id: Uuid,
+    capacity: &ConfigCapacity,
+    new: &InstanceRuntimeState,
+    existing: &[&InstanceRuntimeState],
+) -> Result<(), Error> {
+    let ninstances = existing.len() as u64 + 1;
+    let ncpus: u64 = existing
+        .iter()
+        .chain(std::iter::once(&new))
+        .map(|i| u64::from(i.ncpus.0))
+        .sum();
+    let memory: u64 = existing
+        .iter()
+        .chain(std::iter::once(&new))
+        .map(|i| i.memory.to_bytes())
+        .sum();
+
+    let exceeded = if ninstances > u64::from(capacity.instances) {
+        format!("at most {} instances", capacity.instances)
+    } else if ncpus > capacity.cpus {
+        format!("at most {} CPUs", capacity.cpus)
+    } else if memory > capacity.memory_bytes {
+        format!("at most {} bytes of memory", capacity.memory_bytes)
+    } else {
+        return Ok(());
+    };
+    Err(Error::unavail(&format!(
+        "sled {} has insufficient capacity for instance (allows {})",
+        sled_id, exceeded
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::SledAgent;
+    use super::SledPowerState;
+    use crate::sim::collection::SimFailures;
+    use crate::sim::config::ConfigCapacity;
+    use crate::sim::config::ConfigWatchdog;
+    use crate::sim::config::SimMode;
+    use crate::sim::config::SimQueue;
+    use crate::sim::config::WatchdogAction;
+    use crate::sim::simulatable::Simulatable;
+    use chrono::Utc;
+    use dropshot::test_util::LogContext;
+    use nexus_client::Client as NexusClient;
+    use omicron_common::api::external::ByteCount;
+    use omicron_common::api::external::Error;
+    use omicron_common::api::external::Generation;
+    use omicron_common::api::external::InstanceCpuCount;
+    use omicron_common::api::external::InstanceState;
+    use omicron_common::api::internal::nexus::InstanceRuntimeState;
+    use omicron_common::api::internal::sled_agent::InstanceHardware;
+    use omicron_common::api::internal::sled_agent::InstanceRuntimeStateRequested;
+    use omicron_common::api::internal::sled_agent::InstanceStateRequested;
+    use omicron_test_utils::dev::test_setup_log;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+    use uuid::Uuid;
+
+    /*
+     * The sled agents made here are not able to contact Nexus.  Anything that
+     * would notify Nexus logs a warning and moves on.
+     */
+    fn make_sled_agent(
+        logctx: &LogContext,
+        sim_mode: SimMode,
+    ) -> Arc<SledAgent> {
+        let nexus_client = Arc::new(NexusClient::new(
+            "http://127.0.0.1:0",
+            logctx.log.new(o!()),
+        ));
+        Arc::new(SledAgent::new_simulated_with_id(
+            &Uuid::new_v4(),
+            sim_mode,
+            SimQueue::Coalescing,
+            None,
+            logctx.log.new(o!()),
+            nexus_client,
+        ))
+    }
+
+    /** Returns the hardware description of a new Instance on `sa`. */
+    fn instance_hardware(sa: &SledAgent) -> InstanceHardware {
+        InstanceHardware {
+            runtime: InstanceRuntimeState {
+                run_state: InstanceState::Creating,
+                sled_uuid: sa.id,
+                propolis_uuid: Uuid::new_v4(),
+                ncpus: InstanceCpuCount(2),
+                memory: ByteCount::from_mebibytes_u32(512),
+                hostname: "myvm".to_string(),
+                gen: Generation::new(),
+                time_updated: Utc::now(),
+            },
+            nics: vec![],
+        }
+    }
+
+    fn instance_running() -> InstanceRuntimeStateRequested {
+        InstanceRuntimeStateRequested {
+            run_state: InstanceStateRequested::Running,
+        }
+    }
+
+    /** Creates an Instance on `sa` and asks for it to boot. */
+    async fn instance_boot(sa: &Arc<SledAgent>, id: Uuid) {
+        let runtime = sa
+            .instance_ensure(id, instance_hardware(sa), instance_running())
+            .await
+            .unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Starting);
+    }
+
+    async fn instance_run_state(sa: &SledAgent, id: Uuid) -> InstanceState {
+        sa.instances.sim_inspect(&id, |i| i.current().run_state).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_flags_stalled_instance() {
+        let logctx = test_setup_log("test_watchdog_flags_stalled_instance");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+        let config =
+            ConfigWatchdog { threshold_ms: 100, action: WatchdogAction::Warn };
+
+        /*
+         * Boot an Instance.  In explicit mode, nothing completes the transition
+         * for us, so it stays in "starting".
+         */
+        let id = Uuid::new_v4();
+        instance_boot(&sa, id).await;
+
+        /* The transition is not overdue yet. */
+        assert!(sa.instance_watchdog_check(&config).await.is_empty());
+
+        /* Once the threshold passes, the watchdog flags the Instance. */
+        tokio::time::sleep(Duration::from_millis(config.threshold_ms)).await;
+        assert_eq!(sa.instance_watchdog_check(&config).await, vec![id]);
+
+        logctx.cleanup_successful();
     }
 
-    pub async fn disk_poke(&self, id: Uuid) {
-        self.disks.sim_poke(id).await;
+    #[tokio::test]
+    async fn test_watchdog_fails_stalled_instance() {
+        let logctx = test_setup_log("test_watchdog_fails_stalled_instance");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+        let config =
+            ConfigWatchdog { threshold_ms: 100, action: WatchdogAction::Fail };
+
+        let id = Uuid::new_v4();
+        instance_boot(&sa, id).await;
+        assert!(sa.instance_watchdog_check(&config).await.is_empty());
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Starting);
+
+        /*
+         * Once the threshold passes, the watchdog fails the Instance and
+         * records why.
+         */
+        tokio::time::sleep(Duration::from_millis(config.threshold_ms)).await;
+        assert_eq!(sa.instance_watchdog_check(&config).await, vec![id]);
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Failed);
+        assert_eq!(
+            sa.instance_last_error(id).await.unwrap(),
+            Some(format!(
+                "stalled in state starting for more than {} ms",
+                config.threshold_ms
+            ))
+        );
+
+        /* A failed Instance has nothing in progress, so it's not flagged. */
+        assert!(sa.instances_pending().await.is_empty());
+        assert!(sa.instance_watchdog_check(&config).await.is_empty());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let logctx = test_setup_log("test_pause_resume");
+        let sa = make_sled_agent(&logctx, SimMode::Auto);
+
+        /*
+         * Pause the simulation while the Instance is starting.  Even after
+         * more than enough (simulated) time for the boot to finish, it's
+         * still starting.
+         */
+        let id = Uuid::new_v4();
+        tokio::time::pause();
+        sa.pause();
+        instance_boot(&sa, id).await;
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Starting);
+
+        /* Once we resume, the boot completes. */
+        sa.resume();
+        let runtime = sa
+            .instance_ensure_complete(
+                id,
+                instance_hardware(&sa),
+                instance_running(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Running);
+
+        sa.shutdown().await;
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instance_ensure_complete() {
+        let logctx = test_setup_log("test_instance_ensure_complete");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+
+        /* If the boot is allowed to finish, we get back the final state. */
+        let id = Uuid::new_v4();
+        let (runtime, _) = tokio::join!(
+            sa.instance_ensure_complete(
+                id,
+                instance_hardware(&sa),
+                instance_running()
+            ),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                sa.instance_poke(id).await.unwrap();
+            }
+        );
+        let runtime = runtime.unwrap().unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Running);
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instance_ensure_running_twice() {
+        let logctx = test_setup_log("test_instance_ensure_running_twice");
+        let sa = make_sled_agent(&logctx, SimMode::Auto);
+        let id = Uuid::new_v4();
+        let hardware = instance_hardware(&sa);
+        let gen = hardware.runtime.gen;
+
+        /*
+         * Ask for a new Instance to be running twice at once.  Whichever
+         * request comes first creates the Instance and starts it.  The other
+         * finds it already starting and changes nothing, so only one
+         * transition gets simulated.
+         */
+        let (r1, r2) = tokio::join!(
+            sa.instance_ensure(id, hardware.clone(), instance_running()),
+            sa.instance_ensure(id, hardware.clone(), instance_running()),
+        );
+        let (r1, r2) = (r1.unwrap(), r2.unwrap());
+        assert_eq!(r1.run_state, InstanceState::Starting);
+        assert_eq!(r1.gen, gen.next());
+        assert_eq!(r1, r2);
+        assert_eq!(sa.sim_tasks_running(), 1);
+
+        /* The Instance then becomes "running" exactly once. */
+        let runtime = sa
+            .instance_ensure_complete(id, hardware, instance_running())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Running);
+        assert_eq!(runtime.gen, gen.next().next());
+
+        sa.shutdown().await;
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instance_ensure_dropped() {
+        let logctx = test_setup_log("test_instance_ensure_dropped");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+
+        /*
+         * Drop the ensure future while the Instance is still starting.  The
+         * boot must not go on to complete: the Instance winds up "failed",
+         * with no transition outstanding.
+         */
+        let id = Uuid::new_v4();
+        let result = tokio::time::timeout(
+            Duration::from_millis(100),
+            sa.instance_ensure_complete(
+                id,
+                instance_hardware(&sa),
+                instance_running(),
+            ),
+        )
+        .await;
+        assert!(result.is_err());
+
+        /* The cancellation happens asynchronously. */
+        let mut run_state = instance_run_state(&sa, id).await;
+        for _ in 0..100 {
+            if run_state != InstanceState::Starting {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            run_state = instance_run_state(&sa, id).await;
+        }
+        assert_eq!(run_state, InstanceState::Failed);
+        assert!(sa
+            .instances
+            .sim_inspect(&id, |i| i.desired().is_none())
+            .await
+            .unwrap());
+
+        /* A later poke has nothing to do. */
+        sa.instance_poke(id).await.unwrap();
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Failed);
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instance_last_error() {
+        let logctx = test_setup_log("test_instance_last_error");
+        let nexus_client = Arc::new(NexusClient::new(
+            "http://127.0.0.1:0",
+            logctx.log.new(o!()),
+        ));
+        let sa = Arc::new(SledAgent::new_simulated_with_id(
+            &Uuid::new_v4(),
+            SimMode::Explicit,
+            SimQueue::Coalescing,
+            Some(SimFailures::new(1.0, 0).unwrap()),
+            logctx.log.new(o!()),
+            nexus_client,
+        ));
+
+        /* A new Instance has no error. */
+        let id = Uuid::new_v4();
+        instance_boot(&sa, id).await;
+        assert_eq!(sa.instance_last_error(id).await.unwrap(), None);
+
+        /* Every boot fails, and the reason is recorded. */
+        sa.instance_poke(id).await.unwrap();
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Failed);
+        assert_eq!(
+            sa.instance_last_error(id).await.unwrap().as_deref(),
+            Some("simulated failure while starting")
+        );
+
+        /* Cancelled transitions record a reason, too. */
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+        let id = Uuid::new_v4();
+        instance_boot(&sa, id).await;
+        sa.instance_cancel(id).await.unwrap();
+        assert_eq!(
+            sa.instance_last_error(id).await.unwrap().as_deref(),
+            Some("transition to running cancelled")
+        );
+
+        /* Asking about an unknown Instance fails. */
+        assert!(sa.instance_last_error(Uuid::new_v4()).await.is_err());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_sim_tasks() {
+        let logctx = test_setup_log("test_shutdown_stops_sim_tasks");
+        let sa = make_sled_agent(&logctx, SimMode::Auto);
+
+        /* Each Instance gets its own background task in automatic mode. */
+        let ids: Vec<Uuid> = (0..20).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            instance_boot(&sa, *id).await;
+        }
+        assert_eq!(sa.sim_tasks_running(), ids.len());
+
+        for id in &ids {
+            sa.instance_ensure(
+                *id,
+                instance_hardware(&sa),
+                InstanceRuntimeStateRequested {
+                    run_state: InstanceStateRequested::Destroyed,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        /* Shutting down leaves no tasks behind, and is idempotent. */
+        sa.shutdown().await;
+        assert_eq!(sa.sim_tasks_running(), 0);
+        sa.shutdown().await;
+        assert_eq!(sa.sim_tasks_running(), 0);
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_watchdog() {
+        let logctx = test_setup_log("test_shutdown_stops_watchdog");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+        assert_eq!(sa.sim_tasks_running(), 0);
+
+        /* Restarting the watchdog replaces the old task. */
+        let config =
+            ConfigWatchdog { threshold_ms: 100, action: WatchdogAction::Warn };
+        sa.start_watchdog(config.clone());
+        sa.start_watchdog(config);
+        assert_eq!(sa.sim_tasks_running(), 1);
+
+        sa.shutdown().await;
+        assert_eq!(sa.sim_tasks_running(), 0);
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instance_cancel() {
+        let logctx = test_setup_log("test_instance_cancel");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+
+        let id = Uuid::new_v4();
+        instance_boot(&sa, id).await;
+        let runtime = sa.instance_cancel(id).await.unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Failed);
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Failed);
+
+        /* Cancelling when nothing is in progress changes nothing. */
+        assert_eq!(sa.instance_cancel(id).await.unwrap(), runtime);
+
+        /* Cancelling an unknown Instance fails. */
+        assert!(sa.instance_cancel(Uuid::new_v4()).await.is_err());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instances_in_states() {
+        let logctx = test_setup_log("test_instances_in_states");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+
+        /*
+         * Leave one Instance starting, get one running, and make one fail
+         * partway through its boot.
+         */
+        let mut ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        ids.sort();
+        let (starting, running, failed) = (ids[0], ids[1], ids[2]);
+        for id in &ids {
+            instance_boot(&sa, *id).await;
+        }
+        sa.instance_poke(running).await.unwrap();
+        sa.instance_cancel(failed).await.unwrap();
+
+        let selected = |states: &'static [InstanceState]| {
+            let sa = Arc::clone(&sa);
+            async move {
+                sa.instances_in_states(states)
+                    .await
+                    .into_iter()
+                    .map(|(id, runtime)| {
+                        assert!(states.contains(&runtime.run_state));
+                        id
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(selected(&[InstanceState::Failed]).await, vec![failed]);
+        assert_eq!(
+            selected(&[InstanceState::Starting, InstanceState::Running]).await,
+            vec![starting, running]
+        );
+        assert_eq!(
+            selected(&[
+                InstanceState::Failed,
+                InstanceState::Running,
+                InstanceState::Starting
+            ])
+            .await,
+            ids
+        );
+        assert!(selected(&[InstanceState::Stopped]).await.is_empty());
+        assert!(selected(&[]).await.is_empty());
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_instance_destroy_grace() {
+        let logctx = test_setup_log("test_instance_destroy_grace");
+        let sa = make_sled_agent(&logctx, SimMode::Auto);
+        let stop = InstanceRuntimeStateRequested {
+            run_state: InstanceStateRequested::Stopped,
+        };
+        let destroy = InstanceRuntimeStateRequested {
+            run_state: InstanceStateRequested::Destroyed,
+        };
+
+        /* By default, a stopped Instance is destroyed right away. */
+        let id = Uuid::new_v4();
+        sa.instance_ensure(id, instance_hardware(&sa), stop.clone())
+            .await
+            .unwrap();
+        let runtime = sa
+            .instance_ensure(id, instance_hardware(&sa), destroy.clone())
+            .await
+            .unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Destroyed);
+
+        /* With a grace period, it stays stopped until that's over. */
+        let grace = Duration::from_millis(500);
+        sa.set_destroy_grace(grace);
+        let id = Uuid::new_v4();
+        sa.instance_ensure(id, instance_hardware(&sa), stop).await.unwrap();
+        let start = Instant::now();
+        let runtime = sa
+            .instance_ensure(id, instance_hardware(&sa), destroy.clone())
+            .await
+            .unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Stopped);
+        assert_eq!(instance_run_state(&sa, id).await, InstanceState::Stopped);
+        let runtime = sa
+            .instance_ensure_complete(id, instance_hardware(&sa), destroy)
+            .await
+            .unwrap();
+        assert!(runtime.is_none());
+        assert!(start.elapsed() >= grace);
+
+        sa.shutdown().await;
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_sled_powered_off() {
+        let logctx = test_setup_log("test_sled_powered_off");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+        assert_eq!(sa.power_state(), SledPowerState::On);
+
+        /* While the sled is off, Instances cannot be created or booted. */
+        sa.set_power_state(SledPowerState::Off);
+        let id = Uuid::new_v4();
+        let error = sa
+            .instance_ensure(id, instance_hardware(&sa), instance_running())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::unavail(&format!("sled {} is powered off", sa.id))
+        );
+        assert!(sa.instances.sim_inspect(&id, |_| ()).await.is_none());
+
+        /* Once it's back on, they can. */
+        sa.set_power_state(SledPowerState::On);
+        instance_boot(&sa, id).await;
+
+        logctx.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_sled_capacity() {
+        let logctx = test_setup_log("test_sled_capacity");
+        let sa = make_sled_agent(&logctx, SimMode::Explicit);
+        assert_eq!(sa.capacity(), None);
+
+        /* Fill the sled up with Instances. */
+        let capacity =
+            ConfigCapacity { instances: 3, cpus: 64, memory_bytes: 1 << 40 };
+        sa.set_capacity(Some(capacity.clone()));
+        assert_eq!(sa.capacity(), Some(capacity));
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            instance_boot(&sa, *id).await;
+        }
+
+        /* The next one is rejected, and isn't created. */
+        let id = Uuid::new_v4();
+        let error = sa
+            .instance_ensure(id, instance_hardware(&sa), instance_running())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::unavail(&format!(
+                "sled {} has insufficient capacity for instance \
+                 (allows at most 3 instances)",
+                sa.id
+            ))
+        );
+        assert!(sa.instances.sim_inspect(&id, |_| ()).await.is_none());
+
+        /* Instances that are already there can still be changed. */
+        let runtime = sa
+            .instance_ensure(ids[0], instance_hardware(&sa), instance_running())
+            .await
+            .unwrap();
+        assert_eq!(runtime.run_state, InstanceState::Starting);
+
+        /*
+         * Each Instance has 2 CPUs, so with room for more Instances but only 7
+         * CPUs, the sled is still full.
+         */
+        sa.set_capacity(Some(ConfigCapacity {
+            instances: 10,
+            cpus: 7,
+            memory_bytes: 1 << 40,
+        }));
+        let error = sa
+            .instance_ensure(id, instance_hardware(&sa), instance_running())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::unavail(&format!(
+                "sled {} has insufficient capacity for instance \
+                 (allows at most 7 CPUs)",
+                sa.id
+            ))
+        );
+
+        /* With enough CPUs, it fits. */
+        sa.set_capacity(Some(ConfigCapacity {
+            instances: 10,
+            cpus: 8,
+            memory_bytes: 1 << 40,
+        }));
+        instance_boot(&sa, id).await;
+
+        logctx.cleanup_successful();
     }
 }
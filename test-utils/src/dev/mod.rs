@@ -9,6 +9,7 @@
 
 pub mod clickhouse;
 pub mod db;
+pub mod openapi;
 pub mod poll;
 pub mod test_cmds;
 
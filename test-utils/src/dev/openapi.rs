@@ -0,0 +1,542 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*!
+ * Structured comparison of OpenAPI documents
+ *
+ * The checked-in OpenAPI documents catch any change to an API, but a textual
+ * diff of a large JSON file doesn't make it obvious which operations changed.
+ * [`diff_operations()`] summarizes the differences per operation instead.
+ */
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/** HTTP methods that may appear as operations in an OpenAPI path item */
+const METHODS: [&str; 8] =
+    ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/**
+ * Describes how one operation (an HTTP method on a path) differs between two
+ * versions of an OpenAPI document
+ */
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum OperationChange {
+    /** the operation exists only in the new document */
+    Added { method: String, path: String },
+    /** the operation exists only in the old document */
+    Removed { method: String, path: String },
+    /**
+     * the operation exists in both documents, but its definition differs
+     *
+     * `breaking` describes each difference that could break existing clients
+     * (see [`breaking_differences()`]), and is empty if there are none.
+     */
+    Changed { method: String, path: String, breaking: Vec<String> },
+}
+
+impl OperationChange {
+    /**
+     * Returns whether this change could break existing clients: either the
+     * operation was removed, or it was changed in a way that clients of the
+     * old version may not cope with.
+     */
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            OperationChange::Added { .. } => false,
+            OperationChange::Removed { .. } => true,
+            OperationChange::Changed { breaking, .. } => !breaking.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for OperationChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (label, method, path) = match self {
+            OperationChange::Added { method, path } => ("added", method, path),
+            OperationChange::Removed { method, path } => {
+                ("removed", method, path)
+            }
+            OperationChange::Changed { method, path, .. } => {
+                ("changed", method, path)
+            }
+        };
+        write!(f, "{}: {} {}", label, method.to_uppercase(), path)?;
+        if let OperationChange::Changed { breaking, .. } = self {
+            if !breaking.is_empty() {
+                write!(f, " ({})", breaking.join("; "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Returns the set of (method, path) pairs of the operations in `doc`
+ */
+fn operations(doc: &Value) -> BTreeSet<(String, String)> {
+    let mut rv = BTreeSet::new();
+    if let Some(paths) = doc["paths"].as_object() {
+        for (path, item) in paths {
+            for method in METHODS {
+                if item.get(method).is_some() {
+                    rv.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+    }
+    rv
+}
+
+/**
+ * Compares the operations defined by two OpenAPI documents (as JSON) and
+ * returns the differences, sorted by kind, then method, then path.
+ *
+ * Operations are compared by their own definitions only.  A change to a
+ * schema under "components" that an operation refers to does not show up as
+ * a change to that operation.
+ */
+pub fn diff_operations(old: &Value, new: &Value) -> Vec<OperationChange> {
+    let old_ops = operations(old);
+    let new_ops = operations(new);
+    let mut changes = Vec::new();
+
+    for (method, path) in new_ops.difference(&old_ops) {
+        changes.push(OperationChange::Added {
+            method: method.clone(),
+            path: path.clone(),
+        });
+    }
+    for (method, path) in old_ops.difference(&new_ops) {
+        changes.push(OperationChange::Removed {
+            method: method.clone(),
+            path: path.clone(),
+        });
+    }
+    for (method, path) in old_ops.intersection(&new_ops) {
+        let old_op = &old["paths"][path][method];
+        let new_op = &new["paths"][path][method];
+        if old_op != new_op {
+            changes.push(OperationChange::Changed {
+                method: method.clone(),
+                path: path.clone(),
+                breaking: breaking_differences(old, new, old_op, new_op),
+            });
+        }
+    }
+
+    changes.sort();
+    changes
+}
+
+/**
+ * Describes each difference between two versions of an operation (`old_op`
+ * from document `old` and `new_op` from document `new`) that could break
+ * clients of the old version:
+ *
+ * - a parameter or the request body was removed
+ * - a parameter, the request body, or a property of the request body became
+ *   required
+ * - a response was removed, or its body was narrowed: it lost a property,
+ *   stopped promising one (the property is no longer required), or changed
+ *   type
+ *
+ * Anything else, like a new optional parameter, a new response, or a new
+ * description, is taken to be compatible.  Schemas are only compared at the
+ * top level, after following a "$ref" into the document's components.
+ */
+fn breaking_differences(
+    old: &Value,
+    new: &Value,
+    old_op: &Value,
+    new_op: &Value,
+) -> Vec<String> {
+    let mut rv = Vec::new();
+
+    /* Parameters are identified by their location and name. */
+    let old_params = parameters(old_op);
+    let new_params = parameters(new_op);
+    for (location, name) in old_params.keys() {
+        if !new_params.contains_key(&(location.clone(), name.clone())) {
+            rv.push(format!("removed {} parameter \"{}\"", location, name));
+        }
+    }
+    for ((location, name), required) in &new_params {
+        let was_required =
+            old_params.get(&(location.clone(), name.clone())).copied();
+        if *required && was_required != Some(true) {
+            rv.push(format!(
+                "{} parameter \"{}\" is newly required",
+                location, name
+            ));
+        }
+    }
+
+    let old_body = &old_op["requestBody"];
+    let new_body = &new_op["requestBody"];
+    let body_required = |body: &Value| body["required"].as_bool() == Some(true);
+    if !old_body.is_null() && new_body.is_null() {
+        rv.push(String::from("removed request body"));
+    }
+    if body_required(new_body) && !body_required(old_body) {
+        rv.push(String::from("request body is newly required"));
+    }
+    if !old_body.is_null() && !new_body.is_null() {
+        let old_required = required(body_schema(old, old_body));
+        for name in required(body_schema(new, new_body)) {
+            if !old_required.contains(name) {
+                rv.push(format!(
+                    "request body property \"{}\" is newly required",
+                    name
+                ));
+            }
+        }
+    }
+
+    let no_responses = serde_json::Map::new();
+    let old_responses =
+        old_op["responses"].as_object().unwrap_or(&no_responses);
+    for (code, old_response) in old_responses {
+        let new_response = &new_op["responses"][code];
+        if new_response.is_null() {
+            rv.push(format!("removed {} response", code));
+            continue;
+        }
+
+        let old_schema = body_schema(old, old_response);
+        let new_schema = body_schema(new, new_response);
+        if old_schema.is_null() {
+            continue;
+        }
+        if new_schema.is_null() {
+            rv.push(format!("{} response no longer has a body", code));
+            continue;
+        }
+        if old_schema["type"] != new_schema["type"] {
+            rv.push(format!("{} response body changed type", code));
+        }
+        let new_properties = properties(new_schema);
+        for name in properties(old_schema) {
+            if !new_properties.contains(name) {
+                rv.push(format!(
+                    "{} response body lost property \"{}\"",
+                    code, name
+                ));
+            }
+        }
+        let new_required = required(new_schema);
+        for name in required(old_schema) {
+            if new_properties.contains(name) && !new_required.contains(name) {
+                rv.push(format!(
+                    "{} response body property \"{}\" is no longer required",
+                    code, name
+                ));
+            }
+        }
+    }
+
+    rv
+}
+
+/**
+ * Returns whether each of an operation's parameters is required, keyed by the
+ * parameter's location and name
+ */
+fn parameters(op: &Value) -> BTreeMap<(String, String), bool> {
+    op["parameters"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|param| {
+            let location = param["in"].as_str().unwrap_or_default();
+            let name = param["name"].as_str().unwrap_or_default();
+            let required = param["required"].as_bool() == Some(true);
+            ((location.to_string(), name.to_string()), required)
+        })
+        .collect()
+}
+
+/**
+ * Returns the JSON schema of a request or response body (`body`) in `doc`,
+ * following a reference to the document's components, or `Value::Null` if
+ * there isn't one
+ */
+fn body_schema<'a>(doc: &'a Value, body: &'a Value) -> &'a Value {
+    let schema = &body["content"]["application/json"]["schema"];
+    match schema["$ref"].as_str().and_then(|r| r.strip_prefix('#')) {
+        Some(pointer) => doc.pointer(pointer).unwrap_or(&Value::Null),
+        None => schema,
+    }
+}
+
+/** Returns the names of the properties that `schema` defines */
+fn properties(schema: &Value) -> BTreeSet<&str> {
+    schema["properties"]
+        .as_object()
+        .into_iter()
+        .flat_map(|properties| properties.keys().map(String::as_str))
+        .collect()
+}
+
+/** Returns the names of the properties that `schema` requires */
+fn required(schema: &Value) -> BTreeSet<&str> {
+    schema["required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::diff_operations;
+    use super::OperationChange;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_operations() {
+        let old = json!({
+            "paths": {
+                "/projects": {
+                    "get": { "operationId": "projects_get" },
+                    "post": { "operationId": "projects_post" }
+                },
+                "/projects/{name}": {
+                    "get": { "operationId": "project_get" },
+                    "delete": { "operationId": "project_delete" }
+                }
+            }
+        });
+
+        /* Identical documents have no differences. */
+        assert!(diff_operations(&old, &old).is_empty());
+
+        let new = json!({
+            "paths": {
+                "/projects": {
+                    "get": { "operationId": "projects_get" },
+                    "post": { "operationId": "projects_create" }
+                },
+                "/projects/{name}": {
+                    "get": { "operationId": "project_get" }
+                },
+                "/instances": {
+                    "get": { "operationId": "instances_get" }
+                }
+            }
+        });
+        let changes = diff_operations(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                OperationChange::Added {
+                    method: "get".to_string(),
+                    path: "/instances".to_string(),
+                },
+                OperationChange::Removed {
+                    method: "delete".to_string(),
+                    path: "/projects/{name}".to_string(),
+                },
+                OperationChange::Changed {
+                    method: "post".to_string(),
+                    path: "/projects".to_string(),
+                    breaking: vec![],
+                },
+            ]
+        );
+        assert_eq!(
+            changes.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec![
+                "added: GET /instances",
+                "removed: DELETE /projects/{name}",
+                "changed: POST /projects",
+            ]
+        );
+        assert_eq!(
+            changes.iter().map(|c| c.is_breaking()).collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+    }
+
+    /**
+     * Returns a document with one operation, "POST /projects", whose
+     * parameters, request body schema, and 200 response body schema are given
+     */
+    fn doc_with_operation(
+        parameters: serde_json::Value,
+        body: serde_json::Value,
+        response: serde_json::Value,
+    ) -> serde_json::Value {
+        let body_ref = json!({ "$ref": "#/components/schemas/Create" });
+        let response_ref = json!({ "$ref": "#/components/schemas/Project" });
+        json!({
+            "paths": {
+                "/projects": {
+                    "post": {
+                        "parameters": parameters,
+                        "requestBody": {
+                            "content": {
+                                "application/json": { "schema": body_ref }
+                            },
+                            "required": true
+                        },
+                        "responses": {
+                            "200": {
+                                "content": {
+                                    "application/json": {
+                                        "schema": response_ref
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "components": {
+                "schemas": { "Create": body, "Project": response }
+            }
+        })
+    }
+
+    fn breaking(changes: &[OperationChange]) -> &[String] {
+        match changes {
+            [OperationChange::Changed { breaking, .. }] => breaking,
+            _ => panic!("expected exactly one changed operation"),
+        }
+    }
+
+    #[test]
+    fn test_diff_operations_compatible() {
+        let old = doc_with_operation(
+            json!([
+                { "in": "path", "name": "org", "required": true }
+            ]),
+            json!({
+                "type": "object",
+                "properties": { "name": { "type": "string" } },
+                "required": ["name"]
+            }),
+            json!({
+                "type": "object",
+                "properties": { "id": { "type": "string" } },
+                "required": ["id"]
+            }),
+        );
+
+        /*
+         * Adding an optional parameter, an optional request body property, a
+         * response property, and a new response doesn't break anybody.
+         */
+        let mut new = doc_with_operation(
+            json!([
+                { "in": "path", "name": "org", "required": true },
+                { "in": "query", "name": "pretty", "required": false }
+            ]),
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "description": { "type": "string" }
+                },
+                "required": ["name"]
+            }),
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "name": { "type": "string" }
+                },
+                "required": ["id", "name"]
+            }),
+        );
+        new["paths"]["/projects"]["post"]["responses"]["304"] =
+            json!({ "description": "not modified" });
+
+        let changes = diff_operations(&old, &new);
+        assert!(breaking(&changes).is_empty());
+        assert!(!changes[0].is_breaking());
+        assert_eq!(changes[0].to_string(), "changed: POST /projects");
+    }
+
+    #[test]
+    fn test_diff_operations_breaking() {
+        let old = doc_with_operation(
+            json!([
+                { "in": "path", "name": "org", "required": true },
+                { "in": "query", "name": "limit", "required": false },
+                { "in": "query", "name": "pretty", "required": false }
+            ]),
+            json!({
+                "type": "object",
+                "properties": { "name": { "type": "string" } },
+                "required": ["name"]
+            }),
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "name": { "type": "string" },
+                    "size": { "type": "integer" }
+                },
+                "required": ["id", "name"]
+            }),
+        );
+
+        /*
+         * Drop a parameter, require another, require a new request body
+         * property, and narrow the response body.
+         */
+        let new = doc_with_operation(
+            json!([
+                { "in": "path", "name": "org", "required": true },
+                { "in": "query", "name": "limit", "required": true }
+            ]),
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "zone": { "type": "string" }
+                },
+                "required": ["name", "zone"]
+            }),
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "name": { "type": "string" }
+                },
+                "required": ["id"]
+            }),
+        );
+
+        let changes = diff_operations(&old, &new);
+        assert_eq!(
+            breaking(&changes),
+            [
+                "removed query parameter \"pretty\"",
+                "query parameter \"limit\" is newly required",
+                "request body property \"zone\" is newly required",
+                "200 response body lost property \"size\"",
+                "200 response body property \"name\" is no longer required",
+            ]
+        );
+        assert!(changes[0].is_breaking());
+
+        /* Removing a response is breaking too. */
+        let mut new = old.clone();
+        new["paths"]["/projects"]["post"]["responses"]
+            .as_object_mut()
+            .unwrap()
+            .remove("200");
+        let changes = diff_operations(&old, &new);
+        assert_eq!(breaking(&changes), ["removed 200 response"]);
+        assert_eq!(
+            changes[0].to_string(),
+            "changed: POST /projects (removed 200 response)"
+        );
+    }
+}